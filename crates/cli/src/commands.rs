@@ -1,18 +1,23 @@
 #![allow(clippy::module_inception)]
 pub mod asset;
+pub mod audit;
 pub mod branch;
 pub mod commands;
 pub mod create;
+pub mod db;
 pub mod deploy;
 pub mod dev;
 pub mod function;
 pub mod generate;
 pub mod migration;
 pub mod plugin;
+pub mod policy;
+pub mod role;
 pub mod settings;
 pub mod shell;
 pub mod task;
 pub mod token;
 pub mod user;
 pub mod user_token;
+pub mod webhook;
 pub mod test;
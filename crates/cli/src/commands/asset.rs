@@ -143,7 +143,7 @@ fn asset_builder(file_path: &str) -> Result<Asset> {
 
     let name = file_path
         .split(path::MAIN_SEPARATOR)
-        .last()
+        .next_back()
         .unwrap()
         .to_string();
     let mime_type = mime_guess::from_path(name)
@@ -0,0 +1,97 @@
+use anyhow::Result;
+use colored::Colorize;
+use reqwest::{Method, Url};
+
+use crate::utils::{http_client, json_to_table};
+
+use super::commands::AuditArgs;
+
+pub async fn command_audit(command: &AuditArgs) -> Result<()> {
+    match http_client(&audit_path(command)?, None, Method::GET).await {
+        Ok(v) => {
+            let is_empty = match v["data"]["entries"].as_array() {
+                Some(v) => v.is_empty(),
+                None => true,
+            };
+
+            if is_empty {
+                eprintln!("{} No data returned", String::from('●').red());
+            } else {
+                eprintln!("{}", json_to_table(&v["data"]["entries"])?);
+                eprintln!(
+                    "{} Next cursor: {}",
+                    String::from('●').green(),
+                    v["data"]["cursor"]
+                );
+            }
+        }
+        Err(err) => {
+            eprintln!("{} {}", String::from('●').red(), err);
+        }
+    };
+
+    Ok(())
+}
+
+// NOTE: The filters are encoded, the actions have spaces and slashes
+fn audit_path(args: &AuditArgs) -> Result<String> {
+    let mut url = Url::parse("http://localhost/audit")?;
+
+    let params = [
+        ("actor", args.actor.to_owned()),
+        ("action", args.action.to_owned()),
+        ("target", args.target.to_owned()),
+        ("outcome", args.outcome.to_owned()),
+        ("from", args.from.map(|v| v.to_string())),
+        ("to", args.to.map(|v| v.to_string())),
+        ("cursor", args.cursor.map(|v| v.to_string())),
+        ("limit", args.limit.map(|v| v.to_string())),
+    ];
+
+    for (name, value) in params {
+        if let Some(value) = value {
+            url.query_pairs_mut().append_pair(name, &value);
+        }
+    }
+
+    match url.query() {
+        Some(query) => Ok(format!("audit?{}", query)),
+        None => Ok("audit".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> AuditArgs {
+        AuditArgs {
+            actor: None,
+            action: None,
+            target: None,
+            outcome: None,
+            from: None,
+            to: None,
+            cursor: None,
+            limit: None,
+        }
+    }
+
+    #[test]
+    fn test_audit_path() {
+        assert_eq!(audit_path(&args()).unwrap(), "audit");
+
+        let path = audit_path(&AuditArgs {
+            actor: Some("admin@example.com".to_string()),
+            action: Some("DELETE /_/user".to_string()),
+            cursor: Some(100),
+            ..args()
+        })
+        .unwrap();
+
+        assert_eq!(
+            path,
+            "audit?actor=admin%40example.com&action=DELETE+%2F_%2Fuser&cursor=100"
+        );
+    }
+}
@@ -1,8 +1,9 @@
 use anyhow::Result;
+use chrono::DateTime;
 use cliclack::{input, intro, outro};
 use colored::Colorize;
 use reqwest::Method;
-use serde_json::json;
+use serde_json::{json, Value};
 
 use crate::utils::{http_client, json_to_table};
 
@@ -25,10 +26,25 @@ pub async fn command_branch(command: &BranchArgs) -> Result<()> {
             let branch_name: String = input("What is the branch name?")
                 .placeholder("Give a name to the branch.")
                 .interact()?;
+            let ttl: String = input(format!(
+                "How many seconds should the branch be kept? {}",
+                "(Optional)".to_string().yellow()
+            ))
+            .placeholder("The branch is deleted after them, leave it empty to keep it.")
+            .required(false)
+            .validate(|input: &String| {
+                if input.is_empty() || input.parse::<i64>().is_ok_and(|v| v > 0) {
+                    Ok(())
+                } else {
+                    Err("Please enter a number of seconds greater than 0.")
+                }
+            })
+            .interact()?;
 
             let body = json!({
                 "db_name": db_name,
-                "branch_name": branch_name
+                "branch_name": branch_name,
+                "ttl": ttl.parse::<i64>().ok(),
             })
             .to_string();
 
@@ -66,6 +82,20 @@ pub async fn command_branch(command: &BranchArgs) -> Result<()> {
 
             Ok(())
         }
+        BranchCommands::Diff(args) => {
+            match http_client(
+                &format!("branch/diff?branch={}", args.branch),
+                None,
+                Method::GET,
+            )
+            .await
+            {
+                Ok(v) => print_diff(&v["data"])?,
+                Err(e) => eprintln!("{} {}", String::from('●').red(), e),
+            };
+
+            Ok(())
+        }
         BranchCommands::List => {
             match http_client("branch", None, Method::GET).await {
                 Ok(v) => {
@@ -77,7 +107,7 @@ pub async fn command_branch(command: &BranchArgs) -> Result<()> {
                     if is_empty {
                         eprintln!("{} No data returned", String::from('●').red());
                     } else {
-                        println!("{}", json_to_table(&v["data"])?);
+                        println!("{}", json_to_table(&branch_rows(&v["data"]))?);
                     }
                 }
                 Err(e) => eprintln!("{} {}", String::from('●').red(), e),
@@ -85,5 +115,114 @@ pub async fn command_branch(command: &BranchArgs) -> Result<()> {
 
             Ok(())
         }
+        BranchCommands::Merge(args) => {
+            let body = json!({
+                "branch": args.branch,
+                "tables": args.tables,
+            })
+            .to_string();
+
+            match http_client("branch/merge", Some(&body), Method::POST).await {
+                Ok(v) => {
+                    print_diff(&v["data"])?;
+                    eprintln!(
+                        "{} Branch merged into {}",
+                        String::from('●').green(),
+                        v["data"]["db_name"].as_str().unwrap_or_default()
+                    );
+                }
+                Err(e) => eprintln!("{} {}", String::from('●').red(), e),
+            };
+
+            Ok(())
+        }
+        BranchCommands::Promote(args) => {
+            let body = json!({ "branch": args.branch }).to_string();
+
+            match http_client("branch/promote", Some(&body), Method::POST).await {
+                Ok(v) => eprintln!(
+                    "{} Branch promoted to {}, the previous database is kept in {}",
+                    String::from('●').green(),
+                    v["data"]["db_name"].as_str().unwrap_or_default(),
+                    v["data"]["backup"].as_str().unwrap_or_default()
+                ),
+                Err(e) => eprintln!("{} {}", String::from('●').red(), e),
+            };
+
+            Ok(())
+        }
+    }
+}
+
+// NOTE: The source position is the checkpoint sequence and the offset of the WAL of the database
+// when the branch was created
+fn branch_rows(data: &Value) -> Value {
+    let rows = data
+        .as_array()
+        .cloned()
+        .unwrap_or_default()
+        .iter()
+        .map(|branch| {
+            let source_position = match (
+                branch["source_wal_checkpoint"].as_i64(),
+                branch["source_wal_offset"].as_i64(),
+            ) {
+                (Some(checkpoint), Some(offset)) => format!("{}:{}", checkpoint, offset),
+                _ => "-".to_string(),
+            };
+
+            json!({
+                "branch": branch["branch"],
+                "db_name": branch["db_name"],
+                "created_by": branch["created_by"].as_str().unwrap_or("-"),
+                "created_at": format_timestamp(&branch["created_at"]),
+                "expires_at": format_timestamp(&branch["expires_at"]),
+                "source_position": source_position,
+            })
+        })
+        .collect();
+
+    Value::Array(rows)
+}
+
+fn format_timestamp(value: &Value) -> String {
+    match value.as_i64().and_then(|v| DateTime::from_timestamp(v, 0)) {
+        Some(v) => v.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => "-".to_string(),
     }
 }
+
+fn print_diff(data: &Value) -> Result<()> {
+    let mut changes = Vec::new();
+
+    for (key, object_type) in [
+        ("tables", "table"),
+        ("indexes", "index"),
+        ("views", "view"),
+        ("triggers", "trigger"),
+    ] {
+        for change in data["diff"][key].as_array().cloned().unwrap_or_default() {
+            changes.push(json!({
+                "type": object_type,
+                "name": change["name"],
+                "change": change["change"],
+            }));
+        }
+    }
+
+    if changes.is_empty() {
+        eprintln!(
+            "{} The branch {} has the same schema as {}",
+            String::from('●').green(),
+            data["branch"].as_str().unwrap_or_default(),
+            data["db_name"].as_str().unwrap_or_default()
+        );
+
+        return Ok(());
+    }
+
+    eprintln!("{}", json_to_table(&Value::Array(changes))?);
+    println!("{}", data["sql"].as_str().unwrap_or_default());
+
+    Ok(())
+}
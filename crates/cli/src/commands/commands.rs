@@ -6,6 +6,8 @@ pub enum Commands {
     /// or an asset defining a file path
     #[clap(verbatim_doc_comment)]
     Asset(AssetArgs),
+    /// Read the audit log of the administrative and the write operations
+    Audit(AuditArgs),
     /// Manage branches
     Branch(BranchArgs),
     /// Create a new project using a template
@@ -13,6 +15,8 @@ pub enum Commands {
     /// - If you provide a repository URL as an argument, the project will be created using it
     #[clap(verbatim_doc_comment)]
     Create(CreateArgs),
+    /// Manage databases
+    Db(DbArgs),
     /// Deploy the project to the server
     Deploy(DeployArgs),
     /// Development experience
@@ -29,10 +33,15 @@ pub enum Commands {
     /// - The version should be in the format of YYYYMMDD
     /// - The name should be in the format of <name>_<description>
     /// - The type should be up or down
+    /// - The up migrations are recorded in the migration history of the database
     #[clap(verbatim_doc_comment)]
     Migration(MigrationArgs),
     /// Manage plugins
     Plugin(PluginArgs),
+    /// Manage the row-level security policies of the user tokens
+    Policy(PolicyArgs),
+    /// Manage the roles of the users and the tokens
+    Role(RoleArgs),
     /// Sets the initial configuration
     Settings,
     /// SQLite shell to manage the databases locally
@@ -47,6 +56,8 @@ pub enum Commands {
     User(UserArgs),
     /// Manage users tokens
     UserToken(UserTokenArgs),
+    /// Manage the webhooks that receive the changes of the tables
+    Webhook(WebhookArgs),
 }
 
 #[derive(Args)]
@@ -62,6 +73,34 @@ pub struct AssetArgs {
     pub path: Option<String>,
 }
 
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Email of the user or name of the token that did the operations
+    #[arg(long)]
+    pub actor: Option<String>,
+    /// Start of the action, e.g. "DELETE /_/user"
+    #[arg(long)]
+    pub action: Option<String>,
+    /// Start of the target, e.g. the email of a user or the name of a database
+    #[arg(long)]
+    pub target: Option<String>,
+    /// Outcome: success or failure
+    #[arg(long)]
+    pub outcome: Option<String>,
+    /// Unix timestamp of the first operation
+    #[arg(long)]
+    pub from: Option<i64>,
+    /// Unix timestamp of the last operation
+    #[arg(long)]
+    pub to: Option<i64>,
+    /// Returns the operations after the cursor, the cursor of the previous page
+    #[arg(long)]
+    pub cursor: Option<i64>,
+    /// Maximum number of operations returned, 100 by default
+    #[arg(long)]
+    pub limit: Option<i64>,
+}
+
 #[derive(Args)]
 pub struct BranchArgs {
     #[command(subcommand)]
@@ -74,8 +113,29 @@ pub enum BranchCommands {
     Create,
     /// Delete a branch
     Delete,
+    /// Show the schema changes of a branch and the migration to apply them to its database
+    Diff(BranchDiffArgs),
     /// List all the branches
     List,
+    /// Apply the schema changes of a branch, and the rows of some tables, to its database
+    Merge(BranchMergeArgs),
+    /// Replace the database of a branch with the branch, keeping a backup of the database
+    Promote(BranchDiffArgs),
+}
+
+#[derive(Args)]
+pub struct BranchDiffArgs {
+    /// Name of the branch database, e.g. example.dev.branch.sql
+    pub branch: String,
+}
+
+#[derive(Args)]
+pub struct BranchMergeArgs {
+    /// Name of the branch database, e.g. example.dev.branch.sql
+    pub branch: String,
+    /// Tables whose rows are replaced with the rows of the branch
+    #[arg(short, long, value_delimiter = ',')]
+    pub tables: Vec<String>,
 }
 
 #[derive(Args)]
@@ -83,6 +143,86 @@ pub struct CreateArgs {
     pub repo_url: Option<String>,
 }
 
+#[derive(Args)]
+pub struct DbArgs {
+    #[command(subcommand)]
+    pub command: DbCommands,
+}
+
+#[derive(Subcommand)]
+pub enum DbCommands {
+    /// List the WAL archive generations of a database
+    Archive(DbNameArgs),
+    /// Download a consistent backup of a database
+    Backup(DbBackupArgs),
+    /// Show the scheduled backups
+    Backups(DbBackupsArgs),
+    /// Create a database
+    Create(DbNameArgs),
+    /// Delete a database
+    Delete(DbNameArgs),
+    /// Show the details of a database
+    Info(DbNameArgs),
+    /// List all the databases
+    List,
+    /// Replace a database with a backup file or with the WAL archive
+    Restore(DbRestoreArgs),
+}
+
+#[derive(Args)]
+pub struct DbBackupArgs {
+    /// Name of the database
+    pub db_name: String,
+    /// Path of the backup file, by default the name of the database
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// Compress the backup with gzip
+    #[arg(short, long, default_value_t = false)]
+    pub gzip: bool,
+}
+
+#[derive(Args)]
+pub struct DbBackupsArgs {
+    #[command(subcommand)]
+    pub command: DbBackupsCommands,
+}
+
+#[derive(Subcommand)]
+pub enum DbBackupsCommands {
+    /// List the scheduled backups of all the scheduled databases or of one of them
+    List(DbBackupsListArgs),
+    /// Show the status of the scheduled backups of each database
+    Status,
+}
+
+#[derive(Args)]
+pub struct DbBackupsListArgs {
+    /// Name of the database
+    pub db_name: Option<String>,
+}
+
+#[derive(Args)]
+pub struct DbNameArgs {
+    /// Name of the database
+    pub db_name: String,
+}
+
+#[derive(Args)]
+pub struct DbRestoreArgs {
+    /// Name of the database
+    pub db_name: String,
+    /// Path of the backup file, it can be gzip-compressed. Without it, the database is
+    /// rebuilt from the WAL archive
+    pub path: Option<String>,
+    /// Rebuild the database from the WAL archive as it was at this RFC 3339 timestamp,
+    /// e.g. 2025-01-01T00:00:00Z
+    #[arg(short, long, conflicts_with = "path")]
+    pub timestamp: Option<String>,
+    /// Create the database if it doesn't exist
+    #[arg(short, long)]
+    pub create: bool,
+}
+
 #[derive(Args)]
 pub struct DeployArgs {
     /// Force the use of environment variables
@@ -120,11 +260,51 @@ pub struct GenerateArgs {
 }
 
 #[derive(Args)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct MigrationArgs {
+    #[command(subcommand)]
+    pub command: Option<MigrationCommands>,
     /// Name of the database to migrate
-    pub db_name: String,
+    pub db_name: Option<String>,
     /// Path to the migration file
-    pub path: String,
+    pub path: Option<String>,
+    /// Run the migration in a copy of the database and show what it would change
+    #[arg(short, long)]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand)]
+pub enum MigrationCommands {
+    /// Roll back the last applied migrations of a database with their down files
+    Down(MigrationDownArgs),
+    /// Show the applied and pending migrations of a database
+    Status(MigrationStatusArgs),
+    /// Run the pending up migrations of the migrations folder in order
+    Up(MigrationUpArgs),
+}
+
+#[derive(Args)]
+pub struct MigrationDownArgs {
+    /// Name of the database
+    pub db_name: String,
+    /// Number of migrations to roll back
+    #[arg(short, long, default_value_t = 1)]
+    pub steps: usize,
+}
+
+#[derive(Args)]
+pub struct MigrationStatusArgs {
+    /// Name of the database
+    pub db_name: String,
+}
+
+#[derive(Args)]
+pub struct MigrationUpArgs {
+    /// Name of the database, all the databases of the migrations folder if it's not set
+    pub db_name: Option<String>,
+    /// Last version to migrate, included
+    #[arg(short, long)]
+    pub to: Option<String>,
 }
 
 #[derive(Args)]
@@ -191,6 +371,105 @@ pub struct TestArgs {
     pub watch: bool,
 }
 
+#[derive(Args)]
+pub struct PolicyArgs {
+    #[command(subcommand)]
+    pub command: PolicyCommands,
+}
+
+#[derive(Subcommand)]
+pub enum PolicyCommands {
+    /// Create or replace a policy
+    Create(PolicyCreateArgs),
+    /// Delete a policy
+    Delete(PolicyDeleteArgs),
+    /// List the policies
+    List(PolicyListArgs),
+}
+
+#[derive(Args)]
+pub struct PolicyCreateArgs {
+    /// Name of the database
+    pub db_name: String,
+    /// Name of the table
+    pub table_name: String,
+    /// SQL expression that the rows must match, e.g. "user_uuid = auth_uid()"
+    pub predicate: String,
+    /// Operation: SELECT, INSERT, UPDATE, DELETE or * for any operation
+    #[arg(short, long, default_value_t = String::from("*"))]
+    pub operation: String,
+}
+
+#[derive(Args)]
+pub struct PolicyDeleteArgs {
+    /// Name of the database
+    pub db_name: String,
+    /// Name of the table
+    pub table_name: String,
+    /// Operation: SELECT, INSERT, UPDATE, DELETE or * for any operation
+    #[arg(short, long, default_value_t = String::from("*"))]
+    pub operation: String,
+}
+
+#[derive(Args)]
+pub struct PolicyListArgs {
+    /// Name of the database, all the databases if it isn't provided
+    pub db_name: Option<String>,
+}
+
+#[derive(Args)]
+pub struct RoleArgs {
+    #[command(subcommand)]
+    pub command: RoleCommands,
+}
+
+#[derive(Subcommand)]
+pub enum RoleCommands {
+    /// Assign a role to a user or a token
+    Assign(RoleAssignArgs),
+    /// Create or replace a role
+    Create(RoleCreateArgs),
+    /// Delete a role
+    Delete(RoleDeleteArgs),
+    /// List the roles
+    List,
+    /// Unassign a role from a user or a token
+    Unassign(RoleAssignArgs),
+}
+
+#[derive(Args)]
+pub struct RoleCreateArgs {
+    /// Name of the role
+    pub name: String,
+    /// Permissions: deploy_functions, push_assets, run_migrations, manage_branches, manage_users
+    /// or manage_tokens
+    #[arg(short, long, value_delimiter = ',')]
+    pub permissions: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct RoleDeleteArgs {
+    /// Name of the role
+    pub name: String,
+}
+
+#[derive(Args)]
+pub struct RoleAssignArgs {
+    /// Name of the role
+    pub role: String,
+    /// Email of the user
+    #[arg(
+        short,
+        long,
+        conflicts_with = "token",
+        required_unless_present = "token"
+    )]
+    pub email: Option<String>,
+    /// Name of the token
+    #[arg(short, long)]
+    pub token: Option<String>,
+}
+
 #[derive(Args)]
 pub struct TokenArgs {
     #[command(subcommand)]
@@ -203,14 +482,49 @@ pub enum TokenCommands {
     Create,
     /// Delete a token
     Delete,
+    /// Grant a permission to a token
+    Grant(TokenPermissionArgs),
+    /// Revoke a token, it is rejected from now on
+    Invalidate(TokenInvalidateArgs),
     /// List all the tokens
     List,
+    /// List the permissions of a token
+    Permissions(TokenPermissionsArgs),
+    /// Revoke a permission from a token
+    Revoke(TokenPermissionArgs),
     /// Update token
     Update,
     /// Get the token value
     Value,
 }
 
+#[derive(Args)]
+pub struct TokenPermissionArgs {
+    /// Name of the token
+    pub name: String,
+    /// Name of the database, * for any database
+    #[arg(short, long = "db-name", default_value_t = String::from("*"))]
+    pub db_name: String,
+    /// Action: query, migration, branch or * for any action
+    #[arg(short, long, default_value_t = String::from("*"))]
+    pub action: String,
+    /// Operation: SELECT, INSERT, UPDATE, DELETE or * for any operation
+    #[arg(short, long, default_value_t = String::from("*"))]
+    pub operation: String,
+}
+
+#[derive(Args)]
+pub struct TokenPermissionsArgs {
+    /// Name of the token
+    pub name: String,
+}
+
+#[derive(Args)]
+pub struct TokenInvalidateArgs {
+    /// Name of the token
+    pub name: String,
+}
+
 #[derive(Args)]
 pub struct UserArgs {
     #[command(subcommand)]
@@ -225,10 +539,33 @@ pub enum UserCommands {
     Delete,
     /// List all the users
     List,
+    /// Manage the two-factor authentication of your user
+    Totp(UserTotpArgs),
     /// Update user
     Update,
 }
 
+#[derive(Args)]
+pub struct UserTotpArgs {
+    #[command(subcommand)]
+    pub command: UserTotpCommands,
+}
+
+#[derive(Subcommand)]
+pub enum UserTotpCommands {
+    /// Disable the two-factor authentication
+    Disable(UserTotpDisableArgs),
+    /// Enable the two-factor authentication with an authenticator app
+    Enable,
+}
+
+#[derive(Args)]
+pub struct UserTotpDisableArgs {
+    /// Email of another user, to disable its two-factor authentication without a code
+    #[arg(short, long)]
+    pub email: Option<String>,
+}
+
 #[derive(Args)]
 pub struct UserTokenArgs {
     #[command(subcommand)]
@@ -241,10 +578,92 @@ pub enum UserTokenCommands {
     Create,
     /// Delete a user token
     Delete,
+    /// Grant a permission to a user token
+    Grant(UserTokenPermissionArgs),
     /// List all the users tokens
     List,
+    /// List the permissions of a user token
+    Permissions(UserTokenPermissionsArgs),
+    /// Revoke a permission from a user token
+    Revoke(UserTokenPermissionArgs),
     /// Update user token
     Update,
     /// Get the token value related to a user
     Value,
 }
+
+#[derive(Args)]
+pub struct UserTokenPermissionArgs {
+    /// Email of the user
+    pub email: String,
+    /// Name of the database, * for any database
+    #[arg(short, long = "db-name", default_value_t = String::from("*"))]
+    pub db_name: String,
+    /// Action: query, migration, branch or * for any action
+    #[arg(short, long, default_value_t = String::from("*"))]
+    pub action: String,
+    /// Operation: SELECT, INSERT, UPDATE, DELETE or * for any operation
+    #[arg(short, long, default_value_t = String::from("*"))]
+    pub operation: String,
+}
+
+#[derive(Args)]
+pub struct UserTokenPermissionsArgs {
+    /// Email of the user
+    pub email: String,
+}
+
+#[derive(Args)]
+pub struct WebhookArgs {
+    #[command(subcommand)]
+    pub command: WebhookCommands,
+}
+
+#[derive(Subcommand)]
+pub enum WebhookCommands {
+    /// Create or update a webhook
+    Create(WebhookCreateArgs),
+    /// Delete a webhook
+    Delete(WebhookNameArgs),
+    /// List the last delivery attempts of a webhook
+    Deliveries(WebhookDeliveriesArgs),
+    /// List the webhooks
+    List,
+}
+
+#[derive(Args)]
+pub struct WebhookCreateArgs {
+    /// Name of the webhook
+    pub name: String,
+    /// Name of the database
+    pub db_name: String,
+    /// URL that receives the changes, e.g. https://example.com/webhook
+    pub url: String,
+    /// Name of the table, * for any captured table
+    #[arg(short, long = "table", default_value_t = String::from("*"))]
+    pub table_name: String,
+    /// Comma-separated operations: INSERT, UPDATE, DELETE or * for any operation
+    #[arg(short, long, default_value_t = String::from("*"))]
+    pub operations: String,
+    /// Secret to sign the payloads, a random one is generated if it isn't provided
+    #[arg(short, long)]
+    pub secret: Option<String>,
+    /// Create the webhook without delivering the changes
+    #[arg(short, long, default_value_t = false)]
+    pub inactive: bool,
+}
+
+#[derive(Args)]
+pub struct WebhookDeliveriesArgs {
+    /// Name of the webhook
+    pub name: String,
+    /// Maximum number of delivery attempts
+    #[arg(short, long, default_value_t = 20)]
+    pub limit: u32,
+}
+
+#[derive(Args)]
+pub struct WebhookNameArgs {
+    /// Name of the webhook
+    pub name: String,
+}
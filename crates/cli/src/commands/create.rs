@@ -52,7 +52,7 @@ pub async fn command_create(command: &CreateArgs) -> Result<()> {
         vec![]
     };
     let application = if let Some(url) = &repo_url {
-        url.split('/').last().unwrap().to_string()
+        url.split('/').next_back().unwrap().to_string()
     } else {
         let items: Vec<(String, String, String)> = packages
             .iter()
@@ -0,0 +1,184 @@
+use std::fs;
+
+use anyhow::Result;
+use chrono::DateTime;
+use colored::Colorize;
+use reqwest::Method;
+use serde_json::json;
+
+use crate::utils::{http_client, http_client_bytes, json_to_table};
+
+use super::commands::{DbArgs, DbBackupsCommands, DbCommands};
+
+pub async fn command_db(command: &DbArgs) -> Result<()> {
+    match &command.command {
+        DbCommands::Archive(args) => {
+            let path = format!("archive?db_name={}", args.db_name);
+
+            match http_client(&path, None, Method::GET).await {
+                Ok(v) => {
+                    let is_empty = match v["data"].as_array() {
+                        Some(v) => v.is_empty(),
+                        None => true,
+                    };
+
+                    if is_empty {
+                        eprintln!("{} No data returned", String::from('●').red());
+                    } else {
+                        eprintln!("{}", json_to_table(&v["data"])?);
+                    }
+                }
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        DbCommands::Backup(args) => {
+            let path = format!("backup?db_name={}&gzip={}", args.db_name, args.gzip);
+            let output = match &args.output {
+                Some(output) => output.to_string(),
+                None if args.gzip => format!("{}.gz", args.db_name),
+                None => args.db_name.to_string(),
+            };
+
+            match http_client_bytes(&path, None, Method::GET).await {
+                Ok(bytes) => {
+                    fs::write(&output, bytes)?;
+
+                    eprintln!(
+                        "{} Database {} backed up to {}",
+                        String::from('●').green(),
+                        args.db_name,
+                        output
+                    );
+                }
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        DbCommands::Backups(args) => {
+            let path = match &args.command {
+                DbBackupsCommands::List(args) => match &args.db_name {
+                    Some(db_name) => format!("backups?db_name={}", db_name),
+                    None => "backups".to_string(),
+                },
+                DbBackupsCommands::Status => "backups/status".to_string(),
+            };
+
+            match http_client(&path, None, Method::GET).await {
+                Ok(v) => {
+                    let is_empty = match v["data"].as_array() {
+                        Some(v) => v.is_empty(),
+                        None => true,
+                    };
+
+                    if is_empty {
+                        eprintln!("{} No data returned", String::from('●').red());
+                    } else {
+                        eprintln!("{}", json_to_table(&v["data"])?);
+                    }
+                }
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        DbCommands::Create(args) => {
+            let body = json!({ "db_name": args.db_name }).to_string();
+
+            match http_client("database", Some(&body), Method::POST).await {
+                Ok(_) => eprintln!(
+                    "{} Database created: {}",
+                    String::from('●').green(),
+                    args.db_name
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        DbCommands::Delete(args) => {
+            let body = json!({ "db_name": args.db_name }).to_string();
+
+            match http_client("database", Some(&body), Method::DELETE).await {
+                Ok(_) => eprintln!(
+                    "{} Database deleted: {}",
+                    String::from('●').green(),
+                    args.db_name
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        DbCommands::Info(args) => {
+            let path = format!("database/info?db_name={}", args.db_name);
+
+            match http_client(&path, None, Method::GET).await {
+                Ok(v) => eprintln!("{}", json_to_table(&v["data"])?),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        DbCommands::List => {
+            match http_client("database", None, Method::GET).await {
+                Ok(v) => {
+                    let is_empty = match v["data"].as_array() {
+                        Some(v) => v.is_empty(),
+                        None => true,
+                    };
+
+                    if is_empty {
+                        eprintln!("{} No data returned", String::from('●').red());
+                    } else {
+                        eprintln!("{}", json_to_table(&v["data"])?);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{} {}", String::from('●').red(), err);
+                }
+            };
+
+            Ok(())
+        }
+        DbCommands::Restore(args) => {
+            let result = match &args.path {
+                Some(file_path) => {
+                    let path = format!("restore?db_name={}&create={}", args.db_name, args.create);
+                    let bytes = fs::read(file_path)?;
+
+                    http_client_bytes(&path, Some(bytes), Method::POST).await
+                }
+                None => {
+                    let timestamp = match &args.timestamp {
+                        Some(timestamp) => {
+                            Some(DateTime::parse_from_rfc3339(timestamp)?.timestamp_millis())
+                        }
+                        None => None,
+                    };
+                    let body =
+                        json!({ "db_name": args.db_name, "timestamp": timestamp, "create": args.create })
+                            .to_string();
+
+                    http_client("archive/restore", Some(&body), Method::POST)
+                        .await
+                        .map(|_| vec![])
+                }
+            };
+
+            match result {
+                Ok(_) => eprintln!(
+                    "{} Database {} restored from {}",
+                    String::from('●').green(),
+                    args.db_name,
+                    args.path.as_deref().unwrap_or("the WAL archive")
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+    }
+}
@@ -10,23 +10,12 @@ use cliclack::outro;
 use cliclack::{input, intro, password};
 use colored::Colorize;
 use reqwest::Method;
-use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::utils::http_client;
 
 use super::commands::DeployArgs;
 
-#[derive(Deserialize, Serialize)]
-struct Config {
-    server: ServerOptions,
-}
-
-#[derive(Deserialize, Serialize)]
-struct ServerOptions {
-    url: String,
-}
-
 const QUERY_DEPLOY_URL: &str = "QUERY_DEPLOY_URL";
 const QUERY_DEPLOY_TOKEN: &str = "QUERY_DEPLOY_TOKEN";
 const QUERY_DEPLOY_EMAIL: &str = "QUERY_DEPLOY_EMAIL";
@@ -339,7 +339,11 @@ pub fn esbuild(function_path: &str) -> Result<Vec<u8>> {
         };
     };
 
-    let bundle_path = format!("{}/{}", out_dir, function_path.split('/').last().unwrap());
+    let bundle_path = format!(
+        "{}/{}",
+        out_dir,
+        function_path.split('/').next_back().unwrap()
+    );
     let re = Regex::new(r"(\.jsx|\.tsx|\.ts)$").unwrap();
     let bundle_path = re.replace(&bundle_path, ".js").to_string();
 
@@ -1,16 +1,43 @@
-use std::fs;
+use std::{cmp::Ordering, fs, path::Path};
 
+use anyhow::Result;
 use colored::Colorize;
+use openssl::sha::sha256;
 use reqwest::Method;
-use serde_json::json;
+use serde_json::{json, Value};
 
-use crate::{config::CONFIG, utils::http_client};
+use crate::{
+    config::CONFIG,
+    utils::{http_client, json_to_table},
+};
 
-use super::commands::MigrationArgs;
+use super::commands::{MigrationArgs, MigrationCommands, MigrationDownArgs, MigrationUpArgs};
 
-pub async fn command_migration(command: &MigrationArgs) {
-    let db_name = &command.db_name;
-    let path = &command.path;
+#[derive(Debug, PartialEq)]
+struct MigrationFile {
+    version: String,
+    name: String,
+    kind: String,
+}
+
+pub async fn command_migration(command: &MigrationArgs) -> Result<()> {
+    match (&command.command, &command.db_name, &command.path) {
+        (Some(MigrationCommands::Down(args)), _, _) => down(args).await,
+        (Some(MigrationCommands::Status(args)), _, _) => status(&args.db_name).await,
+        (Some(MigrationCommands::Up(args)), _, _) => up(args).await,
+        (None, Some(db_name), Some(path)) => push(db_name, path, command.dry_run).await,
+        _ => {
+            eprintln!(
+                "{} Usage: query migration <DB_NAME> <PATH>",
+                String::from('●').red()
+            );
+
+            Ok(())
+        }
+    }
+}
+
+async fn push(db_name: &str, path: &str, dry_run: bool) -> Result<()> {
     let path = path.to_string();
     let query = match fs::read_to_string(&path) {
         Ok(content) => content,
@@ -19,18 +46,374 @@ pub async fn command_migration(command: &MigrationArgs) {
         }
     };
 
-    let body = json!({
+    // NOTE: The up migrations are recorded in the migration history and the down migrations
+    // remove their version from it
+    let mut body = match parse_migration_file(&path) {
+        Some(file) => migration_body(db_name, &query, &file),
+        None => json!({
+            "db_name": db_name,
+            "query": query
+        }),
+    };
+
+    if dry_run {
+        body["dry_run"] = json!(true);
+
+        return match http_client("migration", Some(&body.to_string()), Method::POST).await {
+            Ok(v) => print_dry_run(&v["data"]),
+            Err(e) => {
+                eprintln!("{} {}", String::from('●').red(), e);
+                Ok(())
+            }
+        };
+    }
+
+    if let Err(e) = post_migration(&body, &path).await {
+        eprintln!("{} {}", String::from('●').red(), e);
+    }
+
+    Ok(())
+}
+
+async fn up(args: &MigrationUpArgs) -> Result<()> {
+    let db_names = match &args.db_name {
+        Some(v) => vec![v.to_string()],
+        None => migration_databases()?,
+    };
+
+    for db_name in db_names {
+        let applied = applied_versions(&db_name).await?;
+
+        let pending = local_migrations(&db_name, "up")?
+            .into_iter()
+            .filter(|(file, _, _)| !applied.contains(&file.version))
+            .filter(|(file, _, _)| match &args.to {
+                Some(to) => compare_versions(&file.version, to) != Ordering::Greater,
+                None => true,
+            })
+            .collect::<Vec<_>>();
+
+        if pending.is_empty() {
+            eprintln!(
+                "{} No pending migrations: {db_name}",
+                String::from('●').green()
+            );
+            continue;
+        }
+
+        for (file, query, path) in pending {
+            // NOTE: The next migrations may depend on the failed one, so the batch stops
+            if let Err(e) = post_migration(&migration_body(&db_name, &query, &file), &path).await {
+                eprintln!("{} {}", String::from('●').red(), e);
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn down(args: &MigrationDownArgs) -> Result<()> {
+    let db_name = &args.db_name;
+    let mut applied = applied_versions(db_name).await?;
+
+    applied.reverse();
+
+    let files = local_migrations(db_name, "down")?;
+
+    for version in applied.iter().take(args.steps) {
+        let (file, query, path) = match files.iter().find(|(file, _, _)| file.version == *version) {
+            Some(v) => v,
+            None => {
+                eprintln!(
+                    "{} The down migration of the version {} doesn't exist",
+                    String::from('●').red(),
+                    version
+                );
+                return Ok(());
+            }
+        };
+
+        if let Err(e) = post_migration(&migration_body(db_name, query, file), path).await {
+            eprintln!("{} {}", String::from('●').red(), e);
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+fn migration_body(db_name: &str, query: &str, file: &MigrationFile) -> Value {
+    json!({
         "db_name": db_name,
-        "query": query
+        "query": query,
+        "version": file.version,
+        "name": file.name,
+        "rollback": file.kind == "down",
     })
-    .to_string();
+}
+
+async fn post_migration(body: &Value, path: &str) -> Result<()> {
+    let v = http_client("migration", Some(&body.to_string()), Method::POST).await?;
+    let path = path.replace(&CONFIG.structure.migrations_folder.clone(), "");
+
+    if v["data"]["skipped"].as_bool().unwrap_or(false) {
+        println!(
+            "{} Migration already applied: {path}",
+            String::from('●').yellow()
+        );
+    } else if body["rollback"].as_bool().unwrap_or(false) {
+        println!(
+            "{} Migration rolled back: {path}",
+            String::from('●').green()
+        );
+    } else {
+        println!("{} Migration executed: {path}", String::from('●').green());
+    }
+
+    Ok(())
+}
+
+fn print_dry_run(report: &Value) -> Result<()> {
+    if report["skipped"].as_bool().unwrap_or(false) {
+        eprintln!("{} Migration already applied", String::from('●').yellow());
+        return Ok(());
+    }
+
+    match report["error"].as_str() {
+        Some(e) => eprintln!("{} Migration failed: {}", String::from('●').red(), e),
+        None => eprintln!(
+            "{} Migration succeeded, {} rows changed",
+            String::from('●').green(),
+            report["changes"]
+        ),
+    }
+
+    if report["schema_changes"]
+        .as_array()
+        .is_some_and(|v| !v.is_empty())
+    {
+        eprintln!("{}", json_to_table(&report["schema_changes"])?);
+    }
+
+    if report["foreign_key_violations"]
+        .as_array()
+        .is_some_and(|v| !v.is_empty())
+    {
+        eprintln!("{} Foreign key violations:", String::from('●').red());
+        eprintln!("{}", json_to_table(&report["foreign_key_violations"])?);
+    }
+
+    Ok(())
+}
+
+// NOTE: Returns the applied versions in order
+async fn applied_versions(db_name: &str) -> Result<Vec<String>> {
+    let applied = applied_migrations(db_name).await?;
+
+    let mut versions = applied
+        .iter()
+        .filter_map(|m| m["version"].as_str().map(|v| v.to_string()))
+        .collect::<Vec<String>>();
+
+    versions.sort_by(|a, b| compare_versions(a, b));
+
+    Ok(versions)
+}
 
-    match http_client("migration", Some(&body), Method::POST).await {
-        Ok(_) => {
-            let path = path.replace(&CONFIG.structure.migrations_folder.clone(), "");
+async fn applied_migrations(db_name: &str) -> Result<Vec<Value>> {
+    let v = http_client(&format!("migration?db_name={}", db_name), None, Method::GET).await?;
 
-            println!("{} Migration executed: {path}", String::from('●').green());
+    Ok(v["data"].as_array().cloned().unwrap_or_default())
+}
+
+async fn status(db_name: &str) -> Result<()> {
+    let applied = match applied_migrations(db_name).await {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{} {}", String::from('●').red(), e);
+            return Ok(());
+        }
+    };
+
+    let mut rows: Vec<Value> = Vec::new();
+
+    for (file, query, _) in local_migrations(db_name, "up")? {
+        let migration = applied
+            .iter()
+            .find(|m| m["version"].as_str() == Some(file.version.as_str()));
+
+        let status = match migration {
+            Some(m) if m["checksum"].as_str() == Some(checksum(&query).as_str()) => "applied",
+            Some(_) => "modified",
+            None => "pending",
+        };
+
+        rows.push(json!({
+            "version": file.version,
+            "name": file.name,
+            "status": status,
+            "applied_at": migration.map(|m| m["applied_at"].clone()),
+        }));
+    }
+
+    // NOTE: The migrations applied without a local file are listed too
+    for migration in &applied {
+        if !rows.iter().any(|r| r["version"] == migration["version"]) {
+            rows.push(json!({
+                "version": migration["version"],
+                "name": migration["name"],
+                "status": "applied",
+                "applied_at": migration["applied_at"],
+            }));
+        }
+    }
+
+    rows.sort_by(|a, b| {
+        compare_versions(
+            a["version"].as_str().unwrap_or_default(),
+            b["version"].as_str().unwrap_or_default(),
+        )
+    });
+
+    if rows.is_empty() {
+        eprintln!("{} No migrations found", String::from('●').red());
+    } else {
+        eprintln!("{}", json_to_table(&Value::Array(rows))?);
+    }
+
+    Ok(())
+}
+
+// NOTE: The databases are the folders of the migrations folder
+fn migration_databases() -> Result<Vec<String>> {
+    let folder = Path::new(&CONFIG.structure.migrations_folder);
+
+    if !folder.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut db_names = Vec::new();
+
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
+                db_names.push(name.to_string());
+            }
+        }
+    }
+
+    db_names.sort();
+
+    Ok(db_names)
+}
+
+// NOTE: The migrations of a database are in the <migrations_folder>/<db_name> folder
+fn local_migrations(db_name: &str, kind: &str) -> Result<Vec<(MigrationFile, String, String)>> {
+    let folder = Path::new(&CONFIG.structure.migrations_folder).join(db_name);
+
+    if !folder.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut migrations = Vec::new();
+
+    for entry in fs::read_dir(folder)? {
+        let path = entry?.path().to_string_lossy().to_string();
+
+        if let Some(file) = parse_migration_file(&path) {
+            if file.kind == kind {
+                migrations.push((file, fs::read_to_string(&path)?, path));
+            }
         }
-        Err(e) => eprintln!("{} {}", String::from('●').red(), e),
+    }
+
+    migrations.sort_by(|a, b| compare_versions(&a.0.version, &b.0.version));
+
+    Ok(migrations)
+}
+
+// NOTE: The file name is <version>_<name>_<type>.sql, the generated migrations use dashes
+// instead of underscores, e.g. 20250101120000-book-up.sql
+fn parse_migration_file(path: &str) -> Option<MigrationFile> {
+    let stem = Path::new(path).file_stem()?.to_str()?;
+    let (rest, kind) = stem.rsplit_once(['_', '-'])?;
+
+    if kind != "up" && kind != "down" {
+        return None;
+    }
+
+    let (version, name) = match rest.split_once(['_', '-']) {
+        Some((version, name)) => (version, name),
+        None => (rest, ""),
     };
+
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(MigrationFile {
+        version: version.to_string(),
+        name: name.to_string(),
+        kind: kind.to_string(),
+    })
+}
+
+// NOTE: The versions are compared as integers, so 9 is before 10, without parsing them because
+// they can be longer than an u64
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+fn checksum(query: &str) -> String {
+    sha256(query.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_migration_file() {
+        assert_eq!(
+            parse_migration_file("src/migrations/example.sql/20250101120000-book-up.sql"),
+            Some(MigrationFile {
+                version: "20250101120000".to_string(),
+                name: "book".to_string(),
+                kind: "up".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_migration_file("20250101_create_book_down.sql"),
+            Some(MigrationFile {
+                version: "20250101".to_string(),
+                name: "create_book".to_string(),
+                kind: "down".to_string(),
+            })
+        );
+        assert_eq!(parse_migration_file("book-up.sql"), None);
+        assert_eq!(parse_migration_file("20250101-book.sql"), None);
+    }
+
+    #[test]
+    fn test_compare_versions() {
+        assert_eq!(compare_versions("9", "10"), Ordering::Less);
+        assert_eq!(compare_versions("010", "10"), Ordering::Equal);
+        assert_eq!(
+            compare_versions("20250102", "20250101120000"),
+            Ordering::Less
+        );
+        assert_eq!(
+            compare_versions("20250101120001", "20250101120000"),
+            Ordering::Greater
+        );
+    }
 }
@@ -451,7 +451,7 @@ pub async fn push_command(args: &PluginPushArgs) -> Result<()> {
 
         let name = path
             .split(std::path::MAIN_SEPARATOR)
-            .last()
+            .next_back()
             .unwrap()
             .to_string();
 
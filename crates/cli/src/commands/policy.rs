@@ -0,0 +1,82 @@
+use anyhow::Result;
+use colored::Colorize;
+use reqwest::Method;
+use serde_json::json;
+
+use crate::utils::{http_client, json_to_table};
+
+use super::commands::{PolicyArgs, PolicyCommands};
+
+pub async fn command_policy(command: &PolicyArgs) -> Result<()> {
+    match &command.command {
+        PolicyCommands::Create(args) => {
+            let body = json!({
+                "db_name": args.db_name,
+                "table_name": args.table_name,
+                "operation": args.operation,
+                "predicate": args.predicate,
+            })
+            .to_string();
+
+            match http_client("policy", Some(&body), Method::POST).await {
+                Ok(_) => eprintln!(
+                    "{} Policy created: {} {} {}",
+                    String::from('●').green(),
+                    args.db_name,
+                    args.table_name,
+                    args.operation
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        PolicyCommands::Delete(args) => {
+            let body = json!({
+                "db_name": args.db_name,
+                "table_name": args.table_name,
+                "operation": args.operation,
+            })
+            .to_string();
+
+            match http_client("policy", Some(&body), Method::DELETE).await {
+                Ok(_) => eprintln!(
+                    "{} Policy deleted: {} {} {}",
+                    String::from('●').green(),
+                    args.db_name,
+                    args.table_name,
+                    args.operation
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        PolicyCommands::List(args) => {
+            let path = match &args.db_name {
+                Some(db_name) => format!("policy?db_name={}", db_name),
+                None => "policy".to_string(),
+            };
+
+            match http_client(&path, None, Method::GET).await {
+                Ok(v) => {
+                    let is_empty = match v["data"].as_array() {
+                        Some(v) => v.is_empty(),
+                        None => true,
+                    };
+
+                    if is_empty {
+                        eprintln!("{} No data returned", String::from('●').red());
+                    } else {
+                        eprintln!("{}", json_to_table(&v["data"])?);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{} {}", String::from('●').red(), err);
+                }
+            };
+
+            Ok(())
+        }
+    }
+}
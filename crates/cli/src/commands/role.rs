@@ -0,0 +1,106 @@
+use anyhow::Result;
+use colored::Colorize;
+use reqwest::Method;
+use serde_json::json;
+
+use crate::utils::{http_client, json_to_table};
+
+use super::commands::{RoleArgs, RoleAssignArgs, RoleCommands};
+
+pub async fn command_role(command: &RoleArgs) -> Result<()> {
+    match &command.command {
+        RoleCommands::Assign(args) => {
+            match http_client("role/assign", Some(&assign_body(args)), Method::POST).await {
+                Ok(_) => eprintln!(
+                    "{} Role {} assigned to {}",
+                    String::from('●').green(),
+                    args.role,
+                    assignee(args)
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        RoleCommands::Create(args) => {
+            let body = json!({
+                "name": args.name,
+                "permissions": args.permissions,
+            })
+            .to_string();
+
+            match http_client("role", Some(&body), Method::POST).await {
+                Ok(_) => eprintln!(
+                    "{} Role created: {} {}",
+                    String::from('●').green(),
+                    args.name,
+                    args.permissions.join(",")
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        RoleCommands::Delete(args) => {
+            let body = json!({ "name": args.name }).to_string();
+
+            match http_client("role", Some(&body), Method::DELETE).await {
+                Ok(_) => eprintln!("{} Role deleted: {}", String::from('●').green(), args.name),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        RoleCommands::List => {
+            match http_client("role", None, Method::GET).await {
+                Ok(v) => {
+                    let is_empty = match v["data"].as_array() {
+                        Some(v) => v.is_empty(),
+                        None => true,
+                    };
+
+                    if is_empty {
+                        eprintln!("{} No data returned", String::from('●').red());
+                    } else {
+                        eprintln!("{}", json_to_table(&v["data"])?);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{} {}", String::from('●').red(), err);
+                }
+            };
+
+            Ok(())
+        }
+        RoleCommands::Unassign(args) => {
+            match http_client("role/assign", Some(&assign_body(args)), Method::DELETE).await {
+                Ok(_) => eprintln!(
+                    "{} Role {} unassigned from {}",
+                    String::from('●').green(),
+                    args.role,
+                    assignee(args)
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+    }
+}
+
+fn assign_body(args: &RoleAssignArgs) -> String {
+    json!({
+        "role": args.role,
+        "email": args.email,
+        "token": args.token,
+    })
+    .to_string()
+}
+
+fn assignee(args: &RoleAssignArgs) -> String {
+    match (&args.email, &args.token) {
+        (Some(email), _) => format!("the user {}", email),
+        (_, Some(token)) => format!("the token {}", token),
+        _ => String::new(),
+    }
+}
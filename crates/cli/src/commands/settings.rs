@@ -5,12 +5,12 @@ use cliclack::outro;
 use cliclack::{input, intro, password};
 use colored::Colorize;
 use reqwest::Method;
-use serde_derive::{Deserialize, Serialize};
 use serde_json::json;
 use toml_edit::{value, DocumentMut};
 
 use crate::{
     config::CLI,
+    prompts::totp_code,
     run_server::run_query_server,
     utils::{
         block_until_server_is_ready, check_port_usage, http_client, read_file_content,
@@ -18,16 +18,6 @@ use crate::{
     },
 };
 
-#[derive(Deserialize, Serialize)]
-struct Config {
-    server: ServerOptions,
-}
-
-#[derive(Deserialize, Serialize)]
-struct ServerOptions {
-    url: String,
-}
-
 pub async fn command_settings() -> Result<()> {
     intro("Query - Set Server Settings".to_string().cyan().reversed())?;
 
@@ -137,7 +127,22 @@ async fn get_user_token_value() -> Result<()> {
 
     block_until_server_is_ready();
 
-    match http_client("user/token/value", Some(&body), Method::POST).await {
+    // NOTE: The two-factor code is only asked for when the user has enabled it
+    let response = match http_client("user/token/value", Some(&body), Method::POST).await {
+        Err(e) if e.to_string().contains("two-factor code is required") => {
+            let body = json!({
+                "email": email,
+                "password": password,
+                "totp_code": totp_code()?,
+            })
+            .to_string();
+
+            http_client("user/token/value", Some(&body), Method::POST).await
+        }
+        response => response,
+    };
+
+    match response {
         Ok(v) => {
             if v["data"][0].is_null() {
                 stop_query_server();
@@ -2,7 +2,7 @@ use std::{fs::File, path::Path};
 
 use reqwest::Method;
 use rustyline::{error::ReadlineError, DefaultEditor};
-use serde_json::json;
+use serde_json::{json, Value};
 
 use crate::{
     config::{CLI, CONFIG},
@@ -42,32 +42,29 @@ pub async fn command_shell(command: &ShellArgs) -> anyhow::Result<()> {
                     break;
                 }
 
-                let line = if line.starts_with(".tables") {
-                    let pattern = line.replace(".tables", "").trim().to_owned();
+                if line.starts_with(".tables") || line.starts_with(".schema") {
+                    let (command_name, pattern) = line.split_once(' ').unwrap_or((&line, ""));
+                    let path = format!("schema?db_name={}", command.db_name);
 
-                    if pattern.is_empty() {
-                        "SELECT DISTINCT name FROM pragma_table_list WHERE name NOT LIKE 'sqlite%'"
-                            .to_string()
-                    } else {
-                        format!(
-                            "SELECT DISTINCT name FROM pragma_table_list WHERE name NOT LIKE 'sqlite%' AND name LIKE '%{}%'",
-                            &pattern
-                        )
-                    }
-                } else if line.starts_with(".schema") {
-                    let table_name = line.replace(".schema", "").trim().to_owned();
+                    let value = match http_client(&path, None, Method::GET).await {
+                        Ok(value) => value,
+                        Err(err) => {
+                            eprintln!("Error: {:?}", err);
+                            continue;
+                        }
+                    };
 
-                    if table_name.is_empty() {
-                        "SELECT sql FROM sqlite_master WHERE name NOT LIKE 'sqlite_%'".to_string()
+                    let rows = schema_rows(&value["data"], command_name, pattern.trim());
+
+                    line_break();
+                    if rows.is_empty() {
+                        eprintln!("No data returned.");
                     } else {
-                        format!(
-                            "SELECT sql FROM sqlite_master WHERE name LIKE '%{}%'",
-                            &table_name
-                        )
+                        eprintln!("{}", json_to_table(&Value::Array(rows))?);
                     }
-                } else {
-                    line
-                };
+                    line_break();
+                    continue;
+                }
 
                 let body = json!({
                     "db_name": command.db_name,
@@ -113,6 +110,40 @@ pub async fn command_shell(command: &ShellArgs) -> anyhow::Result<()> {
     Ok(())
 }
 
+// NOTE: ".tables" lists the names of the tables and the views, and ".schema" the SQL of every
+// object, both filtered by the pattern
+fn schema_rows(schema: &Value, command_name: &str, pattern: &str) -> Vec<Value> {
+    let mut rows = Vec::new();
+
+    for key in ["tables", "views", "triggers"] {
+        for object in schema[key].as_array().into_iter().flatten() {
+            let name = object["name"].as_str().unwrap_or_default();
+
+            if !name.contains(pattern) {
+                continue;
+            }
+
+            if command_name == ".tables" {
+                if key != "triggers" {
+                    rows.push(json!({ "name": name }));
+                }
+
+                continue;
+            }
+
+            rows.push(json!({ "sql": object["sql"] }));
+
+            for index in object["indexes"].as_array().into_iter().flatten() {
+                if !index["sql"].is_null() {
+                    rows.push(json!({ "sql": index["sql"] }));
+                }
+            }
+        }
+    }
+
+    rows
+}
+
 fn create_history_file() {
     let history_file_path = CLI::default().history_file_path;
     let history_file_path = Path::new(&history_file_path);
@@ -121,3 +152,56 @@ fn create_history_file() {
         File::create(history_file_path).unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Value {
+        json!({
+            "tables": [
+                {
+                    "name": "author",
+                    "sql": "CREATE TABLE author (id INTEGER PRIMARY KEY)",
+                    "indexes": [{ "name": "sqlite_autoindex_author_1", "sql": null }]
+                },
+                {
+                    "name": "book",
+                    "sql": "CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT)",
+                    "indexes": [{ "name": "book_title", "sql": "CREATE INDEX book_title ON book (title)" }]
+                }
+            ],
+            "views": [{ "name": "book_view", "sql": "CREATE VIEW book_view AS SELECT * FROM book" }],
+            "triggers": [{ "name": "book_insert", "sql": "CREATE TRIGGER book_insert AFTER INSERT ON book BEGIN SELECT 1; END" }]
+        })
+    }
+
+    #[test]
+    fn test_schema_rows_tables() {
+        assert_eq!(
+            schema_rows(&schema(), ".tables", ""),
+            vec![
+                json!({ "name": "author" }),
+                json!({ "name": "book" }),
+                json!({ "name": "book_view" })
+            ]
+        );
+        assert_eq!(
+            schema_rows(&schema(), ".tables", "auth"),
+            vec![json!({ "name": "author" })]
+        );
+    }
+
+    #[test]
+    fn test_schema_rows_schema() {
+        assert_eq!(
+            schema_rows(&schema(), ".schema", "book"),
+            vec![
+                json!({ "sql": "CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT)" }),
+                json!({ "sql": "CREATE INDEX book_title ON book (title)" }),
+                json!({ "sql": "CREATE VIEW book_view AS SELECT * FROM book" }),
+                json!({ "sql": "CREATE TRIGGER book_insert AFTER INSERT ON book BEGIN SELECT 1; END" })
+            ]
+        );
+    }
+}
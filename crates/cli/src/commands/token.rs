@@ -5,7 +5,7 @@ use reqwest::Method;
 use serde_json::json;
 
 use crate::{
-    prompts::expiration_date,
+    prompts::{allowed_ips, expiration_date},
     utils::{http_client, json_to_table},
 };
 
@@ -30,11 +30,14 @@ pub async fn command_token(command: &TokenArgs) -> Result<()> {
 
             let expiration_date = expiration_date()?;
 
+            let allowed_ips = allowed_ips()?;
+
             let body = json!({
                 "name": name,
                 "expiration_date": expiration_date,
                 "active": active,
                 "write": write,
+                "allowed_ips": allowed_ips,
             })
             .to_string();
 
@@ -79,6 +82,41 @@ pub async fn command_token(command: &TokenArgs) -> Result<()> {
 
             Ok(())
         }
+        TokenCommands::Grant(args) => {
+            let body = json!({
+                "name": args.name,
+                "db_name": args.db_name,
+                "action": args.action,
+                "operation": args.operation,
+            })
+            .to_string();
+
+            match http_client("token/permission", Some(&body), Method::POST).await {
+                Ok(_) => eprintln!(
+                    "{} Permission granted: {} {} {}",
+                    String::from('●').green(),
+                    args.db_name,
+                    args.action,
+                    args.operation
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        TokenCommands::Invalidate(args) => {
+            let body = json!({
+                "name": args.name,
+            })
+            .to_string();
+
+            match http_client("token/revoke", Some(&body), Method::POST).await {
+                Ok(_) => eprintln!("{} Token revoked: {}", String::from('●').green(), args.name),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
         TokenCommands::List => {
             match http_client("token", None, Method::GET).await {
                 Ok(v) => {
@@ -100,6 +138,54 @@ pub async fn command_token(command: &TokenArgs) -> Result<()> {
 
             Ok(())
         }
+        TokenCommands::Permissions(args) => {
+            let path = format!("token/permission?name={}", args.name);
+
+            match http_client(&path, None, Method::GET).await {
+                Ok(v) => {
+                    let is_empty = match v["data"].as_array() {
+                        Some(v) => v.is_empty(),
+                        None => true,
+                    };
+
+                    if is_empty {
+                        eprintln!(
+                            "{} The token doesn't have permissions, it isn't restricted",
+                            String::from('●').green()
+                        );
+                    } else {
+                        eprintln!("{}", json_to_table(&v["data"])?);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{} {}", String::from('●').red(), err);
+                }
+            };
+
+            Ok(())
+        }
+        TokenCommands::Revoke(args) => {
+            let body = json!({
+                "name": args.name,
+                "db_name": args.db_name,
+                "action": args.action,
+                "operation": args.operation,
+            })
+            .to_string();
+
+            match http_client("token/permission", Some(&body), Method::DELETE).await {
+                Ok(_) => eprintln!(
+                    "{} Permission revoked: {} {} {}",
+                    String::from('●').green(),
+                    args.db_name,
+                    args.action,
+                    args.operation
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
         TokenCommands::Update => {
             intro("Update a Token".to_string().cyan().reversed())?;
 
@@ -124,11 +210,14 @@ pub async fn command_token(command: &TokenArgs) -> Result<()> {
 
             let expiration_date = expiration_date()?;
 
+            let allowed_ips = allowed_ips()?;
+
             let body = json!({
                 "name": name,
                 "expiration_date": expiration_date,
                 "active": active,
                 "write": write,
+                "allowed_ips": allowed_ips,
             })
             .to_string();
 
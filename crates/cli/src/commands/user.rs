@@ -4,9 +4,12 @@ use colored::Colorize;
 use reqwest::Method;
 use serde_json::json;
 
-use crate::utils::{http_client, json_to_table};
+use crate::{
+    prompts::totp_code,
+    utils::{http_client, json_to_table},
+};
 
-use super::commands::{UserArgs, UserCommands};
+use super::commands::{UserArgs, UserCommands, UserTotpCommands};
 
 pub async fn command_user(command: &UserArgs) -> Result<()> {
     match &command.command {
@@ -95,6 +98,96 @@ pub async fn command_user(command: &UserArgs) -> Result<()> {
 
             Ok(())
         }
+        UserCommands::Totp(args) => match &args.command {
+            UserTotpCommands::Enable => {
+                intro(
+                    "Enable the Two-Factor Authentication"
+                        .to_string()
+                        .cyan()
+                        .reversed(),
+                )?;
+
+                let enrolment = match http_client("user/totp", None, Method::POST).await {
+                    Ok(v) => v,
+                    Err(err) => {
+                        outro(err.to_string().red().reversed())?;
+                        return Ok(());
+                    }
+                };
+
+                eprintln!(
+                    "{} Add this secret to your authenticator app: {}",
+                    String::from('●').green(),
+                    enrolment["data"][0]["secret"].as_str().unwrap_or_default()
+                );
+                eprintln!(
+                    "{} Or use this URL: {}",
+                    String::from('●').green(),
+                    enrolment["data"][0]["otpauth_url"]
+                        .as_str()
+                        .unwrap_or_default()
+                );
+
+                let body = json!({ "code": totp_code()? }).to_string();
+
+                match http_client("user/totp/confirm", Some(&body), Method::POST).await {
+                    Ok(v) => {
+                        eprintln!(
+                            "{} Keep these recovery codes in a safe place, each one can be used once instead of a code:",
+                            String::from('●').yellow()
+                        );
+
+                        if let Some(codes) = v["data"][0]["recovery_codes"].as_array() {
+                            for code in codes {
+                                eprintln!("  {}", code.as_str().unwrap_or_default());
+                            }
+                        }
+
+                        outro(
+                            "Two-factor authentication enabled"
+                                .to_string()
+                                .green()
+                                .reversed(),
+                        )?;
+                    }
+                    Err(err) => {
+                        outro(err.to_string().red().reversed())?;
+                    }
+                };
+
+                Ok(())
+            }
+            UserTotpCommands::Disable(args) => {
+                intro(
+                    "Disable the Two-Factor Authentication"
+                        .to_string()
+                        .cyan()
+                        .reversed(),
+                )?;
+
+                let body = match &args.email {
+                    Some(email) => json!({ "email": email }),
+                    None => json!({ "code": totp_code()? }),
+                }
+                .to_string();
+
+                match http_client("user/totp", Some(&body), Method::DELETE).await {
+                    Ok(_) => {
+                        outro(
+                            "Two-factor authentication disabled"
+                                .to_string()
+                                .green()
+                                .reversed(),
+                        )?;
+                    }
+                    Err(err) => {
+                        outro(err.to_string().red().reversed())?;
+                    }
+                };
+
+                Ok(())
+            }
+        },
         UserCommands::Update => {
             intro("Update a User".to_string().cyan().reversed())?;
 
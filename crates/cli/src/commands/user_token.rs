@@ -62,6 +62,28 @@ pub async fn command_user_token(command: &UserTokenArgs) -> Result<()> {
 
             Ok(())
         }
+        UserTokenCommands::Grant(args) => {
+            let body = json!({
+                "email": args.email,
+                "db_name": args.db_name,
+                "action": args.action,
+                "operation": args.operation,
+            })
+            .to_string();
+
+            match http_client("user/token/permission", Some(&body), Method::POST).await {
+                Ok(_) => eprintln!(
+                    "{} Permission granted: {} {} {}",
+                    String::from('●').green(),
+                    args.db_name,
+                    args.action,
+                    args.operation
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
         UserTokenCommands::List => {
             match http_client("user/token", None, Method::GET).await {
                 Ok(v) => {
@@ -83,6 +105,54 @@ pub async fn command_user_token(command: &UserTokenArgs) -> Result<()> {
 
             Ok(())
         }
+        UserTokenCommands::Permissions(args) => {
+            let path = format!("user/token/permission?email={}", args.email);
+
+            match http_client(&path, None, Method::GET).await {
+                Ok(v) => {
+                    let is_empty = match v["data"].as_array() {
+                        Some(v) => v.is_empty(),
+                        None => true,
+                    };
+
+                    if is_empty {
+                        eprintln!(
+                            "{} The user token doesn't have permissions, it isn't restricted",
+                            String::from('●').green()
+                        );
+                    } else {
+                        eprintln!("{}", json_to_table(&v["data"])?);
+                    }
+                }
+                Err(err) => {
+                    eprintln!("{} {}", String::from('●').red(), err);
+                }
+            };
+
+            Ok(())
+        }
+        UserTokenCommands::Revoke(args) => {
+            let body = json!({
+                "email": args.email,
+                "db_name": args.db_name,
+                "action": args.action,
+                "operation": args.operation,
+            })
+            .to_string();
+
+            match http_client("user/token/permission", Some(&body), Method::DELETE).await {
+                Ok(_) => eprintln!(
+                    "{} Permission revoked: {} {} {}",
+                    String::from('●').green(),
+                    args.db_name,
+                    args.action,
+                    args.operation
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
         UserTokenCommands::Update => {
             intro("Update a User Token".to_string().cyan().reversed())?;
 
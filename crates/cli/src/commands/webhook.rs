@@ -0,0 +1,104 @@
+use anyhow::Result;
+use colored::Colorize;
+use openssl::rand::rand_bytes;
+use reqwest::Method;
+use serde_json::json;
+
+use crate::utils::{http_client, json_to_table};
+
+use super::commands::{WebhookArgs, WebhookCommands};
+
+pub async fn command_webhook(command: &WebhookArgs) -> Result<()> {
+    match &command.command {
+        WebhookCommands::Create(args) => {
+            let secret = match &args.secret {
+                Some(v) => v.to_string(),
+                None => random_secret()?,
+            };
+
+            let body = json!({
+                "name": args.name,
+                "db_name": args.db_name,
+                "table_name": args.table_name,
+                "operations": args.operations,
+                "url": args.url,
+                "secret": secret,
+                "active": !args.inactive,
+            })
+            .to_string();
+
+            match http_client("webhook", Some(&body), Method::POST).await {
+                Ok(_) => {
+                    eprintln!(
+                        "{} Webhook created: {} {} {}",
+                        String::from('●').green(),
+                        args.name,
+                        args.db_name,
+                        args.url
+                    );
+
+                    if args.secret.is_none() {
+                        eprintln!(
+                            "{} Secret to verify the signatures: {}",
+                            String::from('●').green(),
+                            secret
+                        );
+                    }
+                }
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        WebhookCommands::Delete(args) => {
+            let body = json!({ "name": args.name }).to_string();
+
+            match http_client("webhook", Some(&body), Method::DELETE).await {
+                Ok(_) => eprintln!(
+                    "{} Webhook deleted: {}",
+                    String::from('●').green(),
+                    args.name
+                ),
+                Err(err) => eprintln!("{} {}", String::from('●').red(), err),
+            };
+
+            Ok(())
+        }
+        WebhookCommands::Deliveries(args) => {
+            let path = format!("webhook/delivery?name={}&limit={}", args.name, args.limit);
+
+            list(&path).await
+        }
+        WebhookCommands::List => list("webhook").await,
+    }
+}
+
+async fn list(path: &str) -> Result<()> {
+    match http_client(path, None, Method::GET).await {
+        Ok(v) => {
+            let is_empty = match v["data"].as_array() {
+                Some(v) => v.is_empty(),
+                None => true,
+            };
+
+            if is_empty {
+                eprintln!("{} No data returned", String::from('●').red());
+            } else {
+                eprintln!("{}", json_to_table(&v["data"])?);
+            }
+        }
+        Err(err) => {
+            eprintln!("{} {}", String::from('●').red(), err);
+        }
+    };
+
+    Ok(())
+}
+
+fn random_secret() -> Result<String> {
+    let mut bytes = [0; 32];
+
+    rand_bytes(&mut bytes)?;
+
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
@@ -7,18 +7,19 @@ pub mod utils;
 
 use std::env;
 
-use clap::{command, Parser};
+use clap::Parser;
 use colored::Colorize;
 use dotenv::dotenv;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
 
 use commands::{
-    asset::command_asset, branch::command_branch, commands::Commands, create::command_create,
-    deploy::command_deploy, dev::command_dev, function::command_function,
-    generate::command_generate, migration::command_migration, plugin::command_plugin,
-    settings::command_settings, shell::command_shell, task::command_task, test::command_test,
-    token::command_token, user::command_user, user_token::command_user_token,
+    asset::command_asset, audit::command_audit, branch::command_branch, commands::Commands,
+    create::command_create, db::command_db, deploy::command_deploy, dev::command_dev,
+    function::command_function, generate::command_generate, migration::command_migration,
+    plugin::command_plugin, policy::command_policy, role::command_role, settings::command_settings,
+    shell::command_shell, task::command_task, test::command_test, token::command_token,
+    user::command_user, user_token::command_user_token, webhook::command_webhook,
 };
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -54,21 +55,26 @@ async fn main() {
 
     match &command {
         Commands::Asset(command) => command_asset(command).await.unwrap(),
+        Commands::Audit(command) => command_audit(command).await.unwrap(),
         Commands::Branch(command) => command_branch(command).await.unwrap(),
         Commands::Create(command) => command_create(command).await.unwrap(),
+        Commands::Db(command) => command_db(command).await.unwrap(),
         Commands::Deploy(command) => command_deploy(command).await.unwrap(),
         Commands::Dev(command) => command_dev(command).await.unwrap(),
         Commands::Function(command) => command_function(command).await.unwrap(),
         Commands::Generate(command) => command_generate(command).await.unwrap(),
-        Commands::Migration(command) => command_migration(command).await,
+        Commands::Migration(command) => command_migration(command).await.unwrap(),
         Commands::Settings => command_settings().await.unwrap(),
         Commands::Plugin(command) => command_plugin(command).await,
+        Commands::Policy(command) => command_policy(command).await.unwrap(),
+        Commands::Role(command) => command_role(command).await.unwrap(),
         Commands::Shell(command) => command_shell(command).await.unwrap(),
         Commands::Task(command) => command_task(command).unwrap(),
         Commands::Test(command) => command_test(command).await.unwrap(),
         Commands::Token(command) => command_token(command).await.unwrap(),
         Commands::User(command) => command_user(command).await.unwrap(),
         Commands::UserToken(command) => command_user_token(command).await.unwrap(),
+        Commands::Webhook(command) => command_webhook(command).await.unwrap(),
     }
 }
 
@@ -29,11 +29,39 @@ pub fn expiration_date() -> Result<Option<i64>> {
     let expiration_date = if expiration_date.is_empty() {
         None
     } else {
-        match expiration_date.parse::<i64>() {
-            Ok(val) => Some(val),
-            Err(_) => None,
-        }
+        expiration_date.parse::<i64>().ok()
     };
 
     Ok(expiration_date)
 }
+
+pub fn allowed_ips() -> Result<Vec<String>> {
+    let allowed_ips: String = input(format!(
+        "Which IPs can use the token? {}",
+        "(Optional)".to_string().yellow()
+    ))
+    .placeholder("Enter comma-separated CIDRs, e.g. 10.0.0.0/8, or leave it empty for any IP.")
+    .required(false)
+    .interact()?;
+
+    Ok(allowed_ips
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect())
+}
+
+pub fn totp_code() -> Result<String> {
+    let code: String = input("What is the two-factor code?")
+        .placeholder("Enter the code of your authenticator app or a recovery code.")
+        .validate(|input: &String| {
+            if input.trim().is_empty() {
+                Err("Please enter a code.")
+            } else {
+                Ok(())
+            }
+        })
+        .interact()?;
+
+    Ok(code.trim().to_string())
+}
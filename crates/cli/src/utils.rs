@@ -11,7 +11,7 @@ use std::{
 use anyhow::{anyhow, Result};
 use reqwest::{
     header::{HeaderMap, HeaderName, HeaderValue},
-    Body, Method, Url,
+    Body, Method, RequestBuilder, Url,
 };
 use serde_json::{self, Value};
 use tabled::{builder::Builder, settings::Style};
@@ -74,6 +74,61 @@ pub fn json_to_table(value: &Value) -> Result<String> {
 }
 
 pub async fn http_client(path: &str, body: Option<&String>, method: Method) -> Result<Value> {
+    let body = Body::from(match body {
+        Some(body) => body.as_str().to_string(),
+        None => String::new(),
+    });
+
+    let response = http_request(path, method)?.body(body).send().await?;
+
+    let status = response.status();
+    let body = response.text().await?;
+
+    if !status.is_success() {
+        if body.is_empty() {
+            return Err(anyhow!("{}", status));
+        } else {
+            return Err(anyhow!("{}", body));
+        }
+    }
+
+    if body.is_empty() {
+        return Ok(Value::Null);
+    }
+
+    let value: Value = match serde_json::from_str(&body) {
+        Ok(value) => value,
+        Err(_) => Value::String(body),
+    };
+
+    Ok(value)
+}
+
+pub async fn http_client_bytes(
+    path: &str,
+    body: Option<Vec<u8>>,
+    method: Method,
+) -> Result<Vec<u8>> {
+    let body = Body::from(body.unwrap_or_default());
+
+    let response = http_request(path, method)?.body(body).send().await?;
+
+    let status = response.status();
+
+    if !status.is_success() {
+        let body = response.text().await?;
+
+        if body.is_empty() {
+            return Err(anyhow!("{}", status));
+        } else {
+            return Err(anyhow!("{}", body));
+        }
+    }
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+fn http_request(path: &str, method: Method) -> Result<RequestBuilder> {
     let env_deploy_url = env::var(QUERY_DEPLOY_URL).unwrap_or("".to_string());
     let config_url = if !env_deploy_url.is_empty() {
         &env_deploy_url
@@ -104,43 +159,11 @@ pub async fn http_client(path: &str, body: Option<&String>, method: Method) -> R
         );
     }
 
-    let body = Body::from(match body {
-        Some(body) => body.as_str().to_string(),
-        None => String::new(),
-    });
-
     let client = reqwest::Client::builder()
         .pool_max_idle_per_host(0)
         .build()?;
 
-    let response = client
-        .request(method, url)
-        .headers(headers)
-        .body(body)
-        .send()
-        .await?;
-
-    let status = response.status();
-    let body = response.text().await?;
-
-    if !status.is_success() {
-        if body.is_empty() {
-            return Err(anyhow!("{}", status));
-        } else {
-            return Err(anyhow!("{}", body));
-        }
-    }
-
-    if body.is_empty() {
-        return Ok(Value::Null);
-    }
-
-    let value: Value = match serde_json::from_str(&body) {
-        Ok(value) => value,
-        Err(_) => Value::String(body),
-    };
-
-    Ok(value)
+    Ok(client.request(method, url).headers(headers))
 }
 
 // CREDIT: https://github.com/cloudflare/workers-sdk/blob/235c4398268322b6c0c13060bc3da91f52b4b066/packages/create-cloudflare/src/helpers/packageManagers.ts#L1
@@ -1,6 +1,6 @@
 use std::{env, fs, path::Path};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use rusqlite::{limits::Limit, Connection};
 
 use crate::sqlite::functions::{
@@ -8,8 +8,42 @@ use crate::sqlite::functions::{
     _token_function, _uuid_function, _valid_json_function,
 };
 
+// NOTE: The databases of a preview request are resolved to the branch with the same name, e.g.
+// example.sql to example.dev.branch.sql. The branch has to exist, so a preview request never
+// creates a database.
+pub fn branch_connection(db_name: &str, branch: Option<&str>) -> Result<Connection> {
+    let branch = match branch {
+        Some(v) if db_name != ":memory:" => v,
+        _ => return connection(db_name),
+    };
+
+    let branch_name = branch_db_name(db_name, branch);
+
+    if !Path::new(&format!("{}/{}", dbs_path(), branch_name)).is_file() {
+        bail!(
+            "The branch {} of the database {} doesn't exist",
+            branch,
+            db_name
+        );
+    }
+
+    connection(&branch_name)
+}
+
+pub fn branch_db_name(db_name: &str, branch: &str) -> String {
+    format!(
+        "{}.{}.branch.sql",
+        db_name.trim_end_matches(".sql").trim_end_matches(".db"),
+        branch
+    )
+}
+
+fn dbs_path() -> String {
+    env::var("QUERY_SERVER_DBS_PATH").unwrap_or("/mnt/dbs".to_string())
+}
+
 pub fn connection(db_name: &str) -> Result<Connection> {
-    let path = env::var("QUERY_SERVER_DBS_PATH").unwrap_or("/mnt/dbs".to_string());
+    let path = dbs_path();
 
     let conn = if db_name == ":memory:" {
         Connection::open_in_memory()?
@@ -29,6 +63,14 @@ pub fn connection(db_name: &str) -> Result<Connection> {
     conn.pragma_update(None, "temp_store", "MEMORY")?;
     conn.pragma_update(None, "foreign_keys", "ON")?;
 
+    // NOTE: The WAL archive of the server is the only one that checkpoints the WAL
+    if !env::var("QUERY_SERVER_WAL_ARCHIVE_PATH")
+        .unwrap_or_default()
+        .is_empty()
+    {
+        conn.pragma_update(None, "wal_autocheckpoint", 0)?;
+    }
+
     // Performance pragmas
     conn.pragma_update(None, "mmap_size", "30000000000")?;
     conn.pragma_update(None, "cache_size", -32000)?;
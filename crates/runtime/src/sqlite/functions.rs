@@ -1,7 +1,8 @@
 use std::env;
+use std::fs;
 use std::sync::Arc;
 
-use jsonwebtoken::{encode, EncodingKey, Header};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
 
 use regex::Regex;
 use rusqlite::ffi;
@@ -73,11 +74,19 @@ pub fn _token_function(conn: &Connection) -> Result<(), Error> {
             let claims = ctx.get_raw(0).as_str()?;
             let claims: serde_json::Value = serde_json::from_str(claims).unwrap();
 
-            let token = match encode(
-                &Header::default(),
-                &claims,
-                &EncodingKey::from_secret(token_secret().as_ref()),
-            ) {
+            let (header, encoding_key) = match signing_key() {
+                Ok(v) => v,
+                Err(e) => {
+                    return Err(Error::SqlInputError {
+                        error: ffi::Error::new(0),
+                        msg: e,
+                        sql: String::new(),
+                        offset: -1,
+                    })
+                }
+            };
+
+            let token = match encode(&header, &claims, &encoding_key) {
                 Ok(v) => v,
                 Err(e) => {
                     return Err(Error::SqlInputError {
@@ -142,6 +151,86 @@ fn token_secret() -> String {
     env::var("QUERY_SERVER_TOKEN_SECRET").expect("QUERY_SERVER_TOKEN_SECRET is not set")
 }
 
+// NOTE: The same signing key as the server, the QUERY_SERVER_TOKEN_KEY_ID key or the last private
+// key of the QUERY_SERVER_TOKEN_KEYS_PATH folder, or the QUERY_SERVER_TOKEN_SECRET (HS256)
+fn signing_key() -> Result<(Header, EncodingKey), String> {
+    let path = env::var("QUERY_SERVER_TOKEN_KEYS_PATH").unwrap_or("".to_string());
+    let kid = env::var("QUERY_SERVER_TOKEN_KEY_ID").unwrap_or("".to_string());
+
+    let secret = || {
+        (
+            Header::default(),
+            EncodingKey::from_secret(token_secret().as_ref()),
+        )
+    };
+
+    if path.is_empty() {
+        return Ok(secret());
+    }
+
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(&path).map_err(|e| e.to_string())? {
+        let file = entry.map_err(|e| e.to_string())?.path();
+
+        if file.extension().is_some_and(|e| e == "pem") {
+            files.push(file);
+        }
+    }
+
+    files.sort();
+
+    if files.is_empty() {
+        return Ok(secret());
+    }
+
+    for file in files.iter().rev() {
+        let file_kid = match file.file_stem().and_then(|s| s.to_str()) {
+            Some(v) => v.to_string(),
+            None => continue,
+        };
+
+        if !kid.is_empty() && file_kid != kid {
+            continue;
+        }
+
+        let pem = fs::read(file).map_err(|e| e.to_string())?;
+
+        // NOTE: A retired key only has its public key, so it can't sign
+        if !String::from_utf8_lossy(&pem).contains("PRIVATE KEY") {
+            if kid.is_empty() {
+                continue;
+            }
+
+            return Err(format!("The token key {} doesn't have a private key", kid));
+        }
+
+        let (algorithm, encoding_key) = match EncodingKey::from_ed_pem(&pem) {
+            Ok(v) => (Algorithm::EdDSA, v),
+            Err(_) => match EncodingKey::from_rsa_pem(&pem) {
+                Ok(v) => (Algorithm::RS256, v),
+                Err(_) => {
+                    return Err(format!(
+                        "The token key {} isn't an Ed25519 or RSA key",
+                        file_kid
+                    ))
+                }
+            },
+        };
+
+        let mut header = Header::new(algorithm);
+        header.kid = Some(file_kid);
+
+        return Ok((header, encoding_key));
+    }
+
+    if kid.is_empty() {
+        return Ok(secret());
+    }
+
+    Err(format!("The token key {} doesn't exist", kid))
+}
+
 // TODO: add tests
 #[cfg(test)]
 mod tests {
@@ -12,7 +12,9 @@ mod functions;
 use crate::utils::bind_to_params::{bind_array_to_params, bind_named_params};
 use crate::utils::query_to_json::query_to_json;
 
-use self::connect_db::connection;
+use self::connect_db::{branch_connection, branch_db_name};
+
+const BRANCH_GLOBAL: &str = "___sqlite_branch";
 
 static CACHE: OnceLock<Cache<String, (SystemTime, String)>> = OnceLock::new();
 
@@ -24,6 +26,14 @@ pub fn init(ctx: &Ctx) -> Result<()> {
     Ok(())
 }
 
+// NOTE: The queries of the context run against the branch of each database, used by the preview
+// requests
+pub fn set_branch(ctx: &Ctx, branch: &str) -> Result<()> {
+    ctx.globals().set(BRANCH_GLOBAL, branch)?;
+
+    Ok(())
+}
+
 #[instrument(err, skip(ctx, params))]
 fn sqlite_query(
     ctx: Ctx<'_>,
@@ -32,8 +42,20 @@ fn sqlite_query(
     params: String,
     ttl: u64,
 ) -> Result<String> {
+    let branch: Option<String> = ctx.globals().get(BRANCH_GLOBAL)?;
+    let branch = branch.as_deref();
+
     let cache = CACHE.get_or_init(|| Cache::new(1000));
-    let cache_key = format!("{}-{}-{}-{}", db_name, query, params, ttl);
+    let cache_key = match branch {
+        Some(v) => format!(
+            "{}-{}-{}-{}",
+            branch_db_name(&db_name, v),
+            query,
+            params,
+            ttl
+        ),
+        None => format!("{}-{}-{}-{}", db_name, query, params, ttl),
+    };
 
     if ttl > 0 && is_select(&query) {
         if let Some((timestamp, result)) = cache.get(&cache_key) {
@@ -45,7 +67,7 @@ fn sqlite_query(
         }
     }
 
-    let connection = match connection(&db_name) {
+    let connection = match branch_connection(&db_name, branch) {
         Ok(v) => Ok(v),
         Err(e) => Err(Exception::throw_syntax(
             &ctx,
@@ -240,6 +262,48 @@ mod tests {
         .await;
     }
 
+    #[test]
+    fn test_branch_db_name() {
+        assert_eq!(
+            branch_db_name("example.sql", "dev"),
+            "example.dev.branch.sql"
+        );
+        assert_eq!(
+            branch_db_name("example.db", "dev"),
+            "example.dev.branch.sql"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_query_with_branch() {
+        with_js_runtime(|ctx| {
+            init(&ctx)?;
+            set_branch(&ctx, "missing-branch")?;
+
+            let result = sqlite_query(
+                ctx.clone(),
+                ":memory:".to_string(),
+                "SELECT 1 as num".to_string(),
+                "[]".to_string(),
+                0,
+            )?;
+
+            assert_eq!(result, r#"[{"num":1}]"#);
+
+            assert!(sqlite_query(
+                ctx.clone(),
+                "missing-database.sql".to_string(),
+                "SELECT 1 as num".to_string(),
+                "[]".to_string(),
+                0,
+            )
+            .is_err());
+
+            Ok(())
+        })
+        .await;
+    }
+
     #[tokio::test]
     async fn test_sqlite_query_named_params() {
         with_js_runtime(|ctx| {
@@ -0,0 +1,5 @@
+pub mod archiver;
+pub mod file_system;
+pub mod restore;
+pub mod storage;
+pub mod wal;
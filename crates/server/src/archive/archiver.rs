@@ -0,0 +1,535 @@
+use std::{
+    collections::HashMap,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::Path,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use rusqlite::{backup::Progress, Connection, DatabaseName, ErrorCode};
+
+use crate::{
+    controllers::{
+        backup::{remove_temp_files, temp_path},
+        database::is_database_file,
+    },
+    env::Env,
+    sqlite::connect_db::connection,
+};
+
+use super::{
+    restore::generations,
+    storage::{archive_storage, ArchiveStorage},
+    wal::{has_next_frame, read_committed_frames, read_header, WalPosition},
+};
+
+const CHECKPOINT_WAL_SIZE: u64 = 4 * 1024 * 1024; // 4 MB
+
+// NOTE: The automatic checkpoint of the connections, in pages, is a fallback for a WAL that the
+// archiver doesn't checkpoint, e.g. if it falls behind. It can't restart the WAL while the
+// archiver holds its read transaction, and a restart anyway starts a new generation.
+pub const WAL_AUTOCHECKPOINT_FALLBACK: i64 = 10000;
+
+pub const SNAPSHOT_NAME: &str = "snapshot.sql";
+pub const WAL_DIR_NAME: &str = "wal";
+
+// NOTE: A generation is a snapshot of the database and the WAL segments archived after it. The
+// archiver holds a read transaction between the ticks, so the WAL can't be restarted by other
+// connections before its frames are archived, and it checkpoints the WAL when it grows (see
+// WAL_AUTOCHECKPOINT_FALLBACK for the other connections). If the WAL is restarted anyway, the
+// continuity is lost and a new generation starts.
+struct DatabaseArchive {
+    read_conn: Connection,
+    lock_conn: Connection,
+    inode: u64,
+    generation: String,
+    index: u64,
+    position: Option<WalPosition>,
+    checkpointed: bool,
+    snapshot_at: u64,
+}
+
+pub fn start_wal_archive_task() {
+    let storage = match archive_storage() {
+        Some(v) => v,
+        None => return,
+    };
+
+    let interval = Duration::from_secs(Env::wal_archive_interval());
+
+    tracing::info!("WAL archive interval duration: {:?}", interval);
+
+    thread::Builder::new()
+        .name("wal-archive".to_string())
+        .spawn(move || {
+            let mut archives: HashMap<String, DatabaseArchive> = HashMap::new();
+
+            loop {
+                if let Err(e) = archive_databases(storage.as_ref(), &mut archives) {
+                    tracing::error!("Error archiving the databases: {}", e);
+                }
+
+                thread::sleep(interval);
+            }
+        })
+        .expect("Failed to start the WAL archive task");
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+fn archive_databases(
+    storage: &dyn ArchiveStorage,
+    archives: &mut HashMap<String, DatabaseArchive>,
+) -> Result<()> {
+    let mut db_names = Vec::new();
+
+    for entry in fs::read_dir(Env::dbs_path())? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if entry.path().is_file() && is_database_file(&file_name) {
+            db_names.push(file_name);
+        }
+    }
+
+    archives.retain(|db_name, _| db_names.contains(db_name));
+
+    for db_name in db_names {
+        if let Err(e) = archive_database(storage, archives, &db_name) {
+            tracing::error!(db_name, "Error archiving the database: {}", e);
+
+            archives.remove(&db_name);
+        }
+    }
+
+    Ok(())
+}
+
+fn archive_database(
+    storage: &dyn ArchiveStorage,
+    archives: &mut HashMap<String, DatabaseArchive>,
+    db_name: &str,
+) -> Result<()> {
+    let inode = fs::metadata(db_path(db_name))?.ino();
+    let now = now_millis();
+    let snapshot_interval = Env::wal_archive_snapshot_interval() * 1000;
+
+    let is_current = match archives.get(db_name) {
+        Some(v) => v.inode == inode && now.saturating_sub(v.snapshot_at) < snapshot_interval,
+        None => false,
+    };
+
+    if !is_current {
+        let archive = new_generation(storage, db_name, inode, now)?;
+        archives.insert(db_name.to_string(), archive);
+
+        return Ok(());
+    }
+
+    let archive = archives.get_mut(db_name).unwrap();
+
+    if is_idle(db_name, archive)? {
+        return Ok(());
+    }
+
+    // NOTE: The write lock is held while the WAL is read, so its end is a committed transaction
+    match archive.lock_conn.execute_batch("BEGIN IMMEDIATE") {
+        Ok(_) => {}
+        Err(e) if e.sqlite_error_code() == Some(ErrorCode::DatabaseBusy) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+
+    let result = archive_wal(storage, db_name, archive, now);
+
+    archive.lock_conn.execute_batch("ROLLBACK")?;
+
+    if !result? {
+        tracing::warn!(
+            db_name,
+            "The WAL was restarted before it was archived, starting a new generation"
+        );
+
+        let archive = new_generation(storage, db_name, inode, now)?;
+        archives.insert(db_name.to_string(), archive);
+    }
+
+    Ok(())
+}
+
+// NOTE: The write lock is only taken if the WAL has frames after the archived position, or if it
+// wasn't checkpointed yet. The WAL is read without the lock, so a frame that is being written is
+// found in the next tick. A restart rewrites the header, so the salt changes.
+fn is_idle(db_name: &str, archive: &DatabaseArchive) -> Result<bool> {
+    let wal_path = format!("{}-wal", db_path(db_name));
+    let wal_path = Path::new(&wal_path);
+
+    if !archive.checkpointed
+        && fs::metadata(wal_path).map(|m| m.len()).unwrap_or(0) >= CHECKPOINT_WAL_SIZE
+    {
+        return Ok(false);
+    }
+
+    match (read_header(wal_path)?, archive.position) {
+        (None, None) => Ok(true),
+        (Some(header), Some(position)) if header.salt == position.header.salt => {
+            Ok(!has_next_frame(wal_path, &position)?)
+        }
+        _ => Ok(false),
+    }
+}
+
+// NOTE: Returns false if the WAL was restarted by another connection, so the frames between the
+// archived position and the restart are lost
+fn archive_wal(
+    storage: &dyn ArchiveStorage,
+    db_name: &str,
+    archive: &mut DatabaseArchive,
+    now: u64,
+) -> Result<bool> {
+    let wal_path = format!("{}-wal", db_path(db_name));
+    let wal_path = Path::new(&wal_path);
+
+    if let Some(header) = read_header(wal_path)? {
+        let start = match archive.position {
+            Some(v) if v.header.salt == header.salt => v,
+            Some(v)
+                if archive.checkpointed
+                    && header.checkpoint_seq == v.header.checkpoint_seq.wrapping_add(1) =>
+            {
+                WalPosition::start(&header)
+            }
+            Some(_) => return Ok(false),
+            None => WalPosition::start(&header),
+        };
+
+        let (frames, position) = read_committed_frames(wal_path, &start)?;
+
+        if !frames.is_empty() {
+            let key = format!(
+                "{}/{}/{}/{:016}-{:016}.wal",
+                db_name, archive.generation, WAL_DIR_NAME, archive.index, now
+            );
+            let path = temp_path(db_name, "segment");
+
+            let result = fs::write(&path, [&position.header.bytes[..], &frames].concat())
+                .map_err(anyhow::Error::from)
+                .and_then(|_| storage.put(&key, Path::new(&path)));
+
+            remove_temp_files(&path);
+
+            result?;
+
+            archive.index += 1;
+            archive.checkpointed = false;
+        }
+
+        archive.position = Some(position);
+    }
+
+    release_wal(&archive.read_conn)?;
+
+    // NOTE: A complete checkpoint allows the next writer to restart the WAL, every frame of the
+    // current WAL is archived, so the restart doesn't lose the continuity
+    if fs::metadata(wal_path).map(|m| m.len()).unwrap_or(0) >= CHECKPOINT_WAL_SIZE {
+        let (busy, log, checkpointed): (i64, i64, i64) =
+            archive
+                .read_conn
+                .query_row("PRAGMA wal_checkpoint(PASSIVE)", [], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?;
+
+        if busy == 0 && log == checkpointed {
+            archive.checkpointed = true;
+        }
+    }
+
+    pin_wal(&archive.read_conn)?;
+
+    Ok(true)
+}
+
+fn new_generation(
+    storage: &dyn ArchiveStorage,
+    db_name: &str,
+    inode: u64,
+    now: u64,
+) -> Result<DatabaseArchive> {
+    let read_conn = connection(db_name)?;
+    let lock_conn = connection(db_name)?;
+
+    lock_conn.execute_batch("BEGIN IMMEDIATE")?;
+
+    let result = snapshot(storage, db_name, &read_conn, now);
+
+    lock_conn.execute_batch("ROLLBACK")?;
+
+    let (generation, position) = result?;
+
+    pin_wal(&read_conn)?;
+
+    tracing::info!(db_name, generation, "New WAL archive generation");
+
+    if let Err(e) = prune_generations(storage, db_name, now, Env::wal_archive_retention() * 1000) {
+        tracing::error!(db_name, "Error pruning the WAL archive generations: {}", e);
+    }
+
+    Ok(DatabaseArchive {
+        read_conn,
+        lock_conn,
+        inode,
+        generation,
+        index: 0,
+        position,
+        checkpointed: false,
+        snapshot_at: now,
+    })
+}
+
+// NOTE: The snapshot is taken while the write lock is held by another connection, so it matches
+// the end of the WAL. The backup API can't read from a connection with a write transaction.
+fn snapshot(
+    storage: &dyn ArchiveStorage,
+    db_name: &str,
+    read_conn: &Connection,
+    now: u64,
+) -> Result<(String, Option<WalPosition>)> {
+    let wal_path = format!("{}-wal", db_path(db_name));
+    let wal_path = Path::new(&wal_path);
+
+    let position = match read_header(wal_path)? {
+        Some(header) => Some(read_committed_frames(wal_path, &WalPosition::start(&header))?.1),
+        None => None,
+    };
+
+    let generation = format!("{:016}", now);
+    let key = format!("{}/{}/{}", db_name, generation, SNAPSHOT_NAME);
+    let path = temp_path(db_name, "snapshot");
+
+    let result = read_conn
+        .backup(DatabaseName::Main, &path, None::<fn(Progress)>)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| storage.put(&key, Path::new(&path)));
+
+    remove_temp_files(&path);
+
+    result?;
+
+    Ok((generation, position))
+}
+
+// NOTE: A generation restores the points in time until the next one starts, so it is removed
+// once the next generation is older than the retention. The last generation is always kept.
+fn prune_generations(
+    storage: &dyn ArchiveStorage,
+    db_name: &str,
+    now: u64,
+    retention: u64,
+) -> Result<()> {
+    let generations = generations(storage, db_name)?;
+
+    for (current, next) in generations.iter().zip(generations.iter().skip(1)) {
+        if next.1 > now.saturating_sub(retention) {
+            break;
+        }
+
+        storage.delete(&format!("{}/{}", db_name, current.0))?;
+
+        tracing::info!(
+            db_name,
+            generation = current.0,
+            "WAL archive generation pruned"
+        );
+    }
+
+    Ok(())
+}
+
+fn pin_wal(conn: &Connection) -> Result<()> {
+    release_wal(conn)?;
+
+    conn.execute_batch("BEGIN")?;
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })?;
+
+    Ok(())
+}
+
+fn release_wal(conn: &Connection) -> Result<()> {
+    if !conn.is_autocommit() {
+        conn.execute_batch("COMMIT")?;
+    }
+
+    Ok(())
+}
+
+fn db_path(db_name: &str) -> String {
+    format!("{}/{}", Env::dbs_path(), db_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        archive::{file_system::FileSystemStorage, restore::rebuild_database},
+        db_test,
+        sqlite::connect_db::connect_db,
+    };
+
+    use super::*;
+
+    fn count(path: &str) -> i64 {
+        let conn = Connection::open(path).unwrap();
+
+        conn.query_row("SELECT COUNT(*) FROM test", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    db_test!(
+        test_archive_and_rebuild_database,
+        TestArchiveAndRebuildDatabase,
+        {
+            let storage = FileSystemStorage::new(format!("{}/archive", Env::dbs_path()));
+            let mut archives = HashMap::new();
+
+            let conn = connect_db("example.sql").unwrap();
+            conn.execute_batch("CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);")
+                .unwrap();
+
+            archive_database(&storage, &mut archives, "example.sql").unwrap();
+
+            let generation = archives["example.sql"].generation.clone();
+            let snapshot_at = archives["example.sql"].snapshot_at;
+
+            // The segments are archived after the snapshot
+            thread::sleep(Duration::from_millis(5));
+
+            conn.execute("INSERT INTO test (value) VALUES ('a')", [])
+                .unwrap();
+            archive_database(&storage, &mut archives, "example.sql").unwrap();
+
+            conn.execute("INSERT INTO test (value) VALUES ('b')", [])
+                .unwrap();
+            archive_database(&storage, &mut archives, "example.sql").unwrap();
+
+            assert_eq!(archives["example.sql"].generation, generation);
+            assert_eq!(archives["example.sql"].index, 2);
+            assert_eq!(
+                storage
+                    .list(&format!("example.sql/{}/{}", generation, WAL_DIR_NAME))
+                    .unwrap()
+                    .len(),
+                2
+            );
+
+            let path = format!("{}/rebuilt.sql", Env::dbs_path());
+
+            rebuild_database(&storage, "example.sql", None, Path::new(&path)).unwrap();
+            assert_eq!(count(&path), 2);
+
+            rebuild_database(&storage, "example.sql", Some(snapshot_at), Path::new(&path)).unwrap();
+            assert_eq!(count(&path), 0);
+
+            assert!(rebuild_database(&storage, "example.sql", Some(0), Path::new(&path)).is_err());
+        }
+    );
+    db_test!(test_archive_when_idle, TestArchiveWhenIdle, {
+        let storage = FileSystemStorage::new(format!("{}/archive", Env::dbs_path()));
+        let mut archives = HashMap::new();
+
+        let conn = connect_db("example.sql").unwrap();
+        conn.execute_batch("CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);")
+            .unwrap();
+
+        archive_database(&storage, &mut archives, "example.sql").unwrap();
+
+        assert!(is_idle("example.sql", &archives["example.sql"]).unwrap());
+
+        conn.execute("INSERT INTO test (value) VALUES ('a')", [])
+            .unwrap();
+
+        assert!(!is_idle("example.sql", &archives["example.sql"]).unwrap());
+
+        archive_database(&storage, &mut archives, "example.sql").unwrap();
+
+        assert!(is_idle("example.sql", &archives["example.sql"]).unwrap());
+        assert_eq!(archives["example.sql"].index, 1);
+    });
+
+    db_test!(test_prune_generations, TestPruneGenerations, {
+        let storage = FileSystemStorage::new(format!("{}/archive", Env::dbs_path()));
+        let source = format!("{}/source", Env::dbs_path());
+
+        fs::write(&source, "snapshot").unwrap();
+
+        for generation in [1000, 2000, 3000, 4000] {
+            storage
+                .put(
+                    &format!("example.sql/{:016}/{}", generation, SNAPSHOT_NAME),
+                    Path::new(&source),
+                )
+                .unwrap();
+        }
+
+        // NOTE: The generation 2000 restores the points in time after 2500 until 3000
+        prune_generations(&storage, "example.sql", 3500, 1000).unwrap();
+
+        assert_eq!(
+            storage.list("example.sql").unwrap(),
+            vec![
+                format!("{:016}", 2000),
+                format!("{:016}", 3000),
+                format!("{:016}", 4000)
+            ]
+        );
+
+        prune_generations(&storage, "example.sql", 10000, 1000).unwrap();
+
+        assert_eq!(
+            storage.list("example.sql").unwrap(),
+            vec![format!("{:016}", 4000)]
+        );
+    });
+
+    db_test!(test_archive_after_checkpoint, TestArchiveAfterCheckpoint, {
+        let storage = FileSystemStorage::new(format!("{}/archive", Env::dbs_path()));
+        let mut archives = HashMap::new();
+
+        let conn = connect_db("example.sql").unwrap();
+        conn.pragma_update(None, "wal_autocheckpoint", 0).unwrap();
+        conn.execute_batch("CREATE TABLE test (id INTEGER PRIMARY KEY, value BLOB);")
+            .unwrap();
+
+        archive_database(&storage, &mut archives, "example.sql").unwrap();
+
+        let generation = archives["example.sql"].generation.clone();
+
+        conn.execute(
+            "INSERT INTO test (value) VALUES (zeroblob(?))",
+            [CHECKPOINT_WAL_SIZE],
+        )
+        .unwrap();
+        archive_database(&storage, &mut archives, "example.sql").unwrap();
+
+        assert!(archives["example.sql"].checkpointed);
+
+        // The next writer restarts the WAL
+        conn.execute("INSERT INTO test (value) VALUES (NULL)", [])
+            .unwrap();
+        archive_database(&storage, &mut archives, "example.sql").unwrap();
+
+        assert_eq!(archives["example.sql"].generation, generation);
+        assert_eq!(archives["example.sql"].index, 2);
+
+        let path = format!("{}/rebuilt.sql", Env::dbs_path());
+
+        rebuild_database(&storage, "example.sql", None, Path::new(&path)).unwrap();
+        assert_eq!(count(&path), 2);
+    });
+}
@@ -0,0 +1,123 @@
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use super::storage::ArchiveStorage;
+
+const TEMP_SUFFIX: &str = ".tmp";
+
+pub struct FileSystemStorage {
+    root: PathBuf,
+}
+
+impl FileSystemStorage {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl ArchiveStorage for FileSystemStorage {
+    fn put(&self, key: &str, source: &Path) -> Result<()> {
+        let destination = self.root.join(key);
+
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temp = PathBuf::from(format!("{}{}", destination.display(), TEMP_SUFFIX));
+
+        fs::copy(source, &temp)?;
+        File::open(&temp)?.sync_all()?;
+        fs::rename(&temp, &destination)?;
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str, destination: &Path) -> Result<()> {
+        fs::copy(self.root.join(key), destination)?;
+
+        Ok(())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let dir = self.root.join(prefix);
+
+        if !dir.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut names = Vec::new();
+
+        for entry in fs::read_dir(dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+
+            if !name.ends_with(TEMP_SUFFIX) {
+                names.push(name);
+            }
+        }
+
+        names.sort();
+
+        Ok(names)
+    }
+
+    fn delete(&self, prefix: &str) -> Result<()> {
+        let path = self.root.join(prefix);
+
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else if path.is_file() {
+            fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct AfterFileSystemStorage;
+
+    const PATH_FILE_SYSTEM_STORAGE: &str = "../../.tests/file_system_storage";
+
+    impl Drop for AfterFileSystemStorage {
+        fn drop(&mut self) {
+            fs::remove_dir_all(PATH_FILE_SYSTEM_STORAGE).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_file_system_storage() {
+        let _after = AfterFileSystemStorage;
+
+        let storage = FileSystemStorage::new(format!("{}/archive", PATH_FILE_SYSTEM_STORAGE));
+        let source = PathBuf::from(format!("{}/source", PATH_FILE_SYSTEM_STORAGE));
+        let destination = PathBuf::from(format!("{}/destination", PATH_FILE_SYSTEM_STORAGE));
+
+        assert!(storage.list("example.sql").unwrap().is_empty());
+
+        fs::create_dir_all(PATH_FILE_SYSTEM_STORAGE).unwrap();
+        fs::write(&source, "content").unwrap();
+
+        storage.put("example.sql/2/snapshot.sql", &source).unwrap();
+        storage.put("example.sql/1/snapshot.sql", &source).unwrap();
+
+        assert_eq!(storage.list("example.sql").unwrap(), vec!["1", "2"]);
+
+        storage
+            .get("example.sql/1/snapshot.sql", &destination)
+            .unwrap();
+
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "content");
+
+        storage.delete("example.sql/1").unwrap();
+        storage.delete("example.sql/missing").unwrap();
+
+        assert_eq!(storage.list("example.sql").unwrap(), vec!["2"]);
+    }
+}
@@ -0,0 +1,130 @@
+use std::{
+    fs::{self, File},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+use serde_json::{json, Value};
+
+use super::{
+    archiver::{SNAPSHOT_NAME, WAL_DIR_NAME},
+    storage::ArchiveStorage,
+    wal::apply_segment,
+};
+
+struct Segment {
+    name: String,
+    archived_at: u64,
+}
+
+pub fn list_generations(storage: &dyn ArchiveStorage, db_name: &str) -> Result<Vec<Value>> {
+    let mut values = Vec::new();
+
+    for (generation, snapshot_at) in generations(storage, db_name)? {
+        let segments = segments(storage, db_name, &generation)?;
+
+        values.push(json!({
+            "generation": generation,
+            "snapshot_at": snapshot_at,
+            "segments": segments.len(),
+            "archived_until": segments.last().map(|s| s.archived_at).unwrap_or(snapshot_at),
+        }));
+    }
+
+    Ok(values)
+}
+
+// NOTE: Rebuilds the database as it was at the timestamp, in milliseconds, with the latest
+// snapshot taken before it and the WAL segments archived until it. Without a timestamp, it
+// rebuilds the latest archived state.
+pub fn rebuild_database(
+    storage: &dyn ArchiveStorage,
+    db_name: &str,
+    timestamp: Option<u64>,
+    destination: &Path,
+) -> Result<()> {
+    let timestamp = timestamp.unwrap_or(u64::MAX);
+
+    let generation = match generations(storage, db_name)?
+        .into_iter()
+        .rfind(|(_, snapshot_at)| *snapshot_at <= timestamp)
+    {
+        Some((v, _)) => v,
+        None => bail!(
+            "The database {} doesn't have a snapshot before the timestamp",
+            db_name
+        ),
+    };
+
+    storage.get(
+        &format!("{}/{}/{}", db_name, generation, SNAPSHOT_NAME),
+        destination,
+    )?;
+
+    let mut database = File::options().write(true).open(destination)?;
+
+    let segment_path = format!("{}.segment", destination.display());
+
+    for segment in segments(storage, db_name, &generation)? {
+        if segment.archived_at > timestamp {
+            break;
+        }
+
+        storage.get(
+            &format!(
+                "{}/{}/{}/{}",
+                db_name, generation, WAL_DIR_NAME, segment.name
+            ),
+            Path::new(&segment_path),
+        )?;
+
+        let result = fs::read(&segment_path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| apply_segment(&mut database, &bytes));
+
+        let _ = fs::remove_file(&segment_path);
+
+        result?;
+    }
+
+    database.sync_all()?;
+
+    Ok(())
+}
+
+// NOTE: The generations are named with the zero-padded timestamp of their snapshot
+pub fn generations(storage: &dyn ArchiveStorage, db_name: &str) -> Result<Vec<(String, u64)>> {
+    let generations = storage
+        .list(db_name)?
+        .into_iter()
+        .filter_map(|name| {
+            let snapshot_at = name.parse::<u64>().ok()?;
+
+            Some((name, snapshot_at))
+        })
+        .collect();
+
+    Ok(generations)
+}
+
+// NOTE: The segments are named <index>-<archived_at>.wal, with zero-padded numbers, so the
+// sorted names are in the archive order
+fn segments(storage: &dyn ArchiveStorage, db_name: &str, generation: &str) -> Result<Vec<Segment>> {
+    let names = storage.list(&format!("{}/{}/{}", db_name, generation, WAL_DIR_NAME))?;
+
+    let segments = names
+        .into_iter()
+        .filter_map(|name| {
+            let archived_at = name
+                .strip_suffix(".wal")?
+                .split_once('-')?
+                .1
+                .parse::<u64>()
+                .ok()?;
+
+            Some(Segment { name, archived_at })
+        })
+        .collect();
+
+    Ok(segments)
+}
@@ -0,0 +1,30 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Result;
+
+use crate::env::Env;
+
+use super::file_system::FileSystemStorage;
+
+// NOTE: The keys are relative paths separated by slashes, like
+// example.sql/0001760868000000/snapshot.sql, so a backend can map them to files or objects.
+pub trait ArchiveStorage: Send + Sync {
+    // Copies the local file to the key, the key is replaced atomically
+    fn put(&self, key: &str, source: &Path) -> Result<()>;
+    // Copies the key to the local file
+    fn get(&self, key: &str, destination: &Path) -> Result<()>;
+    // Returns the sorted names of the entries under the prefix
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+    // Removes the key, or every key under the prefix
+    fn delete(&self, prefix: &str) -> Result<()>;
+}
+
+pub fn archive_storage() -> Option<Arc<dyn ArchiveStorage>> {
+    let path = Env::wal_archive_path();
+
+    if path.is_empty() {
+        return None;
+    }
+
+    Some(Arc::new(FileSystemStorage::new(path)))
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Result};
+
+// NOTE: The WAL format is described in https://www.sqlite.org/fileformat2.html#the_write_ahead_log
+pub const WAL_HEADER_SIZE: usize = 32;
+const WAL_FRAME_HEADER_SIZE: usize = 24;
+const WAL_MAGIC: u32 = 0x377f0682;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WalHeader {
+    pub bytes: [u8; WAL_HEADER_SIZE],
+    pub big_endian: bool,
+    pub page_size: usize,
+    pub checkpoint_seq: u32,
+    pub salt: (u32, u32),
+    pub checksum: (u32, u32),
+}
+
+impl WalHeader {
+    pub fn parse(bytes: &[u8]) -> Option<WalHeader> {
+        let bytes: [u8; WAL_HEADER_SIZE] = bytes.get(..WAL_HEADER_SIZE)?.try_into().ok()?;
+
+        let magic = read_u32(&bytes[0..4]);
+
+        if magic & !1 != WAL_MAGIC {
+            return None;
+        }
+
+        let big_endian = magic & 1 == 1;
+        let page_size = match read_u32(&bytes[8..12]) {
+            1 => 65536,
+            v => v as usize,
+        };
+        let checksum = (read_u32(&bytes[24..28]), read_u32(&bytes[28..32]));
+
+        // A header that isn't completely written doesn't have a valid checksum
+        if !page_size.is_power_of_two()
+            || page_size < 512
+            || wal_checksum(big_endian, &bytes[..24], (0, 0)) != checksum
+        {
+            return None;
+        }
+
+        Some(WalHeader {
+            bytes,
+            big_endian,
+            page_size,
+            checkpoint_seq: read_u32(&bytes[12..16]),
+            salt: (read_u32(&bytes[16..20]), read_u32(&bytes[20..24])),
+            checksum,
+        })
+    }
+
+    fn frame_size(&self) -> usize {
+        WAL_FRAME_HEADER_SIZE + self.page_size
+    }
+}
+
+// NOTE: The position after the last archived commit frame, with the cumulative checksum that
+// validates the next frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WalPosition {
+    pub header: WalHeader,
+    pub offset: u64,
+    pub checksum: (u32, u32),
+}
+
+impl WalPosition {
+    pub fn start(header: &WalHeader) -> WalPosition {
+        WalPosition {
+            header: *header,
+            offset: WAL_HEADER_SIZE as u64,
+            checksum: header.checksum,
+        }
+    }
+}
+
+pub fn read_header(wal_path: &Path) -> Result<Option<WalHeader>> {
+    let mut file = match File::open(wal_path) {
+        Ok(v) => v,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => bail!(e),
+    };
+
+    let mut bytes = Vec::with_capacity(WAL_HEADER_SIZE);
+    Read::take(&mut file, WAL_HEADER_SIZE as u64).read_to_end(&mut bytes)?;
+
+    Ok(WalHeader::parse(&bytes))
+}
+
+// NOTE: Returns the frames of the transactions committed after the position, and the position
+// after the last commit frame. The frames of a transaction that isn't committed, or that
+// belong to a previous WAL, are left out by the salts and the checksums.
+pub fn read_committed_frames(
+    wal_path: &Path,
+    start: &WalPosition,
+) -> Result<(Vec<u8>, WalPosition)> {
+    let header = &start.header;
+    let frame_size = header.frame_size();
+
+    let mut file = File::open(wal_path)?;
+    file.seek(SeekFrom::Start(start.offset))?;
+
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    let mut checksum = start.checksum;
+    let mut committed = 0;
+    let mut position = *start;
+
+    for (i, frame) in bytes.chunks_exact(frame_size).enumerate() {
+        let salt = (read_u32(&frame[8..12]), read_u32(&frame[12..16]));
+
+        if salt != header.salt {
+            break;
+        }
+
+        checksum = wal_checksum(header.big_endian, &frame[..8], checksum);
+        checksum = wal_checksum(header.big_endian, &frame[WAL_FRAME_HEADER_SIZE..], checksum);
+
+        if checksum != (read_u32(&frame[16..20]), read_u32(&frame[20..24])) {
+            break;
+        }
+
+        // The size of the database after the commit is only set in the commit frames
+        if read_u32(&frame[4..8]) != 0 {
+            committed = (i + 1) * frame_size;
+            position = WalPosition {
+                header: *header,
+                offset: start.offset + committed as u64,
+                checksum,
+            };
+        }
+    }
+
+    bytes.truncate(committed);
+
+    Ok((bytes, position))
+}
+
+// NOTE: Returns true if a valid frame, committed or not, follows the position. Only the next
+// frame is read, so it is cheaper than reading the committed frames.
+pub fn has_next_frame(wal_path: &Path, position: &WalPosition) -> Result<bool> {
+    let header = &position.header;
+    let mut frame = vec![0; header.frame_size()];
+
+    let mut file = File::open(wal_path)?;
+    file.seek(SeekFrom::Start(position.offset))?;
+
+    match file.read_exact(&mut frame) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+
+    if (read_u32(&frame[8..12]), read_u32(&frame[12..16])) != header.salt {
+        return Ok(false);
+    }
+
+    let checksum = wal_checksum(header.big_endian, &frame[..8], position.checksum);
+    let checksum = wal_checksum(header.big_endian, &frame[WAL_FRAME_HEADER_SIZE..], checksum);
+
+    Ok(checksum == (read_u32(&frame[16..20]), read_u32(&frame[20..24])))
+}
+
+// NOTE: A segment is a WAL header followed by the frames of committed transactions, the pages
+// of each transaction are written to the database when its commit frame is reached.
+pub fn apply_segment(database: &mut File, segment: &[u8]) -> Result<()> {
+    let header = match WalHeader::parse(segment) {
+        Some(v) => v,
+        None => bail!("The WAL segment doesn't have a valid header"),
+    };
+
+    let mut pages: BTreeMap<u32, &[u8]> = BTreeMap::new();
+
+    for frame in segment[WAL_HEADER_SIZE..].chunks_exact(header.frame_size()) {
+        let page_number = read_u32(&frame[0..4]);
+        let database_size = read_u32(&frame[4..8]);
+
+        pages.insert(page_number, &frame[WAL_FRAME_HEADER_SIZE..]);
+
+        if database_size != 0 {
+            for (page_number, page) in &pages {
+                database.seek(SeekFrom::Start(
+                    (*page_number as u64 - 1) * header.page_size as u64,
+                ))?;
+                database.write_all(page)?;
+            }
+
+            database.set_len(database_size as u64 * header.page_size as u64)?;
+            pages.clear();
+        }
+    }
+
+    Ok(())
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn wal_checksum(big_endian: bool, data: &[u8], seed: (u32, u32)) -> (u32, u32) {
+    let word = |bytes: &[u8]| -> u32 {
+        let bytes: [u8; 4] = bytes.try_into().unwrap();
+
+        if big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    };
+
+    let (mut s0, mut s1) = seed;
+
+    for chunk in data.chunks_exact(8) {
+        s0 = s0.wrapping_add(word(&chunk[0..4])).wrapping_add(s1);
+        s1 = s1.wrapping_add(word(&chunk[4..8])).wrapping_add(s0);
+    }
+
+    (s0, s1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rusqlite::Connection;
+
+    use super::*;
+
+    struct AfterWal;
+
+    const PATH_WAL: &str = "../../.tests/wal";
+
+    impl Drop for AfterWal {
+        fn drop(&mut self) {
+            fs::remove_dir_all(PATH_WAL).unwrap();
+        }
+    }
+
+    fn count(path: &str) -> i64 {
+        let conn = Connection::open(path).unwrap();
+
+        conn.query_row("SELECT COUNT(*) FROM test", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_and_apply_committed_frames() {
+        let _after = AfterWal;
+
+        fs::create_dir_all(PATH_WAL).unwrap();
+
+        let path = format!("{}/test.sql", PATH_WAL);
+        let wal_path = format!("{}-wal", path);
+        let copy_path = format!("{}/copy.sql", PATH_WAL);
+
+        let conn = Connection::open(&path).unwrap();
+        conn.pragma_update(None, "journal_mode", "WAL").unwrap();
+        conn.pragma_update(None, "wal_autocheckpoint", 0).unwrap();
+        conn.execute_batch("CREATE TABLE test (id INTEGER PRIMARY KEY, value TEXT);")
+            .unwrap();
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")
+            .unwrap();
+
+        fs::copy(&path, &copy_path).unwrap();
+
+        assert!(read_header(Path::new(&wal_path)).unwrap().is_none());
+
+        conn.execute("INSERT INTO test (value) VALUES ('a')", [])
+            .unwrap();
+
+        let header = read_header(Path::new(&wal_path)).unwrap().unwrap();
+        let (frames, position) =
+            read_committed_frames(Path::new(&wal_path), &WalPosition::start(&header)).unwrap();
+
+        assert!(!frames.is_empty());
+        assert_eq!(position.offset, (WAL_HEADER_SIZE + frames.len()) as u64);
+
+        conn.execute("INSERT INTO test (value) VALUES ('b')", [])
+            .unwrap();
+
+        let (next_frames, next_position) =
+            read_committed_frames(Path::new(&wal_path), &position).unwrap();
+
+        assert!(!next_frames.is_empty());
+        assert!(next_position.offset > position.offset);
+
+        let (empty_frames, _) =
+            read_committed_frames(Path::new(&wal_path), &next_position).unwrap();
+
+        assert!(empty_frames.is_empty());
+        assert!(has_next_frame(Path::new(&wal_path), &position).unwrap());
+        assert!(!has_next_frame(Path::new(&wal_path), &next_position).unwrap());
+
+        let mut copy = File::options().write(true).open(&copy_path).unwrap();
+
+        apply_segment(&mut copy, &[&header.bytes[..], &frames].concat()).unwrap();
+        assert_eq!(count(&copy_path), 1);
+
+        apply_segment(&mut copy, &[&header.bytes[..], &next_frames].concat()).unwrap();
+        assert_eq!(count(&copy_path), 2);
+    }
+
+    #[test]
+    fn test_parse_invalid_header() {
+        assert!(WalHeader::parse(&[0; 16]).is_none());
+        assert!(WalHeader::parse(&[0; WAL_HEADER_SIZE]).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeSet;
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+// NOTE: Keeps the latest backup of each of the last `keep_daily` days and of each of the last
+// `keep_weekly` weeks that have a backup, and always the latest backup. The timestamps are in
+// milliseconds, and the days and the weeks are in UTC, with the weeks starting on Monday.
+pub fn backups_to_keep(created_at: &[u64], keep_daily: usize, keep_weekly: usize) -> BTreeSet<u64> {
+    let mut sorted = created_at.to_vec();
+    sorted.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut keep = BTreeSet::new();
+
+    if let Some(latest) = sorted.first() {
+        keep.insert(*latest);
+    }
+
+    keep.extend(latest_by_period(&sorted, keep_daily, day));
+    keep.extend(latest_by_period(&sorted, keep_weekly, week));
+
+    keep
+}
+
+fn latest_by_period(sorted: &[u64], limit: usize, period: fn(u64) -> u64) -> Vec<u64> {
+    let mut periods = BTreeSet::new();
+    let mut latest = Vec::new();
+
+    for created_at in sorted {
+        if periods.len() == limit {
+            break;
+        }
+
+        if periods.insert(period(*created_at)) {
+            latest.push(*created_at);
+        }
+    }
+
+    latest
+}
+
+fn day(created_at: u64) -> u64 {
+    created_at / DAY_MILLIS
+}
+
+// The Unix epoch was a Thursday
+fn week(created_at: u64) -> u64 {
+    (day(created_at) + 3) / 7
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2025-01-06 00:00:00 UTC, a Monday
+    const MONDAY: u64 = 1736121600000;
+    const HOUR: u64 = 60 * 60 * 1000;
+
+    #[test]
+    fn test_backups_to_keep_daily() {
+        let created_at = [
+            MONDAY,
+            MONDAY + HOUR,
+            MONDAY + DAY_MILLIS,
+            MONDAY + 2 * DAY_MILLIS,
+            MONDAY + 2 * DAY_MILLIS + HOUR,
+        ];
+
+        let keep = backups_to_keep(&created_at, 2, 0);
+
+        assert_eq!(
+            keep.into_iter().collect::<Vec<_>>(),
+            vec![MONDAY + DAY_MILLIS, MONDAY + 2 * DAY_MILLIS + HOUR]
+        );
+    }
+
+    #[test]
+    fn test_backups_to_keep_weekly() {
+        let created_at = [
+            MONDAY - DAY_MILLIS,
+            MONDAY - 2 * DAY_MILLIS,
+            MONDAY,
+            MONDAY + 6 * DAY_MILLIS,
+            MONDAY + 7 * DAY_MILLIS,
+        ];
+
+        let keep = backups_to_keep(&created_at, 1, 2);
+
+        assert_eq!(
+            keep.into_iter().collect::<Vec<_>>(),
+            vec![MONDAY + 6 * DAY_MILLIS, MONDAY + 7 * DAY_MILLIS]
+        );
+
+        let keep = backups_to_keep(&created_at, 0, 3);
+
+        assert_eq!(
+            keep.into_iter().collect::<Vec<_>>(),
+            vec![
+                MONDAY - DAY_MILLIS,
+                MONDAY + 6 * DAY_MILLIS,
+                MONDAY + 7 * DAY_MILLIS
+            ]
+        );
+    }
+
+    #[test]
+    fn test_backups_to_keep_latest() {
+        assert_eq!(
+            backups_to_keep(&[MONDAY, MONDAY + HOUR], 0, 0)
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![MONDAY + HOUR]
+        );
+        assert!(backups_to_keep(&[], 7, 4).is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use rusqlite::{backup::Progress, Connection, DatabaseName};
+use serde_json::{json, Value};
+
+use crate::{
+    archive::archiver::now_millis, controllers::database::is_database_file, env::Env,
+    sqlite::connect_db::connect_db,
+};
+
+use super::retention::backups_to_keep;
+
+const BACKUP_EXTENSION: &str = ".sql";
+const MAX_TICK: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Default)]
+struct BackupStatus {
+    last_run_at: Option<u64>,
+    last_success_at: Option<u64>,
+    last_error: Option<String>,
+}
+
+static STATUS: Mutex<BTreeMap<String, BackupStatus>> = Mutex::new(BTreeMap::new());
+
+pub struct ScheduledBackup {
+    pub db_name: String,
+    pub file_name: String,
+    pub created_at: u64,
+    pub size: u64,
+}
+
+impl ScheduledBackup {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "db_name": self.db_name,
+            "file_name": self.file_name,
+            "created_at": self.created_at,
+            "size": self.size,
+        })
+    }
+}
+
+pub fn backup_path() -> Option<PathBuf> {
+    let path = Env::backup_path();
+
+    if path.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(path))
+    }
+}
+
+pub fn start_backup_schedule_task() {
+    let destination = match backup_path() {
+        Some(v) => v,
+        None => return,
+    };
+
+    let interval = Duration::from_secs(Env::backup_interval());
+
+    tracing::info!("Scheduled backup interval duration: {:?}", interval);
+
+    thread::Builder::new()
+        .name("backup-schedule".to_string())
+        .spawn(move || loop {
+            if let Err(e) = run_scheduled_backups(&destination) {
+                tracing::error!("Error running the scheduled backups: {}", e);
+            }
+
+            thread::sleep(interval.min(MAX_TICK));
+        })
+        .expect("Failed to start the backup schedule task");
+}
+
+// NOTE: An empty list of databases schedules all of them
+pub fn scheduled_db_names() -> Result<Vec<String>> {
+    let db_names: Vec<String> = Env::backup_dbs()
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    if !db_names.is_empty() {
+        return Ok(db_names);
+    }
+
+    let mut db_names = Vec::new();
+
+    for entry in fs::read_dir(Env::dbs_path())? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if entry.path().is_file() && is_database_file(&file_name) {
+            db_names.push(file_name);
+        }
+    }
+
+    db_names.sort();
+
+    Ok(db_names)
+}
+
+fn run_scheduled_backups(destination: &Path) -> Result<()> {
+    let interval = Env::backup_interval() * 1000;
+
+    for db_name in scheduled_db_names()? {
+        let now = now_millis();
+
+        let is_due = match list_backups(destination, &db_name)?.last() {
+            Some(v) => now.saturating_sub(v.created_at) >= interval,
+            None => true,
+        };
+
+        if !is_due {
+            continue;
+        }
+
+        let result = backup_database(destination, &db_name, now).and_then(|_| {
+            apply_retention(
+                destination,
+                &db_name,
+                Env::backup_keep_daily(),
+                Env::backup_keep_weekly(),
+            )
+        });
+
+        let mut status = STATUS.lock().unwrap();
+        let status = status.entry(db_name.clone()).or_default();
+
+        status.last_run_at = Some(now);
+
+        match result {
+            Ok(_) => {
+                tracing::info!(db_name, "Scheduled backup created");
+
+                status.last_success_at = Some(now);
+                status.last_error = None;
+            }
+            Err(e) => {
+                tracing::error!(db_name, "Error creating the scheduled backup: {}", e);
+
+                status.last_error = Some(e.to_string());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// NOTE: The backup is written to a temporary file, and it is renamed only if it passes the
+// integrity check, so every listed backup has been verified.
+pub fn backup_database(destination: &Path, db_name: &str, now: u64) -> Result<ScheduledBackup> {
+    if !Path::new(&format!("{}/{}", Env::dbs_path(), db_name)).is_file() {
+        bail!("The database {} doesn't exist", db_name);
+    }
+
+    let dir = destination.join(db_name);
+
+    fs::create_dir_all(&dir)?;
+
+    let file_name = format!("{:016}{}", now, BACKUP_EXTENSION);
+    let path = dir.join(&file_name);
+    let temp = dir.join(format!(".{}.tmp", file_name));
+
+    let result = connect_db(db_name)
+        .and_then(|conn| Ok(conn.backup(DatabaseName::Main, &temp, None::<fn(Progress)>)?))
+        .and_then(|_| verify_backup(&temp))
+        .and_then(|_| Ok(fs::rename(&temp, &path)?));
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(&temp);
+
+        return Err(e);
+    }
+
+    Ok(ScheduledBackup {
+        db_name: db_name.to_string(),
+        file_name,
+        created_at: now,
+        size: fs::metadata(&path)?.len(),
+    })
+}
+
+fn verify_backup(path: &Path) -> Result<()> {
+    let conn = Connection::open(path)?;
+
+    let integrity: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+
+    if integrity != "ok" {
+        bail!("The backup didn't pass the integrity check: {}", integrity);
+    }
+
+    Ok(())
+}
+
+// NOTE: The backups are named with the zero-padded timestamp of their creation, so the sorted
+// names are in the creation order
+pub fn list_backups(destination: &Path, db_name: &str) -> Result<Vec<ScheduledBackup>> {
+    let dir = destination.join(db_name);
+
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+
+    let mut backups = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        let created_at = match file_name
+            .strip_suffix(BACKUP_EXTENSION)
+            .and_then(|v| v.parse::<u64>().ok())
+        {
+            Some(v) => v,
+            None => continue,
+        };
+
+        backups.push(ScheduledBackup {
+            db_name: db_name.to_string(),
+            file_name,
+            created_at,
+            size: entry.metadata()?.len(),
+        });
+    }
+
+    backups.sort_by_key(|b| b.created_at);
+
+    Ok(backups)
+}
+
+pub fn apply_retention(
+    destination: &Path,
+    db_name: &str,
+    keep_daily: usize,
+    keep_weekly: usize,
+) -> Result<()> {
+    let backups = list_backups(destination, db_name)?;
+    let created_at: Vec<u64> = backups.iter().map(|b| b.created_at).collect();
+    let keep = backups_to_keep(&created_at, keep_daily, keep_weekly);
+
+    for backup in backups {
+        if !keep.contains(&backup.created_at) {
+            fs::remove_file(destination.join(db_name).join(&backup.file_name))?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn backup_status(destination: &Path) -> Result<Vec<Value>> {
+    let interval = Env::backup_interval() * 1000;
+    let status = STATUS.lock().unwrap().clone();
+
+    let mut values = Vec::new();
+
+    for db_name in scheduled_db_names()? {
+        let backups = list_backups(destination, &db_name)?;
+        let db_status = status.get(&db_name).cloned().unwrap_or_default();
+        let last_backup_at = backups.last().map(|b| b.created_at);
+
+        values.push(json!({
+            "db_name": db_name,
+            "backups": backups.len(),
+            "last_backup_at": last_backup_at,
+            "next_backup_at": last_backup_at.map(|v| v + interval),
+            "last_run_at": db_status.last_run_at,
+            "last_success_at": db_status.last_success_at,
+            "last_error": db_status.last_error,
+        }));
+    }
+
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_test;
+
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60 * 1000;
+
+    db_test!(
+        test_backup_database_and_apply_retention,
+        TestBackupDatabaseAndApplyRetention,
+        {
+            let destination = PathBuf::from(format!("{}/backups", Env::dbs_path()));
+
+            let conn = connect_db("example.sql").unwrap();
+            conn.execute_batch(
+                "CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT); INSERT INTO test (name) VALUES ('a');",
+            )
+            .unwrap();
+
+            let now = now_millis();
+
+            for i in (0..3).rev() {
+                backup_database(&destination, "example.sql", now - i * DAY).unwrap();
+            }
+
+            let backups = list_backups(&destination, "example.sql").unwrap();
+            assert_eq!(backups.len(), 3);
+            assert_eq!(backups[2].created_at, now);
+            assert!(backups[2].size > 0);
+
+            let backup =
+                Connection::open(destination.join("example.sql").join(&backups[2].file_name))
+                    .unwrap();
+            let count: i64 = backup
+                .query_row("SELECT COUNT(*) FROM test", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(count, 1);
+
+            apply_retention(&destination, "example.sql", 2, 0).unwrap();
+
+            let backups = list_backups(&destination, "example.sql").unwrap();
+            assert_eq!(
+                backups.iter().map(|b| b.created_at).collect::<Vec<_>>(),
+                vec![now - DAY, now]
+            );
+        }
+    );
+}
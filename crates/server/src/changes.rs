@@ -0,0 +1,3 @@
+pub mod capture;
+pub mod subscriber;
+pub mod webhook;
@@ -0,0 +1,380 @@
+use anyhow::{bail, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::{json, Value};
+
+use crate::sqlite::schema::get_columns;
+
+pub const CHANGES_TABLE: &str = "_query_changes";
+
+const TRIGGER_PREFIX: &str = "_query_changes_";
+const OPERATIONS: [&str; 3] = ["INSERT", "UPDATE", "DELETE"];
+
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub id: i64,
+    pub table_name: String,
+    pub operation: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+    pub created_at: i64,
+}
+
+impl Change {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "id": self.id,
+            "table_name": self.table_name,
+            "operation": self.operation,
+            "old": self.old,
+            "new": self.new,
+            "created_at": self.created_at,
+        })
+    }
+}
+
+// NOTE: The AUTOINCREMENT keeps the ids of the deleted changes from being reused, so a cursor
+// always points to the same position of the log
+fn create_changes_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!(
+        "
+        CREATE TABLE IF NOT EXISTS {CHANGES_TABLE} (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            table_name TEXT NOT NULL,
+            operation TEXT NOT NULL CHECK (operation IN ('INSERT', 'UPDATE', 'DELETE')),
+            old TEXT,
+            new TEXT,
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', datetime('now')))
+        );
+        "
+    ))?;
+
+    Ok(())
+}
+
+// NOTE: The triggers are created with the columns of the table at that moment, so enabling the
+// capture again refreshes them after a change of the schema
+pub fn enable_capture(conn: &Connection, table_name: &str) -> Result<()> {
+    if table_name.starts_with(CHANGES_TABLE) || table_name.starts_with("sqlite_") {
+        bail!("The changes of the table {} can't be captured", table_name);
+    }
+
+    let is_table: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [table_name],
+        |row| row.get(0),
+    )?;
+
+    if !is_table {
+        bail!("The table {} doesn't exist", table_name);
+    }
+
+    let columns = get_columns(conn, table_name)?;
+
+    let row = |alias: &str| {
+        let pairs = columns
+            .iter()
+            .map(|c| {
+                let column = format!("{}.{}", alias, quote(&c.name));
+
+                format!(
+                    "{}, CASE WHEN typeof({column}) = 'blob' THEN hex({column}) ELSE {column} END",
+                    quote_literal(&c.name)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        format!("json_object({})", pairs)
+    };
+
+    let mut sql = vec!["BEGIN;".to_string()];
+
+    for operation in OPERATIONS {
+        let (old, new) = match operation {
+            "INSERT" => ("NULL".to_string(), row("NEW")),
+            "UPDATE" => (row("OLD"), row("NEW")),
+            _ => (row("OLD"), "NULL".to_string()),
+        };
+
+        let trigger_name = quote(&trigger_name(table_name, operation));
+
+        sql.push(format!("DROP TRIGGER IF EXISTS {trigger_name};"));
+        sql.push(format!(
+            "
+            CREATE TRIGGER {trigger_name}
+                AFTER {operation} ON {table}
+            BEGIN
+                INSERT INTO {CHANGES_TABLE} (table_name, operation, old, new)
+                VALUES ({table_literal}, '{operation}', {old}, {new});
+            END;
+            ",
+            table = quote(table_name),
+            table_literal = quote_literal(table_name),
+        ));
+    }
+
+    sql.push("COMMIT;".to_string());
+
+    create_changes_table(conn)?;
+    conn.execute_batch(&sql.join("\n"))?;
+
+    Ok(())
+}
+
+pub fn disable_capture(conn: &Connection, table_name: &str) -> Result<()> {
+    let sql = OPERATIONS
+        .iter()
+        .map(|operation| {
+            format!(
+                "DROP TRIGGER IF EXISTS {};",
+                quote(&trigger_name(table_name, operation))
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    conn.execute_batch(&sql)?;
+
+    Ok(())
+}
+
+pub fn captured_tables(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT DISTINCT
+            tbl_name
+        FROM
+            sqlite_master
+        WHERE
+            type = 'trigger'
+        AND
+            substr(name, 1, length(?1)) = ?1
+        ORDER BY
+            tbl_name;
+        ",
+    )?;
+
+    let tables = stmt
+        .query_map([TRIGGER_PREFIX], |row| row.get(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+    Ok(tables)
+}
+
+pub fn has_changes_table(conn: &Connection) -> Result<bool> {
+    let exists = conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?",
+            [CHANGES_TABLE],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?;
+
+    Ok(exists.is_some())
+}
+
+// NOTE: Returns the id of the last change, or 0 if nothing has been captured yet
+pub fn last_change_id(conn: &Connection) -> Result<i64> {
+    if !has_changes_table(conn)? {
+        return Ok(0);
+    }
+
+    let id = conn.query_row(
+        &format!("SELECT COALESCE(MAX(id), 0) FROM {CHANGES_TABLE}"),
+        [],
+        |row| row.get(0),
+    )?;
+
+    Ok(id)
+}
+
+// NOTE: Returns the changes after the cursor, in the order they were made
+pub fn read_changes(
+    conn: &Connection,
+    cursor: i64,
+    limit: i64,
+    table_name: Option<&str>,
+) -> Result<Vec<Change>> {
+    if !has_changes_table(conn)? {
+        return Ok(Vec::new());
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "
+        SELECT
+            id,
+            table_name,
+            operation,
+            old,
+            new,
+            created_at
+        FROM
+            {CHANGES_TABLE}
+        WHERE
+            id > ?1
+        AND
+            (?2 IS NULL OR table_name = ?2)
+        ORDER BY
+            id
+        LIMIT ?3;
+        "
+    ))?;
+
+    let changes = stmt
+        .query_map(params![cursor, table_name, limit], |row| {
+            let old: Option<String> = row.get(3)?;
+            let new: Option<String> = row.get(4)?;
+
+            Ok(Change {
+                id: row.get(0)?,
+                table_name: row.get(1)?,
+                operation: row.get(2)?,
+                old: old.and_then(|v| serde_json::from_str(&v).ok()),
+                new: new.and_then(|v| serde_json::from_str(&v).ok()),
+                created_at: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<Change>, rusqlite::Error>>()?;
+
+    Ok(changes)
+}
+
+// NOTE: Removes the changes up to the cursor, included
+pub fn trim_changes(conn: &Connection, cursor: i64) -> Result<usize> {
+    if !has_changes_table(conn)? {
+        return Ok(0);
+    }
+
+    let deleted = conn.execute(
+        &format!("DELETE FROM {CHANGES_TABLE} WHERE id <= ?"),
+        [cursor],
+    )?;
+
+    Ok(deleted)
+}
+
+fn trigger_name(table_name: &str, operation: &str) -> String {
+    format!(
+        "{}{}_{}",
+        TRIGGER_PREFIX,
+        table_name,
+        operation.to_lowercase()
+    )
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(
+            "
+            CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT NOT NULL, cover BLOB);
+            CREATE TABLE author (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+            ",
+        )
+        .unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn test_capture_changes() {
+        let conn = conn();
+
+        enable_capture(&conn, "book").unwrap();
+
+        conn.execute_batch(
+            "
+            INSERT INTO book (id, title, cover) VALUES (1, 'Dune', x'0102');
+            UPDATE book SET title = 'Dune Messiah' WHERE id = 1;
+            DELETE FROM book WHERE id = 1;
+            INSERT INTO author (id, name) VALUES (1, 'Frank Herbert');
+            ",
+        )
+        .unwrap();
+
+        let changes = read_changes(&conn, 0, 100, None).unwrap();
+
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].operation, "INSERT");
+        assert_eq!(changes[0].old, None);
+        assert_eq!(
+            changes[0].new,
+            Some(json!({ "id": 1, "title": "Dune", "cover": "0102" }))
+        );
+        assert_eq!(changes[1].operation, "UPDATE");
+        assert_eq!(changes[1].old.as_ref().unwrap()["title"], "Dune");
+        assert_eq!(changes[1].new.as_ref().unwrap()["title"], "Dune Messiah");
+        assert_eq!(changes[2].operation, "DELETE");
+        assert_eq!(changes[2].new, None);
+
+        let changes = read_changes(&conn, changes[0].id, 1, Some("book")).unwrap();
+
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].operation, "UPDATE");
+        assert_eq!(last_change_id(&conn).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_enable_and_disable_capture() {
+        let conn = conn();
+
+        assert_eq!(read_changes(&conn, 0, 100, None).unwrap(), Vec::new());
+        assert_eq!(last_change_id(&conn).unwrap(), 0);
+
+        enable_capture(&conn, "book").unwrap();
+        enable_capture(&conn, "author").unwrap();
+        enable_capture(&conn, "book").unwrap();
+
+        assert_eq!(captured_tables(&conn).unwrap(), vec!["author", "book"]);
+
+        disable_capture(&conn, "author").unwrap();
+
+        conn.execute("INSERT INTO author (name) VALUES ('Ursula K. Le Guin')", [])
+            .unwrap();
+
+        assert_eq!(captured_tables(&conn).unwrap(), vec!["book"]);
+        assert_eq!(read_changes(&conn, 0, 100, None).unwrap(), Vec::new());
+
+        assert!(enable_capture(&conn, "missing").is_err());
+        assert!(enable_capture(&conn, CHANGES_TABLE).is_err());
+    }
+
+    #[test]
+    fn test_trim_changes() {
+        let conn = conn();
+
+        enable_capture(&conn, "author").unwrap();
+
+        conn.execute_batch(
+            "
+            INSERT INTO author (name) VALUES ('Frank Herbert');
+            INSERT INTO author (name) VALUES ('Ursula K. Le Guin');
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(trim_changes(&conn, 1).unwrap(), 1);
+
+        conn.execute("DELETE FROM author", []).unwrap();
+
+        let ids: Vec<i64> = read_changes(&conn, 0, 100, None)
+            .unwrap()
+            .iter()
+            .map(|c| c.id)
+            .collect();
+
+        assert_eq!(ids, vec![2, 3, 4]);
+    }
+}
@@ -0,0 +1,304 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Result};
+use http_body_util::Full;
+use hyper::{body::Bytes, header::CONTENT_TYPE, Method, Request};
+use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+use rusqlite::named_params;
+use serde_json::{json, Value};
+use tokio::{task::JoinHandle, time};
+
+use crate::{
+    archive::archiver::now_millis,
+    env::Env,
+    sqlite::connect_db::{connect_config_db, connect_db},
+};
+
+use super::capture::{last_change_id, read_changes};
+
+const BATCH_SIZE: i64 = 100;
+
+pub struct Subscription {
+    pub id: i64,
+    pub db_name: String,
+    pub table_name: String,
+    pub function_path: String,
+    pub cursor: i64,
+    pub last_delivered_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+impl Subscription {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "id": self.id,
+            "db_name": self.db_name,
+            "table_name": self.table_name,
+            "function_path": self.function_path,
+            "cursor": self.cursor,
+            "last_delivered_at": self.last_delivered_at,
+            "last_error": self.last_error,
+        })
+    }
+}
+
+pub fn start_change_subscriber_task() -> JoinHandle<()> {
+    let interval_duration = Duration::from_secs(Env::changes_interval());
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(interval_duration);
+
+        tracing::info!(
+            "Change subscriber interval duration: {:?}",
+            interval_duration
+        );
+
+        loop {
+            interval.tick().await;
+
+            let subscriptions = match list_subscriptions() {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!("Error listing the change subscriptions: {}", e);
+                    continue;
+                }
+            };
+
+            for subscription in subscriptions {
+                let result = deliver_changes(&subscription).await;
+
+                if let Err(e) = update_status(&subscription, result) {
+                    tracing::error!("Error updating the change subscription: {}", e);
+                }
+            }
+        }
+    })
+}
+
+pub fn list_subscriptions() -> Result<Vec<Subscription>> {
+    let conn = connect_config_db()?;
+
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            id,
+            db_name,
+            table_name,
+            function_path,
+            cursor,
+            last_delivered_at,
+            last_error
+        FROM
+            _config_change_subscription
+        ORDER BY
+            id;
+        ",
+    )?;
+
+    let subscriptions = stmt
+        .query_map([], |row| {
+            Ok(Subscription {
+                id: row.get(0)?,
+                db_name: row.get(1)?,
+                table_name: row.get(2)?,
+                function_path: row.get(3)?,
+                cursor: row.get(4)?,
+                last_delivered_at: row.get(5)?,
+                last_error: row.get(6)?,
+            })
+        })?
+        .collect::<Result<Vec<Subscription>, rusqlite::Error>>()?;
+
+    Ok(subscriptions)
+}
+
+// NOTE: The subscription starts after the last captured change, so it only receives the
+// changes made from then on
+pub fn create_subscription(db_name: &str, table_name: &str, function_path: &str) -> Result<()> {
+    let cursor = last_change_id(&connect_db(db_name)?)?;
+
+    connect_config_db()?.execute(
+        "
+        INSERT OR IGNORE INTO _config_change_subscription (db_name, table_name, function_path, cursor)
+        VALUES (:db_name, :table_name, :function_path, :cursor);
+        ",
+        named_params! {
+            ":db_name": db_name,
+            ":table_name": table_name,
+            ":function_path": function_path,
+            ":cursor": cursor,
+        },
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_subscription(db_name: &str, table_name: &str, function_path: &str) -> Result<usize> {
+    let deleted = connect_config_db()?.execute(
+        "
+        DELETE FROM
+            _config_change_subscription
+        WHERE
+            db_name = :db_name
+        AND
+            table_name = :table_name
+        AND
+            function_path = :function_path;
+        ",
+        named_params! {
+            ":db_name": db_name,
+            ":table_name": table_name,
+            ":function_path": function_path,
+        },
+    )?;
+
+    Ok(deleted)
+}
+
+// NOTE: The cursor only moves forward when the function responds with a success status, so a
+// batch is delivered again until it's accepted
+async fn deliver_changes(subscription: &Subscription) -> Result<Option<i64>> {
+    if !Path::new(&format!("{}/{}", Env::dbs_path(), subscription.db_name)).is_file() {
+        bail!("The database {} doesn't exist", subscription.db_name);
+    }
+
+    let table_name = match subscription.table_name.as_str() {
+        "*" => None,
+        v => Some(v),
+    };
+
+    let changes = read_changes(
+        &connect_db(&subscription.db_name)?,
+        subscription.cursor,
+        BATCH_SIZE,
+        table_name,
+    )?;
+
+    let cursor = match changes.last() {
+        Some(v) => v.id,
+        None => return Ok(None),
+    };
+
+    let body = json!({
+        "db_name": subscription.db_name,
+        "cursor": cursor,
+        "changes": changes.iter().map(|c| c.to_value()).collect::<Vec<Value>>(),
+    });
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(format!(
+            "http://localhost:{}/_/function{}",
+            Env::port(),
+            subscription.function_path
+        ))
+        .header(CONTENT_TYPE, "application/json")
+        .body(Full::new(Bytes::from(body.to_string())))?;
+
+    let client = Client::builder(TokioExecutor::new()).build_http();
+
+    let res = client.request(req).await?;
+
+    if !res.status().is_success() {
+        bail!(
+            "The function {} responded with the status {}",
+            subscription.function_path,
+            res.status()
+        );
+    }
+
+    Ok(Some(cursor))
+}
+
+fn update_status(subscription: &Subscription, result: Result<Option<i64>>) -> Result<()> {
+    let conn = connect_config_db()?;
+
+    match result {
+        Ok(Some(cursor)) => {
+            conn.execute(
+                "
+                UPDATE
+                    _config_change_subscription
+                SET
+                    cursor = :cursor,
+                    last_delivered_at = :now,
+                    last_error = NULL
+                WHERE
+                    id = :id;
+                ",
+                named_params! {
+                    ":cursor": cursor,
+                    ":now": now_millis() / 1000,
+                    ":id": subscription.id,
+                },
+            )?;
+        }
+        Ok(None) => (),
+        Err(e) => {
+            tracing::error!(
+                "Error delivering the changes of {} to {}: {}",
+                subscription.db_name,
+                subscription.function_path,
+                e
+            );
+
+            conn.execute(
+                "UPDATE _config_change_subscription SET last_error = :error WHERE id = :id;",
+                named_params! {
+                    ":error": e.to_string(),
+                    ":id": subscription.id,
+                },
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{changes::capture::enable_capture, db_test};
+
+    use super::*;
+
+    db_test!(test_subscription, TestSubscription, {
+        let conn = connect_db("subscription.sql").unwrap();
+
+        conn.execute_batch(
+            "
+            CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT);
+            INSERT INTO book (title) VALUES ('Dune');
+            ",
+        )
+        .unwrap();
+
+        enable_capture(&conn, "book").unwrap();
+
+        conn.execute("INSERT INTO book (title) VALUES ('Dune Messiah')", [])
+            .unwrap();
+
+        create_subscription("subscription.sql", "book", "/api/changes").unwrap();
+        create_subscription("subscription.sql", "book", "/api/changes").unwrap();
+
+        let subscriptions = list_subscriptions().unwrap();
+
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].cursor, 1);
+        assert_eq!(subscriptions[0].table_name, "book");
+
+        update_status(&subscriptions[0], Ok(Some(2))).unwrap();
+        update_status(&subscriptions[0], Err(anyhow::anyhow!("Unavailable"))).unwrap();
+
+        let subscriptions = list_subscriptions().unwrap();
+
+        assert_eq!(subscriptions[0].cursor, 2);
+        assert_eq!(subscriptions[0].last_error, Some("Unavailable".to_string()));
+        assert!(subscriptions[0].last_delivered_at.is_some());
+
+        assert_eq!(
+            delete_subscription("subscription.sql", "book", "/api/changes").unwrap(),
+            1
+        );
+        assert!(list_subscriptions().unwrap().is_empty());
+    });
+}
@@ -0,0 +1,806 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Result};
+use http_body_util::Full;
+use hyper::{body::Bytes, header::CONTENT_TYPE, Method, Request};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use hyper_util::{
+    client::legacy::{connect::HttpConnector, Client},
+    rt::TokioExecutor,
+};
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use rusqlite::{named_params, OptionalExtension, Row};
+use rustls::{crypto::ring, ClientConfig, RootCertStore};
+use serde_json::{json, Value};
+use tokio::{task::JoinHandle, time};
+
+use crate::{
+    archive::archiver::now_millis,
+    controllers::utils::hex::to_hex,
+    env::Env,
+    sqlite::connect_db::{connect_config_db, connect_db},
+};
+
+use super::capture::{last_change_id, read_changes, Change};
+
+pub const OPERATIONS: [&str; 3] = ["INSERT", "UPDATE", "DELETE"];
+
+const BATCH_SIZE: i64 = 100;
+const BACKOFF_BASE: u64 = 5;
+const BACKOFF_MAX: u64 = 3600;
+const DELIVERIES_TO_KEEP: i64 = 100;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub const HEADER_WEBHOOK_NAME: &str = "query-webhook-name";
+pub const HEADER_WEBHOOK_DELIVERY: &str = "query-webhook-delivery";
+pub const HEADER_WEBHOOK_SIGNATURE: &str = "query-webhook-signature";
+
+type HttpsClient = Client<HttpsConnector<HttpConnector>, Full<Bytes>>;
+
+pub struct Webhook {
+    pub id: i64,
+    pub name: String,
+    pub db_name: String,
+    pub table_name: String,
+    pub operations: String,
+    pub url: String,
+    pub secret: String,
+    pub active: bool,
+    pub cursor: i64,
+    pub attempts: i64,
+    pub retry_at: Option<i64>,
+}
+
+impl Webhook {
+    // NOTE: The secret isn't included, it's only known by the admin that created the webhook
+    pub fn to_value(&self) -> Value {
+        json!({
+            "id": self.id,
+            "name": self.name,
+            "db_name": self.db_name,
+            "table_name": self.table_name,
+            "operations": self.operations,
+            "url": self.url,
+            "active": self.active,
+            "cursor": self.cursor,
+            "attempts": self.attempts,
+            "retry_at": self.retry_at,
+        })
+    }
+
+    pub fn matches(&self, operation: &str) -> bool {
+        self.operations == "*" || self.operations.split(',').any(|v| v == operation)
+    }
+
+    fn from_row(row: &Row) -> Result<Self, rusqlite::Error> {
+        Ok(Webhook {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            db_name: row.get(2)?,
+            table_name: row.get(3)?,
+            operations: row.get(4)?,
+            url: row.get(5)?,
+            secret: row.get(6)?,
+            active: row.get(7)?,
+            cursor: row.get(8)?,
+            attempts: row.get(9)?,
+            retry_at: row.get(10)?,
+        })
+    }
+}
+
+pub struct Delivery {
+    pub id: i64,
+    pub webhook_name: String,
+    pub change_id: i64,
+    pub attempt: i64,
+    pub status: Option<u16>,
+    pub error: Option<String>,
+    pub duration: i64,
+    pub created_at: i64,
+}
+
+impl Delivery {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "id": self.id,
+            "webhook_name": self.webhook_name,
+            "change_id": self.change_id,
+            "attempt": self.attempt,
+            "status": self.status,
+            "error": self.error,
+            "success": self.error.is_none(),
+            "duration": self.duration,
+            "created_at": self.created_at,
+        })
+    }
+}
+
+pub fn start_webhook_task() -> JoinHandle<()> {
+    let interval_duration = Duration::from_secs(Env::changes_interval());
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(interval_duration);
+        let client = https_client();
+
+        tracing::info!("Webhook interval duration: {:?}", interval_duration);
+
+        loop {
+            interval.tick().await;
+
+            let webhooks = match list_webhooks() {
+                Ok(v) => v,
+                Err(e) => {
+                    tracing::error!("Error listing the webhooks: {}", e);
+                    continue;
+                }
+            };
+
+            for webhook in webhooks.iter().filter(|w| w.active) {
+                if let Err(e) = deliver_webhook(&client, webhook, now_millis() / 1000).await {
+                    tracing::error!("Error delivering the webhook {}: {}", webhook.name, e);
+                }
+            }
+        }
+    })
+}
+
+pub fn https_client() -> HttpsClient {
+    let mut root_certificates = RootCertStore::empty();
+
+    for cert in webpki_roots::TLS_SERVER_ROOTS.iter().cloned() {
+        root_certificates.roots.push(cert)
+    }
+
+    let tls_config = ClientConfig::builder_with_provider(ring::default_provider().into())
+        .with_safe_default_protocol_versions()
+        .expect("Failed to create the TLS configuration")
+        .with_root_certificates(root_certificates)
+        .with_no_client_auth();
+
+    let https = HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http()
+        .enable_http1()
+        .build();
+
+    Client::builder(TokioExecutor::new()).build(https)
+}
+
+pub fn list_webhooks() -> Result<Vec<Webhook>> {
+    let conn = connect_config_db()?;
+
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            id,
+            name,
+            db_name,
+            table_name,
+            operations,
+            url,
+            secret,
+            active,
+            cursor,
+            attempts,
+            retry_at
+        FROM
+            _config_webhook
+        ORDER BY
+            id;
+        ",
+    )?;
+
+    let webhooks = stmt
+        .query_map([], Webhook::from_row)?
+        .collect::<Result<Vec<Webhook>, rusqlite::Error>>()?;
+
+    Ok(webhooks)
+}
+
+// NOTE: A new webhook starts after the last captured change, so it only receives the changes
+// made from then on. Saving an existing webhook keeps its position.
+pub fn save_webhook(webhook: &Webhook) -> Result<()> {
+    let cursor = last_change_id(&connect_db(&webhook.db_name)?)?;
+
+    connect_config_db()?.execute(
+        "
+        INSERT INTO _config_webhook (name, db_name, table_name, operations, url, secret, active, cursor)
+        VALUES (:name, :db_name, :table_name, :operations, :url, :secret, :active, :cursor)
+        ON CONFLICT (name) DO UPDATE SET
+            db_name = excluded.db_name,
+            table_name = excluded.table_name,
+            operations = excluded.operations,
+            url = excluded.url,
+            secret = excluded.secret,
+            active = excluded.active,
+            cursor = CASE WHEN db_name = excluded.db_name THEN cursor ELSE excluded.cursor END;
+        ",
+        named_params! {
+            ":name": webhook.name,
+            ":db_name": webhook.db_name,
+            ":table_name": webhook.table_name,
+            ":operations": webhook.operations,
+            ":url": webhook.url,
+            ":secret": webhook.secret,
+            ":active": webhook.active,
+            ":cursor": cursor,
+        },
+    )?;
+
+    Ok(())
+}
+
+pub fn delete_webhook(name: &str) -> Result<usize> {
+    let conn = connect_config_db()?;
+
+    conn.execute(
+        "DELETE FROM _config_webhook_delivery WHERE webhook_name = ?",
+        [name],
+    )?;
+
+    let deleted = conn.execute("DELETE FROM _config_webhook WHERE name = ?", [name])?;
+
+    Ok(deleted)
+}
+
+pub fn list_deliveries(name: &str, limit: i64) -> Result<Vec<Delivery>> {
+    let conn = connect_config_db()?;
+
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            id,
+            webhook_name,
+            change_id,
+            attempt,
+            status,
+            error,
+            duration,
+            created_at
+        FROM
+            _config_webhook_delivery
+        WHERE
+            webhook_name = ?
+        ORDER BY
+            id DESC
+        LIMIT ?;
+        ",
+    )?;
+
+    let deliveries = stmt
+        .query_map(rusqlite::params![name, limit], |row| {
+            Ok(Delivery {
+                id: row.get(0)?,
+                webhook_name: row.get(1)?,
+                change_id: row.get(2)?,
+                attempt: row.get(3)?,
+                status: row.get(4)?,
+                error: row.get(5)?,
+                duration: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })?
+        .collect::<Result<Vec<Delivery>, rusqlite::Error>>()?;
+
+    Ok(deliveries)
+}
+
+// NOTE: The delays grow exponentially from 5 seconds up to 1 hour
+pub fn backoff(attempts: i64) -> u64 {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+
+    BACKOFF_BASE
+        .saturating_mul(2u64.pow(exponent))
+        .min(BACKOFF_MAX)
+}
+
+// NOTE: The signature is the HMAC-SHA256 of the timestamp and the body, joined by a dot, with
+// the secret of the webhook as the key. The timestamp allows the receivers to reject old
+// deliveries.
+pub fn signature(secret: &str, timestamp: u64, body: &str) -> Result<String> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+
+    signer.update(format!("{}.{}", timestamp, body).as_bytes())?;
+
+    let hex = to_hex(&signer.sign_to_vec()?);
+
+    Ok(format!("t={},v1={}", timestamp, hex))
+}
+
+// NOTE: The changes are delivered one by one, in order. A failed delivery is retried with
+// backoff, blocking the next changes, until it succeeds or it reaches the maximum attempts.
+pub async fn deliver_webhook(client: &HttpsClient, webhook: &Webhook, now: u64) -> Result<()> {
+    if webhook.retry_at.is_some_and(|v| v > now as i64) {
+        return Ok(());
+    }
+
+    if !Path::new(&format!("{}/{}", Env::dbs_path(), webhook.db_name)).is_file() {
+        bail!("The database {} doesn't exist", webhook.db_name);
+    }
+
+    let table_name = match webhook.table_name.as_str() {
+        "*" => None,
+        v => Some(v),
+    };
+
+    let changes = read_changes(
+        &connect_db(&webhook.db_name)?,
+        webhook.cursor,
+        BATCH_SIZE,
+        table_name,
+    )?;
+
+    let mut cursor = webhook.cursor;
+    let mut attempts = webhook.attempts;
+
+    for change in changes {
+        if !webhook.matches(&change.operation) {
+            update_position(webhook.id, change.id, 0, None)?;
+            cursor = change.id;
+            continue;
+        }
+
+        attempts += 1;
+
+        let started_at = now_millis();
+        let result = send(client, webhook, &change, now).await;
+        let duration = (now_millis() - started_at) as i64;
+
+        let (status, error) = match result {
+            Ok(status) if (200..300).contains(&status) => (Some(status), None),
+            Ok(status) => (
+                Some(status),
+                Some(format!("The server responded with the status {}", status)),
+            ),
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        insert_delivery(&webhook.name, change.id, attempts, status, &error, duration)?;
+
+        if error.is_none() {
+            update_position(webhook.id, change.id, 0, None)?;
+            cursor = change.id;
+            attempts = 0;
+            continue;
+        }
+
+        if attempts >= Env::webhook_max_attempts() {
+            tracing::error!(
+                "The webhook {} gave up the change {} after {} attempts",
+                webhook.name,
+                change.id,
+                attempts
+            );
+
+            update_position(webhook.id, change.id, 0, None)?;
+            cursor = change.id;
+            attempts = 0;
+            continue;
+        }
+
+        update_position(webhook.id, cursor, attempts, Some(now + backoff(attempts)))?;
+
+        break;
+    }
+
+    Ok(())
+}
+
+async fn send(client: &HttpsClient, webhook: &Webhook, change: &Change, now: u64) -> Result<u16> {
+    let body = json!({
+        "webhook": webhook.name,
+        "db_name": webhook.db_name,
+        "change": change.to_value(),
+    })
+    .to_string();
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(&webhook.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header(HEADER_WEBHOOK_NAME, &webhook.name)
+        .header(
+            HEADER_WEBHOOK_DELIVERY,
+            format!("{}-{}", webhook.id, change.id),
+        )
+        .header(
+            HEADER_WEBHOOK_SIGNATURE,
+            signature(&webhook.secret, now, &body)?,
+        )
+        .body(Full::new(Bytes::from(body)))?;
+
+    let res = match time::timeout(REQUEST_TIMEOUT, client.request(req)).await {
+        Ok(v) => v?,
+        Err(_) => bail!("The request timed out after {:?}", REQUEST_TIMEOUT),
+    };
+
+    Ok(res.status().as_u16())
+}
+
+fn update_position(id: i64, cursor: i64, attempts: i64, retry_at: Option<u64>) -> Result<()> {
+    connect_config_db()?.execute(
+        "
+        UPDATE
+            _config_webhook
+        SET
+            cursor = :cursor,
+            attempts = :attempts,
+            retry_at = :retry_at
+        WHERE
+            id = :id;
+        ",
+        named_params! {
+            ":cursor": cursor,
+            ":attempts": attempts,
+            ":retry_at": retry_at,
+            ":id": id,
+        },
+    )?;
+
+    Ok(())
+}
+
+fn insert_delivery(
+    webhook_name: &str,
+    change_id: i64,
+    attempt: i64,
+    status: Option<u16>,
+    error: &Option<String>,
+    duration: i64,
+) -> Result<()> {
+    let conn = connect_config_db()?;
+
+    conn.execute(
+        "
+        INSERT INTO _config_webhook_delivery (webhook_name, change_id, attempt, status, error, duration)
+        VALUES (:webhook_name, :change_id, :attempt, :status, :error, :duration);
+        ",
+        named_params! {
+            ":webhook_name": webhook_name,
+            ":change_id": change_id,
+            ":attempt": attempt,
+            ":status": status,
+            ":error": error,
+            ":duration": duration,
+        },
+    )?;
+
+    // NOTE: Only the last deliveries of each webhook are kept
+    let oldest_kept: Option<i64> = conn
+        .query_row(
+            "
+            SELECT
+                id
+            FROM
+                _config_webhook_delivery
+            WHERE
+                webhook_name = ?
+            ORDER BY
+                id DESC
+            LIMIT 1 OFFSET ?;
+            ",
+            rusqlite::params![webhook_name, DELIVERIES_TO_KEEP - 1],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    if let Some(id) = oldest_kept {
+        conn.execute(
+            "DELETE FROM _config_webhook_delivery WHERE webhook_name = ? AND id < ?",
+            rusqlite::params![webhook_name, id],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{Arc, Mutex},
+    };
+
+    use http_body_util::BodyExt;
+    use hyper::{server::conn::http1, service::service_fn, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    use crate::{changes::capture::enable_capture, db_test, sqlite::branch::promote_branch};
+
+    use super::*;
+
+    type Received = Arc<Mutex<Vec<(String, String)>>>;
+
+    // NOTE: A local HTTP server that stands in for the receiver of the webhooks. It responds
+    // with the given statuses, in order, and records the signature and the body of each request.
+    async fn stand_in(statuses: Vec<u16>) -> (String, Received) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let received: Received = Arc::new(Mutex::new(Vec::new()));
+        let statuses = Arc::new(Mutex::new(statuses));
+
+        let received_clone = received.clone();
+
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let received = received_clone.clone();
+                let statuses = statuses.clone();
+
+                tokio::spawn(async move {
+                    let service = service_fn(move |req: Request<hyper::body::Incoming>| {
+                        let received = received.clone();
+                        let statuses = statuses.clone();
+
+                        async move {
+                            let signature = req.headers()[HEADER_WEBHOOK_SIGNATURE]
+                                .to_str()
+                                .unwrap()
+                                .to_string();
+                            let body = req.into_body().collect().await.unwrap().to_bytes();
+                            let body = String::from_utf8(body.to_vec()).unwrap();
+
+                            received.lock().unwrap().push((signature, body));
+
+                            let status = statuses.lock().unwrap().remove(0);
+
+                            Ok::<_, Infallible>(
+                                Response::builder()
+                                    .status(StatusCode::from_u16(status).unwrap())
+                                    .body(Full::new(Bytes::new()))
+                                    .unwrap(),
+                            )
+                        }
+                    });
+
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        (url, received)
+    }
+
+    fn webhook(name: &str) -> Webhook {
+        list_webhooks()
+            .unwrap()
+            .into_iter()
+            .find(|w| w.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(1), 5);
+        assert_eq!(backoff(2), 10);
+        assert_eq!(backoff(5), 80);
+        assert_eq!(backoff(100), 3600);
+    }
+
+    #[test]
+    fn test_signature() {
+        assert_eq!(
+            signature("secret", 1735689600, "{\"id\":1}").unwrap(),
+            "t=1735689600,v1=8aa4e997c26ba502480c37eadeaf49ae3482f319ab1a0a8c77310e9edd9e020a"
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut webhook = Webhook {
+            id: 1,
+            name: "hook".to_string(),
+            db_name: "example.sql".to_string(),
+            table_name: "*".to_string(),
+            operations: "*".to_string(),
+            url: "http://localhost".to_string(),
+            secret: "secret".to_string(),
+            active: true,
+            cursor: 0,
+            attempts: 0,
+            retry_at: None,
+        };
+
+        assert!(webhook.matches("DELETE"));
+
+        webhook.operations = "INSERT,UPDATE".to_string();
+
+        assert!(webhook.matches("UPDATE"));
+        assert!(!webhook.matches("DELETE"));
+    }
+
+    db_test!(test_deliver_webhook, TestDeliverWebhook, {
+        let conn = connect_db("webhook.sql").unwrap();
+
+        conn.execute_batch("CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT);")
+            .unwrap();
+
+        enable_capture(&conn, "book").unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let (url, received) = stand_in(vec![500, 200, 200]).await;
+
+            save_webhook(&Webhook {
+                id: 0,
+                name: "hook".to_string(),
+                db_name: "webhook.sql".to_string(),
+                table_name: "book".to_string(),
+                operations: "INSERT,DELETE".to_string(),
+                url,
+                secret: "secret".to_string(),
+                active: true,
+                cursor: 0,
+                attempts: 0,
+                retry_at: None,
+            })
+            .unwrap();
+
+            conn.execute_batch(
+                "
+                INSERT INTO book (id, title) VALUES (1, 'Dune');
+                UPDATE book SET title = 'Dune Messiah' WHERE id = 1;
+                DELETE FROM book WHERE id = 1;
+                ",
+            )
+            .unwrap();
+
+            let client = https_client();
+
+            deliver_webhook(&client, &webhook("hook"), 1000)
+                .await
+                .unwrap();
+
+            let failed = webhook("hook");
+
+            assert_eq!(failed.cursor, 0);
+            assert_eq!(failed.attempts, 1);
+            assert_eq!(failed.retry_at, Some(1005));
+
+            // NOTE: It waits until the retry time
+            deliver_webhook(&client, &failed, 1001).await.unwrap();
+
+            assert_eq!(received.lock().unwrap().len(), 1);
+
+            deliver_webhook(&client, &failed, 1005).await.unwrap();
+
+            let delivered = webhook("hook");
+
+            assert_eq!(delivered.cursor, 3);
+            assert_eq!(delivered.attempts, 0);
+            assert_eq!(delivered.retry_at, None);
+
+            let received = received.lock().unwrap();
+            let operations: Vec<String> = received
+                .iter()
+                .map(|(_, body)| {
+                    let body: Value = serde_json::from_str(body).unwrap();
+                    body["change"]["operation"].as_str().unwrap().to_string()
+                })
+                .collect();
+
+            assert_eq!(operations, vec!["INSERT", "INSERT", "DELETE"]);
+            assert_eq!(
+                received[1].0,
+                signature("secret", 1005, &received[1].1).unwrap()
+            );
+
+            let deliveries = list_deliveries("hook", 10).unwrap();
+
+            assert_eq!(deliveries.len(), 3);
+            assert_eq!(deliveries[2].status, Some(500));
+            assert_eq!(deliveries[2].attempt, 1);
+            assert!(deliveries[2].error.is_some());
+            assert_eq!(deliveries[1].attempt, 2);
+            assert_eq!(deliveries[1].error, None);
+        });
+
+        assert_eq!(delete_webhook("hook").unwrap(), 1);
+        assert!(list_deliveries("hook", 10).unwrap().is_empty());
+    });
+
+    db_test!(test_deliver_after_promote, TestDeliverAfterPromote, {
+        let conn = connect_db("webhook.sql").unwrap();
+
+        conn.execute_batch("CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT);")
+            .unwrap();
+
+        enable_capture(&conn, "book").unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        rt.block_on(async {
+            let (url, received) = stand_in(vec![200, 200, 200]).await;
+
+            save_webhook(&Webhook {
+                id: 0,
+                name: "hook".to_string(),
+                db_name: "webhook.sql".to_string(),
+                table_name: "book".to_string(),
+                operations: "*".to_string(),
+                url,
+                secret: "secret".to_string(),
+                active: true,
+                cursor: 0,
+                attempts: 0,
+                retry_at: None,
+            })
+            .unwrap();
+
+            conn.execute("INSERT INTO book (id, title) VALUES (1, 'Dune');", [])
+                .unwrap();
+
+            let client = https_client();
+
+            deliver_webhook(&client, &webhook("hook"), 1000)
+                .await
+                .unwrap();
+
+            assert_eq!(webhook("hook").cursor, 1);
+
+            conn.execute(
+                "VACUUM INTO ?;",
+                [format!("{}/webhook.dev.branch.sql", Env::dbs_path())],
+            )
+            .unwrap();
+
+            connect_db("webhook.dev.branch.sql")
+                .unwrap()
+                .execute_batch(
+                    "
+                INSERT INTO book (id, title) VALUES (2, 'Emma');
+                INSERT INTO book (id, title) VALUES (3, 'Ulysses');
+                ",
+                )
+                .unwrap();
+
+            conn.execute("INSERT INTO book (id, title) VALUES (4, 'Hamlet');", [])
+                .unwrap();
+
+            promote_branch(
+                "webhook.sql",
+                "webhook.dev.branch.sql",
+                "webhook.backup.sql",
+            )
+            .unwrap();
+
+            // NOTE: The changes of the database are kept, so the cursor of the webhook still
+            // points to them and the next changes continue the sequence
+            conn.execute("UPDATE book SET title = 'Persuasion' WHERE id = 2;", [])
+                .unwrap();
+
+            deliver_webhook(&client, &webhook("hook"), 1001)
+                .await
+                .unwrap();
+
+            assert_eq!(webhook("hook").cursor, 3);
+
+            let received = received.lock().unwrap();
+            let changes: Vec<(i64, String)> = received
+                .iter()
+                .map(|(_, body)| {
+                    let body: Value = serde_json::from_str(body).unwrap();
+                    (
+                        body["change"]["id"].as_i64().unwrap(),
+                        body["change"]["operation"].as_str().unwrap().to_string(),
+                    )
+                })
+                .collect();
+
+            assert_eq!(
+                changes,
+                vec![
+                    (1, "INSERT".to_string()),
+                    (2, "INSERT".to_string()),
+                    (3, "UPDATE".to_string()),
+                ]
+            );
+        });
+    });
+}
@@ -1,5 +1,6 @@
 // DBs
 pub const DB_ASSET_NAME: &str = "query_asset.sql";
+pub const DB_AUDIT_NAME: &str = "query_audit.sql";
 pub const DB_CACHE_INVALIDATION_NAME: &str = "query_cache_invalidation.sql";
 pub const DB_CONFIG_NAME: &str = "query_config.sql";
 pub const DB_FUNCTION_NAME: &str = "query_function.sql";
@@ -1,16 +1,30 @@
+pub mod archive;
 pub mod asset;
 pub mod asset_builder;
+pub mod audit;
+pub mod backup;
 pub mod branch;
 pub mod cache;
 pub mod cache_manager;
 pub mod cache_response;
+pub mod changes;
+pub mod database;
 pub mod function;
 pub mod function_builder;
 pub mod migration;
+pub mod openapi;
+pub mod persisted_query;
 pub mod plugin_builder;
+pub mod policy;
 pub mod proxy;
 pub mod query;
+pub mod rest;
+pub mod role;
+pub mod schema;
 pub mod token;
 pub mod user;
 pub mod user_token;
+pub mod user_totp;
 pub mod utils;
+pub mod webhook;
+pub mod well_known;
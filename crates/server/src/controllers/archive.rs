@@ -0,0 +1,166 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Result;
+use hyper::{body::Incoming, Method, Request, Response};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+use crate::{
+    archive::{
+        restore::{list_generations, rebuild_database},
+        storage::{archive_storage, ArchiveStorage},
+    },
+    controllers::{
+        backup::{remove_temp_files, restore_database, run_blocking, temp_path},
+        database::INTERNAL_DBS,
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::{Body, BoxBody},
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            validate_db_exists::validate_db_exists,
+            validate_db_name::validate_db_name,
+            validate_is_admin::validate_is_admin,
+            validate_token::validate_token,
+        },
+    },
+};
+
+#[derive(Deserialize, JsonSchema)]
+struct RestoreArchiveOptions {
+    db_name: String,
+    timestamp: Option<u64>,
+    create: Option<bool>,
+}
+
+#[instrument(err(Debug), skip(req))]
+pub async fn archive(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["archive"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let db_name = match get_query_string(req, "db_name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&db_name)?;
+
+            let storage = get_archive_storage()?;
+
+            match list_generations(storage.as_ref(), &db_name) {
+                Ok(v) => match ok(json!({ "data": v }).to_string()) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["archive", "restore"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let RestoreArchiveOptions {
+                db_name,
+                timestamp,
+                create,
+            } = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&db_name)?;
+
+            if INTERNAL_DBS.contains(&db_name.as_str()) {
+                return Err(bad_request(format!(
+                    "The database {} is internal and can't be restored",
+                    db_name
+                )));
+            }
+
+            // NOTE: A misspelled name doesn't create a new database, unless it is asked for
+            if !create.unwrap_or(false) {
+                // IMPORTANT! don't remove this validation
+                validate_db_exists(&db_name)?;
+            }
+
+            let storage = get_archive_storage()?;
+
+            let path = temp_path(&db_name, "rebuild");
+
+            let result = {
+                let (db_name, path) = (db_name.to_owned(), path.to_owned());
+
+                run_blocking(move || {
+                    match rebuild_database(storage.as_ref(), &db_name, timestamp, Path::new(&path))
+                    {
+                        Ok(_) => restore_database(&db_name, &path),
+                        Err(e) => Err(bad_request(e.to_string())),
+                    }
+                })
+                .await
+            };
+
+            remove_temp_files(&path);
+
+            result?;
+
+            match created() {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let restore = api.schema::<RestoreArchiveOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/archive",
+        Operation::new("Archive", "List the WAL archive generations of a database")
+            .query("db_name", "string", true)
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/archive/restore",
+        Operation::new("Archive", "Restore a database from the WAL archive")
+            .body(restore)
+            .created(),
+    );
+}
+
+fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_is_admin(&token)?;
+
+    Ok(())
+}
+
+fn get_archive_storage() -> Result<Arc<dyn ArchiveStorage>, HttpError> {
+    match archive_storage() {
+        Some(v) => Ok(v),
+        None => Err(bad_request(
+            "The WAL archive isn't enabled, set QUERY_SERVER_WAL_ARCHIVE_PATH".to_string(),
+        )),
+    }
+}
@@ -3,35 +3,40 @@ use hyper::{body::Incoming, Method, Request, Response};
 use query_runtime::sqlite::query_cache_invalidate;
 use regex::Regex;
 use rusqlite::named_params;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_bytes::ByteBuf;
 use tracing::instrument;
 
 use crate::{
-    controllers::utils::{
-        body::{Body, BoxBody},
-        get_token::get_token,
-        http_error::{bad_request, not_implemented, HttpError},
-        responses::ok,
-        validate_is_admin::validate_is_admin,
-        validate_token::validate_token,
-        validate_token_creation::validate_token_creation,
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::{Body, BoxBody},
+            get_token::get_token,
+            http_error::{bad_request, not_implemented, HttpError},
+            responses::ok,
+            validate_role::{validate_role, ROLE_PUSH_ASSETS},
+            validate_token::validate_token,
+            validate_token_creation::validate_token_creation,
+        },
     },
     sqlite::connect_db::{connect_asset_db, connect_cache_invalidation_db},
 };
 
 use super::cache_manager::{clear_response_cache, CacheResponseType};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct AddAssetOptions {
     pub active: bool,
+    #[schemars(with = "Vec<u8>")]
     pub data: ByteBuf,
     pub file_hash: String,
     pub mime_type: String,
     pub name: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct DeleteAssetOptions {
     pub name: String,
 }
@@ -96,6 +101,22 @@ pub async fn asset_builder(
     }
 }
 
+pub fn openapi(api: &mut OpenApi) {
+    let add = api.schema::<AddAssetOptions>();
+    let delete = api.schema::<DeleteAssetOptions>();
+
+    api.add(
+        Method::POST,
+        "/_/asset-builder",
+        Operation::new("Asset Builder", "Upload an asset").body(add),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/asset-builder",
+        Operation::new("Asset Builder", "Delete an asset").body(delete),
+    );
+}
+
 #[instrument(skip(options), fields(mime_type = options.mime_type, asset_name = options.name))]
 fn add_asset(options: AddAssetOptions) -> Result<(), HttpError> {
     let AddAssetOptions {
@@ -165,7 +186,7 @@ fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
     // IMPORTANT! don't remove this validation
     validate_token(&token)?;
     // IMPORTANT! don't remove this validation
-    validate_is_admin(&token)?;
+    validate_role(&token, ROLE_PUSH_ASSETS)?;
 
     Ok(())
 }
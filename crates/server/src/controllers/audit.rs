@@ -0,0 +1,107 @@
+use hyper::{body::Incoming, Method, Request, Response};
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::{
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::BoxBody,
+            get_query_string::{get_query_string, number_query_string},
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::ok,
+            validate_is_admin::validate_is_admin,
+            validate_token::validate_token,
+        },
+    },
+    sqlite::{
+        audit::{read_audit_entries, AuditFilter},
+        connect_db::connect_audit_db,
+    },
+};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[instrument(err(Debug), skip(req))]
+pub async fn audit(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["audit"]) => {
+            let token = get_token(req.headers().to_owned())?;
+
+            // IMPORTANT! don't remove this validation
+            validate_token(&token)?;
+            // IMPORTANT! don't remove this validation
+            validate_is_admin(&token)?;
+
+            let cursor = number_query_string(req, "cursor", 0)?;
+            let limit = number_query_string(req, "limit", DEFAULT_LIMIT)?.clamp(1, MAX_LIMIT);
+
+            let filter = AuditFilter {
+                actor: get_query_string(req, "actor").ok(),
+                action: get_query_string(req, "action").ok(),
+                target: get_query_string(req, "target").ok(),
+                outcome: get_query_string(req, "outcome").ok(),
+                from: optional_number_query_string(req, "from")?,
+                to: optional_number_query_string(req, "to")?,
+            };
+
+            if let Some(outcome) = &filter.outcome {
+                if outcome != "success" && outcome != "failure" {
+                    return Err(bad_request(
+                        "The outcome should be success or failure".to_string(),
+                    ));
+                }
+            }
+
+            let entries = match read_audit_entries(&connect_audit_db()?, &filter, cursor, limit) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }?;
+
+            let next_cursor = entries.last().map(|e| e.id).unwrap_or(cursor);
+
+            let data = json!({
+                "entries": entries.iter().map(|e| e.to_value()).collect::<Vec<Value>>(),
+                "cursor": next_cursor,
+            });
+
+            match ok(json!({ "data": data }).to_string()) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    api.add(
+        Method::GET,
+        "/_/audit",
+        Operation::new("Audit", "Read the audit log after a cursor")
+            .query("actor", "string", false)
+            .query("action", "string", false)
+            .query("target", "string", false)
+            .query("outcome", "string", false)
+            .query("from", "integer", false)
+            .query("to", "integer", false)
+            .query("cursor", "integer", false)
+            .query("limit", "integer", false)
+            .data(json!({ "type": "object" })),
+    );
+}
+
+fn optional_number_query_string(
+    req: &Request<Incoming>,
+    param: &str,
+) -> Result<Option<i64>, HttpError> {
+    match get_query_string(req, param) {
+        Ok(_) => Ok(Some(number_query_string(req, param, 0)?)),
+        Err(_) => Ok(None),
+    }
+}
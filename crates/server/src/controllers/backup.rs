@@ -0,0 +1,534 @@
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Write},
+    path::PathBuf,
+};
+
+use anyhow::Result;
+use flate2::{
+    write::{GzDecoder, GzEncoder},
+    Compression,
+};
+use futures_util::stream;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{
+    body::{Bytes, Frame, Incoming},
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    Method, Request, Response, StatusCode,
+};
+use rusqlite::{backup::Progress, Connection, DatabaseName};
+use serde_json::json;
+use tokio::io::AsyncReadExt;
+use tracing::instrument;
+
+use crate::{
+    backup_schedule::scheduler::{backup_path, backup_status, list_backups, scheduled_db_names},
+    controllers::{
+        database::INTERNAL_DBS,
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::BoxBody,
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            validate_db_exists::validate_db_exists,
+            validate_db_name::validate_db_name,
+            validate_is_admin::validate_is_admin,
+            validate_token::validate_token,
+        },
+    },
+    env::Env,
+    sqlite::connect_db::connect_db,
+};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[instrument(err(Debug), skip(req))]
+pub async fn backup(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["backup"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let db_name = match get_query_string(req, "db_name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+            let gzip = get_query_string(req, "gzip").is_ok_and(|v| v == "true");
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&db_name)?;
+            // IMPORTANT! don't remove this validation
+            validate_db_exists(&db_name)?;
+
+            let snapshot = {
+                let db_name = db_name.to_owned();
+
+                run_blocking(move || snapshot_database(&db_name)).await?
+            };
+
+            let content_type = if gzip {
+                "application/gzip"
+            } else {
+                "application/vnd.sqlite3"
+            };
+            let file_name = if gzip {
+                format!("{}.gz", db_name)
+            } else {
+                db_name
+            };
+
+            match Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, content_type)
+                .header(
+                    CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", file_name),
+                )
+                .body(snapshot_body(snapshot, gzip))
+            {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::GET, ["backups"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let destination = get_backup_path()?;
+
+            let db_names = match get_query_string(req, "db_name") {
+                Ok(v) => {
+                    // IMPORTANT! don't remove this validation
+                    validate_db_name(&v)?;
+
+                    Ok(vec![v])
+                }
+                Err(_) => match scheduled_db_names() {
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+            }?;
+
+            let mut backups = Vec::new();
+
+            for db_name in db_names {
+                match list_backups(&destination, &db_name) {
+                    Ok(v) => backups.extend(v.iter().map(|b| b.to_value())),
+                    Err(e) => return Err(internal_server_error(e.to_string())),
+                }
+            }
+
+            match ok(json!({ "data": backups }).to_string()) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::GET, ["backups", "status"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let destination = get_backup_path()?;
+
+            match backup_status(&destination) {
+                Ok(v) => match ok(json!({ "data": v }).to_string()) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["restore"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let db_name = match get_query_string(req, "db_name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&db_name)?;
+
+            if INTERNAL_DBS.contains(&db_name.as_str()) {
+                return Err(bad_request(format!(
+                    "The database {} is internal and can't be restored",
+                    db_name
+                )));
+            }
+
+            // NOTE: A misspelled name doesn't create a new database, unless it is asked for
+            if !get_query_string(req, "create").is_ok_and(|v| v == "true") {
+                // IMPORTANT! don't remove this validation
+                validate_db_exists(&db_name)?;
+            }
+
+            let path = temp_path(&db_name, "restore");
+
+            let result = match write_upload(req.body_mut(), &path).await {
+                Ok(_) => {
+                    let (db_name, path) = (db_name.to_owned(), path.to_owned());
+
+                    run_blocking(move || restore_database(&db_name, &path)).await
+                }
+                Err(e) => Err(e),
+            };
+
+            remove_temp_files(&path);
+
+            result?;
+
+            match created() {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    api.add(
+        Method::GET,
+        "/_/backup",
+        Operation::new("Backup", "Download a consistent backup of a database")
+            .query("db_name", "string", true)
+            .query("gzip", "boolean", false)
+            .binary_response("application/vnd.sqlite3"),
+    );
+    api.add(
+        Method::GET,
+        "/_/backups",
+        Operation::new("Backup", "List the scheduled backups")
+            .query("db_name", "string", false)
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::GET,
+        "/_/backups/status",
+        Operation::new("Backup", "Show the status of the scheduled backups")
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/restore",
+        Operation::new("Backup", "Replace a database with a backup file")
+            .query("db_name", "string", true)
+            .query("create", "boolean", false)
+            .binary_body("application/octet-stream")
+            .created(),
+    );
+}
+
+fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_is_admin(&token)?;
+
+    Ok(())
+}
+
+fn get_backup_path() -> Result<PathBuf, HttpError> {
+    match backup_path() {
+        Some(v) => Ok(v),
+        None => Err(bad_request(
+            "The scheduled backups aren't enabled, set QUERY_SERVER_BACKUP_PATH".to_string(),
+        )),
+    }
+}
+
+// NOTE: The backup API copies the whole database, so it runs in a blocking thread instead of
+// blocking the async runtime
+pub async fn run_blocking<T, F>(f: F) -> Result<T, HttpError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, HttpError> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(v) => v,
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }
+}
+
+// NOTE: The temporary files are dotfiles in the databases path, so they aren't listed as
+// databases and they are in the same file system as the database.
+pub fn temp_path(db_name: &str, kind: &str) -> String {
+    format!(
+        "{}/.{}.{}.{}",
+        Env::dbs_path(),
+        db_name,
+        uuid::Uuid::new_v4(),
+        kind
+    )
+}
+
+pub fn remove_temp_files(path: &str) {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let _ = fs::remove_file(format!("{}{}", path, suffix));
+    }
+}
+
+// NOTE: The online backup API copies a consistent snapshot of the database without blocking
+// the writers. The snapshot file is removed once it is open, so it is deleted by the system
+// when the response has been streamed.
+fn snapshot_database(db_name: &str) -> Result<File, HttpError> {
+    let path = temp_path(db_name, "backup");
+    let conn = connect_db(db_name)?;
+
+    let snapshot = match conn.backup(DatabaseName::Main, &path, None::<fn(Progress)>) {
+        Ok(_) => match File::open(&path) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(internal_server_error(e.to_string())),
+        },
+        Err(e) => Err(internal_server_error(e.to_string())),
+    };
+
+    remove_temp_files(&path);
+
+    snapshot
+}
+
+struct Snapshot {
+    file: tokio::fs::File,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+}
+
+fn snapshot_body(file: File, gzip: bool) -> BoxBody {
+    let snapshot = Snapshot {
+        file: tokio::fs::File::from_std(file),
+        encoder: gzip.then(|| GzEncoder::new(Vec::new(), Compression::default())),
+    };
+
+    let stream = stream::unfold(Some(snapshot), |snapshot| async move {
+        let mut snapshot = snapshot?;
+        let mut buf = vec![0; CHUNK_SIZE];
+
+        loop {
+            // NOTE: The status is already sent, so an error ends the body and the client
+            // receives an incomplete file
+            let n = match snapshot.file.read(&mut buf).await {
+                Ok(n) => n,
+                Err(e) => {
+                    tracing::error!("Error reading the backup: {e}");
+                    return None;
+                }
+            };
+
+            let chunk = match (n, snapshot.encoder.as_mut()) {
+                (0, _) => match snapshot.encoder.take().map(|encoder| encoder.finish()) {
+                    Some(Ok(v)) => return Some((Ok(Frame::data(Bytes::from(v))), None)),
+                    Some(Err(e)) => {
+                        tracing::error!("Error compressing the backup: {e}");
+                        return None;
+                    }
+                    None => return None,
+                },
+                (n, Some(encoder)) => match encoder.write_all(&buf[..n]) {
+                    Ok(_) => std::mem::take(encoder.get_mut()),
+                    Err(e) => {
+                        tracing::error!("Error compressing the backup: {e}");
+                        return None;
+                    }
+                },
+                (n, None) => buf[..n].to_vec(),
+            };
+
+            // The encoder buffers the input until it has a block to write
+            if !chunk.is_empty() {
+                return Some((
+                    Ok::<_, hyper::Error>(Frame::data(Bytes::from(chunk))),
+                    Some(snapshot),
+                ));
+            }
+        }
+    });
+
+    StreamBody::new(stream).boxed()
+}
+
+enum UploadWriter {
+    Raw(File),
+    Gzip(Box<GzDecoder<File>>),
+}
+
+impl UploadWriter {
+    fn new(path: &str, gzip: bool) -> Result<Self, HttpError> {
+        let file = match File::create(path) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(internal_server_error(e.to_string())),
+        }?;
+
+        if gzip {
+            Ok(Self::Gzip(Box::new(GzDecoder::new(file))))
+        } else {
+            Ok(Self::Raw(file))
+        }
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), HttpError> {
+        let result = match self {
+            Self::Raw(file) => file.write_all(buf),
+            Self::Gzip(decoder) => decoder.write_all(buf),
+        };
+
+        result.map_err(upload_error)
+    }
+
+    fn finish(self) -> Result<(), HttpError> {
+        let result = match self {
+            Self::Raw(file) => file.sync_all(),
+            Self::Gzip(decoder) => decoder.finish().and_then(|file| file.sync_all()),
+        };
+
+        result.map_err(upload_error)
+    }
+}
+
+fn upload_error(e: io::Error) -> HttpError {
+    match e.kind() {
+        ErrorKind::InvalidData | ErrorKind::InvalidInput | ErrorKind::UnexpectedEof => {
+            bad_request(format!("The upload isn't a valid gzip file: {}", e))
+        }
+        _ => internal_server_error(e.to_string()),
+    }
+}
+
+// NOTE: The upload is a database file or a gzip-compressed database file, it is detected
+// by the first bytes of the body.
+async fn write_upload(body: &mut Incoming, path: &str) -> Result<(), HttpError> {
+    let mut writer: Option<UploadWriter> = None;
+    let mut pending: Vec<u8> = Vec::new();
+
+    while let Some(frame) = body.frame().await {
+        let frame = match frame {
+            Ok(v) => Ok(v),
+            Err(e) => Err(internal_server_error(e.to_string())),
+        }?;
+
+        let Ok(data) = frame.into_data() else {
+            continue;
+        };
+
+        match writer.as_mut() {
+            Some(writer) => writer.write_all(&data)?,
+            None => {
+                pending.extend_from_slice(&data);
+
+                if pending.len() >= GZIP_MAGIC.len() {
+                    let mut new_writer = UploadWriter::new(path, pending.starts_with(&GZIP_MAGIC))?;
+                    new_writer.write_all(&pending)?;
+                    writer = Some(new_writer);
+                }
+            }
+        }
+    }
+
+    let writer = match writer {
+        Some(v) => v,
+        None if pending.is_empty() => {
+            return Err(bad_request("The body is empty".to_string()));
+        }
+        None => {
+            let mut writer = UploadWriter::new(path, false)?;
+            writer.write_all(&pending)?;
+            writer
+        }
+    };
+
+    writer.finish()
+}
+
+// NOTE: The database is replaced with the online backup API, in a single transaction, so the
+// open connections see the previous or the restored database and never a partial copy.
+pub fn restore_database(db_name: &str, path: &str) -> Result<(), HttpError> {
+    let upload = match Connection::open(path) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(bad_request(e.to_string())),
+    }?;
+
+    // IMPORTANT! don't remove this validation
+    let integrity: String = match upload.query_row("PRAGMA integrity_check", [], |row| row.get(0)) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(bad_request(e.to_string())),
+    }?;
+
+    if integrity != "ok" {
+        return Err(bad_request(format!(
+            "The database {} didn't pass the integrity check: {}",
+            db_name, integrity
+        )));
+    }
+
+    let mut conn = connect_db(db_name)?;
+
+    let page_size: i64 = conn.pragma_query_value(None, "page_size", |row| row.get(0))?;
+    let upload_page_size: i64 = upload.pragma_query_value(None, "page_size", |row| row.get(0))?;
+
+    // The backup API can't change the page size of a database in WAL mode
+    if page_size != upload_page_size {
+        upload.pragma_update(None, "journal_mode", "DELETE")?;
+        upload.pragma_update(None, "page_size", page_size)?;
+        upload.execute_batch("VACUUM")?;
+    }
+
+    drop(upload);
+
+    match conn.restore(DatabaseName::Main, path, None::<fn(Progress)>) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use crate::db_test;
+
+    use super::*;
+
+    db_test!(test_snapshot_and_restore, TestSnapshotAndRestore, {
+        let conn = connect_db("example.sql").unwrap();
+
+        conn.execute_batch(
+            "
+            CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT);
+            INSERT INTO test (name) VALUES ('a'), ('b');
+            ",
+        )
+        .unwrap();
+
+        let mut snapshot = snapshot_database("example.sql").unwrap();
+        let mut bytes = Vec::new();
+        snapshot.read_to_end(&mut bytes).unwrap();
+        assert!(bytes.starts_with(b"SQLite format 3\0"));
+
+        let path = temp_path("restored.sql", "restore");
+        fs::write(&path, &bytes).unwrap();
+        restore_database("restored.sql", &path).unwrap();
+        remove_temp_files(&path);
+
+        let restored = connect_db("restored.sql").unwrap();
+        let count: i64 = restored
+            .query_row("SELECT COUNT(*) FROM test", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+    });
+
+    db_test!(test_restore_invalid_file, TestRestoreInvalidFile, {
+        let path = temp_path("example.sql", "restore");
+        fs::write(&path, "not a database").unwrap();
+
+        assert!(restore_database("example.sql", &path).is_err());
+
+        remove_temp_files(&path);
+    });
+}
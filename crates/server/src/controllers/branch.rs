@@ -1,36 +1,67 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{fs, path::Path};
 
 use anyhow::Result;
 use hyper::{body::Incoming, Method, Request, Response};
-use rusqlite::limits::Limit;
+use rusqlite::{limits::Limit, OptionalExtension};
+use schemars::JsonSchema;
 use serde::Deserialize;
-use serde_json::json;
+use serde_json::{json, Value};
 use tracing::instrument;
 
 use crate::{
-    controllers::utils::{
-        body::{Body, BoxBody},
-        get_token::get_token,
-        http_error::{bad_request, internal_server_error, not_found, HttpError},
-        responses::{created, ok},
-        validate_is_admin::validate_is_admin,
-        validate_token::validate_token,
+    archive::archiver::now_millis,
+    controllers::{
+        backup::remove_temp_files,
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::{Body, BoxBody},
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            validate_audit_db::validate_audit_db,
+            validate_db_exists::validate_db_exists,
+            validate_db_name::validate_db_name,
+            validate_permission::{validate_permission, ACTION_BRANCH},
+            validate_role::{validate_internal_db_access, validate_role, ROLE_MANAGE_BRANCHES},
+            validate_token::validate_token,
+        },
     },
     env::Env,
-    sqlite::connect_db::connect_db,
+    sqlite::{
+        branch::{
+            list_branch_records, merge_branch, promote_branch, register_branch, unregister_branch,
+            wal_position,
+        },
+        connect_db::{connect_config_db, connect_db},
+        schema::get_schema,
+        schema_diff::{diff_schema, migration_sql},
+    },
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct CreateBranchOptions {
     pub db_name: String,
     pub branch_name: String,
+    pub ttl: Option<i64>, // seconds
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct DeleteBranchOptions {
     pub db_name: String, // has to have branch in the name
 }
 
+#[derive(Deserialize, JsonSchema)]
+struct PromoteBranchOptions {
+    pub branch: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct MergeBranchOptions {
+    pub branch: String,
+    pub tables: Option<Vec<String>>,
+}
+
 #[instrument(err(Debug), skip(req))]
 pub async fn branch(
     req: &mut Request<Incoming>,
@@ -57,7 +88,12 @@ pub async fn branch(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
-            match create_branch(options) {
+            // IMPORTANT! don't remove this validation
+            validate_branch_permission(req, &options.db_name)?;
+
+            let created_by = token_owner(req)?;
+
+            match create_branch(options, created_by) {
                 Ok(_) => match created() {
                     Ok(r) => Ok(r),
                     Err(e) => Err(internal_server_error(e.to_string())),
@@ -74,6 +110,9 @@ pub async fn branch(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            // IMPORTANT! don't remove this validation
+            validate_branch_permission(req, &options.db_name)?;
+
             match delete_branch(options) {
                 Ok(_) => match ok("") {
                     Ok(r) => Ok(r),
@@ -82,24 +121,183 @@ pub async fn branch(
                 Err(e) => Err(e),
             }
         }
+        (&Method::GET, ["branch", "diff"]) => {
+            validate_request(req)?;
+
+            let branch_name = match get_query_string(req, "branch") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&branch_name)?;
+
+            let db_name = branch_source(&branch_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_branch_permission(req, &db_name)?;
+
+            match branch_diff(&db_name, &branch_name) {
+                Ok(v) => match ok(json!({ "data": v }).to_string()) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::POST, ["branch", "promote"]) => {
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+            let options: PromoteBranchOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&options.branch)?;
+
+            let db_name = branch_source(&options.branch)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_branch_permission(req, &db_name)?;
+
+            let created_by = token_owner(req)?;
+
+            match promote(&db_name, &options.branch, created_by) {
+                Ok(v) => match ok(json!({ "data": v }).to_string()) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::POST, ["branch", "merge"]) => {
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+            let options: MergeBranchOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&options.branch)?;
+
+            let db_name = branch_source(&options.branch)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_branch_permission(req, &db_name)?;
+
+            let tables = options.tables.unwrap_or_default();
+
+            match merge_branch(&db_name, &options.branch, &tables) {
+                Ok((diff, sql)) => {
+                    let data = json!({
+                        "db_name": db_name,
+                        "branch": options.branch,
+                        "diff": diff,
+                        "sql": sql,
+                        "tables": tables,
+                    });
+
+                    match ok(json!({ "data": data }).to_string()) {
+                        Ok(r) => Ok(r),
+                        Err(e) => Err(internal_server_error(e.to_string())),
+                    }
+                }
+                Err(e) => Err(bad_request(e.to_string())),
+            }
+        }
         _ => Err(not_found()),
     }
 }
 
+pub fn openapi(api: &mut OpenApi) {
+    let create = api.schema::<CreateBranchOptions>();
+    let delete = api.schema::<DeleteBranchOptions>();
+    let promote = api.schema::<PromoteBranchOptions>();
+    let merge = api.schema::<MergeBranchOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/branch",
+        Operation::new("Branch", "List the branches").data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/branch",
+        Operation::new("Branch", "Create a branch of a database")
+            .body(create)
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/branch",
+        Operation::new("Branch", "Delete a branch").body(delete),
+    );
+    api.add(
+        Method::GET,
+        "/_/branch/diff",
+        Operation::new(
+            "Branch",
+            "Compare the schema of a branch with its database and generate the migration",
+        )
+        .query("branch", "string", true)
+        .data(json!({ "type": "object" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/branch/promote",
+        Operation::new("Branch", "Replace the database of a branch with the branch")
+            .body(promote)
+            .data(json!({ "type": "object" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/branch/merge",
+        Operation::new(
+            "Branch",
+            "Apply the schema changes of a branch, and the rows of some tables, to its database",
+        )
+        .body(merge)
+        .data(json!({ "type": "object" })),
+    );
+}
+
 fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
     let token = get_token(req.headers().to_owned())?;
 
     // IMPORTANT! don't remove this validation
     validate_token(&token)?;
     // IMPORTANT! don't remove this validation
-    validate_is_admin(&token)?;
+    validate_role(&token, ROLE_MANAGE_BRANCHES)?;
 
     Ok(())
 }
 
+fn validate_branch_permission(req: &Request<Incoming>, db_name: &str) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_audit_db(db_name)?;
+
+    // IMPORTANT! don't remove this validation
+    validate_internal_db_access(&token, db_name)?;
+
+    validate_permission(&token, db_name, ACTION_BRANCH, None)
+}
+
+// NOTE: The branches created before the registry, or copied to the databases folder, don't have
+// a record, so their database is inferred from their name
 fn list_branches() -> Result<String, HttpError> {
     let dir = &Env::dbs_path();
 
+    let records = match list_branch_records() {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
     let mut values = Vec::new();
 
     let entries = match fs::read_dir(dir) {
@@ -115,27 +313,46 @@ fn list_branches() -> Result<String, HttpError> {
 
         let path = entry.path();
 
-        let mut hash_map = HashMap::new();
-
-        if path.is_file() && path.to_string_lossy().ends_with(".branch.sql") {
-            hash_map.insert(
-                "branch",
-                path.display().to_string().replace(&format!("{}/", dir), ""),
-            );
+        if !path.is_file() || !path.to_string_lossy().ends_with(".branch.sql") {
+            continue;
         }
 
-        if !hash_map.is_empty() {
-            values.push(hash_map);
+        let branch_name = path.display().to_string().replace(&format!("{}/", dir), "");
+
+        match records.iter().find(|r| r.name == branch_name) {
+            Some(record) => values.push(record.to_value()),
+            None => values.push(json!({
+                "branch": branch_name,
+                "db_name": branch_parent(&branch_name),
+                "source_wal_checkpoint": null,
+                "source_wal_offset": null,
+                "created_by": null,
+                "expires_at": null,
+                "created_at": null,
+            })),
         }
     }
 
+    values.sort_by(|a, b| a["branch"].as_str().cmp(&b["branch"].as_str()));
+
     Ok(json!({ "data": values }).to_string())
 }
 
-fn create_branch(options: CreateBranchOptions) -> Result<(), HttpError> {
+fn create_branch(
+    options: CreateBranchOptions,
+    created_by: Option<String>,
+) -> Result<(), HttpError> {
     let branch_name = &options.branch_name;
     let db_name = &options.db_name;
 
+    let expires_at = match options.ttl {
+        Some(v) if v <= 0 => {
+            return Err(bad_request("The ttl has to be greater than 0".to_string()))
+        }
+        Some(v) => Some((now_millis() / 1000) as i64 + v),
+        None => None,
+    };
+
     let branch_name = &format!(
         "{}.{}.branch.sql",
         db_name.trim_end_matches(".sql").trim_end_matches(".db"),
@@ -150,14 +367,35 @@ fn create_branch(options: CreateBranchOptions) -> Result<(), HttpError> {
         )));
     }
 
+    validate_db_exists(db_name)?;
+
     let conn = connect_db(db_name)?;
 
     conn.set_limit(Limit::SQLITE_LIMIT_ATTACHED, 1)?;
 
-    match conn.execute("VACUUM INTO ?;", [branch_name_path]) {
-        Ok(_) => Ok(()),
+    // NOTE: The position is read before the copy, so the branch has, at least, the transactions
+    // committed up to it
+    let position = match wal_position(db_name) {
+        Ok(v) => Ok(v),
         Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    if let Err(e) = conn.execute("VACUUM INTO ?;", [branch_name_path]) {
+        return Err(internal_server_error(e.to_string()));
     }
+
+    if let Err(e) = register_branch(
+        branch_name,
+        db_name,
+        position,
+        created_by.as_deref(),
+        expires_at,
+    ) {
+        remove_temp_files(branch_name_path);
+        return Err(internal_server_error(e.to_string()));
+    }
+
+    Ok(())
 }
 
 fn delete_branch(options: DeleteBranchOptions) -> Result<(), HttpError> {
@@ -179,8 +417,151 @@ fn delete_branch(options: DeleteBranchOptions) -> Result<(), HttpError> {
         )));
     }
 
-    match fs::remove_file(path) {
+    if let Err(e) = fs::remove_file(path) {
+        return Err(internal_server_error(e.to_string()));
+    }
+
+    match unregister_branch(&db_name) {
         Ok(_) => Ok(()),
         Err(e) => Err(internal_server_error(e.to_string())),
     }
 }
+
+// NOTE: The branch <name>.<branch_name>.branch.sql is created from <name>.sql or <name>.db
+fn branch_source(branch_name: &str) -> Result<String, HttpError> {
+    if branch_name
+        .strip_suffix(".branch.sql")
+        .and_then(|v| v.rsplit_once('.'))
+        .is_none()
+    {
+        return Err(bad_request(format!(
+            r#"The database name "{}" doesn't corresponds with a branch name"#,
+            branch_name
+        )));
+    }
+
+    // IMPORTANT! don't remove this validation
+    validate_db_exists(branch_name)?;
+
+    match branch_parent(branch_name) {
+        Some(v) => Ok(v),
+        None => Err(bad_request(format!(
+            "The database of the branch {} doesn't exist",
+            branch_name
+        ))),
+    }
+}
+
+// NOTE: The registered database of the branch, or the one inferred from its name
+fn branch_parent(branch_name: &str) -> Option<String> {
+    if let Ok(records) = list_branch_records() {
+        if let Some(record) = records.into_iter().find(|r| r.name == branch_name) {
+            return Some(record.db_name);
+        }
+    }
+
+    let (name, _) = branch_name
+        .strip_suffix(".branch.sql")
+        .and_then(|v| v.rsplit_once('.'))?;
+
+    ["sql", "db"]
+        .iter()
+        .map(|extension| format!("{}.{}", name, extension))
+        .find(|db_name| Path::new(&format!("{}/{}", Env::dbs_path(), db_name)).is_file())
+}
+
+// NOTE: The email of the user of a user token, or the name of a token
+fn token_owner(req: &Request<Incoming>) -> Result<Option<String>, HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    let owner = match connect_config_db()?
+        .query_row(
+            "
+            SELECT
+                u.email
+            FROM
+                _config_user_token t
+            JOIN
+                _config_user u
+            ON
+                u.uuid = t.user_uuid
+            WHERE
+                t.token = :token
+            UNION ALL
+            SELECT
+                name
+            FROM
+                _config_token
+            WHERE
+                token = :token;
+            ",
+            &[(":token", &token)],
+            |row| row.get(0),
+        )
+        .optional()
+    {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    Ok(owner)
+}
+
+fn branch_diff(db_name: &str, branch_name: &str) -> Result<Value, HttpError> {
+    let source = match get_schema(&connect_db(db_name)?) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    let branch = match get_schema(&connect_db(branch_name)?) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    let diff = diff_schema(&source, &branch);
+    let sql = migration_sql(&source, &branch, &diff);
+
+    Ok(json!({
+        "db_name": db_name,
+        "branch": branch_name,
+        "diff": diff,
+        "sql": sql,
+    }))
+}
+
+// NOTE: The previous database is kept as the branch <name>.backup_<timestamp>.branch.sql, so it
+// can be compared or promoted back, and the promoted branch is deleted
+fn promote(
+    db_name: &str,
+    branch_name: &str,
+    created_by: Option<String>,
+) -> Result<Value, HttpError> {
+    let backup_name = format!(
+        "{}.backup_{}.branch.sql",
+        db_name.trim_end_matches(".sql").trim_end_matches(".db"),
+        now_millis() / 1000
+    );
+
+    let position = match wal_position(db_name) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    if let Err(e) = promote_branch(db_name, branch_name, &backup_name) {
+        remove_temp_files(&format!("{}/{}", Env::dbs_path(), backup_name));
+        return Err(bad_request(e.to_string()));
+    }
+
+    remove_temp_files(&format!("{}/{}", Env::dbs_path(), branch_name));
+
+    if let Err(e) = unregister_branch(branch_name)
+        .and_then(|_| register_branch(&backup_name, db_name, position, created_by.as_deref(), None))
+    {
+        return Err(internal_server_error(e.to_string()));
+    }
+
+    Ok(json!({
+        "db_name": db_name,
+        "backup": backup_name,
+    }))
+}
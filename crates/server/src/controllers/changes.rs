@@ -0,0 +1,374 @@
+use anyhow::Result;
+use hyper::{body::Incoming, Method, Request, Response};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::{
+    changes::{
+        capture::{captured_tables, disable_capture, enable_capture, read_changes, trim_changes},
+        subscriber::{create_subscription, delete_subscription, list_subscriptions},
+    },
+    constants::DB_CONFIG_NAME,
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::{Body, BoxBody},
+            get_claims::get_claims,
+            get_query_string::{get_query_string, number_query_string},
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            validate_db_exists::validate_db_exists,
+            validate_db_name::validate_db_name,
+            validate_is_admin::{is_admin, validate_is_admin},
+            validate_permission::{validate_permission, ACTION_QUERY},
+            validate_token::validate_token,
+        },
+    },
+    sqlite::{connect_db::connect_db, policies::get_policies},
+};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Deserialize, JsonSchema)]
+struct CaptureOptions {
+    db_name: String,
+    table_name: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct TrimChangesOptions {
+    db_name: String,
+    cursor: i64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct SubscriptionOptions {
+    db_name: String,
+    table_name: Option<String>,
+    function_path: String,
+}
+
+#[instrument(err(Debug), skip(req))]
+pub async fn changes(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["changes"]) => {
+            let token = get_token(req.headers().to_owned())?;
+
+            // IMPORTANT! don't remove this validation
+            validate_token(&token)?;
+
+            let db_name = match get_query_string(req, "db_name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&db_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_permission(&token, &db_name, ACTION_QUERY, Some("SELECT"))?;
+
+            // IMPORTANT! don't remove this validation
+            validate_no_policies(&token, &db_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_exists(&db_name)?;
+
+            let cursor = number_query_string(req, "cursor", 0)?;
+            let limit = number_query_string(req, "limit", DEFAULT_LIMIT)?.clamp(1, MAX_LIMIT);
+            let table_name = get_query_string(req, "table_name").ok();
+
+            let changes =
+                match read_changes(&connect_db(&db_name)?, cursor, limit, table_name.as_deref()) {
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                }?;
+
+            let next_cursor = changes.last().map(|c| c.id).unwrap_or(cursor);
+
+            let data = json!({
+                "changes": changes.iter().map(|c| c.to_value()).collect::<Vec<Value>>(),
+                "cursor": next_cursor,
+            });
+
+            match ok(json!({ "data": data }).to_string()) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::DELETE, ["changes"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: TrimChangesOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&options.db_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_exists(&options.db_name)?;
+
+            match trim_changes(&connect_db(&options.db_name)?, options.cursor) {
+                Ok(v) => match ok(json!({ "data": { "deleted": v } }).to_string()) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::GET, ["changes", "capture"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let db_name = match get_query_string(req, "db_name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&db_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_exists(&db_name)?;
+
+            match captured_tables(&connect_db(&db_name)?) {
+                Ok(v) => match ok(json!({ "data": v }).to_string()) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["changes", "capture"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: CaptureOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_capture_db(&options.db_name)?;
+
+            match enable_capture(&connect_db(&options.db_name)?, &options.table_name) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(bad_request(e.to_string())),
+            }
+        }
+        (&Method::DELETE, ["changes", "capture"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: CaptureOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_capture_db(&options.db_name)?;
+
+            match disable_capture(&connect_db(&options.db_name)?, &options.table_name) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(bad_request(e.to_string())),
+            }
+        }
+        (&Method::GET, ["changes", "subscription"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            match list_subscriptions() {
+                Ok(v) => {
+                    let data = v.iter().map(|s| s.to_value()).collect::<Vec<Value>>();
+
+                    match ok(json!({ "data": data }).to_string()) {
+                        Ok(r) => Ok(r),
+                        Err(e) => Err(internal_server_error(e.to_string())),
+                    }
+                }
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["changes", "subscription"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: SubscriptionOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_capture_db(&options.db_name)?;
+
+            if !options.function_path.starts_with('/') {
+                return Err(bad_request(
+                    "The function path should start with /".to_string(),
+                ));
+            }
+
+            let table_name = options.table_name.unwrap_or("*".to_string());
+
+            match create_subscription(&options.db_name, &table_name, &options.function_path) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::DELETE, ["changes", "subscription"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: SubscriptionOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            let table_name = options.table_name.unwrap_or("*".to_string());
+
+            match delete_subscription(&options.db_name, &table_name, &options.function_path) {
+                Ok(0) => Err(not_found()),
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let capture = api.schema::<CaptureOptions>();
+    let trim = api.schema::<TrimChangesOptions>();
+    let subscription = api.schema::<SubscriptionOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/changes",
+        Operation::new("Changes", "Read the captured changes after a cursor")
+            .query("db_name", "string", true)
+            .query("cursor", "integer", false)
+            .query("limit", "integer", false)
+            .query("table_name", "string", false)
+            .data(json!({ "type": "object" })),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/changes",
+        Operation::new("Changes", "Delete the captured changes up to a cursor").body(trim),
+    );
+    api.add(
+        Method::GET,
+        "/_/changes/capture",
+        Operation::new("Changes", "List the tables with captured changes")
+            .query("db_name", "string", true)
+            .data(json!({ "type": "array", "items": { "type": "string" } })),
+    );
+    api.add(
+        Method::POST,
+        "/_/changes/capture",
+        Operation::new("Changes", "Capture the changes of a table")
+            .body(capture.clone())
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/changes/capture",
+        Operation::new("Changes", "Stop capturing the changes of a table").body(capture),
+    );
+    api.add(
+        Method::GET,
+        "/_/changes/subscription",
+        Operation::new("Changes", "List the function subscriptions")
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/changes/subscription",
+        Operation::new("Changes", "Subscribe a function to the changes")
+            .body(subscription.clone())
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/changes/subscription",
+        Operation::new("Changes", "Delete a function subscription").body(subscription),
+    );
+}
+
+fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_is_admin(&token)?;
+
+    Ok(())
+}
+
+fn validate_capture_db(db_name: &str) -> Result<(), HttpError> {
+    // IMPORTANT! don't remove this validation
+    validate_db_name(db_name)?;
+
+    if db_name == DB_CONFIG_NAME {
+        return Err(bad_request(
+            "The changes of the config database can't be captured".to_string(),
+        ));
+    }
+
+    // IMPORTANT! don't remove this validation
+    validate_db_exists(db_name)
+}
+
+// NOTE: The changes have the whole rows, so the user tokens restricted by row-level policies
+// can't read them
+fn validate_no_policies(token: &str, db_name: &str) -> Result<(), HttpError> {
+    if get_claims(token)?.iss != "user_token" || is_admin(token)? {
+        return Ok(());
+    }
+
+    let policies = match get_policies(db_name) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    if !policies.is_empty() {
+        return Err(bad_request(format!(
+            "The changes of the database {} can't be read with row-level security policies",
+            db_name
+        )));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,338 @@
+use std::{fs, path::Path};
+
+use anyhow::Result;
+use hyper::{body::Incoming, Method, Request, Response};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::{
+    constants::{
+        DB_ASSET_NAME, DB_AUDIT_NAME, DB_CACHE_INVALIDATION_NAME, DB_CONFIG_NAME, DB_FUNCTION_NAME,
+        DB_PLUGIN_NAME,
+    },
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::{Body, BoxBody},
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            validate_db_exists::validate_db_exists,
+            validate_db_name::validate_db_name,
+            validate_is_admin::validate_is_admin,
+            validate_token::validate_token,
+        },
+    },
+    env::Env,
+    sqlite::connect_db::connect_db,
+};
+
+pub const INTERNAL_DBS: [&str; 6] = [
+    DB_ASSET_NAME,
+    DB_AUDIT_NAME,
+    DB_CACHE_INVALIDATION_NAME,
+    DB_CONFIG_NAME,
+    DB_FUNCTION_NAME,
+    DB_PLUGIN_NAME,
+];
+
+#[derive(Deserialize, JsonSchema)]
+struct DatabaseOptions {
+    pub db_name: String,
+}
+
+#[instrument(err(Debug), skip(req))]
+pub async fn database(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["database"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            match list_databases() {
+                Ok(s) => match ok(s) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::GET, ["database", "info"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let db_name = match get_query_string(req, "db_name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match database_info(&db_name) {
+                Ok(s) => match ok(s) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::POST, ["database"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let DatabaseOptions { db_name } = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match create_database(&db_name) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::DELETE, ["database"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let DatabaseOptions { db_name } = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match delete_database(&db_name) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let options = api.schema::<DatabaseOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/database",
+        Operation::new("Database", "List the databases").data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::GET,
+        "/_/database/info",
+        Operation::new("Database", "Show the details of a database")
+            .query("db_name", "string", true)
+            .data(json!({ "type": "object" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/database",
+        Operation::new("Database", "Create a database")
+            .body(options.clone())
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/database",
+        Operation::new("Database", "Delete a database").body(options),
+    );
+}
+
+fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_is_admin(&token)?;
+
+    Ok(())
+}
+
+pub fn is_database_file(file_name: &str) -> bool {
+    !file_name.starts_with('.')
+        && !file_name.ends_with("-wal")
+        && !file_name.ends_with("-shm")
+        && !file_name.ends_with("-journal")
+}
+
+fn file_size(path: &str) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+fn database_value(db_name: &str) -> Value {
+    let path = format!("{}/{}", Env::dbs_path(), db_name);
+
+    json!({
+        "db_name": db_name,
+        "size": file_size(&path),
+        "wal_size": file_size(&format!("{}-wal", path)),
+        "internal": INTERNAL_DBS.contains(&db_name),
+        "branch": db_name.ends_with(".branch.sql"),
+    })
+}
+
+fn list_databases() -> Result<String, HttpError> {
+    let entries = match fs::read_dir(Env::dbs_path()) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    let mut db_names = Vec::new();
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(v) => Ok(v),
+            Err(e) => Err(internal_server_error(e.to_string())),
+        }?;
+
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if entry.path().is_file() && is_database_file(&file_name) {
+            db_names.push(file_name);
+        }
+    }
+
+    db_names.sort();
+
+    let values = db_names
+        .iter()
+        .map(|db_name| database_value(db_name))
+        .collect::<Vec<Value>>();
+
+    Ok(json!({ "data": values }).to_string())
+}
+
+fn database_info(db_name: &str) -> Result<String, HttpError> {
+    // IMPORTANT! don't remove this validation
+    validate_db_name(db_name)?;
+    // IMPORTANT! don't remove this validation
+    validate_db_exists(db_name)?;
+
+    let conn = connect_db(db_name)?;
+
+    let pragma = |name: &str| -> Result<Value, HttpError> {
+        match conn.pragma_query_value(None, name, |row| row.get::<_, rusqlite::types::Value>(0)) {
+            Ok(rusqlite::types::Value::Integer(v)) => Ok(json!(v)),
+            Ok(rusqlite::types::Value::Text(v)) => Ok(json!(v)),
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(internal_server_error(e.to_string())),
+        }
+    };
+
+    let tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        [],
+        |row| row.get(0),
+    )?;
+
+    let mut value = database_value(db_name);
+
+    value["tables"] = json!(tables);
+    value["page_size"] = pragma("page_size")?;
+    value["page_count"] = pragma("page_count")?;
+    value["freelist_count"] = pragma("freelist_count")?;
+    value["journal_mode"] = pragma("journal_mode")?;
+    value["user_version"] = pragma("user_version")?;
+
+    Ok(json!({ "data": [value] }).to_string())
+}
+
+fn create_database(db_name: &str) -> Result<(), HttpError> {
+    // IMPORTANT! don't remove this validation
+    validate_db_name(db_name)?;
+
+    if Path::new(&format!("{}/{}", Env::dbs_path(), db_name)).exists() {
+        return Err(bad_request(format!(
+            "The database {} already exists",
+            db_name
+        )));
+    }
+
+    // NOTE: Opening the connection creates the file with the journal mode of the server
+    connect_db(db_name)?;
+
+    Ok(())
+}
+
+fn delete_database(db_name: &str) -> Result<(), HttpError> {
+    // IMPORTANT! don't remove this validation
+    validate_db_name(db_name)?;
+
+    if INTERNAL_DBS.contains(&db_name) {
+        return Err(bad_request(format!(
+            "The database {} is internal and can't be deleted",
+            db_name
+        )));
+    }
+
+    // IMPORTANT! don't remove this validation
+    validate_db_exists(db_name)?;
+
+    let path = format!("{}/{}", Env::dbs_path(), db_name);
+
+    match fs::remove_file(&path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    for suffix in ["-wal", "-shm"] {
+        let path = format!("{}{}", path, suffix);
+
+        if Path::new(&path).exists() {
+            match fs::remove_file(&path) {
+                Ok(_) => Ok(()),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_test;
+
+    use super::*;
+
+    #[test]
+    fn test_is_database_file() {
+        assert!(is_database_file("example.sql"));
+        assert!(!is_database_file("example.sql-wal"));
+        assert!(!is_database_file("example.sql-shm"));
+        assert!(!is_database_file("example.sql-journal"));
+    }
+
+    db_test!(
+        test_create_and_delete_database,
+        TestCreateAndDeleteDatabase,
+        {
+            assert!(create_database("example.sql").is_ok());
+            assert!(create_database("example.sql").is_err());
+
+            let list = list_databases().unwrap();
+            assert!(list.contains(r#""db_name":"example.sql""#));
+            assert!(list.contains(r#""db_name":"query_config.sql""#));
+
+            let info = database_info("example.sql").unwrap();
+            assert!(info.contains(r#""journal_mode":"wal""#));
+
+            assert!(delete_database(DB_CONFIG_NAME).is_err());
+            assert!(delete_database("example.sql").is_ok());
+            assert!(delete_database("example.sql").is_err());
+        }
+    );
+}
@@ -12,7 +12,7 @@ use hyper::{
     body::Incoming, header::CONTENT_TYPE, http::HeaderName, Request, Response, StatusCode,
 };
 use multer::Multipart;
-use query_runtime::{poll_timers, Runtime};
+use query_runtime::{poll_timers, sqlite::set_branch, Runtime};
 use rbase64::encode;
 use regex::Regex;
 use rquickjs::{async_with, qjs, Function, Module, Object, Promise, Value};
@@ -27,7 +27,10 @@ use crate::{
         cache_response::CacheResponseValue,
         utils::{
             body::{Body, BoxBody},
-            http_error::{internal_server_error, not_found, HttpError},
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            validate_role::{validate_role, ROLE_MANAGE_BRANCHES},
+            validate_token::validate_token,
         },
     },
     env::Env,
@@ -37,6 +40,7 @@ use crate::{
 const HEADER_CACHE_CONTROL: &str = "query-cache-control";
 const HEADER_CACHE_EXPIRES_AT: &str = "query-cache-expires-at";
 const HEADER_CACHE_HIT: &str = "query-cache-hit";
+const HEADER_BRANCH: &str = "query-branch";
 
 #[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -62,6 +66,9 @@ pub async fn function(req: &mut Request<Incoming>) -> Result<Response<BoxBody>,
     let function_cache_key = format!("{}{}", method, path_and_query);
     let function_response_cache_key = format!("res-{}{}", method, path_and_query);
 
+    let branch = preview_branch(req)?;
+    let is_preview = branch.is_some();
+
     if Env::app() == "true" && !path.starts_with("/api") && !path.starts_with("/_/") {
         path.insert_str(0, "/pages");
     }
@@ -81,7 +88,12 @@ pub async fn function(req: &mut Request<Incoming>) -> Result<Response<BoxBody>,
 
         let function_response_cache = cache_response(CacheResponseType::Function);
 
-        if let Some(cached_response) = function_response_cache.get(&function_response_cache_key) {
+        // NOTE: The responses of a branch aren't cached, so they are never served to other
+        // requests
+        if let Some(cached_response) = function_response_cache
+            .get(&function_response_cache_key)
+            .filter(|_| !is_preview)
+        {
             if let Some(response) = check_cached_response(&cached_response) {
                 return Ok(response);
             }
@@ -206,6 +218,13 @@ pub async fn function(req: &mut Request<Incoming>) -> Result<Response<BoxBody>,
         Err(e) => Err(internal_server_error(e.to_string())),
     }?;
     let res = async_with!(ctx => |ctx| {
+        if let Some(branch) = &branch {
+            if let Err(e) = set_branch(&ctx, branch) {
+                tracing::error!("Error: {}", e);
+                return handle_fatal_error();
+            }
+        }
+
         let module = match Module::declare(ctx.clone(), module_name, handle_response) {
             Ok(m) => m,
             Err(e) => {
@@ -366,6 +385,7 @@ pub async fn function(req: &mut Request<Incoming>) -> Result<Response<BoxBody>,
     let status = response.status().as_u16().to_string();
 
     if method == "GET"
+        && !is_preview
         && response.headers().contains_key(HEADER_CACHE_CONTROL)
         && status.starts_with('2')
     {
@@ -383,6 +403,62 @@ pub async fn function(req: &mut Request<Incoming>) -> Result<Response<BoxBody>,
     Ok(response)
 }
 
+// NOTE: The branch of a preview request comes from the query-branch header, or from the subdomain
+// of the preview domain, e.g. dev.preview.example.com. Only the admins can preview a branch.
+fn preview_branch(req: &Request<Incoming>) -> Result<Option<String>, HttpError> {
+    let branch = match req.headers().get(HEADER_BRANCH) {
+        Some(v) => match v.to_str() {
+            Ok(v) => Some(v.to_string()),
+            Err(e) => return Err(bad_request(e.to_string())),
+        },
+        None => req
+            .headers()
+            .get(HOST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| preview_subdomain(h, &Env::branch_preview_domain())),
+    };
+
+    let branch = match branch {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    if branch.is_empty()
+        || !branch
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(bad_request(format!(
+            r#"The branch name "{}" is not valid"#,
+            branch
+        )));
+    }
+
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_role(&token, ROLE_MANAGE_BRANCHES)?;
+
+    Ok(Some(branch))
+}
+
+fn preview_subdomain(host: &str, domain: &str) -> Option<String> {
+    if domain.is_empty() {
+        return None;
+    }
+
+    let host = host.split(':').next()?;
+    let subdomain = host.strip_suffix(domain)?.strip_suffix('.')?;
+
+    if subdomain.is_empty() || subdomain.contains('.') {
+        return None;
+    }
+
+    Some(subdomain.to_string())
+}
+
 fn check_cached_response(cached_response: &CacheResponseValue) -> Option<Response<BoxBody>> {
     if !cached_response
         .headers
@@ -565,3 +641,26 @@ async fn process_multipart(body: &mut Incoming, boundary: String) -> anyhow::Res
 
     Ok(reconstructed)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preview_subdomain() {
+        let domain = "preview.example.com";
+
+        assert_eq!(
+            preview_subdomain("dev.preview.example.com", domain),
+            Some("dev".to_string())
+        );
+        assert_eq!(
+            preview_subdomain("dev.preview.example.com:3000", domain),
+            Some("dev".to_string())
+        );
+        assert_eq!(preview_subdomain("preview.example.com", domain), None);
+        assert_eq!(preview_subdomain("a.dev.preview.example.com", domain), None);
+        assert_eq!(preview_subdomain("devpreview.example.com", domain), None);
+        assert_eq!(preview_subdomain("dev.preview.example.com", ""), None);
+    }
+}
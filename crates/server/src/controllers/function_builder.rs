@@ -2,6 +2,7 @@ use anyhow::Result;
 use hyper::{body::Incoming, Method, Request, Response};
 use query_runtime::sqlite::query_cache_invalidate;
 use rusqlite::named_params;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_bytes::ByteBuf;
 use tracing::instrument;
@@ -9,12 +10,13 @@ use tracing::instrument;
 use crate::{
     controllers::{
         cache_manager::{clear_cache, clear_response_cache, CacheResponseType, CacheType},
+        openapi::{OpenApi, Operation},
         utils::{
             body::{Body, BoxBody},
             get_token::get_token,
             http_error::{bad_request, not_implemented, HttpError},
             responses::ok,
-            validate_is_admin::validate_is_admin,
+            validate_role::{validate_role, ROLE_DEPLOY_FUNCTIONS},
             validate_token::validate_token,
             validate_token_creation::validate_token_creation,
         },
@@ -22,14 +24,15 @@ use crate::{
     sqlite::connect_db::{connect_cache_invalidation_db, connect_function_db},
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct AddFunctionOptions<'a> {
+    #[schemars(with = "Vec<u8>")]
     pub function: ByteBuf,
     pub method: &'a str,
     pub path: &'a str,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct DeleteFunctionOptions<'a> {
     pub method: &'a str,
     pub path: &'a str,
@@ -99,6 +102,22 @@ pub async fn function_builder(
     }
 }
 
+pub fn openapi(api: &mut OpenApi) {
+    let add = api.schema::<AddFunctionOptions>();
+    let delete = api.schema::<DeleteFunctionOptions>();
+
+    api.add(
+        Method::POST,
+        "/_/function-builder",
+        Operation::new("Function Builder", "Add or update a function").body(add),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/function-builder",
+        Operation::new("Function Builder", "Delete a function").body(delete),
+    );
+}
+
 #[instrument(skip(options), fields(path = options.path, method = options.method))]
 fn add_function(options: AddFunctionOptions) -> Result<(), HttpError> {
     let connect = connect_function_db()?;
@@ -165,7 +184,7 @@ fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
     // IMPORTANT! don't remove this validation
     validate_token(&token)?;
     // IMPORTANT! don't remove this validation
-    validate_is_admin(&token)?;
+    validate_role(&token, ROLE_DEPLOY_FUNCTIONS)?;
 
     Ok(())
 }
@@ -1,26 +1,47 @@
 use anyhow::Result;
 use hyper::{body::Incoming, Method, Request, Response};
+use schemars::JsonSchema;
 use serde::Deserialize;
+use serde_json::{json, Value};
 use tracing::instrument;
 
 use crate::{
-    controllers::utils::{
-        body::{Body, BoxBody},
-        get_token::get_token,
-        http_error::{bad_request, internal_server_error, not_found, unauthorized, HttpError},
-        responses::created,
-        validate_is_admin::validate_is_admin,
-        validate_token::validate_token,
-        validate_user_creation::validate_user_creation,
-        validate_write::validate_write,
+    controllers::{
+        backup::{remove_temp_files, temp_path},
+        openapi::{OpenApi, Operation},
+        utils::{
+            audit::set_audit_target,
+            body::{Body, BoxBody},
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, unauthorized, HttpError},
+            responses::{created, ok},
+            validate_audit_db::validate_audit_db,
+            validate_db_exists::validate_db_exists,
+            validate_permission::{validate_permission, ACTION_MIGRATION},
+            validate_role::{validate_internal_db_access, validate_role, ROLE_RUN_MIGRATIONS},
+            validate_token::validate_token,
+            validate_user_creation::validate_user_creation,
+            validate_write::validate_write,
+        },
+    },
+    sqlite::{
+        connect_db::{connect_db, connection},
+        migrations::{
+            applied_migrations, apply_migration, dry_run_migration, rollback_migration, DryRun,
+            MigrationStatus,
+        },
     },
-    sqlite::connect_db::connect_db,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct MigrationOptions {
     pub db_name: String,
     pub query: String,
+    pub version: Option<String>,
+    pub name: Option<String>,
+    pub rollback: Option<bool>,
+    pub dry_run: Option<bool>,
 }
 
 #[instrument(err(Debug), skip(req))]
@@ -36,7 +57,7 @@ pub async fn migration(
             let token = get_token(req.headers().to_owned())?;
 
             // IMPORTANT! don't remove this validation
-            validate_is_admin(&token)?;
+            validate_role(&token, ROLE_RUN_MIGRATIONS)?;
 
             // IMPORTANT! don't remove this validation
             validate_token(&token)?;
@@ -49,26 +70,133 @@ pub async fn migration(
 
             let body = Body::to_string(req.body_mut()).await?;
 
-            let MigrationOptions { db_name, query } = match serde_json::from_str(&body) {
+            let options: MigrationOptions = match serde_json::from_str(&body) {
                 Ok(v) => Ok(v),
                 Err(e) => Err(internal_server_error(e.to_string())),
             }?;
 
-            match migration_controller(&db_name, &query) {
-                Ok(_) => match created() {
+            set_audit_target(&options.db_name);
+
+            // IMPORTANT! don't remove this validation
+            validate_audit_db(&options.db_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_internal_db_access(&token, &options.db_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_permission(&token, &options.db_name, ACTION_MIGRATION, None)?;
+
+            if options.dry_run.unwrap_or(false) {
+                return match dry_run_controller(&options) {
+                    Ok(v) => match ok(json!({ "data": v.to_value() }).to_string()) {
+                        Ok(r) => Ok(r),
+                        Err(e) => Err(internal_server_error(e.to_string())),
+                    },
+                    Err(e) => Err(e),
+                };
+            }
+
+            match migration_controller(&options) {
+                Ok(MigrationStatus::Applied | MigrationStatus::RolledBack) => match created() {
                     Ok(r) => Ok(r),
                     Err(e) => Err(internal_server_error(e.to_string())),
                 },
+                Ok(MigrationStatus::Skipped) => {
+                    match ok(json!({ "data": { "skipped": true } }).to_string()) {
+                        Ok(r) => Ok(r),
+                        Err(e) => Err(internal_server_error(e.to_string())),
+                    }
+                }
                 Err(e) => Err(e),
             }
         }
+        (&Method::GET, ["migration"]) => {
+            let token = get_token(req.headers().to_owned())?;
+
+            // IMPORTANT! don't remove this validation
+            validate_role(&token, ROLE_RUN_MIGRATIONS)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_token(&token)?;
+
+            let db_name = match get_query_string(req, "db_name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_internal_db_access(&token, &db_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_permission(&token, &db_name, ACTION_MIGRATION, None)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_exists(&db_name)?;
+
+            match applied_migrations(&connect_db(&db_name)?) {
+                Ok(v) => {
+                    let data = v.iter().map(|m| m.to_value()).collect::<Vec<Value>>();
+
+                    match ok(json!({ "data": data }).to_string()) {
+                        Ok(r) => Ok(r),
+                        Err(e) => Err(internal_server_error(e.to_string())),
+                    }
+                }
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
         _ => Err(not_found()),
     }
 }
 
-#[instrument(skip(query))]
-fn migration_controller(db_name: &str, query: &str) -> Result<(), HttpError> {
-    let conn = connect_db(db_name)?;
+pub fn openapi(api: &mut OpenApi) {
+    let options = api.schema::<MigrationOptions>();
+
+    api.add(
+        Method::POST,
+        "/_/migration",
+        Operation::new("Migration", "Run a migration in a database")
+            .body(options)
+            .created(),
+    );
+    api.add(
+        Method::GET,
+        "/_/migration",
+        Operation::new("Migration", "List the migrations applied to a database")
+            .query("db_name", "string", true)
+            .data(json!({ "type": "array" })),
+    );
+}
+
+#[instrument(skip(options), fields(db_name = options.db_name, version = options.version))]
+fn migration_controller(options: &MigrationOptions) -> Result<MigrationStatus, HttpError> {
+    let mut conn = connect_db(&options.db_name)?;
+    let query = &options.query;
+
+    if let Some(version) = &options.version {
+        if version.is_empty() {
+            return Err(bad_request("The version can't be empty".to_string()));
+        }
+
+        let result = if options.rollback.unwrap_or(false) {
+            rollback_migration(&mut conn, version, query)
+        } else {
+            let name = options.name.clone().unwrap_or_default();
+
+            apply_migration(&mut conn, version, &name, query)
+        };
+
+        return match result {
+            Ok(v) => Ok(v),
+            Err(e) => Err(bad_request(e.to_string())),
+        };
+    }
+
+    if options.rollback.unwrap_or(false) {
+        return Err(bad_request(
+            "The version of the migration to roll back is required".to_string(),
+        ));
+    }
 
     match conn.execute_batch(
         format!(
@@ -77,11 +205,46 @@ fn migration_controller(db_name: &str, query: &str) -> Result<(), HttpError> {
                 {}
                 COMMIT;
             "#,
-            &query
+            query
         )
         .as_str(),
     ) {
-        Ok(_) => Ok(()),
+        Ok(_) => Ok(MigrationStatus::Applied),
         Err(e) => Err(bad_request(e.to_string())),
     }
 }
+
+// NOTE: The migration runs in a copy of the database, made with VACUUM INTO as the branches, that
+// is removed after the report, so the database isn't changed
+#[instrument(skip(options), fields(db_name = options.db_name, version = options.version))]
+fn dry_run_controller(options: &MigrationOptions) -> Result<DryRun, HttpError> {
+    // IMPORTANT! don't remove this validation
+    validate_db_exists(&options.db_name)?;
+
+    let path = temp_path(&options.db_name, "dry-run");
+
+    if let Err(e) = connect_db(&options.db_name)?.execute("VACUUM INTO ?;", [&path]) {
+        remove_temp_files(&path);
+        return Err(internal_server_error(e.to_string()));
+    }
+
+    let file_name = path.rsplit('/').next().unwrap_or_default();
+
+    let result = match connection(file_name) {
+        Ok(mut conn) => dry_run_migration(
+            &mut conn,
+            options.version.as_deref(),
+            options.name.as_deref().unwrap_or_default(),
+            &options.query,
+            options.rollback.unwrap_or(false),
+        ),
+        Err(e) => Err(e),
+    };
+
+    remove_temp_files(&path);
+
+    match result {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }
+}
@@ -0,0 +1,339 @@
+use anyhow::Result;
+use hyper::{body::Incoming, Method, Request, Response};
+use schemars::{generate::SchemaSettings, JsonSchema, SchemaGenerator};
+use serde_json::{json, Map, Value};
+use tracing::instrument;
+
+use crate::{
+    controllers::{
+        archive, asset_builder, audit, backup, branch, changes, database, function_builder,
+        migration, persisted_query, plugin_builder, policy, query, rest, role, schema, token, user,
+        user_token, user_totp,
+        utils::{
+            body::BoxBody,
+            get_token::get_token,
+            http_error::{internal_server_error, not_found, HttpError},
+            responses::ok,
+            validate_token::validate_token,
+        },
+        webhook, well_known,
+    },
+    env::Env,
+};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+#[instrument(err(Debug), skip(req))]
+pub async fn openapi(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["openapi.json"]) => {
+            let token = get_token(req.headers().to_owned())?;
+
+            // IMPORTANT! don't remove this validation
+            validate_token(&token)?;
+
+            match ok(document()?.to_string()) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+// NOTE: Each controller adds its operations, with the schemas of its request structs, and the
+// REST resources are added from the schema of the databases that expose them.
+pub fn document() -> Result<Value, HttpError> {
+    let mut api = OpenApi::new();
+
+    archive::openapi(&mut api);
+    asset_builder::openapi(&mut api);
+    audit::openapi(&mut api);
+    backup::openapi(&mut api);
+    branch::openapi(&mut api);
+    changes::openapi(&mut api);
+    database::openapi(&mut api);
+    function_builder::openapi(&mut api);
+    migration::openapi(&mut api);
+    persisted_query::openapi(&mut api);
+    plugin_builder::openapi(&mut api);
+    policy::openapi(&mut api);
+    query::openapi(&mut api);
+    role::openapi(&mut api);
+    schema::openapi(&mut api);
+    token::openapi(&mut api);
+    user::openapi(&mut api);
+    user_token::openapi(&mut api);
+    user_totp::openapi(&mut api);
+    webhook::openapi(&mut api);
+    well_known::openapi(&mut api);
+
+    if !Env::rest_dbs().is_empty() {
+        rest::openapi(&mut api)?;
+    }
+
+    Ok(api.into_value())
+}
+
+pub struct OpenApi {
+    generator: SchemaGenerator,
+    paths: Map<String, Value>,
+    schemas: Map<String, Value>,
+}
+
+impl OpenApi {
+    pub fn new() -> Self {
+        let generator = SchemaSettings::draft2020_12()
+            .with(|s| s.definitions_path = "/components/schemas".into())
+            .into_generator();
+
+        Self {
+            generator,
+            paths: Map::new(),
+            schemas: Map::new(),
+        }
+    }
+
+    // NOTE: Returns a reference to the schema of the type, added to the components
+    pub fn schema<T: JsonSchema>(&mut self) -> Value {
+        self.generator.subschema_for::<T>().to_value()
+    }
+
+    pub fn add_schema(&mut self, name: &str, schema: Value) -> Value {
+        self.schemas.insert(name.to_string(), schema);
+
+        json!({ "$ref": format!("#/components/schemas/{}", name) })
+    }
+
+    pub fn add(&mut self, method: Method, path: &str, operation: Operation) {
+        let item = self
+            .paths
+            .entry(path.to_string())
+            .or_insert_with(|| json!({}));
+
+        item[method.as_str().to_lowercase()] = operation.into_value();
+    }
+
+    pub fn into_value(mut self) -> Value {
+        let mut schemas = self.generator.take_definitions(true);
+        schemas.append(&mut self.schemas);
+
+        json!({
+            "openapi": "3.1.0",
+            "info": {
+                "title": "Query Server",
+                "version": VERSION,
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "securitySchemes": {
+                    "bearer": {
+                        "type": "http",
+                        "scheme": "bearer",
+                        "bearerFormat": "JWT",
+                    },
+                },
+            },
+            "security": [{ "bearer": [] }],
+        })
+    }
+}
+
+impl Default for OpenApi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Operation {
+    tag: String,
+    summary: String,
+    parameters: Vec<Value>,
+    request_body: Option<Value>,
+    response_status: &'static str,
+    response_body: Option<Value>,
+    public: bool,
+}
+
+impl Operation {
+    pub fn new(tag: &str, summary: &str) -> Self {
+        Self {
+            tag: tag.to_string(),
+            summary: summary.to_string(),
+            parameters: vec![],
+            request_body: None,
+            response_status: "200",
+            response_body: None,
+            public: false,
+        }
+    }
+
+    pub fn query(mut self, name: &str, schema_type: &str, required: bool) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "query",
+            "required": required,
+            "schema": { "type": schema_type },
+        }));
+        self
+    }
+
+    pub fn path(mut self, name: &str) -> Self {
+        self.parameters.push(json!({
+            "name": name,
+            "in": "path",
+            "required": true,
+            "schema": { "type": "string" },
+        }));
+        self
+    }
+
+    pub fn body(mut self, schema: Value) -> Self {
+        self.request_body = Some(json!({
+            "required": true,
+            "content": { "application/json": { "schema": schema } },
+        }));
+        self
+    }
+
+    pub fn binary_body(mut self, content_type: &str) -> Self {
+        self.request_body = Some(json!({
+            "required": true,
+            "content": { content_type: { "schema": { "type": "string", "format": "binary" } } },
+        }));
+        self
+    }
+
+    // NOTE: The responses wrap the data in a "data" property
+    pub fn data(mut self, schema: Value) -> Self {
+        self.response_body = Some(json!({
+            "application/json": {
+                "schema": {
+                    "type": "object",
+                    "properties": { "data": schema },
+                },
+            },
+        }));
+        self
+    }
+
+    // NOTE: A response of a standard format, e.g. the JWKS, isn't wrapped in a "data" property
+    pub fn json_response(mut self, schema: Value) -> Self {
+        self.response_body = Some(json!({
+            "application/json": { "schema": schema },
+        }));
+        self
+    }
+
+    pub fn binary_response(mut self, content_type: &str) -> Self {
+        self.response_body = Some(json!({
+            content_type: { "schema": { "type": "string", "format": "binary" } },
+        }));
+        self
+    }
+
+    pub fn created(mut self) -> Self {
+        self.response_status = "201";
+        self
+    }
+
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    fn into_value(self) -> Value {
+        let mut success = json!({ "description": "Success" });
+
+        if let Some(content) = self.response_body {
+            success["content"] = content;
+        }
+
+        let mut operation = json!({
+            "tags": [self.tag],
+            "summary": self.summary,
+            "responses": {
+                self.response_status: success,
+                "400": { "description": "Bad Request" },
+                "401": { "description": "Unauthorized" },
+            },
+        });
+
+        if !self.parameters.is_empty() {
+            operation["parameters"] = Value::Array(self.parameters);
+        }
+
+        if let Some(request_body) = self.request_body {
+            operation["requestBody"] = request_body;
+        }
+
+        if self.public {
+            operation["security"] = json!([]);
+        }
+
+        operation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct ExampleOptions {
+        db_name: String,
+        limit: Option<u32>,
+    }
+
+    #[test]
+    fn test_open_api() {
+        let mut api = OpenApi::new();
+
+        let body = api.schema::<ExampleOptions>();
+        api.add(
+            Method::POST,
+            "/_/example",
+            Operation::new("Example", "Create an example")
+                .query("db_name", "string", true)
+                .body(body)
+                .created(),
+        );
+        api.add(
+            Method::GET,
+            "/_/example",
+            Operation::new("Example", "List the examples")
+                .data(json!({ "type": "array" }))
+                .public(),
+        );
+
+        let value = api.into_value();
+
+        assert_eq!(value["openapi"], "3.1.0");
+
+        let post = &value["paths"]["/_/example"]["post"];
+        assert_eq!(
+            post["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/ExampleOptions"
+        );
+        assert_eq!(post["parameters"][0]["name"], "db_name");
+        assert!(post["responses"]["201"].is_object());
+
+        let get = &value["paths"]["/_/example"]["get"];
+        assert_eq!(get["security"], json!([]));
+        assert_eq!(
+            get["responses"]["200"]["content"]["application/json"]["schema"]["properties"]["data"],
+            json!({ "type": "array" })
+        );
+
+        let schema = &value["components"]["schemas"]["ExampleOptions"];
+        assert_eq!(schema["required"], json!(["db_name"]));
+        assert!(schema["properties"]["limit"].is_object());
+    }
+}
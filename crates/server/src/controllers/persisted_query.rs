@@ -0,0 +1,377 @@
+use anyhow::{anyhow, Result};
+use hyper::{body::Incoming, Method, Request, Response};
+use rusqlite::named_params;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::{
+    constants::{DB_AUDIT_NAME, DB_CONFIG_NAME},
+    controllers::{
+        openapi::{OpenApi, Operation},
+        query::{apply_token_policies, query_controller, read_operation},
+        utils::{
+            audit::set_audit_target,
+            body::{Body, BoxBody},
+            get_token::get_token,
+            hex::sha256_hex,
+            http_error::{bad_request, internal_server_error, not_found, unauthorized, HttpError},
+            responses::{created, ok},
+            statement_to_vec::statement_to_vec,
+            validate_db_exists::validate_db_exists,
+            validate_is_admin::{is_admin, validate_is_admin},
+            validate_permission::{statement_operation, validate_permission, ACTION_QUERY},
+            validate_token::validate_token,
+            validate_write::validate_write,
+        },
+    },
+    sqlite::connect_db::{connect_config_db, connect_db},
+};
+
+#[derive(Deserialize, JsonSchema)]
+struct CreatePersistedQueryOptions {
+    name: String,
+    db_name: String,
+    query: String,
+    permission: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeletePersistedQueryOptions {
+    name: String,
+}
+
+#[derive(Default, Deserialize, JsonSchema)]
+struct RunPersistedQueryOptions {
+    params: Option<Value>,
+}
+
+struct PersistedQuery {
+    db_name: String,
+    query: String,
+    permission: String,
+}
+
+#[instrument(err(Debug), skip(req))]
+pub async fn persisted_query(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["query", "persisted"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            match list_persisted_queries() {
+                Ok(s) => match ok(s) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["query", "persisted"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: CreatePersistedQueryOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match create_persisted_query(options) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::DELETE, ["query", "persisted"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let DeletePersistedQueryOptions { name } = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match delete_persisted_query(&name) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::POST, ["query", "persisted", name]) => {
+            let token = get_token(req.headers().to_owned())?;
+
+            // IMPORTANT! don't remove this validation
+            validate_token(&token)?;
+
+            let PersistedQuery {
+                db_name,
+                query,
+                permission,
+            } = get_persisted_query(name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_exists(&db_name)?;
+
+            let conn = connect_db(&db_name)?;
+            let operation = statement_operation(&conn, &query);
+
+            if !matches!(operation, Ok(Some("SELECT"))) {
+                set_audit_target(&db_name);
+            }
+
+            // IMPORTANT! don't remove this validation
+            validate_persisted_query_permission(&token, &permission)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_permission(&token, &db_name, ACTION_QUERY, read_operation(&operation))?;
+
+            if let Err(e) = operation {
+                return Err(bad_request(e.to_string()));
+            }
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let RunPersistedQueryOptions { params } = if body.trim().is_empty() {
+                RunPersistedQueryOptions::default()
+            } else {
+                match serde_json::from_str(&body) {
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(bad_request(e.to_string())),
+                }?
+            };
+
+            // IMPORTANT! don't remove this validation
+            apply_token_policies(&conn, &token, &db_name, &query)?;
+
+            match query_controller(&conn, &query, params) {
+                Ok(s) => match ok(s) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let create = api.schema::<CreatePersistedQueryOptions>();
+    let delete = api.schema::<DeletePersistedQueryOptions>();
+    let run = api.schema::<RunPersistedQueryOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/query/persisted",
+        Operation::new("Persisted Query", "List the persisted queries")
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/query/persisted",
+        Operation::new("Persisted Query", "Create or update a persisted query")
+            .body(create)
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/query/persisted",
+        Operation::new("Persisted Query", "Delete a persisted query").body(delete),
+    );
+    api.add(
+        Method::POST,
+        "/_/query/persisted/{name}",
+        Operation::new("Persisted Query", "Run a persisted query")
+            .path("name")
+            .body(run)
+            .data(json!({ "type": "array" })),
+    );
+}
+
+fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_is_admin(&token)?;
+
+    Ok(())
+}
+
+fn validate_persisted_query_permission(token: &str, permission: &str) -> Result<(), HttpError> {
+    let is_allowed = match permission {
+        "read" => true,
+        "write" => validate_write(token)?,
+        _ => is_admin(token)?,
+    };
+
+    if !is_allowed {
+        tracing::error!(
+            permission,
+            "Token without permission tried to run a persisted query"
+        );
+        return Err(unauthorized());
+    }
+
+    Ok(())
+}
+
+fn get_persisted_query(name: &str) -> Result<PersistedQuery, HttpError> {
+    let conn = connect_config_db()?;
+
+    match conn.query_row(
+        "
+        SELECT
+            db_name,
+            query,
+            permission
+        FROM
+            _config_persisted_query
+        WHERE
+            name = :name
+        OR
+            hash = :name
+        ",
+        named_params! { ":name": name },
+        |row| {
+            Ok(PersistedQuery {
+                db_name: row.get(0)?,
+                query: row.get(1)?,
+                permission: row.get(2)?,
+            })
+        },
+    ) {
+        Ok(v) => Ok(v),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(not_found()),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }
+}
+
+fn list_persisted_queries() -> Result<String> {
+    let conn = connect_config_db()?;
+
+    let stmt = conn.prepare(
+        "
+        SELECT
+            name,
+            hash,
+            db_name,
+            query,
+            permission,
+            created_at,
+            updated_at
+        FROM
+            _config_persisted_query
+        ORDER BY
+            name
+        ",
+    )?;
+
+    match statement_to_vec(stmt, []) {
+        Ok(v) => Ok(json!({ "data": v }).to_string()),
+        Err(e) => {
+            if let rusqlite::Error::QueryReturnedNoRows = e {
+                Ok(json!({ "data": [] }).to_string())
+            } else {
+                Err(anyhow!(e))
+            }
+        }
+    }
+}
+
+fn create_persisted_query(options: CreatePersistedQueryOptions) -> Result<(), HttpError> {
+    let permission = options.permission.unwrap_or("read".to_string());
+
+    // IMPORTANT! don't remove this validation
+    validate_persisted_query(&options.db_name, &options.query, &permission)?;
+
+    let conn = connect_config_db()?;
+
+    match conn.execute(
+        "
+        INSERT INTO
+            _config_persisted_query(
+                name,
+                hash,
+                db_name,
+                query,
+                permission
+            )
+        VALUES (
+            :name,
+            :hash,
+            :db_name,
+            :query,
+            :permission
+        )
+        ON CONFLICT (name) DO UPDATE SET
+            hash = excluded.hash,
+            db_name = excluded.db_name,
+            query = excluded.query,
+            permission = excluded.permission
+        ",
+        named_params! {
+            ":name": options.name,
+            ":hash": sha256_hex(&options.query),
+            ":db_name": options.db_name,
+            ":query": options.query,
+            ":permission": permission,
+        },
+    ) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(bad_request(e.to_string())),
+    }
+}
+
+fn delete_persisted_query(name: &str) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    conn.execute("DELETE FROM _config_persisted_query WHERE name = ?", [name])?;
+
+    Ok(())
+}
+
+// NOTE: The query is compiled against the database, so a persisted query with a syntax error
+// or a wrong table is rejected when it is registered.
+fn validate_persisted_query(db_name: &str, query: &str, permission: &str) -> Result<(), HttpError> {
+    if db_name == DB_CONFIG_NAME {
+        return Err(bad_request(
+            "Can't persist queries of the config database".to_string(),
+        ));
+    }
+
+    if db_name == DB_AUDIT_NAME {
+        return Err(bad_request(
+            "Can't persist queries of the audit database".to_string(),
+        ));
+    }
+
+    // IMPORTANT! don't remove this validation
+    validate_db_exists(db_name)?;
+
+    let conn = connect_db(db_name)?;
+
+    conn.prepare(query)
+        .map_err(|e| bad_request(e.to_string()))?;
+
+    if permission == "read" && !matches!(statement_operation(&conn, query), Ok(Some("SELECT"))) {
+        return Err(bad_request(
+            "A persisted query that isn't a SELECT needs the write or admin permission".to_string(),
+        ));
+    }
+
+    Ok(())
+}
@@ -1,31 +1,36 @@
 use anyhow::Result;
 use hyper::{body::Incoming, Method, Request, Response};
 use rusqlite::named_params;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_bytes::ByteBuf;
 use tracing::instrument;
 
 use crate::{
-    controllers::utils::{
-        body::{Body, BoxBody},
-        get_token::get_token,
-        http_error::{bad_request, not_implemented, HttpError},
-        responses::ok,
-        validate_is_admin::validate_is_admin,
-        validate_token::validate_token,
-        validate_token_creation::validate_token_creation,
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::{Body, BoxBody},
+            get_token::get_token,
+            http_error::{bad_request, not_implemented, HttpError},
+            responses::ok,
+            validate_role::{validate_role, ROLE_DEPLOY_FUNCTIONS},
+            validate_token::validate_token,
+            validate_token_creation::validate_token_creation,
+        },
     },
     sqlite::connect_db::connect_plugin_db,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct AddPluginOptions {
+    #[schemars(with = "Vec<u8>")]
     pub data: ByteBuf,
     pub name: String,
     pub sha256: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct DeletePluginOptions {
     pub name: String,
 }
@@ -72,6 +77,22 @@ pub async fn plugin_builder(
     }
 }
 
+pub fn openapi(api: &mut OpenApi) {
+    let add = api.schema::<AddPluginOptions>();
+    let delete = api.schema::<DeletePluginOptions>();
+
+    api.add(
+        Method::POST,
+        "/_/plugin-builder",
+        Operation::new("Plugin Builder", "Add or update a plugin").body(add),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/plugin-builder",
+        Operation::new("Plugin Builder", "Delete a plugin").body(delete),
+    );
+}
+
 #[instrument(skip(options), fields(plugin_name = options.name, sha256 = options.sha256))]
 fn add_plugin(options: AddPluginOptions) -> Result<(), HttpError> {
     let AddPluginOptions { data, name, sha256 } = options;
@@ -132,7 +153,7 @@ fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
     // IMPORTANT! don't remove this validation
     validate_token(&token)?;
     // IMPORTANT! don't remove this validation
-    validate_is_admin(&token)?;
+    validate_role(&token, ROLE_DEPLOY_FUNCTIONS)?;
 
     Ok(())
 }
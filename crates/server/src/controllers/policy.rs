@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Result};
+use hyper::{body::Incoming, Method, Request, Response};
+use rusqlite::named_params;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+use crate::{
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::{Body, BoxBody},
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            statement_to_vec::statement_to_vec,
+            validate_db_exists::validate_db_exists,
+            validate_is_admin::validate_is_admin,
+            validate_token::validate_token,
+        },
+    },
+    sqlite::{
+        connect_db::{connect_config_db, connect_db},
+        functions::_auth_uid_function,
+    },
+};
+
+#[derive(Deserialize, JsonSchema)]
+struct CreatePolicyOptions {
+    db_name: String,
+    table_name: String,
+    operation: Option<String>,
+    predicate: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeletePolicyOptions {
+    db_name: String,
+    table_name: String,
+    operation: Option<String>,
+}
+
+#[instrument(err(Debug), skip(req))]
+pub async fn policy(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["policy"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let db_name = get_query_string(req, "db_name").ok();
+
+            match list_policies(db_name) {
+                Ok(s) => match ok(s) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["policy"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: CreatePolicyOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match create_policy(options) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::DELETE, ["policy"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: DeletePolicyOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match delete_policy(options) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let create = api.schema::<CreatePolicyOptions>();
+    let delete = api.schema::<DeletePolicyOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/policy",
+        Operation::new("Policy", "List the row-level security policies")
+            .query("db_name", "string", false)
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/policy",
+        Operation::new("Policy", "Create or update a policy")
+            .body(create)
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/policy",
+        Operation::new("Policy", "Delete a policy").body(delete),
+    );
+}
+
+fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_is_admin(&token)?;
+
+    Ok(())
+}
+
+fn list_policies(db_name: Option<String>) -> Result<String> {
+    let conn = connect_config_db()?;
+
+    let stmt = conn.prepare(
+        "
+        SELECT
+            db_name,
+            table_name,
+            operation,
+            predicate,
+            created_at,
+            updated_at
+        FROM
+            _config_policy
+        WHERE
+            :db_name IS NULL OR db_name = :db_name
+        ORDER BY
+            db_name, table_name, operation
+        ",
+    )?;
+
+    match statement_to_vec(stmt, named_params! { ":db_name": db_name }) {
+        Ok(v) => Ok(json!({ "data": v }).to_string()),
+        Err(e) => {
+            if let rusqlite::Error::QueryReturnedNoRows = e {
+                Ok(json!({ "data": [] }).to_string())
+            } else {
+                Err(anyhow!(e))
+            }
+        }
+    }
+}
+
+fn create_policy(options: CreatePolicyOptions) -> Result<(), HttpError> {
+    let operation = options.operation.unwrap_or("*".to_string()).to_uppercase();
+
+    // IMPORTANT! don't remove this validation
+    validate_predicate(&options.db_name, &options.table_name, &options.predicate)?;
+
+    let conn = connect_config_db()?;
+
+    match conn.execute(
+        "
+        INSERT INTO
+            _config_policy(
+                db_name,
+                table_name,
+                operation,
+                predicate
+            )
+        VALUES (
+            :db_name,
+            :table_name,
+            :operation,
+            :predicate
+        )
+        ON CONFLICT (db_name, table_name, operation) DO UPDATE SET
+            predicate = excluded.predicate
+        ",
+        named_params! {
+            ":db_name": options.db_name,
+            ":table_name": options.table_name,
+            ":operation": operation,
+            ":predicate": options.predicate,
+        },
+    ) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(bad_request(e.to_string())),
+    }
+}
+
+fn delete_policy(options: DeletePolicyOptions) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    conn.execute(
+        "
+        DELETE FROM
+            _config_policy
+        WHERE
+            db_name = :db_name
+        AND
+            table_name = :table_name
+        AND
+            operation = :operation
+        ",
+        named_params! {
+            ":db_name": options.db_name,
+            ":table_name": options.table_name,
+            ":operation": options.operation.unwrap_or("*".to_string()).to_uppercase(),
+        },
+    )?;
+
+    Ok(())
+}
+
+// NOTE: The predicate is compiled against the table, so a policy with a wrong column or
+// function doesn't break the queries of the database.
+fn validate_predicate(db_name: &str, table_name: &str, predicate: &str) -> Result<(), HttpError> {
+    // IMPORTANT! don't remove this validation
+    validate_db_exists(db_name)?;
+
+    let conn = connect_db(db_name)?;
+
+    _auth_uid_function(&conn, String::new())?;
+
+    let table = format!("\"{}\"", table_name.replace('"', "\"\""));
+
+    conn.prepare(&format!(
+        "SELECT 1 FROM main.{table} AS {table} WHERE ({predicate})"
+    ))
+    .map(|_| ())
+    .map_err(|e| bad_request(e.to_string()))
+}
@@ -1,5 +1,8 @@
+use std::sync::LazyLock;
+
 use hyper::{body::Incoming, Method, Request, Response};
 use rusqlite::{Connection, Error, Statement};
+use schemars::JsonSchema;
 use serde::Deserialize;
 
 use anyhow::Result;
@@ -7,23 +10,34 @@ use serde_json::{json, Value};
 use tracing::instrument;
 
 use crate::{
-    constants::DB_CONFIG_NAME,
-    controllers::utils::{
-        bind_to_params::{bind_array_to_params, bind_named_params},
-        body::{Body, BoxBody},
-        get_query_string::get_query_string,
-        get_token::get_token,
-        http_error::{bad_request, internal_server_error, not_found, HttpError},
-        responses::ok,
-        statement_to_vec::statement_to_vec,
-        validate_is_admin::is_admin,
-        validate_token::validate_token,
-        validate_write::validate_write,
+    constants::{DB_AUDIT_NAME, DB_CONFIG_NAME},
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            audit::set_audit_target,
+            bind_to_params::{bind_array_to_params, bind_named_params},
+            body::{Body, BoxBody},
+            get_claims::get_claims,
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::ok,
+            statement_to_vec::statement_to_vec,
+            validate_db_exists::validate_db_exists,
+            validate_is_admin::is_admin,
+            validate_permission::{statement_operation, validate_permission, ACTION_QUERY},
+            validate_token::validate_token,
+            validate_write::validate_write,
+        },
+    },
+    env::Env,
+    sqlite::{
+        connect_db::{connect_config_db, connect_db},
+        policies::{apply_policies, deny_internal_tables, get_policies},
     },
-    sqlite::connect_db::connect_db,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct QueryOptions {
     pub db_name: String,
     pub params: Option<Value>,
@@ -52,12 +66,30 @@ pub async fn query(
             }?;
 
             // IMPORTANT! don't remove this validation
-            if !is_select(&query) && !validate_write(&token)? {
+            validate_db_exists(&db_name)?;
+
+            let conn = connect_db(&db_name)?;
+            let operation = statement_operation(&conn, &query);
+            let is_read = matches!(operation, Ok(Some("SELECT")));
+            let is_write = !is_read && operation.is_ok();
+
+            if !is_read {
+                set_audit_target(&db_name);
+            }
+
+            // IMPORTANT! don't remove this validation
+            if is_write && !validate_write(&token)? {
                 return Err(bad_request(
                     "Token without write permission tried to write to the database".to_string(),
                 ));
             }
 
+            // IMPORTANT! don't remove this validation
+            validate_permission(&token, &db_name, ACTION_QUERY, read_operation(&operation))?;
+
+            // IMPORTANT! don't remove this validation
+            validate_ad_hoc_query(&token)?;
+
             // IMPORTANT! don't remove this validation
             if !is_admin(&token)? && db_name == DB_CONFIG_NAME {
                 return Err(bad_request(
@@ -65,7 +97,14 @@ pub async fn query(
                 ));
             }
 
-            if !is_select(&query) {
+            // IMPORTANT! don't remove this validation
+            validate_audit_db_query(&token, &db_name, !is_write)?;
+
+            if let Err(e) = operation {
+                return Err(bad_request(e.to_string()));
+            }
+
+            if !is_read {
                 return Err(bad_request(
                     "GET requests only allows read queries".to_string(),
                 ));
@@ -76,7 +115,8 @@ pub async fn query(
                 Err(_) => None,
             };
 
-            let conn = connect_db(&db_name)?;
+            // IMPORTANT! don't remove this validation
+            apply_token_policies(&conn, &token, &db_name, &query)?;
 
             match query_controller(&conn, &query, params) {
                 Ok(s) => match ok(s) {
@@ -105,12 +145,30 @@ pub async fn query(
             }?;
 
             // IMPORTANT! don't remove this validation
-            if !is_select(&query) && !validate_write(&token)? {
+            validate_db_exists(&db_name)?;
+
+            let conn = connect_db(&db_name)?;
+            let operation = statement_operation(&conn, &query);
+            let is_read = matches!(operation, Ok(Some("SELECT")));
+            let is_write = !is_read && operation.is_ok();
+
+            if !is_read {
+                set_audit_target(&db_name);
+            }
+
+            // IMPORTANT! don't remove this validation
+            if is_write && !validate_write(&token)? {
                 return Err(bad_request(
                     "Token without write permission tried to write to the database".to_string(),
                 ));
             }
 
+            // IMPORTANT! don't remove this validation
+            validate_permission(&token, &db_name, ACTION_QUERY, read_operation(&operation))?;
+
+            // IMPORTANT! don't remove this validation
+            validate_ad_hoc_query(&token)?;
+
             // IMPORTANT! don't remove this validation
             if !is_admin(&token)? && db_name == DB_CONFIG_NAME {
                 return Err(bad_request(
@@ -118,7 +176,15 @@ pub async fn query(
                 ));
             }
 
-            let conn = connect_db(&db_name)?;
+            // IMPORTANT! don't remove this validation
+            validate_audit_db_query(&token, &db_name, !is_write)?;
+
+            if let Err(e) = operation {
+                return Err(bad_request(e.to_string()));
+            }
+
+            // IMPORTANT! don't remove this validation
+            apply_token_policies(&conn, &token, &db_name, &query)?;
 
             match query_controller(&conn, &query, params) {
                 Ok(s) => match ok(s) {
@@ -133,8 +199,92 @@ pub async fn query(
     }
 }
 
+pub fn openapi(api: &mut OpenApi) {
+    let options = api.schema::<QueryOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/query",
+        Operation::new("Query", "Run a read query in the closest database")
+            .query("db_name", "string", true)
+            .query("query", "string", true)
+            .query("params", "string", false)
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/query",
+        Operation::new("Query", "Run a query in the primary database")
+            .body(options)
+            .data(json!({ "type": "array" })),
+    );
+}
+
+// NOTE: A query that can't be prepared doesn't read or write, its error is returned to the tokens
+// that can read the database
+pub fn read_operation(operation: &rusqlite::Result<Option<&'static str>>) -> Option<&'static str> {
+    match operation {
+        Ok(v) => *v,
+        Err(_) => Some("SELECT"),
+    }
+}
+
+// NOTE: With QUERY_SERVER_PERSISTED_QUERIES_ONLY, only the admins can run queries that aren't persisted
+fn validate_ad_hoc_query(token: &str) -> Result<(), HttpError> {
+    if Env::persisted_queries_only() == "true" && !is_admin(token)? {
+        return Err(bad_request(
+            "Only persisted queries are allowed without being admin".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+// NOTE: The policies only apply to non-admin user tokens. The internal tables of the database
+// are denied to every non-admin token.
+#[instrument(err(Debug), skip(conn, token, query))]
+pub fn apply_token_policies(
+    conn: &Connection,
+    token: &str,
+    db_name: &str,
+    query: &str,
+) -> Result<(), HttpError> {
+    if is_admin(token)? {
+        return Ok(());
+    }
+
+    if get_claims(token)?.iss != "user_token" {
+        deny_internal_tables(conn, query);
+        return Ok(());
+    }
+
+    let policies = match get_policies(db_name) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    if policies.is_empty() {
+        deny_internal_tables(conn, query);
+        return Ok(());
+    }
+
+    let user_uuid: String = match connect_config_db()?.query_row(
+        "SELECT user_uuid FROM _config_user_token WHERE token = ?",
+        [token],
+        |row| row.get(0),
+    ) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    match apply_policies(conn, &policies, &user_uuid, query) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(bad_request(e.to_string())),
+    }
+}
+
 #[instrument(err(Debug), skip(conn, params))]
-fn query_controller(
+pub fn query_controller(
     conn: &Connection,
     query: &str,
     params: Option<Value>,
@@ -189,10 +339,31 @@ fn query_controller(
     }
 }
 
+// NOTE: The audit log is append-only, so the audit database only allows the reads of the admins
+fn validate_audit_db_query(token: &str, db_name: &str, is_read: bool) -> Result<(), HttpError> {
+    if db_name != DB_AUDIT_NAME {
+        return Ok(());
+    }
+
+    if !is_admin(token)? {
+        return Err(bad_request(
+            "Can't query the audit database without being admin".to_string(),
+        ));
+    }
+
+    if !is_read {
+        return Err(bad_request("The audit database is read-only".to_string()));
+    }
+
+    Ok(())
+}
+
+static SELECT_REGEX: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"^(?i)SELECT|^(?i)WITH RECURSIVE.*AS \(([\s\S]+?)\)\s*SELECT").unwrap()
+});
+
 fn is_select(query: &str) -> bool {
-    regex::Regex::new(r"^(?i)SELECT|^(?i)WITH RECURSIVE.*AS \(([\s\S]+?)\)\s*SELECT")
-        .unwrap()
-        .is_match(query)
+    SELECT_REGEX.is_match(query)
 }
 
 #[instrument(err(Debug), skip(stmt, params))]
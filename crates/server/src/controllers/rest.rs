@@ -0,0 +1,424 @@
+use std::{collections::HashMap, fs};
+
+use anyhow::Result;
+use hyper::{body::Incoming, Method, Request, Response};
+use rusqlite::{params_from_iter, Connection};
+use serde_json::{json, Value};
+use tracing::instrument;
+use url::form_urlencoded;
+
+use crate::{
+    controllers::{
+        database::{is_database_file, INTERNAL_DBS},
+        openapi::{OpenApi, Operation},
+        query::apply_token_policies,
+        utils::{
+            bind_to_params::bind_named_params,
+            body::{Body, BoxBody},
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::ok,
+            statement_to_vec::statement_to_vec,
+            validate_db_exists::validate_db_exists,
+            validate_db_name::validate_db_name,
+            validate_is_admin::is_admin,
+            validate_permission::{validate_permission, ACTION_QUERY},
+            validate_token::validate_token,
+            validate_write::validate_write,
+            value::Value as RowValue,
+        },
+    },
+    env::Env,
+    sqlite::{
+        connect_db::connect_db,
+        policies::is_internal_table,
+        rest::{RestQuery, RestTable},
+        schema::{get_schema, Column},
+    },
+};
+
+#[instrument(err(Debug), skip(req))]
+pub async fn rest(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    let (db_name, table_name, key) = match segments {
+        ["rest", db_name, table_name] => (decode(db_name), decode(table_name), None),
+        ["rest", db_name, table_name, key] => {
+            (decode(db_name), decode(table_name), Some(decode(key)))
+        }
+        _ => return Err(not_found()),
+    };
+
+    let operation = match (req.method(), &key) {
+        (&Method::GET, _) => "SELECT",
+        (&Method::POST, None) => "INSERT",
+        (&Method::PATCH, Some(_)) => "UPDATE",
+        (&Method::DELETE, Some(_)) => "DELETE",
+        _ => return Err(not_found()),
+    };
+
+    let has_key = key.is_some();
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+
+    // IMPORTANT! don't remove this validation
+    validate_db_name(&db_name)?;
+
+    // IMPORTANT! don't remove this validation
+    validate_rest_db(&db_name)?;
+
+    // IMPORTANT! don't remove this validation
+    if is_internal_table(&table_name) && !is_admin(&token)? {
+        return Err(bad_request(format!(
+            "The table {} is internal and can't be accessed without being admin",
+            table_name
+        )));
+    }
+
+    // IMPORTANT! don't remove this validation
+    if operation != "SELECT" && !validate_write(&token)? {
+        return Err(bad_request(
+            "Token without write permission tried to write to the database".to_string(),
+        ));
+    }
+
+    // IMPORTANT! don't remove this validation
+    validate_permission(&token, &db_name, ACTION_QUERY, Some(operation))?;
+
+    // IMPORTANT! don't remove this validation
+    validate_db_exists(&db_name)?;
+
+    let values = match operation {
+        "INSERT" | "UPDATE" => {
+            let body = Body::to_string(req.body_mut()).await?;
+
+            match serde_json::from_str::<Value>(&body) {
+                Ok(v) if v.is_object() => Ok(bind_named_params(v)),
+                Ok(_) => Err(bad_request("The body has to be a JSON object".to_string())),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?
+        }
+        _ => vec![],
+    };
+
+    let conn = connect_db(&db_name)?;
+
+    let table = match RestTable::new(&conn, &table_name) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(bad_request(e.to_string())),
+    }?;
+
+    let query = match (operation, key) {
+        ("SELECT", None) => table.select(&query_params(req)),
+        ("SELECT", Some(key)) => table.select_by_key(&key),
+        ("INSERT", _) => table.insert(values),
+        ("UPDATE", Some(key)) => table.update(&key, values),
+        (_, Some(key)) => table.delete(&key),
+        _ => return Err(not_found()),
+    };
+
+    let query = match query {
+        Ok(v) => Ok(v),
+        Err(e) => Err(bad_request(e.to_string())),
+    }?;
+
+    // IMPORTANT! don't remove this validation
+    apply_token_policies(&conn, &token, &db_name, &query.sql)?;
+
+    let body = match (operation, has_key) {
+        ("SELECT", false) => json!({ "data": select(&conn, query)? }),
+        ("SELECT", true) => match select(&conn, query)?.pop() {
+            Some(row) => json!({ "data": row }),
+            None => return Err(not_found()),
+        },
+        ("INSERT", _) => {
+            execute(&conn, query)?;
+
+            json!({ "data": { "success": true, "rowid": conn.last_insert_rowid() } })
+        }
+        _ => match execute(&conn, query)? {
+            0 => return Err(not_found()),
+            changes => json!({ "data": { "success": true, "changes": changes } }),
+        },
+    };
+
+    match ok(body.to_string()) {
+        Ok(r) => Ok(r),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }
+}
+
+// NOTE: Adds the resources of the tables and the views of every exposed database, with the
+// schema of their rows
+pub fn openapi(api: &mut OpenApi) -> Result<(), HttpError> {
+    let rest_dbs = Env::rest_dbs();
+
+    let mut db_names = Vec::new();
+
+    let entries = match fs::read_dir(Env::dbs_path()) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(internal_server_error(e.to_string())),
+    }?;
+
+    for entry in entries {
+        let entry = match entry {
+            Ok(v) => Ok(v),
+            Err(e) => Err(internal_server_error(e.to_string())),
+        }?;
+
+        let file_name = entry.file_name().to_string_lossy().to_string();
+
+        if entry.path().is_file()
+            && is_database_file(&file_name)
+            && is_rest_db(&file_name, &rest_dbs)
+        {
+            db_names.push(file_name);
+        }
+    }
+
+    db_names.sort();
+
+    for db_name in db_names {
+        let schema = get_schema(&connect_db(&db_name)?)?;
+
+        let tables = schema.tables.iter().map(|t| (&t.name, &t.columns, true));
+        let views = schema.views.iter().map(|v| (&v.name, &v.columns, false));
+
+        for (table_name, columns, is_table) in tables.chain(views) {
+            if is_internal_table(table_name) {
+                continue;
+            }
+
+            let tag = format!("REST {}", db_name);
+            let path = format!("/_/rest/{}/{}", db_name, table_name);
+            let row = api.add_schema(&schema_name(&db_name, table_name), row_schema(columns));
+
+            let mut list = Operation::new(&tag, &format!("List the rows of {}", table_name))
+                .query("select", "string", false)
+                .query("order", "string", false)
+                .query("limit", "integer", false)
+                .query("offset", "integer", false);
+
+            for column in columns.iter() {
+                list = list.query(&column.name, "string", false);
+            }
+
+            api.add(
+                Method::GET,
+                &path,
+                list.data(json!({ "type": "array", "items": row })),
+            );
+
+            let has_key = columns.iter().filter(|c| c.primary_key > 0).count() <= 1;
+
+            if !is_table {
+                continue;
+            }
+
+            api.add(
+                Method::POST,
+                &path,
+                Operation::new(&tag, &format!("Insert a row in {}", table_name)).body(row.clone()),
+            );
+
+            if !has_key {
+                continue;
+            }
+
+            let key_path = format!("{}/{{key}}", path);
+
+            api.add(
+                Method::GET,
+                &key_path,
+                Operation::new(&tag, &format!("Get a row of {} by key", table_name))
+                    .path("key")
+                    .data(row.clone()),
+            );
+            api.add(
+                Method::PATCH,
+                &key_path,
+                Operation::new(&tag, &format!("Update a row of {} by key", table_name))
+                    .path("key")
+                    .body(row),
+            );
+            api.add(
+                Method::DELETE,
+                &key_path,
+                Operation::new(&tag, &format!("Delete a row of {} by key", table_name)).path("key"),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// NOTE: The schema names can only have letters, numbers, ".", "-" and "_"
+fn schema_name(db_name: &str, table_name: &str) -> String {
+    format!("{}.{}", db_name, table_name)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// NOTE: The types follow the affinity rules of SQLite, the columns without a type accept any value
+fn row_schema(columns: &[Column]) -> Value {
+    let mut properties = serde_json::Map::new();
+
+    for column in columns {
+        let column_type = column.column_type.to_uppercase();
+
+        let json_type = if column_type.contains("INT") {
+            Some("integer")
+        } else if column_type.contains("CHAR")
+            || column_type.contains("CLOB")
+            || column_type.contains("TEXT")
+        {
+            Some("string")
+        } else if column_type.contains("REAL")
+            || column_type.contains("FLOA")
+            || column_type.contains("DOUB")
+        {
+            Some("number")
+        } else {
+            None
+        };
+
+        let schema = match json_type {
+            Some(t) if column.not_null => json!({ "type": t }),
+            Some(t) => json!({ "type": [t, "null"] }),
+            None => json!({}),
+        };
+
+        properties.insert(column.name.clone(), schema);
+    }
+
+    json!({ "type": "object", "properties": properties })
+}
+
+// NOTE: Only the databases of QUERY_SERVER_REST_DBS, comma-separated or "*" for all of them, are
+// exposed as REST resources. The internal databases are never exposed.
+pub fn is_rest_db(db_name: &str, rest_dbs: &str) -> bool {
+    if INTERNAL_DBS.contains(&db_name) {
+        return false;
+    }
+
+    rest_dbs
+        .split(',')
+        .map(|v| v.trim())
+        .any(|v| v == "*" || v == db_name)
+}
+
+fn validate_rest_db(db_name: &str) -> Result<(), HttpError> {
+    if !is_rest_db(db_name, &Env::rest_dbs()) {
+        return Err(bad_request(format!(
+            "The database {} isn't exposed as REST resources",
+            db_name
+        )));
+    }
+
+    Ok(())
+}
+
+fn query_params(req: &Request<Incoming>) -> HashMap<String, String> {
+    match req.uri().query() {
+        Some(v) => form_urlencoded::parse(v.as_bytes()).into_owned().collect(),
+        None => HashMap::new(),
+    }
+}
+
+// NOTE: The segments are percent-decoded, a "+" is kept as it is
+fn decode(segment: &str) -> String {
+    let segment = format!("v={}", segment.replace('+', "%2B"));
+
+    form_urlencoded::parse(segment.as_bytes())
+        .map(|(_, v)| v.into_owned())
+        .next()
+        .unwrap_or_default()
+}
+
+fn select(
+    conn: &Connection,
+    query: RestQuery,
+) -> Result<Vec<HashMap<String, RowValue>>, HttpError> {
+    let stmt = conn.prepare(&query.sql)?;
+
+    match statement_to_vec(stmt, params_from_iter(query.params)) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(bad_request(e.to_string())),
+    }
+}
+
+fn execute(conn: &Connection, query: RestQuery) -> Result<usize, HttpError> {
+    match conn.execute(&query.sql, params_from_iter(query.params)) {
+        Ok(v) => Ok(v),
+        Err(e) => Err(bad_request(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::DB_CONFIG_NAME;
+
+    use super::*;
+
+    #[test]
+    fn test_is_rest_db() {
+        assert!(is_rest_db("example.sql", "example.sql, other.sql"));
+        assert!(is_rest_db("other.sql", "example.sql, other.sql"));
+        assert!(!is_rest_db("missing.sql", "example.sql, other.sql"));
+        assert!(is_rest_db("missing.sql", "*"));
+        assert!(!is_rest_db(DB_CONFIG_NAME, "*"));
+        assert!(!is_rest_db("example.sql", ""));
+    }
+
+    #[test]
+    fn test_row_schema() {
+        let column = |name: &str, column_type: &str, not_null: bool| Column {
+            name: name.to_string(),
+            column_type: column_type.to_string(),
+            not_null,
+            default: None,
+            primary_key: 0,
+        };
+
+        assert_eq!(
+            row_schema(&[
+                column("id", "INTEGER", true),
+                column("title", "VARCHAR(255)", false),
+                column("price", "REAL", true),
+                column("data", "", false),
+            ]),
+            json!({
+                "type": "object",
+                "properties": {
+                    "id": { "type": "integer" },
+                    "title": { "type": ["string", "null"] },
+                    "price": { "type": "number" },
+                    "data": {},
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_schema_name() {
+        assert_eq!(schema_name("example.sql", "book"), "example.sql.book");
+        assert_eq!(schema_name("example.sql", "my book"), "example.sql.my_book");
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("book"), "book");
+        assert_eq!(decode("a%20b"), "a b");
+        assert_eq!(decode("a+b"), "a+b");
+    }
+}
@@ -0,0 +1,517 @@
+use anyhow::{anyhow, Result};
+use hyper::{body::Incoming, Method, Request, Response};
+use rusqlite::{named_params, Connection};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+use crate::{
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            audit::set_audit_target,
+            body::{Body, BoxBody},
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            statement_to_vec::statement_to_vec,
+            validate_is_admin::validate_is_admin,
+            validate_role::ROLE_PERMISSIONS,
+            validate_token::validate_token,
+        },
+    },
+    sqlite::connect_db::connect_config_db,
+};
+
+#[derive(Deserialize, JsonSchema)]
+struct CreateRoleOptions {
+    name: String,
+    permissions: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeleteRoleOptions {
+    name: String,
+}
+
+// NOTE: A role is assigned to a user, by email, or to a token, by name
+#[derive(Deserialize, JsonSchema)]
+struct AssignRoleOptions {
+    role: String,
+    email: Option<String>,
+    token: Option<String>,
+}
+
+#[instrument(err(Debug), skip(req))]
+pub async fn role(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["role"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            match list_roles() {
+                Ok(s) => match ok(s) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["role"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: CreateRoleOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&options.name);
+
+            match create_role(options) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::DELETE, ["role"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let DeleteRoleOptions { name } = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&name);
+
+            match delete_role(&name) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::POST, ["role", "assign"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: AssignRoleOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&options.role);
+
+            match assign_role(options) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::DELETE, ["role", "assign"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: AssignRoleOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&options.role);
+
+            match unassign_role(options) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let create = api.schema::<CreateRoleOptions>();
+    let delete = api.schema::<DeleteRoleOptions>();
+    let assign = api.schema::<AssignRoleOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/role",
+        Operation::new(
+            "Role",
+            "List the roles, their permissions, users and tokens",
+        )
+        .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/role",
+        Operation::new("Role", "Create or update a role")
+            .body(create)
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/role",
+        Operation::new("Role", "Delete a role").body(delete),
+    );
+    api.add(
+        Method::POST,
+        "/_/role/assign",
+        Operation::new("Role", "Assign a role to a user or a token")
+            .body(assign.clone())
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/role/assign",
+        Operation::new("Role", "Unassign a role from a user or a token").body(assign),
+    );
+}
+
+// NOTE: Only the admins manage the roles, otherwise a role could grant itself any permission
+fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_is_admin(&token)?;
+
+    Ok(())
+}
+
+fn list_roles() -> Result<String> {
+    let conn = connect_config_db()?;
+
+    let stmt = conn.prepare(
+        "
+        SELECT
+            r.name,
+            (
+                SELECT
+                    group_concat(permission, ',')
+                FROM
+                    _config_role_permission
+                WHERE
+                    role_name = r.name
+            ) AS permissions,
+            (
+                SELECT
+                    group_concat(u.email, ',')
+                FROM
+                    _config_user_role ur
+                JOIN
+                    _config_user u ON u.uuid = ur.user_uuid
+                WHERE
+                    ur.role_name = r.name
+            ) AS users,
+            (
+                SELECT
+                    group_concat(token_name, ',')
+                FROM
+                    _config_token_role
+                WHERE
+                    role_name = r.name
+            ) AS tokens,
+            r.created_at
+        FROM
+            _config_role r
+        ORDER BY
+            r.name
+        ",
+    )?;
+
+    match statement_to_vec(stmt, []) {
+        Ok(v) => Ok(json!({ "data": v }).to_string()),
+        Err(e) => {
+            if let rusqlite::Error::QueryReturnedNoRows = e {
+                Ok(json!({ "data": [] }).to_string())
+            } else {
+                Err(anyhow!(e))
+            }
+        }
+    }
+}
+
+// NOTE: The permissions of an existing role are replaced
+fn create_role(options: CreateRoleOptions) -> Result<(), HttpError> {
+    if let Some(p) = options
+        .permissions
+        .iter()
+        .find(|p| !ROLE_PERMISSIONS.contains(&p.as_str()))
+    {
+        return Err(bad_request(format!(
+            r#"The permission "{}" is not valid, the permissions are: {}"#,
+            p,
+            ROLE_PERMISSIONS.join(", ")
+        )));
+    }
+
+    let mut conn = connect_config_db()?;
+    let tx = conn.transaction()?;
+
+    match tx.execute(
+        "INSERT INTO _config_role(name) VALUES (?) ON CONFLICT (name) DO NOTHING",
+        [&options.name],
+    ) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(bad_request(e.to_string())),
+    }?;
+
+    tx.execute(
+        "DELETE FROM _config_role_permission WHERE role_name = ?",
+        [&options.name],
+    )?;
+
+    for permission in options.permissions.iter() {
+        tx.execute(
+            "
+            INSERT INTO
+                _config_role_permission(role_name, permission)
+            VALUES
+                (:role_name, :permission)
+            ON CONFLICT (role_name, permission) DO NOTHING
+            ",
+            named_params! {
+                ":role_name": options.name,
+                ":permission": permission,
+            },
+        )?;
+    }
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+fn delete_role(name: &str) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    match conn.execute("DELETE FROM _config_role WHERE name = ?", [name])? {
+        0 => Err(bad_request(format!(r#"The role "{}" doesn't exist"#, name))),
+        _ => Ok(()),
+    }
+}
+
+fn assign_role(options: AssignRoleOptions) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    let changed = match assignee(&options)? {
+        Assignee::User(email) => conn.execute(
+            "
+            INSERT INTO
+                _config_user_role(user_uuid, role_name)
+            SELECT
+                u.uuid, r.name
+            FROM
+                _config_user u, _config_role r
+            WHERE
+                u.email = :email
+            AND
+                r.name = :role
+            ON CONFLICT (user_uuid, role_name) DO NOTHING
+            ",
+            named_params! {
+                ":email": email,
+                ":role": options.role,
+            },
+        )?,
+        Assignee::Token(name) => conn.execute(
+            "
+            INSERT INTO
+                _config_token_role(token_name, role_name)
+            SELECT
+                t.name, r.name
+            FROM
+                _config_token t, _config_role r
+            WHERE
+                t.name = :name
+            AND
+                r.name = :role
+            ON CONFLICT (token_name, role_name) DO NOTHING
+            ",
+            named_params! {
+                ":name": name,
+                ":role": options.role,
+            },
+        )?,
+    };
+
+    if changed == 0 && !is_assigned(&conn, &options)? {
+        return Err(bad_request(
+            "The role, the user or the token doesn't exist".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+fn unassign_role(options: AssignRoleOptions) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    match assignee(&options)? {
+        Assignee::User(email) => conn.execute(
+            "
+            DELETE FROM
+                _config_user_role
+            WHERE
+                user_uuid = (SELECT uuid FROM _config_user WHERE email = :email)
+            AND
+                role_name = :role
+            ",
+            named_params! {
+                ":email": email,
+                ":role": options.role,
+            },
+        )?,
+        Assignee::Token(name) => conn.execute(
+            "DELETE FROM _config_token_role WHERE token_name = :name AND role_name = :role",
+            named_params! {
+                ":name": name,
+                ":role": options.role,
+            },
+        )?,
+    };
+
+    Ok(())
+}
+
+enum Assignee<'a> {
+    User(&'a str),
+    Token(&'a str),
+}
+
+fn assignee(options: &AssignRoleOptions) -> Result<Assignee<'_>, HttpError> {
+    match (&options.email, &options.token) {
+        (Some(email), None) => Ok(Assignee::User(email)),
+        (None, Some(token)) => Ok(Assignee::Token(token)),
+        _ => Err(bad_request(
+            "Either the email of a user or the name of a token is required".to_string(),
+        )),
+    }
+}
+
+fn is_assigned(conn: &Connection, options: &AssignRoleOptions) -> Result<bool, HttpError> {
+    let count: i64 = match assignee(options)? {
+        Assignee::User(email) => conn.query_row(
+            "
+            SELECT
+                COUNT(*)
+            FROM
+                _config_user_role
+            WHERE
+                user_uuid = (SELECT uuid FROM _config_user WHERE email = :email)
+            AND
+                role_name = :role
+            ",
+            named_params! {
+                ":email": email,
+                ":role": options.role,
+            },
+            |row| row.get(0),
+        )?,
+        Assignee::Token(name) => conn.query_row(
+            "SELECT COUNT(*) FROM _config_token_role WHERE token_name = :name AND role_name = :role",
+            named_params! {
+                ":name": name,
+                ":role": options.role,
+            },
+            |row| row.get(0),
+        )?,
+    };
+
+    Ok(count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_test;
+
+    use super::*;
+
+    fn permissions(role: &str) -> Vec<String> {
+        let conn = connect_config_db().unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT permission FROM _config_role_permission WHERE role_name = ? ORDER BY permission",
+            )
+            .unwrap();
+
+        stmt.query_map([role], |row| row.get(0))
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect()
+    }
+
+    db_test!(test_create_and_assign_role, TestCreateAndAssignRole, {
+        connect_config_db()
+            .unwrap()
+            .execute(
+                "INSERT INTO _config_user(email, password) VALUES ('role@test.com', 'password')",
+                (),
+            )
+            .unwrap();
+
+        create_role(CreateRoleOptions {
+            name: "deployer".to_string(),
+            permissions: vec!["deploy_functions".to_string(), "push_assets".to_string()],
+        })
+        .unwrap();
+        create_role(CreateRoleOptions {
+            name: "deployer".to_string(),
+            permissions: vec!["deploy_functions".to_string()],
+        })
+        .unwrap();
+
+        assert_eq!(permissions("deployer"), vec!["deploy_functions"]);
+        assert!(create_role(CreateRoleOptions {
+            name: "deployer".to_string(),
+            permissions: vec!["admin".to_string()],
+        })
+        .is_err());
+
+        let assign = |role: &str, email: Option<&str>, token: Option<&str>| {
+            assign_role(AssignRoleOptions {
+                role: role.to_string(),
+                email: email.map(|v| v.to_string()),
+                token: token.map(|v| v.to_string()),
+            })
+        };
+
+        assert!(assign("deployer", Some("role@test.com"), None).is_ok());
+        assert!(assign("deployer", Some("role@test.com"), None).is_ok());
+        assert!(assign("deployer", Some("missing@test.com"), None).is_err());
+        assert!(assign("missing", Some("role@test.com"), None).is_err());
+        assert!(assign("deployer", None, Some("missing")).is_err());
+        assert!(assign("deployer", None, None).is_err());
+        assert!(assign("deployer", Some("role@test.com"), Some("token")).is_err());
+
+        let roles = list_roles().unwrap();
+        assert!(roles.contains("role@test.com"));
+
+        delete_role("deployer").unwrap();
+        assert!(delete_role("deployer").is_err());
+        assert!(!list_roles().unwrap().contains("role@test.com"));
+    });
+}
@@ -0,0 +1,86 @@
+use anyhow::Result;
+use hyper::{body::Incoming, Method, Request, Response};
+use serde_json::json;
+use tracing::instrument;
+
+use crate::{
+    constants::DB_CONFIG_NAME,
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::BoxBody,
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::ok,
+            validate_db_exists::validate_db_exists,
+            validate_db_name::validate_db_name,
+            validate_is_admin::is_admin,
+            validate_permission::{validate_permission, ACTION_QUERY},
+            validate_token::validate_token,
+        },
+    },
+    sqlite::{
+        connect_db::connect_db,
+        schema::{get_schema, Schema},
+    },
+};
+
+#[instrument(err(Debug), skip(req))]
+pub async fn schema(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["schema"]) => {
+            let token = get_token(req.headers().to_owned())?;
+
+            // IMPORTANT! don't remove this validation
+            validate_token(&token)?;
+
+            let db_name = match get_query_string(req, "db_name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&db_name)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_permission(&token, &db_name, ACTION_QUERY, Some("SELECT"))?;
+
+            // IMPORTANT! don't remove this validation
+            if !is_admin(&token)? && db_name == DB_CONFIG_NAME {
+                return Err(bad_request(
+                    "Can't read the schema of the config database without being admin".to_string(),
+                ));
+            }
+
+            // IMPORTANT! don't remove this validation
+            validate_db_exists(&db_name)?;
+
+            let conn = connect_db(&db_name)?;
+
+            match get_schema(&conn) {
+                Ok(v) => match ok(json!({ "data": v }).to_string()) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let schema = api.schema::<Schema>();
+
+    api.add(
+        Method::GET,
+        "/_/schema",
+        Operation::new("Schema", "Show the schema of a database or a branch")
+            .query("db_name", "string", true)
+            .data(schema),
+    );
+}
@@ -1,45 +1,66 @@
 use anyhow::{anyhow, Result};
 use hyper::{body::Incoming, Method, Request, Response};
-use rusqlite::named_params;
+use rusqlite::{named_params, Connection};
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::json;
 use tracing::instrument;
 
 use crate::{
-    controllers::utils::{
-        body::{Body, BoxBody},
-        current_time::current_time_millis,
-        get_query_string::get_query_string,
-        get_token::get_token,
-        http_error::{bad_request, internal_server_error, not_found, HttpError},
-        responses::{created, ok},
-        statement_to_vec::statement_to_vec,
-        validate_is_admin::validate_is_admin,
-        validate_token::validate_token,
-        validate_token_creation::validate_token_creation,
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            audit::set_audit_target,
+            body::{Body, BoxBody},
+            client_ip::validate_cidr,
+            current_time::current_time_millis,
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            statement_to_vec::statement_to_vec,
+            validate_role::{validate_role, validate_token_roles, ROLE_MANAGE_TOKENS},
+            validate_token::validate_token,
+            validate_token_creation::validate_token_creation,
+        },
     },
-    sqlite::connect_db::connect_config_db,
+    sqlite::{connect_db::connect_config_db, token_usage::flush_token_usage},
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct CreateTokenOptions {
     name: String,
     expiration_date: Option<i64>,
     active: Option<bool>,
     write: Option<bool>,
+    allowed_ips: Option<Vec<String>>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct DeleteTokenOptions {
     name: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+struct RevokeTokenOptions {
+    name: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct TokenPermissionOptions {
+    name: String,
+    db_name: Option<String>,
+    action: Option<String>,
+    operation: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
 struct UpdateTokenOptions {
     name: String,
     expiration_date: Option<i64>,
     active: Option<bool>,
     write: Option<bool>,
+    allowed_ips: Option<Vec<String>>,
 }
 
 #[instrument(err(Debug), skip(req))]
@@ -71,6 +92,8 @@ pub async fn token(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&options.name);
+
             match crate_token(options) {
                 Ok(_) => match created() {
                     Ok(r) => Ok(r),
@@ -88,6 +111,9 @@ pub async fn token(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            // IMPORTANT! don't remove this validation
+            validate_target(req, &name)?;
+
             match get_token_value(&name) {
                 Ok(t) => match ok(t) {
                     Ok(r) => Ok(r),
@@ -107,6 +133,11 @@ pub async fn token(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&name);
+
+            // IMPORTANT! don't remove this validation
+            validate_target(req, &name)?;
+
             match delete_token(&name) {
                 Ok(_) => match ok("") {
                     Ok(r) => Ok(r),
@@ -126,6 +157,11 @@ pub async fn token(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&options.name);
+
+            // IMPORTANT! don't remove this validation
+            validate_target(req, &options.name)?;
+
             match update_token(options) {
                 Ok(_) => match ok("") {
                     Ok(r) => Ok(r),
@@ -134,10 +170,161 @@ pub async fn token(
                 Err(e) => Err(e),
             }
         }
+        (&Method::POST, ["token", "revoke"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let RevokeTokenOptions { name } = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&name);
+
+            // IMPORTANT! don't remove this validation
+            validate_target(req, &name)?;
+
+            match revoke_token(&name) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::GET, ["token", "permission"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let name = match get_query_string(req, "name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match list_token_permissions(&name) {
+                Ok(s) => match ok(s) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["token", "permission"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: TokenPermissionOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&options.name);
+
+            // IMPORTANT! don't remove this validation
+            validate_target(req, &options.name)?;
+
+            match grant_token_permission(options) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::DELETE, ["token", "permission"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: TokenPermissionOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&options.name);
+
+            // IMPORTANT! don't remove this validation
+            validate_target(req, &options.name)?;
+
+            match revoke_token_permission(options) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
         _ => Err(not_found()),
     }
 }
 
+pub fn openapi(api: &mut OpenApi) {
+    let create = api.schema::<CreateTokenOptions>();
+    let delete = api.schema::<DeleteTokenOptions>();
+    let update = api.schema::<UpdateTokenOptions>();
+    let revoke = api.schema::<RevokeTokenOptions>();
+    let permission = api.schema::<TokenPermissionOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/token",
+        Operation::new("Token", "List the tokens").data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/token",
+        Operation::new("Token", "Create a token")
+            .body(create)
+            .created(),
+    );
+    api.add(
+        Method::PUT,
+        "/_/token",
+        Operation::new("Token", "Update a token").body(update),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/token",
+        Operation::new("Token", "Delete a token").body(delete),
+    );
+    api.add(
+        Method::POST,
+        "/_/token/revoke",
+        Operation::new("Token", "Revoke a token, it is rejected from now on").body(revoke),
+    );
+    api.add(
+        Method::GET,
+        "/_/token/value",
+        Operation::new("Token", "Show the value of a token")
+            .query("name", "string", true)
+            .data(json!({ "type": "object" })),
+    );
+    api.add(
+        Method::GET,
+        "/_/token/permission",
+        Operation::new("Token", "List the permissions of a token")
+            .query("name", "string", true)
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/token/permission",
+        Operation::new("Token", "Add a permission to a token")
+            .body(permission.clone())
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/token/permission",
+        Operation::new("Token", "Remove a permission from a token").body(permission),
+    );
+}
+
 fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
     // IMPORTANT! don't remove this validation
     validate_token_creation()?;
@@ -147,12 +334,26 @@ fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
     // IMPORTANT! don't remove this validation
     validate_token(&token)?;
     // IMPORTANT! don't remove this validation
-    validate_is_admin(&token)?;
+    validate_role(&token, ROLE_MANAGE_TOKENS)?;
+
+    Ok(())
+}
+
+fn validate_target(req: &Request<Incoming>, name: &str) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token_roles(&token, name)?;
 
     Ok(())
 }
 
 fn crate_token(options: CreateTokenOptions) -> Result<(), HttpError> {
+    let allowed_ips = match options.allowed_ips {
+        Some(ref v) => Some(allowed_ips(v)?),
+        None => None,
+    };
+
     let conn = connect_config_db()?;
 
     conn.execute(
@@ -195,6 +396,10 @@ fn crate_token(options: CreateTokenOptions) -> Result<(), HttpError> {
         },
     )?;
 
+    if let Some(v) = allowed_ips {
+        set_allowed_ips(&conn, &options.name, &v)?;
+    }
+
     Ok(())
 }
 
@@ -232,10 +437,26 @@ fn delete_token(name: &str) -> Result<(), HttpError> {
     Ok(())
 }
 
+// NOTE: The pending uses are written first, so the list shows the last use of the tokens
 fn list_tokens() -> Result<String> {
+    flush_token_usage()?;
+
     let conn = connect_config_db()?;
 
-    let stmt = match conn.prepare("SELECT * FROM _config_token") {
+    let stmt = match conn.prepare(
+        "
+        SELECT
+            t.*,
+            COALESCE(a.allowed_ips, '') AS allowed_ips,
+            a.revoked_at,
+            a.last_used_at,
+            a.last_used_ip
+        FROM
+            _config_token t
+        LEFT JOIN
+            _config_token_access a ON a.token_name = t.name
+        ",
+    ) {
         Ok(v) => Ok(v),
         Err(e) => Err(e),
     }?;
@@ -253,6 +474,11 @@ fn list_tokens() -> Result<String> {
 }
 
 fn update_token(options: UpdateTokenOptions) -> Result<(), HttpError> {
+    let allowed_ips = match options.allowed_ips {
+        Some(ref v) => Some(allowed_ips(v)?),
+        None => None,
+    };
+
     let conn = connect_config_db()?;
 
     conn.execute(
@@ -289,5 +515,164 @@ fn update_token(options: UpdateTokenOptions) -> Result<(), HttpError> {
         },
     )?;
 
+    if let Some(v) = allowed_ips {
+        set_allowed_ips(&conn, &options.name, &v)?;
+    }
+
+    Ok(())
+}
+
+fn allowed_ips(ips: &[String]) -> Result<String, HttpError> {
+    for ip in ips {
+        match validate_cidr(ip.trim()) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(bad_request(e.to_string())),
+        }?;
+    }
+
+    Ok(ips
+        .iter()
+        .map(|v| v.trim())
+        .collect::<Vec<&str>>()
+        .join(","))
+}
+
+fn set_allowed_ips(conn: &Connection, name: &str, allowed_ips: &str) -> Result<(), HttpError> {
+    conn.execute(
+        r#"
+        INSERT INTO
+            _config_token_access(token_name, allowed_ips)
+        SELECT
+            name, :allowed_ips
+        FROM
+            _config_token
+        WHERE
+            name = :name
+        ON CONFLICT(token_name) DO UPDATE SET
+            allowed_ips = excluded.allowed_ips
+        "#,
+        named_params! {
+            ":name": name,
+            ":allowed_ips": allowed_ips,
+        },
+    )?;
+
+    Ok(())
+}
+
+// NOTE: A revoked token is rejected by the next request, it can't be activated again
+fn revoke_token(name: &str) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    let revoked = conn.execute(
+        r#"
+        INSERT INTO
+            _config_token_access(token_name, revoked_at)
+        SELECT
+            name, strftime('%s', datetime('now'))
+        FROM
+            _config_token
+        WHERE
+            name = :name
+        ON CONFLICT(token_name) DO UPDATE SET
+            revoked_at = COALESCE(revoked_at, excluded.revoked_at)
+        "#,
+        named_params! { ":name": name },
+    )?;
+
+    if revoked == 0 {
+        return Err(bad_request(format!(
+            r#"The token "{}" doesn't exist"#,
+            name
+        )));
+    }
+
+    Ok(())
+}
+
+fn list_token_permissions(name: &str) -> Result<String> {
+    let conn = connect_config_db()?;
+
+    let stmt = conn.prepare(
+        "
+        SELECT
+            token_name,
+            db_name,
+            action,
+            operation,
+            created_at
+        FROM
+            _config_token_permission
+        WHERE
+            token_name = ?
+        ",
+    )?;
+
+    match statement_to_vec(stmt, [name]) {
+        Ok(v) => Ok(json!({ "data": v }).to_string()),
+        Err(e) => {
+            if let rusqlite::Error::QueryReturnedNoRows = e {
+                Ok(json!({ "data": [] }).to_string())
+            } else {
+                Err(anyhow!(e))
+            }
+        }
+    }
+}
+
+fn grant_token_permission(options: TokenPermissionOptions) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    conn.execute(
+        r#"
+        INSERT OR IGNORE INTO
+            _config_token_permission(
+                token_name,
+                db_name,
+                action,
+                operation
+            )
+        VALUES (
+            :name,
+            :db_name,
+            :action,
+            :operation
+        )
+        "#,
+        named_params! {
+            ":name": options.name,
+            ":db_name": options.db_name.unwrap_or("*".to_string()),
+            ":action": options.action.unwrap_or("*".to_string()).to_lowercase(),
+            ":operation": options.operation.unwrap_or("*".to_string()).to_uppercase(),
+        },
+    )?;
+
+    Ok(())
+}
+
+fn revoke_token_permission(options: TokenPermissionOptions) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    conn.execute(
+        r#"
+        DELETE FROM
+            _config_token_permission
+        WHERE
+            token_name = :name
+        AND
+            db_name = :db_name
+        AND
+            action = :action
+        AND
+            operation = :operation
+        "#,
+        named_params! {
+            ":name": options.name,
+            ":db_name": options.db_name.unwrap_or("*".to_string()),
+            ":action": options.action.unwrap_or("*".to_string()).to_lowercase(),
+            ":operation": options.operation.unwrap_or("*".to_string()).to_uppercase(),
+        },
+    )?;
+
     Ok(())
 }
@@ -3,26 +3,32 @@ use argon2::{password_hash::SaltString, Argon2, PasswordHasher};
 use chacha20poly1305::aead::OsRng;
 use hyper::{body::Incoming, Method, Request, Response};
 use rusqlite::named_params;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::json;
 use tracing::instrument;
 
 use crate::{
-    controllers::utils::{
-        get_token::get_token,
-        http_error::{bad_request, internal_server_error, not_found, HttpError},
-        responses::{created, ok},
-        statement_to_vec::statement_to_vec,
-        validate_is_admin::validate_is_admin,
-        validate_token::validate_token,
-        validate_user_email::validate_user_email,
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            audit::set_audit_target,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            statement_to_vec::statement_to_vec,
+            validate_is_admin::is_admin,
+            validate_role::{validate_admin_user, validate_role, ROLE_MANAGE_USERS},
+            validate_token::validate_token,
+            validate_user_email::validate_user_email,
+        },
     },
     sqlite::connect_db::connect_config_db,
 };
 
 use super::utils::body::{Body, BoxBody};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct CreateUserOptions {
     email: String,
     password: String,
@@ -30,12 +36,12 @@ struct CreateUserOptions {
     active: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct DeleteUserOptions {
     email: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct UpdateUserOptions {
     email: String,
     new_email: Option<String>,
@@ -52,9 +58,9 @@ pub async fn user(
     match (req.method(), segments) {
         (&Method::GET, ["user"]) => {
             // IMPORTANT! don't remove this validation
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
-            match list_users() {
+            match list_users(is_admin(&token)?) {
                 Ok(u) => match ok(u) {
                     Ok(r) => Ok(r),
                     Err(e) => Err(internal_server_error(e.to_string())),
@@ -64,7 +70,7 @@ pub async fn user(
         }
         (&Method::POST, ["user"]) => {
             // IMPORTANT! don't remove this validation
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
             let body = Body::to_string(req.body_mut()).await?;
 
@@ -73,6 +79,11 @@ pub async fn user(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&options.email);
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &options.email, options.admin)?;
+
             match create_user(options) {
                 Ok(_) => match created() {
                     Ok(r) => Ok(r),
@@ -83,7 +94,7 @@ pub async fn user(
         }
         (&Method::DELETE, ["user"]) => {
             // IMPORTANT! don't remove this validation
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
             let body = Body::to_string(req.body_mut()).await?;
 
@@ -92,6 +103,11 @@ pub async fn user(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&email);
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &email, None)?;
+
             // IMPORTANT! don't remove this validation
             validate_user_email(&email)?;
 
@@ -104,7 +120,7 @@ pub async fn user(
             }
         }
         (&Method::PUT, ["user"]) => {
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
             let body = Body::to_string(req.body_mut()).await?;
 
@@ -113,6 +129,11 @@ pub async fn user(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&options.email);
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &options.email, options.admin)?;
+
             match update_user(options) {
                 Ok(_) => match ok("".to_string()) {
                     Ok(r) => Ok(r),
@@ -125,15 +146,62 @@ pub async fn user(
     }
 }
 
-fn list_users() -> Result<String> {
+pub fn openapi(api: &mut OpenApi) {
+    let create = api.schema::<CreateUserOptions>();
+    let delete = api.schema::<DeleteUserOptions>();
+    let update = api.schema::<UpdateUserOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/user",
+        Operation::new("User", "List the users").data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/user",
+        Operation::new("User", "Create a user")
+            .body(create)
+            .created(),
+    );
+    api.add(
+        Method::PUT,
+        "/_/user",
+        Operation::new("User", "Update a user").body(update),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/user",
+        Operation::new("User", "Delete a user").body(delete),
+    );
+}
+
+// NOTE: The admins are only listed to the admins, and the two-factor secrets are never listed
+fn list_users(admins: bool) -> Result<String> {
     let conn = connect_config_db()?;
 
-    let stmt = match conn.prepare("SELECT * FROM _config_user") {
+    let stmt = match conn.prepare(
+        "
+        SELECT
+            id,
+            uuid,
+            email,
+            password,
+            admin,
+            active,
+            totp_enabled,
+            created_at,
+            updated_at
+        FROM
+            _config_user
+        WHERE
+            :admins OR admin = 0
+        ",
+    ) {
         Ok(v) => Ok(v),
         Err(e) => Err(e),
     }?;
 
-    match statement_to_vec(stmt, []) {
+    match statement_to_vec(stmt, named_params! { ":admins": admins }) {
         Ok(v) => Ok(json!({ "data": v }).to_string()),
         Err(e) => {
             if let rusqlite::Error::QueryReturnedNoRows = e {
@@ -222,7 +290,7 @@ fn update_user(options: UpdateUserOptions) -> Result<()> {
     Ok(())
 }
 
-fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+fn validate_request(req: &Request<Incoming>) -> Result<String, HttpError> {
     // IMPORTANT! don't remove this validation
     validate_user_creation()?;
 
@@ -231,9 +299,9 @@ fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
     // IMPORTANT! don't remove this validation
     validate_token(&token)?;
     // IMPORTANT! don't remove this validation
-    validate_is_admin(&token)?;
+    validate_role(&token, ROLE_MANAGE_USERS)?;
 
-    Ok(())
+    Ok(token)
 }
 
 fn validate_user_creation() -> Result<(), HttpError> {
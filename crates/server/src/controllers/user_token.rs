@@ -1,48 +1,75 @@
 use anyhow::{anyhow, Result};
 use hyper::{body::Incoming, Method, Request, Response};
 use rusqlite::named_params;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde_json::json;
 use tracing::instrument;
 
 use crate::{
-    controllers::utils::{
-        body::{Body, BoxBody},
-        current_time::current_time_millis,
-        get_query_string::get_query_string,
-        get_token::get_token,
-        http_error::{bad_request, internal_server_error, not_found, HttpError},
-        responses::{created, ok},
-        statement_to_vec::statement_to_vec,
-        validate_is_admin::validate_is_admin,
-        validate_token::validate_token,
-        validate_token_creation::validate_token_creation,
-        validate_user_creation::validate_user_creation,
-        validate_user_email::validate_user_email,
-        validate_user_password::validate_user_password,
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            audit::set_audit_target,
+            body::{Body, BoxBody},
+            current_time::current_time_millis,
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            refresh_token::{refresh_tokens, start_refresh_token_family},
+            responses::{created, ok},
+            statement_to_vec::statement_to_vec,
+            totp::validate_user_totp,
+            validate_is_admin::is_admin,
+            validate_role::{validate_admin_user, validate_role, ROLE_MANAGE_USERS},
+            validate_token::validate_token,
+            validate_token_creation::validate_token_creation,
+            validate_user_creation::validate_user_creation,
+            validate_user_email::validate_user_email,
+            validate_user_password::validate_user_password,
+        },
     },
     sqlite::connect_db::connect_config_db,
 };
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct CreateUserTokenOptions {
     email: String,
     expiration_date: Option<i64>,
     write: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct GetUserTokenValueWithoutTokenOptions {
     email: String,
     password: String,
+    totp_code: Option<String>,
+}
+
+// NOTE: The refresh token is exchanged for new tokens, or the email and the password start a new
+// family of refresh tokens
+#[derive(Deserialize, JsonSchema)]
+struct RefreshUserTokenOptions {
+    refresh_token: Option<String>,
+    email: Option<String>,
+    password: Option<String>,
+    totp_code: Option<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct DeleteUserTokenOptions {
     email: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
+struct UserTokenPermissionOptions {
+    email: String,
+    db_name: Option<String>,
+    action: Option<String>,
+    operation: Option<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
 struct UpdateUserTokenOptions {
     email: String,
     expiration_date: Option<i64>,
@@ -57,9 +84,9 @@ pub async fn user_token(
     match (req.method(), segments) {
         (&Method::GET, ["user", "token"]) => {
             // IMPORTANT! don't remove this validation
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
-            match list_users_tokens() {
+            match list_users_tokens(is_admin(&token)?) {
                 Ok(u) => match ok(u) {
                     Ok(r) => Ok(r),
                     Err(e) => Err(internal_server_error(e.to_string())),
@@ -69,7 +96,7 @@ pub async fn user_token(
         }
         (&Method::POST, ["user", "token"]) => {
             // IMPORTANT! don't remove this validation
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
             let body = Body::to_string(req.body_mut()).await?;
 
@@ -78,6 +105,11 @@ pub async fn user_token(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&options.email);
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &options.email, None)?;
+
             // IMPORTANT! don't remove this validation
             validate_emails_user_exists(&options.email)?;
 
@@ -91,13 +123,16 @@ pub async fn user_token(
         }
         (&Method::GET, ["user", "token", "value"]) => {
             // IMPORTANT! don't remove this validation
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
             let email = match get_query_string(req, "email") {
                 Ok(v) => Ok(v),
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &email, None)?;
+
             match get_user_token_value(&email) {
                 Ok(t) => match ok(t) {
                     Ok(r) => Ok(r),
@@ -120,9 +155,14 @@ pub async fn user_token(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&options.email);
+
             // IMPORTANT! don't remove this validation
             validate_user_password(&options.email, &options.password)?;
 
+            // IMPORTANT! don't remove this validation
+            validate_user_totp(&options.email, options.totp_code.as_deref())?;
+
             match get_user_token_value(&options.email) {
                 Ok(t) => match ok(t) {
                     Ok(r) => Ok(r),
@@ -131,8 +171,54 @@ pub async fn user_token(
                 Err(e) => Err(internal_server_error(e.to_string())),
             }
         }
+        (&Method::POST, ["user", "token", "refresh"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_user_creation()?;
+
+            // IMPORTANT! don't remove this validation
+            validate_token_creation()?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: RefreshUserTokenOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            let tokens = match options {
+                RefreshUserTokenOptions {
+                    refresh_token: Some(refresh_token),
+                    ..
+                } => refresh_tokens(&refresh_token)?,
+                RefreshUserTokenOptions {
+                    email: Some(email),
+                    password: Some(password),
+                    totp_code,
+                    ..
+                } => {
+                    // IMPORTANT! don't remove this validation
+                    validate_user_password(&email, &password)?;
+
+                    // IMPORTANT! don't remove this validation
+                    validate_user_totp(&email, totp_code.as_deref())?;
+
+                    start_refresh_token_family(&email)?
+                }
+                _ => {
+                    return Err(bad_request(
+                        "The refresh_token, or the email and the password, are required"
+                            .to_string(),
+                    ))
+                }
+            };
+
+            match ok(tokens.to_string()) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
         (&Method::DELETE, ["user", "token"]) => {
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
             let body = Body::to_string(req.body_mut()).await?;
 
@@ -141,6 +227,11 @@ pub async fn user_token(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&email);
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &email, None)?;
+
             // TODO: test it
             // IMPORTANT! don't remove this validation
             validate_user_email(&email)?;
@@ -154,7 +245,7 @@ pub async fn user_token(
             }
         }
         (&Method::PUT, ["user", "token"]) => {
-            validate_request(req)?;
+            let token = validate_request(req)?;
 
             let body = Body::to_string(req.body_mut()).await?;
 
@@ -163,6 +254,11 @@ pub async fn user_token(
                 Err(e) => Err(bad_request(e.to_string())),
             }?;
 
+            set_audit_target(&options.email);
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &options.email, None)?;
+
             match update_user_token(options) {
                 Ok(_) => match ok("".to_string()) {
                     Ok(r) => Ok(r),
@@ -171,19 +267,182 @@ pub async fn user_token(
                 Err(e) => Err(e),
             }
         }
+        (&Method::GET, ["user", "token", "permission"]) => {
+            // IMPORTANT! don't remove this validation
+            let token = validate_request(req)?;
+
+            let email = match get_query_string(req, "email") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &email, None)?;
+
+            match list_user_token_permissions(&email) {
+                Ok(s) => match ok(s) {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["user", "token", "permission"]) => {
+            // IMPORTANT! don't remove this validation
+            let token = validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: UserTokenPermissionOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&options.email);
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &options.email, None)?;
+
+            // IMPORTANT! don't remove this validation
+            validate_emails_user_exists(&options.email)?;
+
+            match grant_user_token_permission(options) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
+        (&Method::DELETE, ["user", "token", "permission"]) => {
+            // IMPORTANT! don't remove this validation
+            let token = validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: UserTokenPermissionOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            set_audit_target(&options.email);
+
+            // IMPORTANT! don't remove this validation
+            validate_admin_user(&token, &options.email, None)?;
+
+            match revoke_user_token_permission(options) {
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(e),
+            }
+        }
         _ => Err(not_found()),
     }
 }
 
-fn list_users_tokens() -> Result<String> {
+pub fn openapi(api: &mut OpenApi) {
+    let create = api.schema::<CreateUserTokenOptions>();
+    let delete = api.schema::<DeleteUserTokenOptions>();
+    let update = api.schema::<UpdateUserTokenOptions>();
+    let value = api.schema::<GetUserTokenValueWithoutTokenOptions>();
+    let refresh = api.schema::<RefreshUserTokenOptions>();
+    let permission = api.schema::<UserTokenPermissionOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/user/token",
+        Operation::new("User Token", "List the user tokens").data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/user/token",
+        Operation::new("User Token", "Create a user token")
+            .body(create)
+            .created(),
+    );
+    api.add(
+        Method::PUT,
+        "/_/user/token",
+        Operation::new("User Token", "Update a user token").body(update),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/user/token",
+        Operation::new("User Token", "Delete a user token").body(delete),
+    );
+    api.add(
+        Method::GET,
+        "/_/user/token/value",
+        Operation::new("User Token", "Show the value of a user token")
+            .query("email", "string", true)
+            .data(json!({ "type": "object" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/user/token/value",
+        Operation::new(
+            "User Token",
+            "Get the value of a user token with the email and the password",
+        )
+        .body(value)
+        .data(json!({ "type": "object" }))
+        .public(),
+    );
+    api.add(
+        Method::POST,
+        "/_/user/token/refresh",
+        Operation::new(
+            "User Token",
+            "Exchange a refresh token, or the email and the password, for an access token and a new refresh token",
+        )
+        .body(refresh)
+        .data(json!({ "type": "array" }))
+        .public(),
+    );
+    api.add(
+        Method::GET,
+        "/_/user/token/permission",
+        Operation::new("User Token", "List the permissions of a user token")
+            .query("email", "string", true)
+            .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/user/token/permission",
+        Operation::new("User Token", "Add a permission to a user token")
+            .body(permission.clone())
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/user/token/permission",
+        Operation::new("User Token", "Remove a permission from a user token").body(permission),
+    );
+}
+
+// NOTE: The tokens of the admins are only listed to the admins
+fn list_users_tokens(admins: bool) -> Result<String> {
     let conn = connect_config_db()?;
 
-    let stmt = match conn.prepare("SELECT * FROM _config_user_token") {
+    let stmt = match conn.prepare(
+        "
+        SELECT
+            *
+        FROM
+            _config_user_token
+        WHERE
+            :admins
+        OR
+            user_uuid NOT IN (SELECT uuid FROM _config_user WHERE admin = 1)
+        ",
+    ) {
         Ok(v) => Ok(v),
         Err(e) => Err(e),
     }?;
 
-    match statement_to_vec(stmt, []) {
+    match statement_to_vec(stmt, named_params! { ":admins": admins }) {
         Ok(v) => Ok(json!({ "data": v }).to_string()),
         Err(e) => {
             if let rusqlite::Error::QueryReturnedNoRows = e {
@@ -303,7 +562,98 @@ fn update_user_token(options: UpdateUserTokenOptions) -> Result<(), HttpError> {
     Ok(())
 }
 
-fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+fn list_user_token_permissions(email: &str) -> Result<String> {
+    let conn = connect_config_db()?;
+
+    let stmt = conn.prepare(
+        "
+        SELECT
+            u.email,
+            p.db_name,
+            p.action,
+            p.operation,
+            p.created_at
+        FROM
+            _config_user_token_permission p
+        LEFT JOIN
+            _config_user u
+        ON
+            u.uuid = p.user_uuid
+        WHERE
+            u.email = ?
+        ",
+    )?;
+
+    match statement_to_vec(stmt, [email]) {
+        Ok(v) => Ok(json!({ "data": v }).to_string()),
+        Err(e) => {
+            if let rusqlite::Error::QueryReturnedNoRows = e {
+                Ok(json!({ "data": [] }).to_string())
+            } else {
+                Err(anyhow!(e))
+            }
+        }
+    }
+}
+
+fn grant_user_token_permission(options: UserTokenPermissionOptions) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    conn.execute(
+        r#"
+        INSERT OR IGNORE INTO
+            _config_user_token_permission(
+                user_uuid,
+                db_name,
+                action,
+                operation
+            )
+        VALUES (
+            (SELECT uuid FROM _config_user WHERE email = :email),
+            :db_name,
+            :action,
+            :operation
+        )
+        "#,
+        named_params! {
+            ":email": options.email,
+            ":db_name": options.db_name.unwrap_or("*".to_string()),
+            ":action": options.action.unwrap_or("*".to_string()).to_lowercase(),
+            ":operation": options.operation.unwrap_or("*".to_string()).to_uppercase(),
+        },
+    )?;
+
+    Ok(())
+}
+
+fn revoke_user_token_permission(options: UserTokenPermissionOptions) -> Result<(), HttpError> {
+    let conn = connect_config_db()?;
+
+    conn.execute(
+        r#"
+        DELETE FROM
+            _config_user_token_permission
+        WHERE
+            user_uuid = (SELECT uuid FROM _config_user WHERE email = :email)
+        AND
+            db_name = :db_name
+        AND
+            action = :action
+        AND
+            operation = :operation
+        "#,
+        named_params! {
+            ":email": options.email,
+            ":db_name": options.db_name.unwrap_or("*".to_string()),
+            ":action": options.action.unwrap_or("*".to_string()).to_lowercase(),
+            ":operation": options.operation.unwrap_or("*".to_string()).to_uppercase(),
+        },
+    )?;
+
+    Ok(())
+}
+
+fn validate_request(req: &Request<Incoming>) -> Result<String, HttpError> {
     // IMPORTANT! don't remove this validation
     validate_user_creation()?;
 
@@ -315,9 +665,9 @@ fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
     // IMPORTANT! don't remove this validation
     validate_token(&token)?;
     // IMPORTANT! don't remove this validation
-    validate_is_admin(&token)?;
+    validate_role(&token, ROLE_MANAGE_USERS)?;
 
-    Ok(())
+    Ok(token)
 }
 
 fn validate_emails_user_exists(email: &str) -> Result<(), HttpError> {
@@ -0,0 +1,187 @@
+use hyper::{body::Incoming, Method, Request, Response};
+use rusqlite::OptionalExtension;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+use crate::{
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            audit::set_audit_target,
+            body::{Body, BoxBody},
+            get_claims::get_claims,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, unauthorized, HttpError},
+            responses::ok,
+            totp::{confirm_totp_enrolment, disable_totp, start_totp_enrolment},
+            validate_role::{validate_admin_user, validate_role, ROLE_MANAGE_USERS},
+            validate_token::validate_token,
+        },
+    },
+    sqlite::connect_db::connect_config_db,
+};
+
+#[derive(Deserialize, JsonSchema)]
+struct ConfirmUserTotpOptions {
+    code: String,
+}
+
+// NOTE: A user disables its own two-factor authentication with a code. The admins, and the users
+// with the manage_users permission, disable it for another user by email.
+#[derive(Deserialize, JsonSchema)]
+struct DisableUserTotpOptions {
+    code: Option<String>,
+    email: Option<String>,
+}
+
+#[instrument(err(Debug), skip(req))]
+pub async fn user_totp(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::POST, ["user", "totp"]) => {
+            // IMPORTANT! don't remove this validation
+            let user_uuid = validate_request(req)?;
+
+            match ok(start_totp_enrolment(&user_uuid)?.to_string()) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["user", "totp", "confirm"]) => {
+            // IMPORTANT! don't remove this validation
+            let user_uuid = validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let ConfirmUserTotpOptions { code } = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match ok(confirm_totp_enrolment(&user_uuid, &code)?.to_string()) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::DELETE, ["user", "totp"]) => {
+            let token = get_token(req.headers().to_owned())?;
+
+            // IMPORTANT! don't remove this validation
+            validate_token(&token)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: DisableUserTotpOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match options {
+                DisableUserTotpOptions {
+                    email: Some(email), ..
+                } => {
+                    set_audit_target(&email);
+
+                    // IMPORTANT! don't remove this validation
+                    validate_role(&token, ROLE_MANAGE_USERS)?;
+                    // IMPORTANT! don't remove this validation
+                    validate_admin_user(&token, &email, None)?;
+
+                    disable_totp(&user_uuid_by_email(&email)?, None)?
+                }
+                DisableUserTotpOptions {
+                    code: Some(code), ..
+                } => disable_totp(&token_user_uuid(&token)?, Some(&code))?,
+                _ => {
+                    return Err(bad_request(
+                        "The code, or the email of the user, is required".to_string(),
+                    ))
+                }
+            };
+
+            match ok("") {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let confirm = api.schema::<ConfirmUserTotpOptions>();
+    let disable = api.schema::<DisableUserTotpOptions>();
+
+    api.add(
+        Method::POST,
+        "/_/user/totp",
+        Operation::new(
+            "User TOTP",
+            "Start the two-factor authentication enrolment of the user of the token",
+        )
+        .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/user/totp/confirm",
+        Operation::new(
+            "User TOTP",
+            "Enable the two-factor authentication with a code, it returns the recovery codes",
+        )
+        .body(confirm)
+        .data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/user/totp",
+        Operation::new("User TOTP", "Disable the two-factor authentication").body(disable),
+    );
+}
+
+// NOTE: The enrolment is done by the user of the token, so it is only available to user tokens
+fn validate_request(req: &Request<Incoming>) -> Result<String, HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+
+    token_user_uuid(&token)
+}
+
+fn token_user_uuid(token: &str) -> Result<String, HttpError> {
+    if get_claims(token)?.iss != "user_token" {
+        return Err(unauthorized());
+    }
+
+    let user_uuid = connect_config_db()?
+        .query_row(
+            "SELECT user_uuid FROM _config_user_token WHERE token = ?",
+            [token],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+
+    match user_uuid {
+        Some(v) => Ok(v),
+        None => Err(unauthorized()),
+    }
+}
+
+fn user_uuid_by_email(email: &str) -> Result<String, HttpError> {
+    let user_uuid = connect_config_db()?
+        .query_row(
+            "SELECT uuid FROM _config_user WHERE email = ?",
+            [email],
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+
+    match user_uuid {
+        Some(v) => Ok(v),
+        None => Err(bad_request("The user doesn't exist".to_string())),
+    }
+}
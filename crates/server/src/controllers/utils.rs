@@ -1,15 +1,26 @@
+pub mod audit;
 pub mod bind_to_params;
 pub mod body;
+pub mod client_ip;
 pub mod current_time;
 pub mod db_test;
 pub mod db_test_before;
 pub mod get_claims;
 pub mod get_query_string;
 pub mod get_token;
+pub mod hex;
 pub mod http_error;
+pub mod refresh_token;
 pub mod responses;
 pub mod statement_to_vec;
+pub mod token_keys;
+pub mod totp;
+pub mod validate_audit_db;
+pub mod validate_db_exists;
+pub mod validate_db_name;
 pub mod validate_is_admin;
+pub mod validate_permission;
+pub mod validate_role;
 pub mod validate_token;
 pub mod validate_token_creation;
 pub mod validate_user_creation;
@@ -0,0 +1,158 @@
+use std::cell::RefCell;
+
+use hyper::{body::Incoming, header::AUTHORIZATION, HeaderMap, Method, Request, StatusCode};
+use rusqlite::OptionalExtension;
+
+use crate::sqlite::{
+    audit::{insert_audit_entry, AuditRecord},
+    connect_db::{connect_audit_db, connect_config_db},
+};
+
+use super::{client_ip::client_ip, get_claims::get_claims, get_token::get_token};
+
+pub struct AuditContext {
+    request_id: String,
+    target: RefCell<Option<String>>,
+}
+
+impl AuditContext {
+    pub fn new(request_id: &str) -> Self {
+        Self {
+            request_id: request_id.to_string(),
+            target: RefCell::new(None),
+        }
+    }
+}
+
+tokio::task_local! {
+    // NOTE: The request ID and the target of the request, set by the handler for each request
+    pub static AUDIT_CONTEXT: AuditContext;
+}
+
+// NOTE: Sets the target of the request in the audit log, e.g. the email of the user or the name of
+// the database. It also records the queries, that are only audited when they write.
+pub fn set_audit_target(target: &str) {
+    let _ = AUDIT_CONTEXT.try_with(|c| *c.target.borrow_mut() = Some(target.to_string()));
+}
+
+// NOTE: Every mutating call of the /_/ routes is audited, except the queries, that set a target
+// when they aren't a SELECT, and the functions, that are the traffic of the app
+pub fn is_audited(method: &Method, segments: &[&str]) -> bool {
+    match segments {
+        ["_", "function", ..] => false,
+        ["_", "query"] | ["_", "query", "persisted", _] => false,
+        ["_", ..] => !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS),
+        _ => false,
+    }
+}
+
+pub struct AuditRequest {
+    method: Method,
+    path: String,
+    headers: HeaderMap,
+}
+
+impl AuditRequest {
+    pub fn new(req: &Request<Incoming>, segments: &[&str]) -> Option<Self> {
+        match segments {
+            ["_", "function", ..] => None,
+            ["_", ..] => Some(Self {
+                method: req.method().to_owned(),
+                path: match req.uri().path_and_query() {
+                    Some(v) => v.to_string(),
+                    None => req.uri().path().to_string(),
+                },
+                headers: req.headers().to_owned(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn record(self, status: StatusCode) {
+        let (request_id, target) = match AUDIT_CONTEXT
+            .try_with(|c| (c.request_id.to_string(), c.target.borrow().to_owned()))
+        {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+
+        let path = self.path.split('?').next().unwrap_or_default().to_string();
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+        if target.is_none() && !is_audited(&self.method, &segments) {
+            return;
+        }
+
+        let (actor, actor_type) = match resolve_actor(self.headers) {
+            Some((actor, actor_type)) => (Some(actor), Some(actor_type.to_string())),
+            None => (None, None),
+        };
+
+        let record = AuditRecord {
+            request_id,
+            actor,
+            actor_type,
+            action: format!("{} {}", self.method, path),
+            target: target.unwrap_or(self.path),
+            ip: client_ip().map(|v| v.to_string()),
+            status: status.as_u16(),
+        };
+
+        let result = connect_audit_db().and_then(|conn| insert_audit_entry(&conn, &record));
+
+        if let Err(e) = result {
+            tracing::error!("Error writing the audit log: {}", e);
+        }
+    }
+}
+
+// NOTE: The actor is the email of the user of a user token, or the name of a token. The requests
+// without a valid token, e.g. a login, don't have an actor.
+fn resolve_actor(headers: HeaderMap) -> Option<(String, &'static str)> {
+    if !headers.contains_key(AUTHORIZATION) {
+        return None;
+    }
+
+    let token = get_token(headers).ok()?;
+
+    let (sql, actor_type) = match get_claims(&token).ok()?.iss.as_str() {
+        "user_token" => (
+            "SELECT u.email FROM _config_user_token t JOIN _config_user u ON u.uuid = t.user_uuid WHERE t.token = ?",
+            "user",
+        ),
+        "token" => ("SELECT name FROM _config_token WHERE token = ?", "token"),
+        _ => return None,
+    };
+
+    let actor = connect_config_db()
+        .ok()?
+        .query_row(sql, [token], |row| row.get::<_, String>(0))
+        .optional()
+        .ok()??;
+
+    Some((actor, actor_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_audited() {
+        assert!(is_audited(&Method::POST, &["_", "user"]));
+        assert!(is_audited(&Method::DELETE, &["_", "token"]));
+        assert!(is_audited(
+            &Method::PATCH,
+            &["_", "rest", "example.sql", "t"]
+        ));
+        assert!(is_audited(&Method::POST, &["_", "query", "persisted"]));
+        assert!(!is_audited(&Method::GET, &["_", "user"]));
+        assert!(!is_audited(&Method::POST, &["_", "query"]));
+        assert!(!is_audited(
+            &Method::POST,
+            &["_", "query", "persisted", "name"]
+        ));
+        assert!(!is_audited(&Method::POST, &["_", "function", "path"]));
+        assert!(!is_audited(&Method::POST, &["app", "path"]));
+    }
+}
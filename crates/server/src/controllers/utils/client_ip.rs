@@ -0,0 +1,199 @@
+use std::net::IpAddr;
+
+use anyhow::{anyhow, bail, Result};
+use hyper::HeaderMap;
+
+tokio::task_local! {
+    // NOTE: The IP address of the connection of the request, set by the handler for each request
+    pub static CLIENT_IP: IpAddr;
+}
+
+pub fn client_ip() -> Option<IpAddr> {
+    CLIENT_IP.try_with(|ip| *ip).ok()
+}
+
+// NOTE: The forwarded headers can be sent by anyone, so they are only read if the connection is
+// from a trusted proxy. The X-Forwarded-For is read from the right, the proxies append to it, and
+// its first address that isn't a trusted proxy is the client.
+pub fn request_ip(remote_ip: IpAddr, headers: &HeaderMap, trusted_proxies: &str) -> IpAddr {
+    let is_trusted =
+        |ip: IpAddr| !trusted_proxies.trim().is_empty() && is_ip_allowed(Some(ip), trusted_proxies);
+
+    if !is_trusted(remote_ip) {
+        return remote_ip;
+    }
+
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+
+    if let Some(ip) = header("fly-client-ip").and_then(|v| v.trim().parse().ok()) {
+        return ip;
+    }
+
+    let forwarded = match header("x-forwarded-for") {
+        Some(v) => v,
+        None => return remote_ip,
+    };
+
+    let mut ip = remote_ip;
+
+    for address in forwarded.rsplit(',') {
+        match address.trim().parse() {
+            Ok(v) => ip = v,
+            Err(_) => break,
+        }
+
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+
+    ip
+}
+
+// NOTE: The allowed IPs are comma-separated CIDRs, e.g. 10.0.0.0/8,192.168.1.10. An empty list
+// allows any IP.
+pub fn is_ip_allowed(ip: Option<IpAddr>, allowed_ips: &str) -> bool {
+    let cidrs: Vec<&str> = allowed_ips
+        .split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    if cidrs.is_empty() {
+        return true;
+    }
+
+    let ip = match ip {
+        Some(v) => to_canonical(v),
+        None => return false,
+    };
+
+    cidrs
+        .iter()
+        .any(|cidr| matches!(cidr_contains(cidr, ip), Ok(true)))
+}
+
+pub fn validate_cidr(cidr: &str) -> Result<()> {
+    parse_cidr(cidr).map(|_| ())
+}
+
+fn cidr_contains(cidr: &str, ip: IpAddr) -> Result<bool> {
+    let (network, prefix) = parse_cidr(cidr)?;
+
+    let contains = match (network, ip) {
+        (IpAddr::V4(n), IpAddr::V4(i)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(n) & mask == u32::from(i) & mask
+        }
+        (IpAddr::V6(n), IpAddr::V6(i)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(n) & mask == u128::from(i) & mask
+        }
+        _ => false,
+    };
+
+    Ok(contains)
+}
+
+fn parse_cidr(cidr: &str) -> Result<(IpAddr, u32)> {
+    let (address, prefix) = match cidr.split_once('/') {
+        Some((a, p)) => (a, Some(p)),
+        None => (cidr, None),
+    };
+
+    let address: IpAddr = address
+        .parse()
+        .map_err(|_| anyhow!(r#"The IP "{}" is not valid"#, cidr))?;
+
+    let max = match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+
+    let prefix = match prefix {
+        Some(p) => p
+            .parse::<u32>()
+            .map_err(|_| anyhow!(r#"The IP "{}" is not valid"#, cidr))?,
+        None => max,
+    };
+
+    if prefix > max {
+        bail!(r#"The IP "{}" is not valid"#, cidr);
+    }
+
+    Ok((to_canonical(address), prefix))
+}
+
+// NOTE: An IPv4 client of an IPv6 socket has an IPv4-mapped address, e.g. ::ffff:10.0.0.1
+fn to_canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v) => match v.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => ip,
+        },
+        _ => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(v: &str) -> Option<IpAddr> {
+        Some(v.parse().unwrap())
+    }
+
+    #[test]
+    fn test_is_ip_allowed() {
+        assert!(is_ip_allowed(ip("10.1.2.3"), ""));
+        assert!(is_ip_allowed(None, ""));
+        assert!(is_ip_allowed(ip("10.1.2.3"), "10.0.0.0/8"));
+        assert!(is_ip_allowed(
+            ip("192.168.1.10"),
+            "10.0.0.0/8, 192.168.1.10"
+        ));
+        assert!(is_ip_allowed(ip("::ffff:10.1.2.3"), "10.0.0.0/8"));
+        assert!(is_ip_allowed(ip("2001:db8::1"), "2001:db8::/32"));
+        assert!(is_ip_allowed(ip("8.8.8.8"), "0.0.0.0/0"));
+        assert!(!is_ip_allowed(ip("11.1.2.3"), "10.0.0.0/8"));
+        assert!(!is_ip_allowed(ip("192.168.1.11"), "192.168.1.10"));
+        assert!(!is_ip_allowed(ip("2001:db9::1"), "2001:db8::/32"));
+        assert!(!is_ip_allowed(None, "10.0.0.0/8"));
+    }
+
+    #[test]
+    fn test_request_ip() {
+        let remote = "10.0.0.1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            "x-forwarded-for",
+            "1.1.1.1, 2.2.2.2, 10.0.0.2".parse().unwrap(),
+        );
+
+        assert_eq!(request_ip(remote, &headers, ""), remote);
+        assert_eq!(request_ip(remote, &headers, "192.168.0.0/16"), remote);
+        assert_eq!(
+            Some(request_ip(remote, &headers, "10.0.0.0/8")),
+            ip("2.2.2.2")
+        );
+
+        headers.insert("fly-client-ip", "3.3.3.3".parse().unwrap());
+
+        assert_eq!(
+            Some(request_ip(remote, &headers, "10.0.0.0/8")),
+            ip("3.3.3.3")
+        );
+        assert_eq!(request_ip(remote, &headers, ""), remote);
+    }
+
+    #[test]
+    fn test_validate_cidr() {
+        assert!(validate_cidr("10.0.0.0/8").is_ok());
+        assert!(validate_cidr("192.168.1.10").is_ok());
+        assert!(validate_cidr("2001:db8::/32").is_ok());
+        assert!(validate_cidr("10.0.0.0/33").is_err());
+        assert!(validate_cidr("10.0.0/8").is_err());
+        assert!(validate_cidr("localhost").is_err());
+    }
+}
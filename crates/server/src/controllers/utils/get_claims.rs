@@ -1,10 +1,10 @@
-use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
-use crate::env::Env;
-
-use super::http_error::{unauthorized, HttpError};
+use super::{
+    http_error::{unauthorized, HttpError},
+    token_keys::decode_token,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
@@ -15,29 +15,20 @@ pub struct Claims {
 
 #[instrument(err(Debug), skip(token))]
 pub fn get_claims(token: &str) -> Result<Claims, HttpError> {
-    let mut validation = Validation::new(Algorithm::default());
-
-    validation.validate_exp = false;
-
-    let decoded = match decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(Env::token_secret().as_ref()),
-        &validation,
-    ) {
-        Ok(v) => v,
+    match decode_token::<Claims>(token) {
+        Ok(v) => Ok(v),
         Err(e) => {
             tracing::error!("{}", e.to_string());
-            return Err(unauthorized());
+            Err(unauthorized())
         }
-    };
-
-    Ok(decoded.claims)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use crate::env::Env;
     use dotenv::dotenv;
     use jsonwebtoken::{encode, EncodingKey, Header};
     use serde_json::{from_str, json};
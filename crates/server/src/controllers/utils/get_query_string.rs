@@ -4,6 +4,8 @@ use anyhow::{anyhow, Result};
 use hyper::{body::Incoming, Request};
 use url::form_urlencoded;
 
+use super::http_error::{bad_request, HttpError};
+
 pub fn get_query_string(req: &Request<Incoming>, param: &str) -> Result<String> {
     let queries = match req.uri().query() {
         Some(v) => Ok(v),
@@ -19,4 +21,19 @@ pub fn get_query_string(req: &Request<Incoming>, param: &str) -> Result<String>
     }
 }
 
+// NOTE: A missing query string is the default value
+pub fn number_query_string(
+    req: &Request<Incoming>,
+    param: &str,
+    default: i64,
+) -> Result<i64, HttpError> {
+    match get_query_string(req, param) {
+        Ok(v) => match v.parse::<i64>() {
+            Ok(v) => Ok(v),
+            Err(_) => Err(bad_request(format!("The {} should be a number", param))),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
 // TODO: test it
@@ -1,7 +1,10 @@
 use hyper::{header::AUTHORIZATION, HeaderMap};
 use tracing::instrument;
 
-use super::http_error::{unauthorized, HttpError};
+use super::{
+    http_error::{unauthorized, HttpError},
+    refresh_token::resolve_access_token,
+};
 
 #[instrument(err(Debug), skip(headers))]
 pub fn get_token(headers: HeaderMap) -> Result<String, HttpError> {
@@ -19,7 +22,11 @@ pub fn get_token(headers: HeaderMap) -> Result<String, HttpError> {
         return Err(unauthorized());
     }
 
-    Ok(token.to_string())
+    // NOTE: An access token is replaced by the user token it acts as
+    match resolve_access_token(token)? {
+        Some(v) => Ok(v),
+        None => Ok(token.to_string()),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,389 @@
+use openssl::rand::rand_bytes;
+use rusqlite::{named_params, Connection, OptionalExtension, TransactionBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{env::Env, sqlite::connect_db::connect_config_db};
+
+use super::{
+    current_time::current_time_millis,
+    hex::{sha256_hex, to_hex},
+    http_error::{internal_server_error, unauthorized, HttpError},
+    token_keys::{decode_token, encode_token},
+};
+
+const ACCESS_TOKEN_ISS: &str = "access_token";
+
+// NOTE: The sub is the family of the refresh token that issued the access token, so revoking the
+// family also rejects its access tokens
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccessClaims {
+    pub sub: String,
+    pub uid: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub iss: String,
+}
+
+// NOTE: Starts a new family of refresh tokens for the user, after the email and the password are
+// validated
+#[instrument(err(Debug))]
+pub fn start_refresh_token_family(email: &str) -> Result<Value, HttpError> {
+    let mut conn = connect_config_db()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let user_uuid: String = match tx.query_row(
+        "SELECT uuid FROM _config_user WHERE email = ? AND active = 1",
+        [email],
+        |row| row.get(0),
+    ) {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("{}", e.to_string());
+            return Err(unauthorized());
+        }
+    };
+
+    let tokens = issue_tokens(&tx, &user_uuid, &Uuid::new_v4().to_string())?;
+
+    tx.commit()?;
+
+    Ok(tokens)
+}
+
+// NOTE: Each refresh token can be used once, it is exchanged for a new access token and a new
+// refresh token of the same family. A refresh token used again means it leaked, so the whole
+// family is revoked, with its access tokens.
+#[instrument(err(Debug), skip(refresh_token))]
+pub fn refresh_tokens(refresh_token: &str) -> Result<Value, HttpError> {
+    let mut conn = connect_config_db()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let now = current_time_millis();
+    let token_hash = sha256_hex(refresh_token);
+
+    let refresh = tx
+        .query_row(
+            "
+            SELECT
+                family_id,
+                user_uuid,
+                expires_at,
+                used_at,
+                revoked_at
+            FROM
+                _config_user_refresh_token
+            WHERE
+                token_hash = ?
+            ",
+            [&token_hash],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                    row.get::<_, Option<i64>>(4)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    let (family_id, user_uuid, expires_at, used_at, revoked_at) = match refresh {
+        Some(v) => v,
+        None => {
+            tracing::error!("The refresh token doesn't exist");
+            return Err(unauthorized());
+        }
+    };
+
+    if revoked_at.is_some() {
+        tracing::error!("The refresh token family {} is revoked", family_id);
+        return Err(unauthorized());
+    }
+
+    if used_at.is_some() {
+        tx.execute(
+            "
+            UPDATE
+                _config_user_refresh_token
+            SET
+                revoked_at = :now
+            WHERE
+                family_id = :family_id
+            ",
+            named_params! {
+                ":now": now,
+                ":family_id": family_id,
+            },
+        )?;
+        tx.commit()?;
+
+        tracing::error!(
+            "The refresh token was reused, the family {} is revoked",
+            family_id
+        );
+        return Err(unauthorized());
+    }
+
+    if expires_at <= now {
+        tracing::error!("The refresh token is expired");
+        return Err(unauthorized());
+    }
+
+    tx.execute(
+        "UPDATE _config_user_refresh_token SET used_at = :now WHERE token_hash = :token_hash",
+        named_params! {
+            ":now": now,
+            ":token_hash": token_hash,
+        },
+    )?;
+
+    let tokens = issue_tokens(&tx, &user_uuid, &family_id)?;
+
+    tx.commit()?;
+
+    Ok(tokens)
+}
+
+// NOTE: An access token acts as the user token of its user, so the permissions, the policies and
+// the admin validations of the user token apply to it. It is rejected once it expires, the family
+// is revoked, or the user or the user token aren't active.
+#[instrument(err(Debug), skip(token))]
+pub fn resolve_access_token(token: &str) -> Result<Option<String>, HttpError> {
+    let claims = match decode_token::<AccessClaims>(token) {
+        Ok(v) if v.iss == ACCESS_TOKEN_ISS => v,
+        _ => return Ok(None),
+    };
+
+    if claims.exp <= current_time_millis() {
+        tracing::error!("The access token is expired");
+        return Err(unauthorized());
+    }
+
+    let conn = match connect_config_db() {
+        Ok(c) => c,
+        Err(e) => return Err(internal_server_error(e.to_string())),
+    };
+
+    let user_token = conn
+        .query_row(
+            "
+            SELECT
+                t.token
+            FROM
+                _config_user_token t
+            JOIN
+                _config_user u ON u.uuid = t.user_uuid
+            WHERE
+                t.user_uuid = :user_uuid
+            AND
+                t.active = 1
+            AND
+                u.active = 1
+            AND
+                EXISTS (
+                    SELECT 1 FROM _config_user_refresh_token WHERE family_id = :family_id
+                )
+            AND
+                NOT EXISTS (
+                    SELECT
+                        1
+                    FROM
+                        _config_user_refresh_token
+                    WHERE
+                        family_id = :family_id
+                    AND
+                        revoked_at IS NOT NULL
+                );
+            ",
+            named_params! {
+                ":user_uuid": claims.uid,
+                ":family_id": claims.sub,
+            },
+            |row| row.get::<_, String>(0),
+        )
+        .optional()?;
+
+    match user_token {
+        Some(v) => Ok(Some(v)),
+        None => {
+            tracing::error!("The access token family {} is not valid", claims.sub);
+            Err(unauthorized())
+        }
+    }
+}
+
+fn issue_tokens(conn: &Connection, user_uuid: &str, family_id: &str) -> Result<Value, HttpError> {
+    let active: i64 = conn.query_row(
+        "
+        SELECT
+            COUNT(*)
+        FROM
+            _config_user_token t
+        JOIN
+            _config_user u ON u.uuid = t.user_uuid
+        WHERE
+            t.user_uuid = ?
+        AND
+            t.active = 1
+        AND
+            u.active = 1
+        ",
+        [user_uuid],
+        |row| row.get(0),
+    )?;
+
+    if active == 0 {
+        tracing::error!("The user {} or its token isn't active", user_uuid);
+        return Err(unauthorized());
+    }
+
+    let now = current_time_millis();
+    let access_expires_at = now + Env::access_token_ttl();
+    let refresh_expires_at = now + Env::refresh_token_ttl();
+
+    let mut bytes = [0; 32];
+    rand_bytes(&mut bytes).map_err(|e| internal_server_error(e.to_string()))?;
+    let refresh_token = to_hex(&bytes);
+
+    conn.execute(
+        "DELETE FROM _config_user_refresh_token WHERE expires_at <= ?",
+        [now],
+    )?;
+    // NOTE: Only the hash of the refresh tokens is stored
+    conn.execute(
+        "
+        INSERT INTO
+            _config_user_refresh_token(token_hash, family_id, user_uuid, expires_at)
+        VALUES
+            (:token_hash, :family_id, :user_uuid, :expires_at)
+        ",
+        named_params! {
+            ":token_hash": sha256_hex(&refresh_token),
+            ":family_id": family_id,
+            ":user_uuid": user_uuid,
+            ":expires_at": refresh_expires_at,
+        },
+    )?;
+
+    let access_token = encode_token(&AccessClaims {
+        sub: family_id.to_string(),
+        uid: user_uuid.to_string(),
+        exp: access_expires_at,
+        iat: now,
+        iss: ACCESS_TOKEN_ISS.to_string(),
+    })?;
+
+    Ok(json!({
+        "data": [{
+            "access_token": access_token,
+            "access_token_expires_at": access_expires_at,
+            "refresh_token": refresh_token,
+            "refresh_token_expires_at": refresh_expires_at,
+        }]
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_test;
+
+    use super::*;
+
+    fn setup() {
+        let conn = connect_config_db().unwrap();
+
+        conn.execute(
+            "INSERT INTO _config_user(email, password) VALUES ('refresh@test.com', 'password')",
+            (),
+        )
+        .unwrap();
+    }
+
+    fn user_token() -> String {
+        connect_config_db()
+            .unwrap()
+            .query_row(
+                "SELECT token FROM _config_user_token WHERE user_uuid = (SELECT uuid FROM _config_user WHERE email = 'refresh@test.com')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    db_test!(test_refresh_tokens, TestRefreshTokens, {
+        setup();
+
+        let first = start_refresh_token_family("refresh@test.com").unwrap();
+        let access_token = first["data"][0]["access_token"].as_str().unwrap();
+        let refresh_token = first["data"][0]["refresh_token"].as_str().unwrap();
+
+        assert_eq!(
+            resolve_access_token(access_token).unwrap(),
+            Some(user_token())
+        );
+        assert_eq!(resolve_access_token(&user_token()).unwrap(), None);
+        assert_eq!(resolve_access_token("invalid_token").unwrap(), None);
+
+        let second = refresh_tokens(refresh_token).unwrap();
+        let second_access_token = second["data"][0]["access_token"].as_str().unwrap();
+        let second_refresh_token = second["data"][0]["refresh_token"].as_str().unwrap();
+
+        assert_ne!(second_refresh_token, refresh_token);
+        assert_eq!(
+            resolve_access_token(second_access_token).unwrap(),
+            Some(user_token())
+        );
+
+        // NOTE: The first refresh token is reused, so the family is revoked
+        assert_eq!(refresh_tokens(refresh_token).unwrap_err(), unauthorized());
+        assert_eq!(
+            refresh_tokens(second_refresh_token).unwrap_err(),
+            unauthorized()
+        );
+        assert_eq!(
+            resolve_access_token(second_access_token).unwrap_err(),
+            unauthorized()
+        );
+
+        // NOTE: The other families aren't revoked
+        let other = start_refresh_token_family("refresh@test.com").unwrap();
+        let other_refresh_token = other["data"][0]["refresh_token"].as_str().unwrap();
+
+        assert!(refresh_tokens(other_refresh_token).is_ok());
+        assert_eq!(refresh_tokens("invalid_token").unwrap_err(), unauthorized());
+    });
+
+    db_test!(
+        test_refresh_tokens_inactive_user,
+        TestRefreshTokensInactiveUser,
+        {
+            setup();
+
+            let tokens = start_refresh_token_family("refresh@test.com").unwrap();
+            let access_token = tokens["data"][0]["access_token"].as_str().unwrap();
+            let refresh_token = tokens["data"][0]["refresh_token"].as_str().unwrap();
+
+            connect_config_db()
+                .unwrap()
+                .execute(
+                    "UPDATE _config_user SET active = 0 WHERE email = 'refresh@test.com'",
+                    (),
+                )
+                .unwrap();
+
+            assert_eq!(
+                resolve_access_token(access_token).unwrap_err(),
+                unauthorized()
+            );
+            assert_eq!(refresh_tokens(refresh_token).unwrap_err(), unauthorized());
+            assert_eq!(
+                start_refresh_token_family("refresh@test.com").unwrap_err(),
+                unauthorized()
+            );
+        }
+    );
+}
@@ -0,0 +1,361 @@
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use jsonwebtoken::{
+    decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use openssl::{
+    base64::encode_block,
+    pkey::{Id, PKey, Public},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::env::Env;
+
+// NOTE: The keys are read again after this time, so a key can be rotated without a restart
+const KEYS_TTL: Duration = Duration::from_secs(60);
+
+pub struct TokenKey {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub encoding_key: Option<EncodingKey>,
+    pub decoding_key: DecodingKey,
+    pub jwk: Value,
+}
+
+struct LoadedKeys {
+    path: String,
+    loaded_at: Instant,
+    keys: Arc<Vec<TokenKey>>,
+}
+
+static KEYS: Mutex<Option<LoadedKeys>> = Mutex::new(None);
+
+// NOTE: The tokens are signed with the signing key, with its id in the kid header, or with the
+// QUERY_SERVER_TOKEN_SECRET (HS256) if there isn't a keys folder
+pub fn encode_token<T: Serialize>(claims: &T) -> Result<String> {
+    sign(&token_keys()?, &Env::token_key_id(), claims)
+}
+
+// NOTE: A token with a kid header is verified by the key with that id, with the algorithm of the
+// key, and a token without it by the QUERY_SERVER_TOKEN_SECRET (HS256), only if there aren't keys
+// or the legacy secret is allowed. The expiration is validated against the tokens tables.
+pub fn decode_token<T: DeserializeOwned>(token: &str) -> Result<T> {
+    verify(&token_keys()?, token, Env::token_legacy_secret() == "true")
+}
+
+fn sign<T: Serialize>(keys: &[TokenKey], kid: &str, claims: &T) -> Result<String> {
+    let token = match signing_key(keys, kid)? {
+        Some((key, encoding_key)) => {
+            let mut header = Header::new(key.algorithm);
+            header.kid = Some(key.kid.to_string());
+
+            encode(&header, claims, encoding_key)?
+        }
+        None => encode(
+            &Header::default(),
+            claims,
+            &EncodingKey::from_secret(Env::token_secret().as_ref()),
+        )?,
+    };
+
+    Ok(token)
+}
+
+fn verify<T: DeserializeOwned>(keys: &[TokenKey], token: &str, legacy_secret: bool) -> Result<T> {
+    let header = decode_header(token)?;
+
+    let kid = match header.kid {
+        Some(v) => v,
+        None if !keys.is_empty() && !legacy_secret => {
+            bail!("The token doesn't have a key id")
+        }
+        None => {
+            let mut validation = Validation::new(Algorithm::default());
+            validation.validate_exp = false;
+
+            let decoded = decode::<T>(
+                token,
+                &DecodingKey::from_secret(Env::token_secret().as_ref()),
+                &validation,
+            )?;
+
+            return Ok(decoded.claims);
+        }
+    };
+
+    let key = match keys.iter().find(|k| k.kid == kid) {
+        Some(v) => v,
+        None => bail!("The token key {} doesn't exist", kid),
+    };
+
+    let mut validation = Validation::new(key.algorithm);
+    validation.validate_exp = false;
+
+    let decoded = decode::<T>(token, &key.decoding_key, &validation)?;
+
+    Ok(decoded.claims)
+}
+
+// NOTE: The public keys of the keys folder, in the JSON Web Key Set format
+pub fn jwks() -> Result<Value> {
+    Ok(key_set(&token_keys()?))
+}
+
+fn key_set(keys: &[TokenKey]) -> Value {
+    json!({
+        "keys": keys.iter().map(|k| k.jwk.clone()).collect::<Vec<Value>>()
+    })
+}
+
+pub fn token_keys() -> Result<Arc<Vec<TokenKey>>> {
+    let path = Env::token_keys_path();
+
+    if path.is_empty() {
+        return Ok(Arc::new(Vec::new()));
+    }
+
+    let mut loaded = match KEYS.lock() {
+        Ok(v) => v,
+        Err(e) => e.into_inner(),
+    };
+
+    if let Some(v) = loaded.as_ref() {
+        if v.path == path && v.loaded_at.elapsed() < KEYS_TTL {
+            return Ok(v.keys.clone());
+        }
+    }
+
+    let keys = Arc::new(load_keys(&path)?);
+
+    *loaded = Some(LoadedKeys {
+        path,
+        loaded_at: Instant::now(),
+        keys: keys.clone(),
+    });
+
+    Ok(keys)
+}
+
+// NOTE: The signing key is the QUERY_SERVER_TOKEN_KEY_ID key, or the last private key by id, so
+// a key named by its creation date, e.g. 2025-01-01.pem, replaces the previous one. The key id is
+// ignored if there isn't a keys folder.
+fn signing_key<'a>(
+    keys: &'a [TokenKey],
+    kid: &str,
+) -> Result<Option<(&'a TokenKey, &'a EncodingKey)>> {
+    if keys.is_empty() || kid.is_empty() {
+        return Ok(keys
+            .iter()
+            .rev()
+            .find_map(|k| k.encoding_key.as_ref().map(|e| (k, e))));
+    }
+
+    match keys.iter().find(|k| k.kid == kid) {
+        Some(key) => match &key.encoding_key {
+            Some(v) => Ok(Some((key, v))),
+            None => bail!("The token key {} doesn't have a private key", kid),
+        },
+        None => bail!("The token key {} doesn't exist", kid),
+    }
+}
+
+// NOTE: Each <kid>.pem file of the folder is a key. The private keys sign and verify the tokens,
+// and the public keys only verify them, so a retired key keeps verifying the tokens signed with
+// it until its file is deleted.
+fn load_keys(path: &str) -> Result<Vec<TokenKey>> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(path)? {
+        let file = entry?.path();
+
+        if file.extension().is_some_and(|e| e == "pem") {
+            files.push(file);
+        }
+    }
+
+    files.sort();
+
+    let mut keys = Vec::new();
+
+    for file in files {
+        let kid = match file.file_stem().and_then(|s| s.to_str()) {
+            Some(v) => v.to_string(),
+            None => continue,
+        };
+
+        keys.push(load_key(&kid, &fs::read(&file)?)?);
+    }
+
+    Ok(keys)
+}
+
+fn load_key(kid: &str, pem: &[u8]) -> Result<TokenKey> {
+    let (public_key, encoding_key) = match PKey::private_key_from_pem(pem) {
+        Ok(private_key) => {
+            let pkcs8 = private_key.private_key_to_pem_pkcs8()?;
+
+            let encoding_key = match private_key.id() {
+                Id::ED25519 => EncodingKey::from_ed_pem(&pkcs8)?,
+                Id::RSA => EncodingKey::from_rsa_pem(&pkcs8)?,
+                _ => bail!("The token key {} isn't an Ed25519 or RSA key", kid),
+            };
+
+            let public_key = PKey::public_key_from_der(&private_key.public_key_to_der()?)?;
+
+            (public_key, Some(encoding_key))
+        }
+        Err(_) => (PKey::public_key_from_pem(pem)?, None),
+    };
+
+    let (algorithm, decoding_key, jwk) = public_jwk(kid, &public_key)?;
+
+    Ok(TokenKey {
+        kid: kid.to_string(),
+        algorithm,
+        encoding_key,
+        decoding_key,
+        jwk,
+    })
+}
+
+fn public_jwk(kid: &str, public_key: &PKey<Public>) -> Result<(Algorithm, DecodingKey, Value)> {
+    match public_key.id() {
+        Id::ED25519 => {
+            let x = base64_url(&public_key.raw_public_key()?);
+
+            Ok((
+                Algorithm::EdDSA,
+                DecodingKey::from_ed_components(&x)?,
+                json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "x": x,
+                    "kid": kid,
+                    "alg": "EdDSA",
+                    "use": "sig",
+                }),
+            ))
+        }
+        Id::RSA => {
+            let rsa = public_key.rsa()?;
+            let n = rsa.n().to_vec();
+            let e = rsa.e().to_vec();
+
+            Ok((
+                Algorithm::RS256,
+                DecodingKey::from_rsa_raw_components(&n, &e),
+                json!({
+                    "kty": "RSA",
+                    "n": base64_url(&n),
+                    "e": base64_url(&e),
+                    "kid": kid,
+                    "alg": "RS256",
+                    "use": "sig",
+                }),
+            ))
+        }
+        _ => bail!("The token key {} isn't an Ed25519 or RSA key", kid),
+    }
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    encode_block(bytes)
+        .replace('+', "-")
+        .replace('/', "_")
+        .trim_end_matches('=')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use openssl::rsa::Rsa;
+
+    use crate::controllers::utils::get_claims::Claims;
+
+    use super::*;
+
+    const KEYS_PATH: &str = "../../.tests/token_keys";
+
+    fn claims() -> Claims {
+        Claims {
+            exp: 10000,
+            iat: 10000,
+            iss: "token".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_token_keys() {
+        env::set_var("QUERY_SERVER_TOKEN_SECRET", "secret");
+
+        let _ = fs::remove_dir_all(KEYS_PATH);
+        fs::create_dir_all(KEYS_PATH).unwrap();
+
+        let rsa = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let ed25519 = PKey::generate_ed25519().unwrap();
+
+        fs::write(
+            format!("{KEYS_PATH}/2025-01.pem"),
+            rsa.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+        fs::write(
+            format!("{KEYS_PATH}/2025-02.pem"),
+            ed25519.private_key_to_pem_pkcs8().unwrap(),
+        )
+        .unwrap();
+
+        let keys = load_keys(KEYS_PATH).unwrap();
+
+        fs::remove_dir_all(KEYS_PATH).unwrap();
+
+        let hs256 = sign(&[], "", &claims()).unwrap();
+
+        assert_eq!(decode_header(&hs256).unwrap().alg, Algorithm::HS256);
+        assert_eq!(verify::<Claims>(&[], &hs256, false).unwrap().iss, "token");
+        assert_eq!(verify::<Claims>(&keys, &hs256, true).unwrap().iss, "token");
+        assert!(verify::<Claims>(&keys, &hs256, false).is_err());
+
+        let rs256 = sign(&keys, "2025-01", &claims()).unwrap();
+        let header = decode_header(&rs256).unwrap();
+
+        assert_eq!(header.alg, Algorithm::RS256);
+        assert_eq!(header.kid, Some("2025-01".to_string()));
+        assert_eq!(verify::<Claims>(&keys, &rs256, false).unwrap().exp, 10000);
+
+        let eddsa = sign(&keys, "", &claims()).unwrap();
+        let header = decode_header(&eddsa).unwrap();
+
+        assert_eq!(header.alg, Algorithm::EdDSA);
+        assert_eq!(header.kid, Some("2025-02".to_string()));
+        assert_eq!(verify::<Claims>(&keys, &eddsa, false).unwrap().exp, 10000);
+
+        assert!(sign(&keys, "missing", &claims()).is_err());
+        assert!(verify::<Claims>(&keys[1..], &rs256, false).is_err());
+
+        let jwks = key_set(&keys);
+
+        assert_eq!(jwks["keys"][0]["kty"], "RSA");
+        assert_eq!(jwks["keys"][0]["kid"], "2025-01");
+        assert_eq!(jwks["keys"][1]["kty"], "OKP");
+        assert_eq!(jwks["keys"][1]["kid"], "2025-02");
+
+        // NOTE: A retired key only has its public key, so it verifies the tokens but can't sign
+        let retired = vec![load_key("2025-01", &rsa.public_key_to_pem().unwrap()).unwrap()];
+
+        assert_eq!(retired[0].jwk, jwks["keys"][0]);
+        assert_eq!(
+            verify::<Claims>(&retired, &rs256, false).unwrap().exp,
+            10000
+        );
+        assert!(sign(&retired, "2025-01", &claims()).is_err());
+    }
+}
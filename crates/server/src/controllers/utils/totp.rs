@@ -0,0 +1,644 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use openssl::{hash::MessageDigest, pkey::PKey, rand::rand_bytes, sha::sha256, sign::Signer};
+use rusqlite::{named_params, Connection, OptionalExtension, TransactionBehavior};
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::{env::Env, sqlite::connect_db::connect_config_db};
+
+use super::{
+    current_time::current_time_millis,
+    hex::{from_hex, sha256_hex, to_hex},
+    http_error::{bad_request, internal_server_error, HttpError},
+};
+
+const TOTP_ISSUER: &str = "Query";
+const TOTP_PERIOD: i64 = 30;
+const TOTP_DIGITS: u32 = 6;
+// NOTE: The codes of the previous and the next periods are accepted, for the clock drift
+const TOTP_SKEW: i64 = 1;
+const RECOVERY_CODES: usize = 10;
+// NOTE: After these wrong codes in a row, the codes of the user aren't checked for a while
+const TOTP_MAX_ATTEMPTS: i64 = 5;
+const TOTP_LOCK_SECONDS: i64 = 15 * 60;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+struct UserTotp {
+    uuid: String,
+    secret: Option<String>,
+    enabled: bool,
+    recovery_codes: Option<String>,
+    last_step: Option<i64>,
+    failed_attempts: i64,
+    locked_until: Option<i64>,
+}
+
+// NOTE: The enrolment is pending until it is confirmed with a code, so a user can't lock itself
+// out with a secret that wasn't added to an authenticator app
+#[instrument(err(Debug), skip(user_uuid))]
+pub fn start_totp_enrolment(user_uuid: &str) -> Result<Value, HttpError> {
+    let conn = connect_config_db()?;
+
+    let email: String = conn
+        .query_row(
+            "SELECT email FROM _config_user WHERE uuid = ? AND totp_enabled = 0",
+            [user_uuid],
+            |row| row.get(0),
+        )
+        .map_err(|_| bad_request("The two-factor authentication is already enabled".to_string()))?;
+
+    let mut secret = [0; 20];
+    rand_bytes(&mut secret).map_err(|e| internal_server_error(e.to_string()))?;
+
+    conn.execute(
+        "
+        UPDATE
+            _config_user
+        SET
+            totp_secret = :secret,
+            totp_recovery_codes = NULL,
+            totp_last_step = NULL
+        WHERE
+            uuid = :uuid
+        ",
+        named_params! {
+            ":secret": encrypt(&secret)?,
+            ":uuid": user_uuid,
+        },
+    )?;
+
+    let secret = base32(&secret);
+    let label: String =
+        url::form_urlencoded::byte_serialize(format!("{}:{}", TOTP_ISSUER, email).as_bytes())
+            .collect();
+
+    Ok(json!({
+        "data": [{
+            "secret": secret,
+            "otpauth_url": format!(
+                "otpauth://totp/{label}?secret={secret}&issuer={TOTP_ISSUER}&digits={TOTP_DIGITS}&period={TOTP_PERIOD}"
+            ),
+        }]
+    }))
+}
+
+// NOTE: The recovery codes are only returned once, only their hashes are stored
+#[instrument(err(Debug), skip(user_uuid, code))]
+pub fn confirm_totp_enrolment(user_uuid: &str, code: &str) -> Result<Value, HttpError> {
+    let mut conn = connect_config_db()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let user = user_totp(&tx, "uuid", user_uuid)?;
+
+    let secret = match (&user.secret, user.enabled) {
+        (Some(secret), false) => decrypt(secret)?,
+        (_, true) => {
+            return Err(bad_request(
+                "The two-factor authentication is already enabled".to_string(),
+            ))
+        }
+        (None, _) => {
+            return Err(bad_request(
+                "The two-factor authentication enrolment wasn't started".to_string(),
+            ))
+        }
+    };
+
+    let step = match verify_code(&secret, code, None) {
+        Some(v) => v,
+        None => return Err(invalid_code()),
+    };
+
+    let mut recovery_codes = Vec::new();
+    for _ in 0..RECOVERY_CODES {
+        let mut bytes = [0; 5];
+        rand_bytes(&mut bytes).map_err(|e| internal_server_error(e.to_string()))?;
+        let code = to_hex(&bytes);
+        recovery_codes.push(format!("{}-{}", &code[..5], &code[5..]));
+    }
+
+    tx.execute(
+        "
+        UPDATE
+            _config_user
+        SET
+            totp_enabled = 1,
+            totp_recovery_codes = :recovery_codes,
+            totp_last_step = :step
+        WHERE
+            uuid = :uuid
+        ",
+        named_params! {
+            ":recovery_codes": recovery_codes
+                .iter()
+                .map(|c| hash_recovery_code(c))
+                .collect::<Vec<String>>()
+                .join(","),
+            ":step": step,
+            ":uuid": user.uuid,
+        },
+    )?;
+
+    tx.commit()?;
+
+    Ok(json!({ "data": [{ "recovery_codes": recovery_codes }] }))
+}
+
+// NOTE: Without a code, the two-factor authentication is disabled without checks, e.g. by an
+// admin for a user that lost the authenticator app and the recovery codes
+#[instrument(err(Debug), skip(user_uuid, code))]
+pub fn disable_totp(user_uuid: &str, code: Option<&str>) -> Result<(), HttpError> {
+    let mut conn = connect_config_db()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    if let Some(code) = code {
+        let user = user_totp(&tx, "uuid", user_uuid)?;
+
+        if !user.enabled {
+            return Err(bad_request(
+                "The two-factor authentication isn't enabled".to_string(),
+            ));
+        }
+
+        if let Err(e) = validate_code(&tx, &user, code) {
+            tx.commit()?;
+            return Err(e);
+        }
+    }
+
+    tx.execute(
+        "
+        UPDATE
+            _config_user
+        SET
+            totp_secret = NULL,
+            totp_enabled = 0,
+            totp_recovery_codes = NULL,
+            totp_last_step = NULL
+        WHERE
+            uuid = ?
+        ",
+        [user_uuid],
+    )?;
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+// NOTE: Once a user has enrolled, the token-issuing endpoints require a code of the authenticator
+// app or a recovery code, after the email and the password are validated
+#[instrument(err(Debug), skip(code))]
+pub fn validate_user_totp(email: &str, code: Option<&str>) -> Result<(), HttpError> {
+    let mut conn = connect_config_db()?;
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let user = user_totp(&tx, "email", email)?;
+
+    if !user.enabled {
+        return Ok(());
+    }
+
+    let result = match code {
+        Some(code) if !code.trim().is_empty() => validate_code(&tx, &user, code),
+        _ => return Err(bad_request("The two-factor code is required.".to_string())),
+    };
+
+    // NOTE: The failed attempts are committed too
+    tx.commit()?;
+
+    result
+}
+
+// NOTE: A code of the authenticator app can't be used twice, and a recovery code is removed once
+// it is used. The wrong codes, of the app or recovery codes, are counted, and the user is locked
+// after TOTP_MAX_ATTEMPTS of them, so the codes can't be guessed. The caller commits the count.
+fn validate_code(conn: &Connection, user: &UserTotp, code: &str) -> Result<(), HttpError> {
+    let now = current_time_millis();
+
+    if user.locked_until.is_some_and(|v| v > now) {
+        tracing::error!("The two-factor codes of the user {} are locked", user.uuid);
+        return Err(bad_request(
+            "Too many wrong two-factor codes. Try again later.".to_string(),
+        ));
+    }
+
+    let secret = match &user.secret {
+        Some(v) => decrypt(v)?,
+        None => return Err(invalid_code()),
+    };
+
+    if let Some(step) = verify_code(&secret, code, user.last_step) {
+        conn.execute(
+            "
+            UPDATE
+                _config_user
+            SET
+                totp_last_step = :step,
+                totp_failed_attempts = 0,
+                totp_locked_until = NULL
+            WHERE
+                uuid = :uuid
+            ",
+            named_params! {
+                ":step": step,
+                ":uuid": user.uuid,
+            },
+        )?;
+
+        return Ok(());
+    }
+
+    let hash = hash_recovery_code(code);
+    let recovery_codes: Vec<&str> = user
+        .recovery_codes
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    if !recovery_codes.contains(&hash.as_str()) {
+        tracing::error!(
+            "The two-factor code of the user {} is not correct",
+            user.uuid
+        );
+
+        let (failed_attempts, locked_until) = if user.failed_attempts + 1 >= TOTP_MAX_ATTEMPTS {
+            (0, Some(now + TOTP_LOCK_SECONDS))
+        } else {
+            (user.failed_attempts + 1, None)
+        };
+
+        conn.execute(
+            "
+            UPDATE
+                _config_user
+            SET
+                totp_failed_attempts = :failed_attempts,
+                totp_locked_until = :locked_until
+            WHERE
+                uuid = :uuid
+            ",
+            named_params! {
+                ":failed_attempts": failed_attempts,
+                ":locked_until": locked_until,
+                ":uuid": user.uuid,
+            },
+        )?;
+
+        return Err(invalid_code());
+    }
+
+    conn.execute(
+        "
+        UPDATE
+            _config_user
+        SET
+            totp_recovery_codes = :recovery_codes,
+            totp_failed_attempts = 0,
+            totp_locked_until = NULL
+        WHERE
+            uuid = :uuid
+        ",
+        named_params! {
+            ":recovery_codes": recovery_codes
+                .into_iter()
+                .filter(|v| *v != hash)
+                .collect::<Vec<&str>>()
+                .join(","),
+            ":uuid": user.uuid,
+        },
+    )?;
+
+    Ok(())
+}
+
+fn user_totp(conn: &Connection, column: &str, value: &str) -> Result<UserTotp, HttpError> {
+    let user = conn
+        .query_row(
+            &format!(
+                "
+                SELECT
+                    uuid,
+                    totp_secret,
+                    totp_enabled,
+                    totp_recovery_codes,
+                    totp_last_step,
+                    totp_failed_attempts,
+                    totp_locked_until
+                FROM
+                    _config_user
+                WHERE
+                    {column} = ?
+                "
+            ),
+            [value],
+            |row| {
+                Ok(UserTotp {
+                    uuid: row.get(0)?,
+                    secret: row.get(1)?,
+                    enabled: row.get(2)?,
+                    recovery_codes: row.get(3)?,
+                    last_step: row.get(4)?,
+                    failed_attempts: row.get(5)?,
+                    locked_until: row.get(6)?,
+                })
+            },
+        )
+        .optional()?;
+
+    match user {
+        Some(v) => Ok(v),
+        None => Err(bad_request("The user doesn't exist".to_string())),
+    }
+}
+
+// NOTE: Returns the step of the code, if it matches a step after the last used one
+fn verify_code(secret: &[u8], code: &str, last_step: Option<i64>) -> Option<i64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+
+    if code.len() != TOTP_DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = current_time_millis() / TOTP_PERIOD;
+
+    (current - TOTP_SKEW..=current + TOTP_SKEW)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| matches!(totp(secret, *step), Ok(v) if format!("{:0width$}", v, width = TOTP_DIGITS as usize) == code))
+}
+
+// NOTE: RFC 6238, with HMAC-SHA1, the algorithm supported by every authenticator app
+fn totp(secret: &[u8], step: i64) -> Result<u32, HttpError> {
+    let key = PKey::hmac(secret).map_err(|e| internal_server_error(e.to_string()))?;
+    let mut signer = Signer::new(MessageDigest::sha1(), &key)
+        .map_err(|e| internal_server_error(e.to_string()))?;
+    let hmac = signer
+        .sign_oneshot_to_vec(&step.to_be_bytes())
+        .map_err(|e| internal_server_error(e.to_string()))?;
+
+    let offset = (hmac[hmac.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hmac[offset] & 0x7f,
+        hmac[offset + 1],
+        hmac[offset + 2],
+        hmac[offset + 3],
+    ]);
+
+    Ok(binary % 10u32.pow(TOTP_DIGITS))
+}
+
+// NOTE: The secrets are encrypted with a key derived from the token secret, so changing the token
+// secret requires enrolling again
+fn cipher() -> ChaCha20Poly1305 {
+    let key = sha256(format!("totp:{}", Env::token_secret()).as_bytes());
+
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+fn encrypt(secret: &[u8]) -> Result<String, HttpError> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher()
+        .encrypt(&nonce, secret)
+        .map_err(|e| internal_server_error(e.to_string()))?;
+
+    Ok(to_hex(&[nonce.as_slice(), &ciphertext].concat()))
+}
+
+fn decrypt(value: &str) -> Result<Vec<u8>, HttpError> {
+    let bytes = from_hex(value).unwrap_or_default();
+
+    if bytes.len() < 12 {
+        return Err(internal_server_error(
+            "The two-factor secret is not valid".to_string(),
+        ));
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(12);
+
+    cipher()
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| internal_server_error("The two-factor secret can't be decrypted".to_string()))
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_lowercase();
+
+    sha256_hex(&code)
+}
+
+fn invalid_code() -> HttpError {
+    bad_request("The two-factor code is not correct.".to_string())
+}
+
+fn base32(bytes: &[u8]) -> String {
+    let mut output = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            output.push(BASE32_ALPHABET[((buffer >> (bits - 5)) & 0x1f) as usize] as char);
+            bits -= 5;
+        }
+    }
+
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_test;
+
+    use super::*;
+
+    fn base32_decode(value: &str) -> Vec<u8> {
+        let mut output = Vec::new();
+        let mut buffer: u32 = 0;
+        let mut bits = 0;
+
+        for c in value.bytes() {
+            let index = BASE32_ALPHABET.iter().position(|v| *v == c).unwrap() as u32;
+            buffer = (buffer << 5) | index;
+            bits += 5;
+
+            if bits >= 8 {
+                output.push((buffer >> (bits - 8)) as u8);
+                bits -= 8;
+            }
+        }
+
+        output
+    }
+
+    fn code(secret: &[u8], step: i64) -> String {
+        format!("{:06}", totp(secret, step).unwrap())
+    }
+
+    #[test]
+    fn test_totp() {
+        // NOTE: The SHA1 test vectors of the RFC 6238, truncated to 6 digits
+        let secret = b"12345678901234567890";
+
+        assert_eq!(totp(secret, 59 / 30).unwrap(), 287082);
+        assert_eq!(totp(secret, 1111111109 / 30).unwrap(), 81804);
+        assert_eq!(totp(secret, 1234567890 / 30).unwrap(), 5924);
+        assert_eq!(totp(secret, 2000000000 / 30).unwrap(), 279037);
+    }
+
+    #[test]
+    fn test_base32() {
+        assert_eq!(base32(b""), "");
+        assert_eq!(base32(b"f"), "MY");
+        assert_eq!(base32(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(
+            base32_decode(&base32(b"12345678901234567890")),
+            b"12345678901234567890"
+        );
+    }
+
+    db_test!(test_totp_enrolment, TestTotpEnrolment, {
+        let conn = connect_config_db().unwrap();
+
+        conn.execute(
+            "INSERT INTO _config_user(email, password) VALUES ('totp@test.com', 'password')",
+            (),
+        )
+        .unwrap();
+
+        let uuid: String = conn
+            .query_row(
+                "SELECT uuid FROM _config_user WHERE email = 'totp@test.com'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        // NOTE: A pending enrolment doesn't require a code
+        let enrolment = start_totp_enrolment(&uuid).unwrap();
+        let secret = base32_decode(enrolment["data"][0]["secret"].as_str().unwrap());
+
+        assert!(enrolment["data"][0]["otpauth_url"]
+            .as_str()
+            .unwrap()
+            .starts_with("otpauth://totp/Query%3Atotp%40test.com?secret="));
+        assert!(validate_user_totp("totp@test.com", None).is_ok());
+        assert!(confirm_totp_enrolment(&uuid, "000000x").is_err());
+
+        // NOTE: The codes of a step are valid until the step after the next one
+        let step = current_time_millis() / TOTP_PERIOD;
+        let confirmed = confirm_totp_enrolment(&uuid, &code(&secret, step)).unwrap();
+        let recovery_codes = confirmed["data"][0]["recovery_codes"].as_array().unwrap();
+
+        assert_eq!(recovery_codes.len(), RECOVERY_CODES);
+        assert!(start_totp_enrolment(&uuid).is_err());
+
+        let encrypted: String = conn
+            .query_row(
+                "SELECT totp_secret FROM _config_user WHERE uuid = ?",
+                [&uuid],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(decrypt(&encrypted).unwrap(), secret);
+
+        assert_eq!(
+            validate_user_totp("totp@test.com", None).unwrap_err(),
+            bad_request("The two-factor code is required.".to_string())
+        );
+        assert_eq!(
+            validate_user_totp("totp@test.com", Some("123")).unwrap_err(),
+            invalid_code()
+        );
+
+        // NOTE: The code used to confirm the enrolment can't be used again
+        assert!(validate_user_totp("totp@test.com", Some(&code(&secret, step))).is_err());
+        assert!(validate_user_totp("totp@test.com", Some(&code(&secret, step + 1))).is_ok());
+        assert!(validate_user_totp("totp@test.com", Some(&code(&secret, step + 1))).is_err());
+
+        let recovery_code = recovery_codes[0].as_str().unwrap();
+
+        assert!(validate_user_totp("totp@test.com", Some(recovery_code)).is_ok());
+        assert!(validate_user_totp("totp@test.com", Some(recovery_code)).is_err());
+
+        let recovery_code = recovery_codes[1].as_str().unwrap().to_uppercase();
+
+        assert!(disable_totp(&uuid, Some("000000")).is_err());
+        assert!(disable_totp(&uuid, Some(&recovery_code)).is_ok());
+        assert!(validate_user_totp("totp@test.com", None).is_ok());
+    });
+
+    db_test!(test_totp_lock, TestTotpLock, {
+        let conn = connect_config_db().unwrap();
+
+        conn.execute(
+            "INSERT INTO _config_user(email, password) VALUES ('totp@test.com', 'password')",
+            (),
+        )
+        .unwrap();
+
+        let uuid: String = conn
+            .query_row(
+                "SELECT uuid FROM _config_user WHERE email = 'totp@test.com'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        let enrolment = start_totp_enrolment(&uuid).unwrap();
+        let secret = base32_decode(enrolment["data"][0]["secret"].as_str().unwrap());
+        let step = current_time_millis() / TOTP_PERIOD;
+        let confirmed = confirm_totp_enrolment(&uuid, &code(&secret, step - 1)).unwrap();
+        let recovery_code = confirmed["data"][0]["recovery_codes"][0]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        for _ in 0..TOTP_MAX_ATTEMPTS {
+            assert_eq!(
+                validate_user_totp("totp@test.com", Some("aaaaa-aaaaa")).unwrap_err(),
+                invalid_code()
+            );
+        }
+
+        // NOTE: The valid codes are rejected too while the user is locked
+        let locked = bad_request("Too many wrong two-factor codes. Try again later.".to_string());
+
+        assert_eq!(
+            validate_user_totp("totp@test.com", Some(&code(&secret, step))).unwrap_err(),
+            locked
+        );
+        assert_eq!(
+            validate_user_totp("totp@test.com", Some(&recovery_code)).unwrap_err(),
+            locked
+        );
+        assert_eq!(
+            disable_totp(&uuid, Some(&recovery_code)).unwrap_err(),
+            locked
+        );
+
+        conn.execute(
+            "UPDATE _config_user SET totp_locked_until = 0 WHERE uuid = ?",
+            [&uuid],
+        )
+        .unwrap();
+
+        assert!(validate_user_totp("totp@test.com", Some(&recovery_code)).is_ok());
+    });
+}
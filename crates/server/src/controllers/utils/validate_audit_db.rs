@@ -0,0 +1,30 @@
+use tracing::instrument;
+
+use crate::constants::DB_AUDIT_NAME;
+
+use super::http_error::{bad_request, HttpError};
+
+// NOTE: The audit log is append-only, so its database can't be migrated or branched, even by
+// the admins. The internal databases, the audit one included, can't be restored or deleted.
+#[instrument(err(Debug))]
+pub fn validate_audit_db(db_name: &str) -> Result<(), HttpError> {
+    if db_name == DB_AUDIT_NAME {
+        return Err(bad_request(format!(
+            "The database {} is append-only and can't be changed",
+            db_name
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_audit_db() {
+        assert!(validate_audit_db("example.sql").is_ok());
+        assert!(validate_audit_db(DB_AUDIT_NAME).is_err());
+    }
+}
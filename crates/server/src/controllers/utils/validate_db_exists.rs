@@ -0,0 +1,36 @@
+use std::path::Path;
+
+use tracing::instrument;
+
+use super::http_error::{bad_request, HttpError};
+
+use crate::env::Env;
+
+// NOTE: Opening a connection creates the database file, so the controllers that don't have
+// to create databases validate that it exists before connecting to it.
+#[instrument(err(Debug))]
+pub fn validate_db_exists(db_name: &str) -> Result<(), HttpError> {
+    if !Path::new(&format!("{}/{}", Env::dbs_path(), db_name)).is_file() {
+        return Err(bad_request(format!(
+            "The database {} doesn't exist",
+            db_name
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{constants::DB_CONFIG_NAME, db_test};
+
+    use super::*;
+
+    db_test!(test_validate_db_exists, TestValidateDbExists, {
+        assert!(validate_db_exists(DB_CONFIG_NAME).is_ok());
+        assert_eq!(
+            validate_db_exists("missing.sql").unwrap_err(),
+            bad_request("The database missing.sql doesn't exist".to_string())
+        );
+    });
+}
@@ -0,0 +1,36 @@
+use tracing::instrument;
+
+use super::http_error::{bad_request, HttpError};
+
+// NOTE: The database name is a file name inside the databases path, so it can't contain
+// path separators or start with a dot.
+#[instrument(err(Debug))]
+pub fn validate_db_name(db_name: &str) -> Result<(), HttpError> {
+    let is_valid = regex::Regex::new(r"^[A-Za-z0-9_-][A-Za-z0-9_.-]*$")
+        .unwrap()
+        .is_match(db_name);
+
+    if !is_valid {
+        return Err(bad_request(format!(
+            "The database name {} is not valid",
+            db_name
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_db_name() {
+        assert!(validate_db_name("example.sql").is_ok());
+        assert!(validate_db_name("example.branch_1.branch.sql").is_ok());
+        assert!(validate_db_name("").is_err());
+        assert!(validate_db_name(".example.sql").is_err());
+        assert!(validate_db_name("../example.sql").is_err());
+        assert!(validate_db_name("dir/example.sql").is_err());
+    }
+}
@@ -0,0 +1,375 @@
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{
+    hooks::{AuthAction, AuthContext, Authorization},
+    named_params, Connection,
+};
+use tracing::instrument;
+
+use super::{
+    get_claims::get_claims,
+    http_error::{internal_server_error, unauthorized, HttpError},
+};
+
+use crate::sqlite::connect_db::connect_config_db;
+
+pub const ACTION_BRANCH: &str = "branch";
+pub const ACTION_MIGRATION: &str = "migration";
+pub const ACTION_QUERY: &str = "query";
+
+// NOTE: A token that was never granted a permission is not restricted. Once a permission is
+// granted, the token is restricted, even after its last permission is revoked, and the request
+// has to match a permission by database name, action and operation. The "*"
+// value matches any database, action or operation. A `None` operation, used by statements
+// that aren't SELECT, INSERT, UPDATE or DELETE, only matches the "*" operation.
+#[instrument(err(Debug), skip(token))]
+pub fn validate_permission(
+    token: &str,
+    db_name: &str,
+    action: &str,
+    operation: Option<&str>,
+) -> Result<(), HttpError> {
+    let conn = match connect_config_db() {
+        Ok(c) => c,
+        Err(e) => return Err(internal_server_error(e.to_string())),
+    };
+
+    let claims = get_claims(token)?;
+
+    let (token_table, permission_table, relation, restriction) = match claims.iss.as_str() {
+        "user_token" => (
+            "_config_user_token",
+            "_config_user_token_permission",
+            "p.user_uuid = t.user_uuid",
+            "SELECT u.permissions_restricted FROM _config_user u WHERE u.uuid = t.user_uuid",
+        ),
+        "token" => (
+            "_config_token",
+            "_config_token_permission",
+            "p.token_name = t.name",
+            "SELECT a.permissions_restricted FROM _config_token_access a WHERE a.token_name = t.name",
+        ),
+        _ => return Err(unauthorized()),
+    };
+
+    match conn.query_row(
+        &format!(
+            "
+            SELECT
+                COALESCE(({restriction}), 0)
+                OR
+                EXISTS (SELECT 1 FROM {permission_table} p WHERE {relation}),
+                (
+                    SELECT
+                        COUNT(*)
+                    FROM
+                        {permission_table} p
+                    WHERE
+                        {relation}
+                    AND
+                        p.db_name IN ('*', :db_name)
+                    AND
+                        p.action IN ('*', :action)
+                    AND
+                        p.operation IN ('*', :operation)
+                )
+            FROM
+                {token_table} t
+            WHERE
+                t.token = :token;
+            "
+        ),
+        named_params! {
+            ":token": token,
+            ":db_name": db_name,
+            ":action": action,
+            ":operation": operation.unwrap_or("*"),
+        },
+        |row| -> std::result::Result<(bool, i64), rusqlite::Error> {
+            Ok((row.get(0)?, row.get(1)?))
+        },
+    ) {
+        Ok((false, _)) => Ok(()),
+        Ok((_, 0)) => {
+            tracing::error!(
+                db_name,
+                action,
+                operation,
+                "Token without permission for the requested operation"
+            );
+            Err(unauthorized())
+        }
+        Ok(_) => Ok(()),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Err(unauthorized()),
+        Err(e) => {
+            tracing::error!("{}", e.to_string());
+            Err(internal_server_error(e.to_string()))
+        }
+    }
+}
+
+// NOTE: The operation is found by preparing the query with an authorizer that records its writes,
+// so a comment or a string in the query can't hide them. A query that only reads is a SELECT. A
+// query with other statements, e.g. CREATE TABLE or PRAGMA, doesn't have an operation, so it only
+// matches the "*" operation. The error of a query that can't be prepared is returned. A query with different writes, e.g. an
+// INSERT with an ON CONFLICT DO UPDATE, doesn't have an operation either. The writes of the triggers and the
+// views, created by the admins, aren't the operation of the query. The authorizer is removed
+// after, so it runs before the policies are applied to the connection.
+pub fn statement_operation(
+    conn: &Connection,
+    query: &str,
+) -> rusqlite::Result<Option<&'static str>> {
+    let operations: Arc<Mutex<Vec<Option<&'static str>>>> = Arc::new(Mutex::new(Vec::new()));
+    let recorder = Arc::clone(&operations);
+
+    conn.authorizer(Some(move |ctx: AuthContext<'_>| {
+        if ctx.accessor.is_some() {
+            return Authorization::Allow;
+        }
+
+        let operation = match ctx.action {
+            AuthAction::Select
+            | AuthAction::Read { .. }
+            | AuthAction::Function { .. }
+            | AuthAction::Recursive => return Authorization::Allow,
+            AuthAction::Insert { .. } => Some("INSERT"),
+            AuthAction::Update { .. } => Some("UPDATE"),
+            AuthAction::Delete { .. } => Some("DELETE"),
+            _ => None,
+        };
+
+        match recorder.lock() {
+            Ok(mut v) => v.push(operation),
+            Err(e) => e.into_inner().push(operation),
+        }
+
+        Authorization::Allow
+    }));
+
+    let readonly = conn.prepare(query).map(|stmt| stmt.readonly());
+
+    conn.authorizer(None::<fn(AuthContext<'_>) -> Authorization>);
+
+    let operations = match operations.lock() {
+        Ok(v) => v.to_owned(),
+        Err(e) => e.into_inner().to_owned(),
+    };
+
+    let readonly = readonly?;
+
+    if operations.contains(&None) {
+        return Ok(None);
+    }
+
+    let mut writes = operations.into_iter().flatten();
+
+    let operation = match writes.next() {
+        Some(operation) if writes.all(|v| v == operation) => Some(operation),
+        Some(_) => None,
+        None if readonly => Some("SELECT"),
+        None => None,
+    };
+
+    Ok(operation)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_test;
+
+    use super::*;
+
+    fn insert_token(name: &str) -> String {
+        let conn = connect_config_db().unwrap();
+
+        conn.execute(
+            r#"
+            INSERT INTO
+                _config_token(
+                    name,
+                    token,
+                    expiration_date,
+                    write
+                )
+            VALUES
+                (
+                    :name,
+                    token('{"sub": "' || (SELECT uuid()) ||  '", "exp": ' || strftime('%s', datetime('now')) || ', "iat": ' || strftime('%s', datetime('now')) || ', "iss": "token"}'),
+                    strftime('%s', datetime('now')),
+                    1
+                );
+            "#,
+            named_params! { ":name": name },
+        )
+        .unwrap();
+
+        conn.query_row(
+            "SELECT token FROM _config_token WHERE name = ?",
+            [name],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    db_test!(
+        test_validate_permission_without_permissions,
+        TestValidatePermissionWithoutPermissions,
+        {
+            let token = insert_token("test");
+
+            assert!(validate_permission(&token, "test.sql", "query", Some("DELETE")).is_ok());
+            assert!(validate_permission(&token, "test.sql", "migration", None).is_ok());
+        }
+    );
+
+    db_test!(
+        test_validate_permission_with_permissions,
+        TestValidatePermissionWithPermissions,
+        {
+            let token = insert_token("test");
+
+            let conn = connect_config_db().unwrap();
+
+            conn.execute(
+                "INSERT INTO _config_token_permission(token_name, db_name, action, operation) VALUES ('test', 'test.sql', 'query', 'SELECT')",
+                (),
+            )
+            .unwrap();
+
+            assert!(validate_permission(&token, "test.sql", "query", Some("SELECT")).is_ok());
+            assert_eq!(
+                validate_permission(&token, "test.sql", "query", Some("INSERT")).unwrap_err(),
+                unauthorized()
+            );
+            assert_eq!(
+                validate_permission(&token, "other.sql", "query", Some("SELECT")).unwrap_err(),
+                unauthorized()
+            );
+            assert_eq!(
+                validate_permission(&token, "test.sql", "migration", None).unwrap_err(),
+                unauthorized()
+            );
+        }
+    );
+
+    db_test!(
+        test_validate_permission_after_revoking_the_last_permission,
+        TestValidatePermissionAfterRevokingTheLastPermission,
+        {
+            let token = insert_token("test");
+
+            let conn = connect_config_db().unwrap();
+
+            conn.execute(
+                "INSERT INTO _config_token_permission(token_name, db_name, action, operation) VALUES ('test', 'test.sql', 'query', 'SELECT')",
+                (),
+            )
+            .unwrap();
+            conn.execute(
+                "DELETE FROM _config_token_permission WHERE token_name = 'test'",
+                (),
+            )
+            .unwrap();
+
+            assert_eq!(
+                validate_permission(&token, "test.sql", "query", Some("SELECT")).unwrap_err(),
+                unauthorized()
+            );
+            assert_eq!(
+                validate_permission(&token, "test.sql", "migration", None).unwrap_err(),
+                unauthorized()
+            );
+        }
+    );
+
+    db_test!(
+        test_validate_permission_with_wildcards,
+        TestValidatePermissionWithWildcards,
+        {
+            let token = insert_token("test");
+
+            let conn = connect_config_db().unwrap();
+
+            conn.execute(
+                "INSERT INTO _config_token_permission(token_name, db_name) VALUES ('test', 'test.sql')",
+                (),
+            )
+            .unwrap();
+
+            assert!(validate_permission(&token, "test.sql", "query", Some("DELETE")).is_ok());
+            assert!(validate_permission(&token, "test.sql", "migration", None).is_ok());
+            assert!(validate_permission(&token, "test.sql", "branch", None).is_ok());
+            assert_eq!(
+                validate_permission(&token, "other.sql", "query", Some("SELECT")).unwrap_err(),
+                unauthorized()
+            );
+        }
+    );
+
+    #[test]
+    fn test_statement_operation() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(
+            "
+            CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT);
+            CREATE TABLE log (id INTEGER PRIMARY KEY);
+            CREATE TRIGGER test_delete AFTER DELETE ON test BEGIN INSERT INTO log (id) VALUES (old.id); END;
+            ",
+        )
+        .unwrap();
+
+        let operation = |query: &str| statement_operation(&conn, query).unwrap();
+
+        assert_eq!(operation("SELECT * FROM test"), Some("SELECT"));
+        assert_eq!(operation("  select * FROM test"), Some("SELECT"));
+        assert_eq!(
+            operation("INSERT INTO test (name) VALUES (?)"),
+            Some("INSERT")
+        );
+        assert_eq!(
+            operation("REPLACE INTO test (name) VALUES (?)"),
+            Some("INSERT")
+        );
+        assert_eq!(
+            operation("INSERT INTO test (id, name) VALUES (1, ?) ON CONFLICT (id) DO NOTHING"),
+            Some("INSERT")
+        );
+        // NOTE: An upsert inserts and updates, so it only matches the "*" operation
+        assert_eq!(
+            operation("INSERT INTO test (id, name) VALUES (1, ?) ON CONFLICT (id) DO UPDATE SET name = excluded.name"),
+            None
+        );
+        assert_eq!(operation("UPDATE test SET name = ?"), Some("UPDATE"));
+        assert_eq!(
+            operation("UPDATE test SET name = ?, id = id + 1"),
+            Some("UPDATE")
+        );
+        assert_eq!(operation("DELETE FROM test WHERE id = ?"), Some("DELETE"));
+        assert_eq!(
+            operation("WITH cte AS (SELECT id FROM test) DELETE FROM test WHERE id IN (SELECT id FROM cte)"),
+            Some("DELETE")
+        );
+        assert_eq!(
+            operation("WITH RECURSIVE cte AS (SELECT 1 AS n UNION ALL SELECT n + 1 FROM cte WHERE n < 10) SELECT * FROM cte"),
+            Some("SELECT")
+        );
+        // NOTE: A comment or a string can't hide the write
+        assert_eq!(
+            operation("WITH y AS (SELECT 1 /*) SELECT */) DELETE FROM test"),
+            Some("DELETE")
+        );
+        assert_eq!(
+            operation("WITH y AS (SELECT ') SELECT') DELETE FROM test"),
+            Some("DELETE")
+        );
+        assert_eq!(operation("CREATE TABLE other (id INTEGER)"), None);
+        assert_eq!(operation("DROP TABLE test"), None);
+        assert_eq!(operation("PRAGMA user_version = 1"), None);
+        assert!(statement_operation(&conn, "SELECT * FROM missing").is_err());
+        assert!(statement_operation(&conn, "SELECT 1; DELETE FROM test").is_err());
+
+        // NOTE: The authorizer is removed after
+        assert!(conn.execute("DROP TABLE log", ()).is_ok());
+    }
+}
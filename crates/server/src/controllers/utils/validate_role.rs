@@ -0,0 +1,365 @@
+use rusqlite::named_params;
+use tracing::instrument;
+
+use super::{
+    get_claims::get_claims,
+    http_error::{internal_server_error, unauthorized, HttpError},
+    validate_is_admin::is_admin,
+};
+
+use crate::{controllers::database::INTERNAL_DBS, sqlite::connect_db::connect_config_db};
+
+pub const ROLE_DEPLOY_FUNCTIONS: &str = "deploy_functions";
+pub const ROLE_PUSH_ASSETS: &str = "push_assets";
+pub const ROLE_RUN_MIGRATIONS: &str = "run_migrations";
+pub const ROLE_MANAGE_BRANCHES: &str = "manage_branches";
+pub const ROLE_MANAGE_USERS: &str = "manage_users";
+pub const ROLE_MANAGE_TOKENS: &str = "manage_tokens";
+
+pub const ROLE_PERMISSIONS: [&str; 6] = [
+    ROLE_DEPLOY_FUNCTIONS,
+    ROLE_PUSH_ASSETS,
+    ROLE_RUN_MIGRATIONS,
+    ROLE_MANAGE_BRANCHES,
+    ROLE_MANAGE_USERS,
+    ROLE_MANAGE_TOKENS,
+];
+
+// NOTE: The admins have every permission. The other users and the tokens have the permissions of
+// the roles assigned to them.
+#[instrument(err(Debug), skip(token))]
+pub fn validate_role(token: &str, permission: &str) -> Result<(), HttpError> {
+    if is_admin(token)? {
+        return Ok(());
+    }
+
+    let conn = match connect_config_db() {
+        Ok(c) => c,
+        Err(e) => return Err(internal_server_error(e.to_string())),
+    };
+
+    let token_permissions = token_permissions(token)?;
+
+    match conn.query_row(
+        &format!(
+            "
+            SELECT
+                COUNT(*)
+            FROM
+                ({token_permissions})
+            WHERE
+                permission = :permission;
+            "
+        ),
+        named_params! {
+            ":token": token,
+            ":permission": permission,
+        },
+        |row| row.get::<_, i64>(0),
+    ) {
+        Ok(0) => {
+            tracing::error!(permission, "Token without a role with the permission");
+            Err(unauthorized())
+        }
+        Ok(_) => Ok(()),
+        Err(e) => {
+            tracing::error!("{}", e.to_string());
+            Err(internal_server_error(e.to_string()))
+        }
+    }
+}
+
+// NOTE: The permissions of the roles of a token, as a query with the :token parameter
+fn token_permissions(token: &str) -> Result<String, HttpError> {
+    let (token_table, role_table, relation) = match get_claims(token)?.iss.as_str() {
+        "user_token" => (
+            "_config_user_token",
+            "_config_user_role",
+            "r.user_uuid = t.user_uuid AND r.user_uuid IN (SELECT uuid FROM _config_user WHERE active = 1)",
+        ),
+        "token" => ("_config_token", "_config_token_role", "r.token_name = t.name"),
+        _ => return Err(unauthorized()),
+    };
+
+    Ok(format!(
+        "
+        SELECT
+            p.permission
+        FROM
+            {token_table} t
+        JOIN
+            {role_table} r ON {relation}
+        JOIN
+            _config_role_permission p ON p.role_name = r.role_name
+        WHERE
+            t.token = :token
+        "
+    ))
+}
+
+// NOTE: The users with the manage_users permission and the tokens with the manage_tokens
+// permission can only read and change the users and the tokens that don't have more permissions,
+// otherwise they could use the value of a token with other roles
+fn validate_held_permissions(
+    token: &str,
+    target_permissions: &str,
+    target: &str,
+) -> Result<(), HttpError> {
+    let conn = match connect_config_db() {
+        Ok(c) => c,
+        Err(e) => return Err(internal_server_error(e.to_string())),
+    };
+
+    let token_permissions = token_permissions(token)?;
+
+    match conn.query_row(
+        &format!(
+            "
+            SELECT
+                COUNT(*)
+            FROM
+                ({target_permissions})
+            WHERE
+                permission NOT IN ({token_permissions});
+            "
+        ),
+        named_params! {
+            ":token": token,
+            ":target": target,
+        },
+        |row| row.get::<_, i64>(0),
+    ) {
+        Ok(0) => Ok(()),
+        Ok(_) => {
+            tracing::error!("Token without every permission of the target");
+            Err(unauthorized())
+        }
+        Err(e) => {
+            tracing::error!("{}", e.to_string());
+            Err(internal_server_error(e.to_string()))
+        }
+    }
+}
+
+// NOTE: Only the admins can read or change a token with permissions that the token of the
+// request doesn't have
+#[instrument(err(Debug), skip(token))]
+pub fn validate_token_roles(token: &str, name: &str) -> Result<(), HttpError> {
+    if is_admin(token)? {
+        return Ok(());
+    }
+
+    validate_held_permissions(
+        token,
+        "
+        SELECT
+            p.permission
+        FROM
+            _config_token_role r
+        JOIN
+            _config_role_permission p ON p.role_name = r.role_name
+        WHERE
+            r.token_name = :target
+        ",
+        name,
+    )
+}
+
+// NOTE: Only the admins can change the internal databases, otherwise a role could grant itself
+// any permission in the config database, hide its operations in the audit database or run any
+// code changing the functions database
+#[instrument(err(Debug), skip(token))]
+pub fn validate_internal_db_access(token: &str, db_name: &str) -> Result<(), HttpError> {
+    if INTERNAL_DBS.contains(&db_name) && !is_admin(token)? {
+        tracing::error!("Only the admins can access the internal databases");
+        return Err(unauthorized());
+    }
+
+    Ok(())
+}
+
+// NOTE: Only the admins can create admins, or change the admins and their tokens, otherwise the
+// users with the manage_users permission could become admins. The same applies to the users with
+// permissions that the token of the request doesn't have.
+#[instrument(err(Debug), skip(token))]
+pub fn validate_admin_user(token: &str, email: &str, admin: Option<bool>) -> Result<(), HttpError> {
+    if is_admin(token)? {
+        return Ok(());
+    }
+
+    if admin == Some(true) {
+        tracing::error!("Only the admins can create admins");
+        return Err(unauthorized());
+    }
+
+    let conn = match connect_config_db() {
+        Ok(c) => c,
+        Err(e) => return Err(internal_server_error(e.to_string())),
+    };
+
+    match conn.query_row(
+        "SELECT COUNT(*) FROM _config_user WHERE email = ? AND admin = 1",
+        [email],
+        |row| row.get::<_, i64>(0),
+    ) {
+        Ok(0) => validate_held_permissions(
+            token,
+            "
+            SELECT
+                p.permission
+            FROM
+                _config_user u
+            JOIN
+                _config_user_role r ON r.user_uuid = u.uuid
+            JOIN
+                _config_role_permission p ON p.role_name = r.role_name
+            WHERE
+                u.email = :target
+            ",
+            email,
+        ),
+        Ok(_) => {
+            tracing::error!("Only the admins can change the admins");
+            Err(unauthorized())
+        }
+        Err(e) => {
+            tracing::error!("{}", e.to_string());
+            Err(internal_server_error(e.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        constants::{DB_AUDIT_NAME, DB_CONFIG_NAME, DB_FUNCTION_NAME},
+        db_test,
+    };
+
+    use super::*;
+
+    fn user_token(email: &str) -> String {
+        connect_config_db()
+            .unwrap()
+            .query_row(
+                "SELECT token FROM _config_user_token WHERE user_uuid = (SELECT uuid FROM _config_user WHERE email = ?)",
+                [email],
+                |row| row.get(0),
+            )
+            .unwrap()
+    }
+
+    db_test!(test_validate_role, TestValidateRole, {
+        let conn = connect_config_db().unwrap();
+
+        conn.execute_batch(
+            r#"
+            INSERT INTO _config_user(email, password, admin) VALUES ('admin@test.com', 'password', 1);
+            INSERT INTO _config_user(email, password, admin) VALUES ('deployer@test.com', 'password', 0);
+            INSERT INTO _config_user(email, password, admin) VALUES ('other@test.com', 'password', 0);
+            INSERT INTO _config_token(name, token, expiration_date, write)
+                VALUES ('ci', token('{"sub": "uuid", "exp": 0, "iat": 0, "iss": "token"}'), 0, 1);
+            INSERT INTO _config_role(name) VALUES ('deployer');
+            INSERT INTO _config_role_permission(role_name, permission) VALUES ('deployer', 'deploy_functions');
+            INSERT INTO _config_role_permission(role_name, permission) VALUES ('deployer', 'push_assets');
+            INSERT INTO _config_role(name) VALUES ('migrator');
+            INSERT INTO _config_role_permission(role_name, permission) VALUES ('migrator', 'run_migrations');
+            INSERT INTO _config_user_role(user_uuid, role_name)
+                VALUES ((SELECT uuid FROM _config_user WHERE email = 'other@test.com'), 'migrator');
+            INSERT INTO _config_user_role(user_uuid, role_name)
+                VALUES ((SELECT uuid FROM _config_user WHERE email = 'deployer@test.com'), 'deployer');
+            INSERT INTO _config_token_role(token_name, role_name) VALUES ('ci', 'deployer');
+            "#,
+        )
+        .unwrap();
+
+        let admin = user_token("admin@test.com");
+        let deployer = user_token("deployer@test.com");
+        let other = user_token("other@test.com");
+        let ci: String = conn
+            .query_row(
+                "SELECT token FROM _config_token WHERE name = 'ci'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert!(validate_role(&admin, ROLE_MANAGE_USERS).is_ok());
+        assert!(validate_role(&deployer, ROLE_DEPLOY_FUNCTIONS).is_ok());
+        assert!(validate_role(&deployer, ROLE_PUSH_ASSETS).is_ok());
+        assert!(validate_role(&ci, ROLE_DEPLOY_FUNCTIONS).is_ok());
+        assert_eq!(
+            validate_role(&deployer, ROLE_MANAGE_USERS).unwrap_err(),
+            unauthorized()
+        );
+        assert_eq!(
+            validate_role(&ci, ROLE_RUN_MIGRATIONS).unwrap_err(),
+            unauthorized()
+        );
+        assert_eq!(
+            validate_role(&other, ROLE_DEPLOY_FUNCTIONS).unwrap_err(),
+            unauthorized()
+        );
+
+        assert!(validate_internal_db_access(&admin, DB_CONFIG_NAME).is_ok());
+        assert!(validate_internal_db_access(&deployer, "example.sql").is_ok());
+        assert_eq!(
+            validate_internal_db_access(&deployer, DB_CONFIG_NAME).unwrap_err(),
+            unauthorized()
+        );
+        assert_eq!(
+            validate_internal_db_access(&deployer, DB_AUDIT_NAME).unwrap_err(),
+            unauthorized()
+        );
+        assert!(validate_role(&other, ROLE_RUN_MIGRATIONS).is_ok());
+        assert_eq!(
+            validate_internal_db_access(&other, DB_FUNCTION_NAME).unwrap_err(),
+            unauthorized()
+        );
+
+        assert!(validate_admin_user(&admin, "admin@test.com", Some(true)).is_ok());
+        assert!(validate_admin_user(&deployer, "deployer@test.com", Some(false)).is_ok());
+        assert!(validate_admin_user(&deployer, "missing@test.com", Some(false)).is_ok());
+        // NOTE: The other user has the run_migrations permission, that the deployer doesn't have
+        assert_eq!(
+            validate_admin_user(&deployer, "other@test.com", Some(false)).unwrap_err(),
+            unauthorized()
+        );
+        assert!(validate_admin_user(&admin, "other@test.com", None).is_ok());
+
+        assert!(validate_token_roles(&admin, "ci").is_ok());
+        assert!(validate_token_roles(&deployer, "ci").is_ok());
+        assert!(validate_token_roles(&deployer, "missing").is_ok());
+        assert_eq!(
+            validate_token_roles(&other, "ci").unwrap_err(),
+            unauthorized()
+        );
+        assert_eq!(
+            validate_admin_user(&deployer, "other@test.com", Some(true)).unwrap_err(),
+            unauthorized()
+        );
+        assert_eq!(
+            validate_admin_user(&deployer, "admin@test.com", None).unwrap_err(),
+            unauthorized()
+        );
+
+        conn.execute(
+            "UPDATE _config_user SET active = 0 WHERE email = 'deployer@test.com'",
+            (),
+        )
+        .unwrap();
+
+        assert_eq!(
+            validate_role(&deployer, ROLE_DEPLOY_FUNCTIONS).unwrap_err(),
+            unauthorized()
+        );
+
+        conn.execute("DELETE FROM _config_role WHERE name = 'deployer'", ())
+            .unwrap();
+
+        assert_eq!(
+            validate_role(&ci, ROLE_DEPLOY_FUNCTIONS).unwrap_err(),
+            unauthorized()
+        );
+    });
+}
@@ -2,16 +2,17 @@ use rusqlite::{named_params, Connection};
 use tracing::instrument;
 
 use super::{
+    client_ip::{client_ip, is_ip_allowed},
     get_claims::get_claims,
     http_error::{internal_server_error, unauthorized, HttpError},
 };
 
-use crate::sqlite::connect_db::connect_config_db;
+use crate::sqlite::{connect_db::connect_config_db, token_usage::record_token_use};
 
 #[instrument(err(Debug), skip(token))]
 pub fn validate_token(token: &str) -> Result<(), HttpError> {
-    let connect: Result<Connection, HttpError> = match connect_config_db() {
-        Ok(c) => Ok(c),
+    let conn: Connection = match connect_config_db() {
+        Ok(c) => c,
         Err(e) => return Err(internal_server_error(e.to_string())),
     };
 
@@ -25,7 +26,7 @@ pub fn validate_token(token: &str) -> Result<(), HttpError> {
         _ => return Err(unauthorized()),
     };
 
-    match connect?.query_row(
+    match conn.query_row(
         &format!(
             "
             SELECT
@@ -55,6 +56,7 @@ pub fn validate_token(token: &str) -> Result<(), HttpError> {
             tracing::error!("0");
             Err(unauthorized())
         }
+        Ok(Some(_)) if table == "_config_token" => validate_token_access(&conn, token),
         Ok(Some(_)) => Ok(()),
         Ok(None) => {
             tracing::error!("None");
@@ -67,11 +69,62 @@ pub fn validate_token(token: &str) -> Result<(), HttpError> {
     }
 }
 
+// NOTE: A revoked token is rejected, and a token with allowed IPs is only accepted from them. The
+// last use of the token is recorded in batches.
+fn validate_token_access(conn: &Connection, token: &str) -> Result<(), HttpError> {
+    let access = conn.query_row(
+        "
+        SELECT
+            t.name,
+            COALESCE(a.allowed_ips, ''),
+            a.revoked_at
+        FROM
+            _config_token t
+        LEFT JOIN
+            _config_token_access a ON a.token_name = t.name
+        WHERE
+            t.token = :token;
+        ",
+        named_params! { ":token": token },
+        |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+            ))
+        },
+    );
+
+    let (name, allowed_ips, revoked_at) = match access {
+        Ok(v) => v,
+        Err(e) => {
+            tracing::error!("{}", e.to_string());
+            return Err(unauthorized());
+        }
+    };
+
+    if revoked_at.is_some() {
+        tracing::error!("The token {} is revoked", name);
+        return Err(unauthorized());
+    }
+
+    let ip = client_ip();
+
+    if !is_ip_allowed(ip, &allowed_ips) {
+        tracing::error!("The token {} is not allowed from {:?}", name, ip);
+        return Err(unauthorized());
+    }
+
+    record_token_use(&name, ip);
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use jsonwebtoken::{encode, EncodingKey, Header};
 
-    use crate::controllers::utils::get_claims::Claims;
+    use crate::controllers::utils::{client_ip::CLIENT_IP, get_claims::Claims};
     use crate::db_test;
 
     use super::*;
@@ -177,6 +230,71 @@ mod tests {
         }
     );
 
+    db_test!(
+        test_validate_token_revoked_and_allowed_ips,
+        TestValidateTokenRevokedAndAllowedIps,
+        {
+            let conn = connect_config_db().unwrap();
+
+            conn.execute(
+            r#"
+            INSERT INTO
+                _config_token(
+                    name,
+                    token,
+                    expiration_date,
+                    write
+                )
+            VALUES
+                (
+                    'test',
+                    token('{"sub": "' || (SELECT uuid()) ||  '", "exp": ' || strftime('%s', datetime('now')) || ', "iat": ' || strftime('%s', datetime('now')) || ', "iss": "token"}'),
+                    strftime('%s', datetime('now')),
+                    1
+                );
+            "#,
+            (),
+        )
+        .unwrap();
+
+            let token: String = conn
+                .query_row("SELECT token FROM _config_token WHERE id = 1", [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+
+            conn.execute(
+                "INSERT INTO _config_token_access(token_name, allowed_ips) VALUES ('test', '10.0.0.0/8')",
+                (),
+            )
+            .unwrap();
+
+            assert_eq!(validate_token(&token).unwrap_err(), unauthorized());
+            assert!(CLIENT_IP
+                .sync_scope("10.1.2.3".parse().unwrap(), || validate_token(&token))
+                .is_ok());
+            assert_eq!(
+                CLIENT_IP
+                    .sync_scope("11.1.2.3".parse().unwrap(), || validate_token(&token))
+                    .unwrap_err(),
+                unauthorized()
+            );
+
+            conn.execute(
+                "UPDATE _config_token_access SET revoked_at = strftime('%s', datetime('now'))",
+                (),
+            )
+            .unwrap();
+
+            assert_eq!(
+                CLIENT_IP
+                    .sync_scope("10.1.2.3".parse().unwrap(), || validate_token(&token))
+                    .unwrap_err(),
+                unauthorized()
+            );
+        }
+    );
+
     db_test!(test_validate_token_expired, TestValidateTokenExpired, {
         let conn = connect_config_db().unwrap();
 
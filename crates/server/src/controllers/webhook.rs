@@ -0,0 +1,280 @@
+use anyhow::Result;
+use hyper::{body::Incoming, Method, Request, Response, Uri};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tracing::instrument;
+
+use crate::{
+    changes::webhook::{
+        delete_webhook, list_deliveries, list_webhooks, save_webhook, Webhook, OPERATIONS,
+    },
+    constants::DB_CONFIG_NAME,
+    controllers::{
+        openapi::{OpenApi, Operation},
+        utils::{
+            body::{Body, BoxBody},
+            get_query_string::get_query_string,
+            get_token::get_token,
+            http_error::{bad_request, internal_server_error, not_found, HttpError},
+            responses::{created, ok},
+            validate_db_exists::validate_db_exists,
+            validate_db_name::validate_db_name,
+            validate_is_admin::validate_is_admin,
+            validate_token::validate_token,
+        },
+    },
+};
+
+const DEFAULT_DELIVERIES_LIMIT: i64 = 20;
+
+#[derive(Deserialize, JsonSchema)]
+struct SaveWebhookOptions {
+    name: String,
+    db_name: String,
+    table_name: Option<String>,
+    operations: Option<String>,
+    url: String,
+    secret: String,
+    active: Option<bool>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeleteWebhookOptions {
+    name: String,
+}
+
+#[instrument(err(Debug), skip(req))]
+pub async fn webhook(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, ["webhook"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            match list_webhooks() {
+                Ok(v) => {
+                    let data = v.iter().map(|w| w.to_value()).collect::<Vec<Value>>();
+
+                    match ok(json!({ "data": data }).to_string()) {
+                        Ok(r) => Ok(r),
+                        Err(e) => Err(internal_server_error(e.to_string())),
+                    }
+                }
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::POST, ["webhook"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: SaveWebhookOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            // IMPORTANT! don't remove this validation
+            validate_db_name(&options.db_name)?;
+
+            if options.db_name == DB_CONFIG_NAME {
+                return Err(bad_request(
+                    "The changes of the config database can't be sent to webhooks".to_string(),
+                ));
+            }
+
+            // IMPORTANT! don't remove this validation
+            validate_db_exists(&options.db_name)?;
+
+            let url = validate_url(&options.url)?;
+            let operations = normalize_operations(options.operations.as_deref())?;
+
+            if options.secret.is_empty() {
+                return Err(bad_request("The secret can't be empty".to_string()));
+            }
+
+            let webhook = Webhook {
+                id: 0,
+                name: options.name,
+                db_name: options.db_name,
+                table_name: options.table_name.unwrap_or("*".to_string()),
+                operations,
+                url,
+                secret: options.secret,
+                active: options.active.unwrap_or(true),
+                cursor: 0,
+                attempts: 0,
+                retry_at: None,
+            };
+
+            match save_webhook(&webhook) {
+                Ok(_) => match created() {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(bad_request(e.to_string())),
+            }
+        }
+        (&Method::DELETE, ["webhook"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let body = Body::to_string(req.body_mut()).await?;
+
+            let options: DeleteWebhookOptions = match serde_json::from_str(&body) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            match delete_webhook(&options.name) {
+                Ok(0) => Err(not_found()),
+                Ok(_) => match ok("") {
+                    Ok(r) => Ok(r),
+                    Err(e) => Err(internal_server_error(e.to_string())),
+                },
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        (&Method::GET, ["webhook", "delivery"]) => {
+            // IMPORTANT! don't remove this validation
+            validate_request(req)?;
+
+            let name = match get_query_string(req, "name") {
+                Ok(v) => Ok(v),
+                Err(e) => Err(bad_request(e.to_string())),
+            }?;
+
+            let limit = match get_query_string(req, "limit") {
+                Ok(v) => match v.parse::<i64>() {
+                    Ok(v) => Ok(v),
+                    Err(_) => Err(bad_request("The limit should be a number".to_string())),
+                },
+                Err(_) => Ok(DEFAULT_DELIVERIES_LIMIT),
+            }?;
+
+            match list_deliveries(&name, limit) {
+                Ok(v) => {
+                    let data = v.iter().map(|d| d.to_value()).collect::<Vec<Value>>();
+
+                    match ok(json!({ "data": data }).to_string()) {
+                        Ok(r) => Ok(r),
+                        Err(e) => Err(internal_server_error(e.to_string())),
+                    }
+                }
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    let save = api.schema::<SaveWebhookOptions>();
+    let delete = api.schema::<DeleteWebhookOptions>();
+
+    api.add(
+        Method::GET,
+        "/_/webhook",
+        Operation::new("Webhook", "List the webhooks").data(json!({ "type": "array" })),
+    );
+    api.add(
+        Method::POST,
+        "/_/webhook",
+        Operation::new("Webhook", "Create or update a webhook")
+            .body(save)
+            .created(),
+    );
+    api.add(
+        Method::DELETE,
+        "/_/webhook",
+        Operation::new("Webhook", "Delete a webhook").body(delete),
+    );
+    api.add(
+        Method::GET,
+        "/_/webhook/delivery",
+        Operation::new("Webhook", "List the last delivery attempts of a webhook")
+            .query("name", "string", true)
+            .query("limit", "integer", false)
+            .data(json!({ "type": "array" })),
+    );
+}
+
+fn validate_request(req: &Request<Incoming>) -> Result<(), HttpError> {
+    let token = get_token(req.headers().to_owned())?;
+
+    // IMPORTANT! don't remove this validation
+    validate_token(&token)?;
+    // IMPORTANT! don't remove this validation
+    validate_is_admin(&token)?;
+
+    Ok(())
+}
+
+fn validate_url(url: &str) -> Result<String, HttpError> {
+    let uri = match url.parse::<Uri>() {
+        Ok(v) => Ok(v),
+        Err(e) => Err(bad_request(e.to_string())),
+    }?;
+
+    match (uri.scheme_str(), uri.host()) {
+        (Some("http" | "https"), Some(_)) => Ok(url.to_string()),
+        _ => Err(bad_request(format!(
+            "The URL {} should be an absolute http or https URL",
+            url
+        ))),
+    }
+}
+
+// NOTE: The operations are stored in upper case and without spaces, e.g. INSERT,DELETE
+fn normalize_operations(operations: Option<&str>) -> Result<String, HttpError> {
+    let operations = match operations {
+        None | Some("*") => return Ok("*".to_string()),
+        Some(v) => v,
+    };
+
+    let mut normalized = Vec::new();
+
+    for operation in operations.split(',') {
+        let operation = operation.trim().to_uppercase();
+
+        if !OPERATIONS.contains(&operation.as_str()) {
+            return Err(bad_request(format!(
+                "The operation {} should be INSERT, UPDATE or DELETE",
+                operation
+            )));
+        }
+
+        if !normalized.contains(&operation) {
+            normalized.push(operation);
+        }
+    }
+
+    Ok(normalized.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_operations() {
+        assert_eq!(normalize_operations(None).unwrap(), "*");
+        assert_eq!(normalize_operations(Some("*")).unwrap(), "*");
+        assert_eq!(
+            normalize_operations(Some("insert, DELETE,insert")).unwrap(),
+            "INSERT,DELETE"
+        );
+        assert!(normalize_operations(Some("SELECT")).is_err());
+    }
+
+    #[test]
+    fn test_validate_url() {
+        assert!(validate_url("https://example.com/hook").is_ok());
+        assert!(validate_url("http://localhost:8080/hook").is_ok());
+        assert!(validate_url("ftp://example.com/hook").is_err());
+        assert!(validate_url("/hook").is_err());
+    }
+}
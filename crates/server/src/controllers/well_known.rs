@@ -0,0 +1,50 @@
+use hyper::{body::Incoming, Method, Request, Response};
+use serde_json::json;
+use tracing::instrument;
+
+use super::{
+    openapi::{OpenApi, Operation},
+    utils::{
+        body::BoxBody,
+        http_error::{internal_server_error, not_found, HttpError},
+        responses::ok,
+        token_keys::jwks,
+    },
+};
+
+#[instrument(err(Debug), skip(req))]
+pub async fn well_known(
+    req: &mut Request<Incoming>,
+    segments: &[&str],
+) -> Result<Response<BoxBody>, HttpError> {
+    match (req.method(), segments) {
+        (&Method::GET, [".well-known", "jwks.json"]) => {
+            let keys = match jwks() {
+                Ok(v) => v,
+                Err(e) => return Err(internal_server_error(e.to_string())),
+            };
+
+            match ok(keys.to_string()) {
+                Ok(r) => Ok(r),
+                Err(e) => Err(internal_server_error(e.to_string())),
+            }
+        }
+        _ => Err(not_found()),
+    }
+}
+
+pub fn openapi(api: &mut OpenApi) {
+    api.add(
+        Method::GET,
+        "/_/.well-known/jwks.json",
+        Operation::new(
+            "Token",
+            "Get the public keys that verify the tokens, in the JSON Web Key Set format",
+        )
+        .json_response(json!({
+            "type": "object",
+            "properties": { "keys": { "type": "array", "items": { "type": "object" } } },
+        }))
+        .public(),
+    );
+}
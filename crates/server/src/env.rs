@@ -30,10 +30,98 @@ impl Env {
         when_proxy_port()
     }
 
+    pub fn persisted_queries_only() -> String {
+        when_persisted_queries_only()
+    }
+
+    pub fn rest_dbs() -> String {
+        when_rest_dbs()
+    }
+
+    pub fn changes_interval() -> u64 {
+        when_changes_interval()
+    }
+
+    pub fn webhook_max_attempts() -> i64 {
+        when_webhook_max_attempts()
+    }
+
+    pub fn branch_expiry_interval() -> u64 {
+        when_branch_expiry_interval()
+    }
+
+    pub fn token_usage_interval() -> u64 {
+        when_token_usage_interval()
+    }
+
+    pub fn trusted_proxies() -> String {
+        when_trusted_proxies()
+    }
+
+    pub fn branch_preview_domain() -> String {
+        when_branch_preview_domain()
+    }
+
+    pub fn wal_archive_path() -> String {
+        when_wal_archive_path()
+    }
+
+    pub fn wal_archive_interval() -> u64 {
+        when_wal_archive_interval()
+    }
+
+    pub fn wal_archive_snapshot_interval() -> u64 {
+        when_wal_archive_snapshot_interval()
+    }
+
+    pub fn wal_archive_retention() -> u64 {
+        when_wal_archive_retention()
+    }
+
+    pub fn backup_path() -> String {
+        when_backup_path()
+    }
+
+    pub fn backup_dbs() -> String {
+        when_backup_dbs()
+    }
+
+    pub fn backup_interval() -> u64 {
+        when_backup_interval()
+    }
+
+    pub fn backup_keep_daily() -> usize {
+        when_backup_keep_daily()
+    }
+
+    pub fn backup_keep_weekly() -> usize {
+        when_backup_keep_weekly()
+    }
+
     pub fn token_secret() -> String {
         when_token_secret()
     }
 
+    pub fn access_token_ttl() -> i64 {
+        when_access_token_ttl()
+    }
+
+    pub fn refresh_token_ttl() -> i64 {
+        when_refresh_token_ttl()
+    }
+
+    pub fn token_keys_path() -> String {
+        when_token_keys_path()
+    }
+
+    pub fn token_key_id() -> String {
+        when_token_key_id()
+    }
+
+    pub fn token_legacy_secret() -> String {
+        when_token_legacy_secret()
+    }
+
     pub fn admin_email() -> String {
         when_admin_email()
     }
@@ -69,10 +157,134 @@ fn when_proxy_port() -> u16 {
         .unwrap()
 }
 
+fn when_persisted_queries_only() -> String {
+    env::var("QUERY_SERVER_PERSISTED_QUERIES_ONLY").unwrap_or("false".to_string())
+}
+
+fn when_rest_dbs() -> String {
+    env::var("QUERY_SERVER_REST_DBS").unwrap_or("".to_string())
+}
+
+fn when_changes_interval() -> u64 {
+    env::var("QUERY_SERVER_CHANGES_INTERVAL")
+        .unwrap_or("1".to_string())
+        .parse::<u64>()
+        .unwrap()
+}
+
+fn when_webhook_max_attempts() -> i64 {
+    env::var("QUERY_SERVER_WEBHOOK_MAX_ATTEMPTS")
+        .unwrap_or("10".to_string())
+        .parse::<i64>()
+        .unwrap()
+}
+
+fn when_branch_expiry_interval() -> u64 {
+    env::var("QUERY_SERVER_BRANCH_EXPIRY_INTERVAL")
+        .unwrap_or("60".to_string())
+        .parse::<u64>()
+        .unwrap()
+}
+
+fn when_token_usage_interval() -> u64 {
+    env::var("QUERY_SERVER_TOKEN_USAGE_INTERVAL")
+        .unwrap_or("60".to_string())
+        .parse::<u64>()
+        .unwrap()
+}
+
+fn when_trusted_proxies() -> String {
+    env::var("QUERY_SERVER_TRUSTED_PROXIES").unwrap_or("".to_string())
+}
+
+fn when_branch_preview_domain() -> String {
+    env::var("QUERY_SERVER_BRANCH_PREVIEW_DOMAIN").unwrap_or("".to_string())
+}
+
+fn when_wal_archive_path() -> String {
+    env::var("QUERY_SERVER_WAL_ARCHIVE_PATH").unwrap_or("".to_string())
+}
+
+fn when_wal_archive_interval() -> u64 {
+    env::var("QUERY_SERVER_WAL_ARCHIVE_INTERVAL")
+        .unwrap_or("1".to_string())
+        .parse::<u64>()
+        .unwrap()
+}
+
+fn when_wal_archive_snapshot_interval() -> u64 {
+    env::var("QUERY_SERVER_WAL_ARCHIVE_SNAPSHOT_INTERVAL")
+        .unwrap_or("86400".to_string())
+        .parse::<u64>()
+        .unwrap()
+}
+
+fn when_wal_archive_retention() -> u64 {
+    env::var("QUERY_SERVER_WAL_ARCHIVE_RETENTION")
+        .unwrap_or("604800".to_string())
+        .parse::<u64>()
+        .unwrap()
+}
+
+fn when_backup_path() -> String {
+    env::var("QUERY_SERVER_BACKUP_PATH").unwrap_or("".to_string())
+}
+
+fn when_backup_dbs() -> String {
+    env::var("QUERY_SERVER_BACKUP_DBS").unwrap_or("".to_string())
+}
+
+fn when_backup_interval() -> u64 {
+    env::var("QUERY_SERVER_BACKUP_INTERVAL")
+        .unwrap_or("86400".to_string())
+        .parse::<u64>()
+        .unwrap()
+}
+
+fn when_backup_keep_daily() -> usize {
+    env::var("QUERY_SERVER_BACKUP_KEEP_DAILY")
+        .unwrap_or("7".to_string())
+        .parse::<usize>()
+        .unwrap()
+}
+
+fn when_backup_keep_weekly() -> usize {
+    env::var("QUERY_SERVER_BACKUP_KEEP_WEEKLY")
+        .unwrap_or("4".to_string())
+        .parse::<usize>()
+        .unwrap()
+}
+
 fn when_token_secret() -> String {
     env::var("QUERY_SERVER_TOKEN_SECRET").expect("QUERY_SERVER_TOKEN_SECRET is not set")
 }
 
+fn when_access_token_ttl() -> i64 {
+    env::var("QUERY_SERVER_ACCESS_TOKEN_TTL")
+        .unwrap_or("900".to_string())
+        .parse::<i64>()
+        .unwrap()
+}
+
+fn when_refresh_token_ttl() -> i64 {
+    env::var("QUERY_SERVER_REFRESH_TOKEN_TTL")
+        .unwrap_or("2592000".to_string())
+        .parse::<i64>()
+        .unwrap()
+}
+
+fn when_token_keys_path() -> String {
+    env::var("QUERY_SERVER_TOKEN_KEYS_PATH").unwrap_or("".to_string())
+}
+
+fn when_token_key_id() -> String {
+    env::var("QUERY_SERVER_TOKEN_KEY_ID").unwrap_or("".to_string())
+}
+
+fn when_token_legacy_secret() -> String {
+    env::var("QUERY_SERVER_TOKEN_LEGACY_SECRET").unwrap_or("false".to_string())
+}
+
 fn when_admin_email() -> String {
     env::var("QUERY_SERVER_ADMIN_EMAIL").expect("QUERY_SERVER_ADMIN_EMAIL is not set")
 }
@@ -183,6 +395,312 @@ mod tests {
         assert_eq!(Env::proxy_port(), 3001);
     }
 
+    #[test]
+    fn test_persisted_queries_only() {
+        before();
+
+        env::set_var("QUERY_SERVER_PERSISTED_QUERIES_ONLY", "true");
+
+        assert_eq!(Env::persisted_queries_only(), "true");
+    }
+
+    #[test]
+    fn test_persisted_queries_only_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_PERSISTED_QUERIES_ONLY");
+
+        assert_eq!(Env::persisted_queries_only(), "false");
+    }
+
+    #[test]
+    fn test_rest_dbs() {
+        before();
+
+        env::set_var("QUERY_SERVER_REST_DBS", "example.sql,other.sql");
+
+        assert_eq!(Env::rest_dbs(), "example.sql,other.sql");
+    }
+
+    #[test]
+    fn test_rest_dbs_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_REST_DBS");
+
+        assert_eq!(Env::rest_dbs(), "");
+    }
+
+    #[test]
+    fn test_changes_interval() {
+        before();
+
+        env::set_var("QUERY_SERVER_CHANGES_INTERVAL", "5");
+
+        assert_eq!(Env::changes_interval(), 5);
+    }
+
+    #[test]
+    fn test_changes_interval_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_CHANGES_INTERVAL");
+
+        assert_eq!(Env::changes_interval(), 1);
+    }
+
+    #[test]
+    fn test_webhook_max_attempts() {
+        before();
+
+        env::set_var("QUERY_SERVER_WEBHOOK_MAX_ATTEMPTS", "3");
+
+        assert_eq!(Env::webhook_max_attempts(), 3);
+    }
+
+    #[test]
+    fn test_webhook_max_attempts_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_WEBHOOK_MAX_ATTEMPTS");
+
+        assert_eq!(Env::webhook_max_attempts(), 10);
+    }
+
+    #[test]
+    fn test_branch_expiry_interval() {
+        before();
+
+        env::set_var("QUERY_SERVER_BRANCH_EXPIRY_INTERVAL", "5");
+
+        assert_eq!(Env::branch_expiry_interval(), 5);
+    }
+
+    #[test]
+    fn test_branch_expiry_interval_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_BRANCH_EXPIRY_INTERVAL");
+
+        assert_eq!(Env::branch_expiry_interval(), 60);
+    }
+
+    #[test]
+    fn test_token_usage_interval() {
+        before();
+
+        env::set_var("QUERY_SERVER_TOKEN_USAGE_INTERVAL", "10");
+
+        assert_eq!(Env::token_usage_interval(), 10);
+    }
+
+    #[test]
+    fn test_token_usage_interval_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_TOKEN_USAGE_INTERVAL");
+
+        assert_eq!(Env::token_usage_interval(), 60);
+    }
+
+    #[test]
+    fn test_trusted_proxies() {
+        before();
+
+        env::set_var("QUERY_SERVER_TRUSTED_PROXIES", "10.0.0.0/8");
+
+        assert_eq!(Env::trusted_proxies(), "10.0.0.0/8");
+    }
+
+    #[test]
+    fn test_trusted_proxies_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_TRUSTED_PROXIES");
+
+        assert_eq!(Env::trusted_proxies(), "");
+    }
+
+    #[test]
+    fn test_branch_preview_domain() {
+        before();
+
+        env::set_var("QUERY_SERVER_BRANCH_PREVIEW_DOMAIN", "preview.example.com");
+
+        assert_eq!(Env::branch_preview_domain(), "preview.example.com");
+    }
+
+    #[test]
+    fn test_branch_preview_domain_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_BRANCH_PREVIEW_DOMAIN");
+
+        assert_eq!(Env::branch_preview_domain(), "");
+    }
+
+    #[test]
+    fn test_wal_archive_path() {
+        before();
+
+        env::set_var("QUERY_SERVER_WAL_ARCHIVE_PATH", "archive");
+
+        assert_eq!(Env::wal_archive_path(), "archive");
+    }
+
+    #[test]
+    fn test_wal_archive_path_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_WAL_ARCHIVE_PATH");
+
+        assert_eq!(Env::wal_archive_path(), "");
+    }
+
+    #[test]
+    fn test_wal_archive_interval() {
+        before();
+
+        env::set_var("QUERY_SERVER_WAL_ARCHIVE_INTERVAL", "10");
+
+        assert_eq!(Env::wal_archive_interval(), 10);
+    }
+
+    #[test]
+    fn test_wal_archive_interval_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_WAL_ARCHIVE_INTERVAL");
+
+        assert_eq!(Env::wal_archive_interval(), 1);
+    }
+
+    #[test]
+    fn test_wal_archive_snapshot_interval() {
+        before();
+
+        env::set_var("QUERY_SERVER_WAL_ARCHIVE_SNAPSHOT_INTERVAL", "3600");
+
+        assert_eq!(Env::wal_archive_snapshot_interval(), 3600);
+    }
+
+    #[test]
+    fn test_wal_archive_snapshot_interval_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_WAL_ARCHIVE_SNAPSHOT_INTERVAL");
+
+        assert_eq!(Env::wal_archive_snapshot_interval(), 86400);
+    }
+
+    #[test]
+    fn test_wal_archive_retention() {
+        before();
+
+        env::set_var("QUERY_SERVER_WAL_ARCHIVE_RETENTION", "3600");
+
+        assert_eq!(Env::wal_archive_retention(), 3600);
+    }
+
+    #[test]
+    fn test_wal_archive_retention_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_WAL_ARCHIVE_RETENTION");
+
+        assert_eq!(Env::wal_archive_retention(), 604800);
+    }
+
+    #[test]
+    fn test_backup_path() {
+        before();
+
+        env::set_var("QUERY_SERVER_BACKUP_PATH", "backups");
+
+        assert_eq!(Env::backup_path(), "backups");
+    }
+
+    #[test]
+    fn test_backup_path_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_BACKUP_PATH");
+
+        assert_eq!(Env::backup_path(), "");
+    }
+
+    #[test]
+    fn test_backup_dbs() {
+        before();
+
+        env::set_var("QUERY_SERVER_BACKUP_DBS", "example.sql,other.sql");
+
+        assert_eq!(Env::backup_dbs(), "example.sql,other.sql");
+    }
+
+    #[test]
+    fn test_backup_dbs_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_BACKUP_DBS");
+
+        assert_eq!(Env::backup_dbs(), "");
+    }
+
+    #[test]
+    fn test_backup_interval() {
+        before();
+
+        env::set_var("QUERY_SERVER_BACKUP_INTERVAL", "3600");
+
+        assert_eq!(Env::backup_interval(), 3600);
+    }
+
+    #[test]
+    fn test_backup_interval_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_BACKUP_INTERVAL");
+
+        assert_eq!(Env::backup_interval(), 86400);
+    }
+
+    #[test]
+    fn test_backup_keep_daily() {
+        before();
+
+        env::set_var("QUERY_SERVER_BACKUP_KEEP_DAILY", "14");
+
+        assert_eq!(Env::backup_keep_daily(), 14);
+    }
+
+    #[test]
+    fn test_backup_keep_daily_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_BACKUP_KEEP_DAILY");
+
+        assert_eq!(Env::backup_keep_daily(), 7);
+    }
+
+    #[test]
+    fn test_backup_keep_weekly() {
+        before();
+
+        env::set_var("QUERY_SERVER_BACKUP_KEEP_WEEKLY", "8");
+
+        assert_eq!(Env::backup_keep_weekly(), 8);
+    }
+
+    #[test]
+    fn test_backup_keep_weekly_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_BACKUP_KEEP_WEEKLY");
+
+        assert_eq!(Env::backup_keep_weekly(), 4);
+    }
+
     #[test]
     fn test_token_secret() {
         env::set_var("QUERY_SERVER_TOKEN_SECRET", "secret");
@@ -200,6 +718,96 @@ mod tests {
         Env::token_secret();
     }
 
+    #[test]
+    fn test_access_token_ttl() {
+        before();
+
+        env::set_var("QUERY_SERVER_ACCESS_TOKEN_TTL", "300");
+
+        assert_eq!(Env::access_token_ttl(), 300);
+    }
+
+    #[test]
+    fn test_access_token_ttl_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_ACCESS_TOKEN_TTL");
+
+        assert_eq!(Env::access_token_ttl(), 900);
+    }
+
+    #[test]
+    fn test_refresh_token_ttl() {
+        before();
+
+        env::set_var("QUERY_SERVER_REFRESH_TOKEN_TTL", "86400");
+
+        assert_eq!(Env::refresh_token_ttl(), 86400);
+    }
+
+    #[test]
+    fn test_refresh_token_ttl_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_REFRESH_TOKEN_TTL");
+
+        assert_eq!(Env::refresh_token_ttl(), 2592000);
+    }
+
+    #[test]
+    fn test_token_keys_path() {
+        before();
+
+        env::set_var("QUERY_SERVER_TOKEN_KEYS_PATH", ".");
+
+        assert_eq!(Env::token_keys_path(), ".");
+    }
+
+    #[test]
+    fn test_token_keys_path_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_TOKEN_KEYS_PATH");
+
+        assert_eq!(Env::token_keys_path(), "");
+    }
+
+    #[test]
+    fn test_token_key_id() {
+        before();
+
+        env::set_var("QUERY_SERVER_TOKEN_KEY_ID", "2025-01");
+
+        assert_eq!(Env::token_key_id(), "2025-01");
+    }
+
+    #[test]
+    fn test_token_key_id_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_TOKEN_KEY_ID");
+
+        assert_eq!(Env::token_key_id(), "");
+    }
+
+    #[test]
+    fn test_token_legacy_secret() {
+        before();
+
+        env::set_var("QUERY_SERVER_TOKEN_LEGACY_SECRET", "true");
+
+        assert_eq!(Env::token_legacy_secret(), "true");
+    }
+
+    #[test]
+    fn test_token_legacy_secret_with_default() {
+        before();
+
+        env::remove_var("QUERY_SERVER_TOKEN_LEGACY_SECRET");
+
+        assert_eq!(Env::token_legacy_secret(), "false");
+    }
+
     #[test]
     fn test_admin_email() {
         env::set_var("QUERY_SERVER_ADMIN_EMAIL", "email");
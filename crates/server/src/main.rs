@@ -1,3 +1,6 @@
+pub mod archive;
+pub mod backup_schedule;
+pub mod changes;
 pub mod constants;
 
 pub mod controllers;
@@ -7,6 +10,9 @@ pub mod sqlite;
 use std::convert::Infallible;
 use std::net::SocketAddr;
 
+use archive::archiver::start_wal_archive_task;
+use backup_schedule::scheduler::start_backup_schedule_task;
+use changes::{subscriber::start_change_subscriber_task, webhook::start_webhook_task};
 use controllers::cache_manager::start_invalidation_task;
 use dotenv::dotenv;
 use hyper::server::conn::http1;
@@ -22,31 +28,49 @@ use tracing_subscriber::{layer::SubscriberExt, Registry};
 
 use crate::{
     controllers::{
+        archive::archive,
         asset::asset,
         asset_builder::asset_builder,
+        audit::audit,
+        backup::backup,
         branch::branch,
+        changes::changes,
+        database::database,
         function::function,
         function_builder::function_builder,
         migration::migration,
+        openapi::openapi,
+        persisted_query::persisted_query,
         plugin_builder::plugin_builder,
+        policy::policy,
         proxy::proxy,
         query::query,
+        rest::rest,
+        role::role,
+        schema::schema,
         token::token,
         user::user,
         user_token::user_token,
+        user_totp::user_totp,
         utils::{
+            audit::{AuditContext, AuditRequest, AUDIT_CONTEXT},
             body::{Body, BoxBody},
+            client_ip::{request_ip, CLIENT_IP},
             http_error::HttpError,
             responses::{
                 bad_request, internal_server_error, method_not_allowed, not_found, not_implemented,
                 unauthorized,
             },
         },
+        webhook::webhook,
+        well_known::well_known,
     },
     env::Env,
     sqlite::{
-        create_asset_db::create_asset_db, create_config_db::create_config_db,
+        branch::start_branch_expiry_task, create_asset_db::create_asset_db,
+        create_audit_db::create_audit_db, create_config_db::create_config_db,
         create_function_db::create_function_db, create_plugin_db::create_plugin_db,
+        token_usage::start_token_usage_task,
     },
 };
 
@@ -69,6 +93,8 @@ async fn main() -> Result<(), std::io::Error> {
 
     // NOTE: Create the asset database
     create_asset_db();
+    // NOTE: Create the audit database
+    create_audit_db();
     // NOTE: Create the create_cache_invaildation_db database
     create_cache_invalidation_db();
     // NOTE: Create the config database
@@ -79,6 +105,18 @@ async fn main() -> Result<(), std::io::Error> {
     create_plugin_db();
     // NOTE: Start the cache invalidation task
     start_invalidation_task();
+    // NOTE: Start the WAL archive task, if the archive path is set
+    start_wal_archive_task();
+    // NOTE: Start the scheduled backups task, if the backup path is set
+    start_backup_schedule_task();
+    // NOTE: Start the task that delivers the captured changes to the subscribed functions
+    start_change_subscriber_task();
+    // NOTE: Start the task that delivers the captured changes to the webhooks
+    start_webhook_task();
+    // NOTE: Start the task that deletes the expired branches
+    start_branch_expiry_task();
+    // NOTE: Start the task that writes the last use of the tokens
+    start_token_usage_task();
 
     let addr = SocketAddr::from(([0, 0, 0, 0], Env::port()));
     // We create a TcpListener and bind it to 127.0.0.1:3000
@@ -90,16 +128,26 @@ async fn main() -> Result<(), std::io::Error> {
     );
 
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, remote_addr) = listener.accept().await?;
         let io = TokioIo::new(stream);
 
         tokio::task::spawn(async move {
-            let service = service_fn(move |req| async {
+            let service = service_fn(move |req| async move {
                 let request_id = uuid::Uuid::new_v4().to_string();
                 let span = tracing::info_span!("request", request_id = %request_id);
                 let _enter = span.enter();
+                let client_ip =
+                    request_ip(remote_addr.ip(), req.headers(), &Env::trusted_proxies());
 
-                Ok::<_, Infallible>(handler(req).instrument(span.clone()).await)
+                Ok::<_, Infallible>(
+                    AUDIT_CONTEXT
+                        .scope(
+                            AuditContext::new(&request_id),
+                            CLIENT_IP.scope(client_ip, handler(req)),
+                        )
+                        .instrument(span.clone())
+                        .await,
+                )
             });
 
             if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
@@ -112,23 +160,30 @@ async fn main() -> Result<(), std::io::Error> {
 async fn handler(req: Request<IncomingBody>) -> Response<BoxBody> {
     let path = req.uri().path().to_owned();
     let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let audit = AuditRequest::new(&req, &segments);
 
-    router(req, &segments)
-        .await
-        .unwrap_or_else(|e: HttpError| -> Response<BoxBody> {
-            let code = e.code.as_u16();
-            let error = e.to_string();
-            tracing::error!(code, path, "{error}");
-
-            match e.code {
-                StatusCode::UNAUTHORIZED => unauthorized().unwrap(),
-                StatusCode::BAD_REQUEST => bad_request(e.message.to_string()).unwrap(),
-                StatusCode::METHOD_NOT_ALLOWED => method_not_allowed().unwrap(),
-                StatusCode::NOT_IMPLEMENTED => not_implemented().unwrap(),
-                StatusCode::NOT_FOUND => not_found().unwrap(),
-                _ => internal_server_error(e.body).unwrap(),
-            }
-        })
+    let response = router(req, &segments).await;
+    let response = response.unwrap_or_else(|e: HttpError| -> Response<BoxBody> {
+        let code = e.code.as_u16();
+        let error = e.to_string();
+        tracing::error!(code, path, "{error}");
+
+        match e.code {
+            StatusCode::UNAUTHORIZED => unauthorized().unwrap(),
+            StatusCode::BAD_REQUEST => bad_request(e.message.to_string()).unwrap(),
+            StatusCode::METHOD_NOT_ALLOWED => method_not_allowed().unwrap(),
+            StatusCode::NOT_IMPLEMENTED => not_implemented().unwrap(),
+            StatusCode::NOT_FOUND => not_found().unwrap(),
+            _ => internal_server_error(e.body).unwrap(),
+        }
+    });
+
+    // NOTE: Record the administrative and the write operations in the audit log
+    if let Some(audit) = audit {
+        audit.record(response.status());
+    }
+
+    response
 }
 
 async fn router(
@@ -160,23 +215,43 @@ async fn router(
 
     match init_segment {
         "_" => match segments[0] {
+            "archive" => archive(&mut req, segments).await,
             "asset" => asset(&mut req, segments).await,
             "asset-builder" => asset_builder(&mut req, segments).await,
+            "audit" => audit(&mut req, segments).await,
+            "backup" | "backups" | "restore" => backup(&mut req, segments).await,
             "branch" => branch(&mut req, segments).await,
+            "changes" => changes(&mut req, segments).await,
+            "database" => database(&mut req, segments).await,
             "function" => function(&mut req).await,
             "function-builder" => function_builder(&mut req, segments).await,
             "healthcheck" => Ok(Response::new(Body::from("OK"))),
             "migration" => migration(&mut req, segments).await,
+            "openapi.json" => openapi(&mut req, segments).await,
             "plugin-builder" => plugin_builder(&mut req, segments).await,
-            "query" => query(&mut req, segments).await,
+            "policy" => policy(&mut req, segments).await,
+            "query" => {
+                if segments.len() > 1 && segments[1] == "persisted" {
+                    persisted_query(&mut req, segments).await
+                } else {
+                    query(&mut req, segments).await
+                }
+            }
+            "rest" => rest(&mut req, segments).await,
+            "role" => role(&mut req, segments).await,
+            "schema" => schema(&mut req, segments).await,
             "token" => token(&mut req, segments).await,
             "user" => {
                 if segments.len() > 1 && segments[1] == "token" {
                     user_token(&mut req, segments).await
+                } else if segments.len() > 1 && segments[1] == "totp" {
+                    user_totp(&mut req, segments).await
                 } else {
                     user(&mut req, segments).await
                 }
             }
+            "webhook" => webhook(&mut req, segments).await,
+            ".well-known" => well_known(&mut req, segments).await,
             _ => Err(HttpError {
                 code: StatusCode::NOT_FOUND,
                 message: StatusCode::NOT_FOUND.to_string(),
@@ -1,7 +1,16 @@
+pub mod audit;
+pub mod branch;
 pub mod connect_db;
 pub mod create_asset_db;
+pub mod create_audit_db;
 pub mod create_cache_invalidation_db;
 pub mod create_config_db;
 pub mod create_function_db;
 pub mod create_plugin_db;
 pub mod functions;
+pub mod migrations;
+pub mod policies;
+pub mod rest;
+pub mod schema;
+pub mod schema_diff;
+pub mod token_usage;
@@ -0,0 +1,244 @@
+use anyhow::Result;
+use rusqlite::{named_params, Connection};
+use serde_json::{json, Value};
+
+#[derive(Debug, PartialEq)]
+pub struct AuditRecord {
+    pub request_id: String,
+    pub actor: Option<String>,
+    pub actor_type: Option<String>,
+    pub action: String,
+    pub target: String,
+    pub ip: Option<String>,
+    pub status: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub record: AuditRecord,
+    pub outcome: String,
+    pub created_at: i64,
+}
+
+impl AuditEntry {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "id": self.id,
+            "request_id": self.record.request_id,
+            "actor": self.record.actor,
+            "actor_type": self.record.actor_type,
+            "action": self.record.action,
+            "target": self.record.target,
+            "ip": self.record.ip,
+            "status": self.record.status,
+            "outcome": self.outcome,
+            "created_at": self.created_at,
+        })
+    }
+}
+
+// NOTE: The action and the target filters match a prefix, e.g. the action "DELETE /_/user"
+// matches every user deletion. The from and the to filters are Unix timestamps, both included.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    pub outcome: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+// NOTE: The 2xx and 3xx responses are a success, the others a failure
+pub fn insert_audit_entry(conn: &Connection, record: &AuditRecord) -> Result<()> {
+    let outcome = if record.status < 400 {
+        "success"
+    } else {
+        "failure"
+    };
+
+    conn.execute(
+        "
+        INSERT INTO
+            audit_log(request_id, actor, actor_type, action, target, ip, status, outcome)
+        VALUES
+            (:request_id, :actor, :actor_type, :action, :target, :ip, :status, :outcome)
+        ",
+        named_params! {
+            ":request_id": record.request_id,
+            ":actor": record.actor,
+            ":actor_type": record.actor_type,
+            ":action": record.action,
+            ":target": record.target,
+            ":ip": record.ip,
+            ":status": record.status,
+            ":outcome": outcome,
+        },
+    )?;
+
+    Ok(())
+}
+
+pub fn read_audit_entries(
+    conn: &Connection,
+    filter: &AuditFilter,
+    cursor: i64,
+    limit: i64,
+) -> Result<Vec<AuditEntry>> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            id,
+            request_id,
+            actor,
+            actor_type,
+            action,
+            target,
+            ip,
+            status,
+            outcome,
+            created_at
+        FROM
+            audit_log
+        WHERE
+            id > :cursor
+        AND
+            (:actor IS NULL OR actor = :actor)
+        AND
+            (:action IS NULL OR substr(action, 1, length(:action)) = :action)
+        AND
+            (:target IS NULL OR substr(target, 1, length(:target)) = :target)
+        AND
+            (:outcome IS NULL OR outcome = :outcome)
+        AND
+            (:from IS NULL OR created_at >= :from)
+        AND
+            (:to IS NULL OR created_at <= :to)
+        ORDER BY
+            id
+        LIMIT :limit;
+        ",
+    )?;
+
+    let entries = stmt
+        .query_map(
+            named_params! {
+                ":cursor": cursor,
+                ":actor": filter.actor,
+                ":action": filter.action,
+                ":target": filter.target,
+                ":outcome": filter.outcome,
+                ":from": filter.from,
+                ":to": filter.to,
+                ":limit": limit,
+            },
+            |row| {
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    record: AuditRecord {
+                        request_id: row.get(1)?,
+                        actor: row.get(2)?,
+                        actor_type: row.get(3)?,
+                        action: row.get(4)?,
+                        target: row.get(5)?,
+                        ip: row.get(6)?,
+                        status: row.get(7)?,
+                    },
+                    outcome: row.get(8)?,
+                    created_at: row.get(9)?,
+                })
+            },
+        )?
+        .collect::<Result<Vec<AuditEntry>, rusqlite::Error>>()?;
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db_test,
+        sqlite::{connect_db::connect_audit_db, create_audit_db::create_audit_db},
+    };
+
+    use super::*;
+
+    fn record(actor: &str, action: &str, target: &str, status: u16) -> AuditRecord {
+        AuditRecord {
+            request_id: "request_id".to_string(),
+            actor: Some(actor.to_string()),
+            actor_type: Some("user".to_string()),
+            action: action.to_string(),
+            target: target.to_string(),
+            ip: Some("127.0.0.1".to_string()),
+            status,
+        }
+    }
+
+    db_test!(test_audit_log, TestAuditLog, {
+        create_audit_db();
+
+        let conn = connect_audit_db().unwrap();
+
+        insert_audit_entry(
+            &conn,
+            &record("admin@test.com", "POST /_/user", "user@test.com", 201),
+        )
+        .unwrap();
+        insert_audit_entry(
+            &conn,
+            &record("user@test.com", "DELETE /_/user", "admin@test.com", 401),
+        )
+        .unwrap();
+        insert_audit_entry(
+            &conn,
+            &record("admin@test.com", "POST /_/query", "example.sql", 200),
+        )
+        .unwrap();
+
+        let entries = read_audit_entries(&conn, &AuditFilter::default(), 0, 100).unwrap();
+
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].outcome, "success");
+        assert_eq!(entries[1].outcome, "failure");
+
+        let entries = read_audit_entries(&conn, &AuditFilter::default(), entries[0].id, 1).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].record.action, "DELETE /_/user");
+
+        let filter = AuditFilter {
+            actor: Some("admin@test.com".to_string()),
+            action: Some("POST /_/u".to_string()),
+            ..Default::default()
+        };
+        let entries = read_audit_entries(&conn, &filter, 0, 100).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].record.target, "user@test.com");
+
+        let filter = AuditFilter {
+            outcome: Some("failure".to_string()),
+            from: Some(0),
+            to: Some(i64::MAX),
+            ..Default::default()
+        };
+        let entries = read_audit_entries(&conn, &filter, 0, 100).unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].record.actor.as_deref(), Some("user@test.com"));
+
+        // NOTE: The audit log is append-only
+        assert!(conn
+            .execute("UPDATE audit_log SET actor = NULL", ())
+            .is_err());
+        assert!(conn.execute("DELETE FROM audit_log", ()).is_err());
+        assert_eq!(
+            read_audit_entries(&conn, &AuditFilter::default(), 0, 100)
+                .unwrap()
+                .len(),
+            3
+        );
+    });
+}
@@ -0,0 +1,703 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Result};
+use rusqlite::{named_params, Connection, DatabaseName, OptionalExtension, TransactionBehavior};
+use serde_json::{json, Value};
+use tokio::{task::JoinHandle, time};
+
+use crate::{
+    archive::{
+        archiver::now_millis,
+        wal::{read_committed_frames, read_header, WalPosition},
+    },
+    changes::capture::CHANGES_TABLE,
+    controllers::backup::remove_temp_files,
+    env::Env,
+};
+
+use super::{
+    connect_db::{connect_config_db, connect_db},
+    schema::{get_schema, Schema},
+    schema_diff::{diff_schema, migration_sql, SchemaDiff},
+};
+
+const BRANCH_SCHEMA: &str = "branch";
+
+pub struct BranchRecord {
+    pub name: String,
+    pub db_name: String,
+    pub source_wal_checkpoint: Option<i64>,
+    pub source_wal_offset: Option<i64>,
+    pub created_by: Option<String>,
+    pub expires_at: Option<i64>,
+    pub created_at: i64,
+}
+
+impl BranchRecord {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "branch": self.name,
+            "db_name": self.db_name,
+            "source_wal_checkpoint": self.source_wal_checkpoint,
+            "source_wal_offset": self.source_wal_offset,
+            "created_by": self.created_by,
+            "expires_at": self.expires_at,
+            "created_at": self.created_at,
+        })
+    }
+}
+
+pub fn start_branch_expiry_task() -> JoinHandle<()> {
+    let interval_duration = Duration::from_secs(Env::branch_expiry_interval());
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(interval_duration);
+
+        tracing::info!("Branch expiry interval duration: {:?}", interval_duration);
+
+        loop {
+            interval.tick().await;
+
+            match delete_expired_branches() {
+                Ok(v) if !v.is_empty() => tracing::info!("Expired branches deleted: {:?}", v),
+                Ok(_) => (),
+                Err(e) => tracing::error!("Error deleting the expired branches: {}", e),
+            }
+        }
+    })
+}
+
+// NOTE: The position after the last frame committed in the WAL of the database, as the
+// checkpoint sequence of the WAL and the offset in it. A database without a WAL has no position.
+pub fn wal_position(db_name: &str) -> Result<Option<(i64, i64)>> {
+    let wal_path = format!("{}-wal", db_path(db_name));
+    let wal_path = Path::new(&wal_path);
+
+    let header = match read_header(wal_path)? {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    let (_, position) = read_committed_frames(wal_path, &WalPosition::start(&header))?;
+
+    Ok(Some((header.checkpoint_seq as i64, position.offset as i64)))
+}
+
+pub fn register_branch(
+    name: &str,
+    db_name: &str,
+    wal_position: Option<(i64, i64)>,
+    created_by: Option<&str>,
+    expires_at: Option<i64>,
+) -> Result<()> {
+    connect_config_db()?.execute(
+        "
+        INSERT OR REPLACE INTO _config_branch (
+            name,
+            db_name,
+            source_wal_checkpoint,
+            source_wal_offset,
+            created_by,
+            expires_at
+        ) VALUES (
+            :name,
+            :db_name,
+            :source_wal_checkpoint,
+            :source_wal_offset,
+            :created_by,
+            :expires_at
+        );
+        ",
+        named_params! {
+            ":name": name,
+            ":db_name": db_name,
+            ":source_wal_checkpoint": wal_position.map(|v| v.0),
+            ":source_wal_offset": wal_position.map(|v| v.1),
+            ":created_by": created_by,
+            ":expires_at": expires_at,
+        },
+    )?;
+
+    Ok(())
+}
+
+pub fn unregister_branch(name: &str) -> Result<usize> {
+    let deleted =
+        connect_config_db()?.execute("DELETE FROM _config_branch WHERE name = ?;", [name])?;
+
+    Ok(deleted)
+}
+
+pub fn list_branch_records() -> Result<Vec<BranchRecord>> {
+    let conn = connect_config_db()?;
+
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            name,
+            db_name,
+            source_wal_checkpoint,
+            source_wal_offset,
+            created_by,
+            expires_at,
+            created_at
+        FROM
+            _config_branch
+        ORDER BY
+            name;
+        ",
+    )?;
+
+    let records = stmt
+        .query_map([], |row| {
+            Ok(BranchRecord {
+                name: row.get(0)?,
+                db_name: row.get(1)?,
+                source_wal_checkpoint: row.get(2)?,
+                source_wal_offset: row.get(3)?,
+                created_by: row.get(4)?,
+                expires_at: row.get(5)?,
+                created_at: row.get(6)?,
+            })
+        })?
+        .collect::<Result<Vec<BranchRecord>, rusqlite::Error>>()?;
+
+    Ok(records)
+}
+
+// NOTE: The record is deleted after the file, so a branch that can't be deleted is tried again
+// in the next tick
+pub fn delete_expired_branches() -> Result<Vec<String>> {
+    let now = (now_millis() / 1000) as i64;
+
+    let expired = list_branch_records()?
+        .into_iter()
+        .filter(|r| r.expires_at.is_some_and(|v| v <= now))
+        .map(|r| r.name)
+        .collect::<Vec<String>>();
+
+    for name in &expired {
+        remove_temp_files(&db_path(name));
+
+        if Path::new(&db_path(name)).exists() {
+            bail!("The expired branch {} can't be deleted", name);
+        }
+
+        unregister_branch(name)?;
+    }
+
+    Ok(expired)
+}
+
+// NOTE: The branch replaces the content of the database in a single write transaction, instead
+// of replacing the file, so the open connections see the previous or the promoted database and
+// the writers wait for the promotion. The backup is made while the write lock is held, so it
+// has every write made before the promotion.
+pub fn promote_branch(db_name: &str, branch_name: &str, backup_name: &str) -> Result<()> {
+    let mut conn = connect_db(db_name)?;
+
+    attach_branch(&conn, branch_name)?;
+
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    connect_db(db_name)?.execute("VACUUM INTO ?;", [db_path(backup_name)])?;
+
+    tx.execute_batch("PRAGMA defer_foreign_keys = ON;")?;
+
+    // NOTE: The captured changes of the database are kept, the webhooks have their cursors in them
+    let has_changes_table = tables(&tx, "main")?
+        .iter()
+        .any(|(_, name)| name == CHANGES_TABLE);
+
+    let mut objects = schema_objects(&tx, "main", &["view", "trigger"])?;
+    objects.extend(
+        tables(&tx, "main")?
+            .into_iter()
+            .filter(|(_, name)| name != CHANGES_TABLE),
+    );
+
+    for (object_type, name) in objects {
+        let object_type = match object_type.as_str() {
+            "virtual" => "table".to_string(),
+            v => v.to_string(),
+        };
+
+        tx.execute_batch(&format!(
+            "DROP {} IF EXISTS main.{};",
+            object_type.to_uppercase(),
+            quote(&name)
+        ))?;
+    }
+
+    let branch_tables = tables(&tx, BRANCH_SCHEMA)?
+        .into_iter()
+        .filter(|(_, name)| name != CHANGES_TABLE)
+        .collect::<Vec<_>>();
+
+    for (_, name) in &branch_tables {
+        tx.execute_batch(&object_sql(&tx, name)?)?;
+    }
+
+    // NOTE: The changes of the branch aren't copied, the capture triggers of the branch need the
+    // table when the database doesn't have it
+    if !has_changes_table
+        && tables(&tx, BRANCH_SCHEMA)?
+            .iter()
+            .any(|(_, name)| name == CHANGES_TABLE)
+    {
+        tx.execute_batch(&object_sql(&tx, CHANGES_TABLE)?)?;
+    }
+
+    for (_, name) in &branch_tables {
+        copy_rows(&tx, name)?;
+    }
+
+    // NOTE: The table of the AUTOINCREMENT sequences exists when a table uses it
+    if schema_objects(&tx, BRANCH_SCHEMA, &["table"])?
+        .iter()
+        .any(|(_, name)| name == "sqlite_sequence")
+    {
+        let sequences: i64 = tx.query_row(
+            "SELECT COUNT(*) FROM main.sqlite_schema WHERE name = 'sqlite_sequence';",
+            [],
+            |row| row.get(0),
+        )?;
+
+        if sequences > 0 {
+            tx.execute_batch(&format!(
+                "
+                DELETE FROM main.sqlite_sequence WHERE name <> '{CHANGES_TABLE}';
+                INSERT INTO main.sqlite_sequence (name, seq)
+                SELECT name, seq FROM branch.sqlite_sequence WHERE name <> '{CHANGES_TABLE}';
+                "
+            ))?;
+        }
+    }
+
+    // NOTE: The indexes are created after the rows are copied, and the triggers too, so they
+    // don't run for the copied rows
+    for (_, name) in schema_objects(&tx, BRANCH_SCHEMA, &["index", "trigger", "view"])? {
+        tx.execute_batch(&object_sql(&tx, &name)?)?;
+    }
+
+    let user_version: i64 = tx.query_row("PRAGMA branch.user_version;", [], |row| row.get(0))?;
+
+    tx.pragma_update(Some(DatabaseName::Main), "user_version", user_version)?;
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+// NOTE: The schema diff is applied and the rows of the tables are replaced with the rows of the
+// branch in a single write transaction, so the writers can't interleave with the merge
+pub fn merge_branch(
+    db_name: &str,
+    branch_name: &str,
+    tables: &[String],
+) -> Result<(SchemaDiff, String)> {
+    let branch = get_schema(&connect_db(branch_name)?)?;
+
+    for table in tables {
+        if !branch.tables.iter().any(|t| t.name == *table) {
+            bail!(
+                "The table {} doesn't exist in the branch {}",
+                table,
+                branch_name
+            );
+        }
+    }
+
+    let mut conn = connect_db(db_name)?;
+
+    attach_branch(&conn, branch_name)?;
+
+    // NOTE: The foreign keys are disabled before the transaction, the pragma is a no-op inside it,
+    // so deleting the rows of a merged table doesn't cascade to the tables that aren't merged. They
+    // are checked before the commit instead.
+    conn.execute_batch("PRAGMA foreign_keys = OFF;")?;
+
+    let result = merge_tables(&mut conn, &branch, tables);
+
+    conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+
+    result
+}
+
+fn merge_tables(
+    conn: &mut Connection,
+    branch: &Schema,
+    tables: &[String],
+) -> Result<(SchemaDiff, String)> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    let source = get_schema(&tx)?;
+    let diff = diff_schema(&source, branch);
+    let sql = migration_sql(&source, branch, &diff);
+
+    tx.execute_batch(&sql)?;
+
+    for table in tables {
+        tx.execute_batch(&format!("DELETE FROM main.{};", quote(table)))?;
+
+        copy_rows(&tx, table)?;
+    }
+
+    let violation = tx
+        .query_row("PRAGMA main.foreign_key_check;", [], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(2)?))
+        })
+        .optional()?;
+
+    if let Some((table, parent)) = violation {
+        bail!(
+            "The merge breaks the foreign key of the table {} to the table {}",
+            table,
+            parent
+        );
+    }
+
+    tx.commit()?;
+
+    Ok((diff, sql))
+}
+
+fn db_path(db_name: &str) -> String {
+    format!("{}/{}", Env::dbs_path(), db_name)
+}
+
+fn attach_branch(conn: &Connection, branch_name: &str) -> Result<()> {
+    conn.execute(
+        &format!("ATTACH DATABASE ? AS {};", BRANCH_SCHEMA),
+        [db_path(branch_name)],
+    )?;
+
+    Ok(())
+}
+
+// NOTE: The shadow tables of the virtual tables are created and filled by their virtual table
+fn tables(conn: &Connection, schema: &str) -> Result<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            type,
+            name
+        FROM
+            pragma_table_list
+        WHERE
+            schema = ?
+        AND
+            type IN ('table', 'virtual')
+        AND
+            name NOT LIKE 'sqlite_%'
+        ORDER BY
+            name;
+        ",
+    )?;
+
+    let tables = stmt
+        .query_map([schema], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?;
+
+    Ok(tables)
+}
+
+fn schema_objects(
+    conn: &Connection,
+    schema: &str,
+    object_types: &[&str],
+) -> Result<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(&format!(
+        "
+        SELECT
+            type,
+            name
+        FROM
+            {}.sqlite_schema
+        WHERE
+            sql IS NOT NULL
+        ORDER BY
+            rowid;
+        ",
+        schema
+    ))?;
+
+    let objects = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, String)>, rusqlite::Error>>()?
+        .into_iter()
+        .filter(|(object_type, _)| object_types.contains(&object_type.as_str()))
+        .collect();
+
+    Ok(objects)
+}
+
+fn object_sql(conn: &Connection, name: &str) -> Result<String> {
+    let sql = conn.query_row(
+        "SELECT sql FROM branch.sqlite_schema WHERE name = ?;",
+        [name],
+        |row| row.get::<_, String>(0),
+    )?;
+
+    Ok(sql)
+}
+
+// NOTE: Only the columns of the table in both databases are copied
+fn copy_rows(conn: &Connection, table: &str) -> Result<()> {
+    let branch_columns = column_names(conn, BRANCH_SCHEMA, table)?;
+
+    let columns = column_names(conn, "main", table)?
+        .into_iter()
+        .filter(|c| branch_columns.contains(c))
+        .map(|c| quote(&c))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    conn.execute_batch(&format!(
+        "INSERT INTO main.{table} ({columns}) SELECT {columns} FROM {BRANCH_SCHEMA}.{table};",
+        table = quote(table),
+    ))?;
+
+    Ok(())
+}
+
+fn column_names(conn: &Connection, schema: &str, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?, ?) ORDER BY cid;")?;
+
+    let columns = stmt
+        .query_map([table, schema], |row| row.get(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+    Ok(columns)
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_test;
+
+    use super::*;
+
+    fn setup() {
+        connect_db("example.sql")
+            .unwrap()
+            .execute_batch(
+                "
+                CREATE TABLE author (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL);
+                CREATE TABLE book (id INTEGER PRIMARY KEY, author_id INTEGER REFERENCES author (id));
+                INSERT INTO author (name) VALUES ('Frank Herbert');
+                INSERT INTO book (author_id) VALUES (1);
+                ",
+            )
+            .unwrap();
+
+        connect_db("example.sql")
+            .unwrap()
+            .execute("VACUUM INTO ?;", [db_path("example.dev.branch.sql")])
+            .unwrap();
+
+        connect_db("example.dev.branch.sql")
+            .unwrap()
+            .execute_batch(
+                "
+                ALTER TABLE author ADD COLUMN bio TEXT;
+                CREATE INDEX author_name ON author (name);
+                CREATE TRIGGER author_insert AFTER INSERT ON author BEGIN SELECT 1; END;
+                INSERT INTO author (name, bio) VALUES ('Ursula K. Le Guin', 'Earthsea');
+                INSERT INTO book (author_id) VALUES (2);
+                PRAGMA user_version = 2;
+                ",
+            )
+            .unwrap();
+
+        connect_db("example.sql")
+            .unwrap()
+            .execute("INSERT INTO book (author_id) VALUES (1);", [])
+            .unwrap();
+    }
+
+    fn count(db_name: &str, table: &str) -> i64 {
+        connect_db(db_name)
+            .unwrap()
+            .query_row(&format!("SELECT COUNT(*) FROM {};", table), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    db_test!(test_promote_branch, TestPromoteBranch, {
+        setup();
+
+        promote_branch(
+            "example.sql",
+            "example.dev.branch.sql",
+            "example.backup.branch.sql",
+        )
+        .unwrap();
+
+        let conn = connect_db("example.sql").unwrap();
+
+        assert_eq!(count("example.sql", "author"), 2);
+        assert_eq!(count("example.sql", "book"), 2);
+        assert_eq!(count("example.backup.branch.sql", "book"), 2);
+
+        let bio: String = conn
+            .query_row("SELECT bio FROM author WHERE id = 2;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(bio, "Earthsea");
+
+        let seq: i64 = conn
+            .query_row(
+                "SELECT seq FROM sqlite_sequence WHERE name = 'author';",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(seq, 2);
+
+        let user_version: i64 = conn
+            .query_row("PRAGMA user_version;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(user_version, 2);
+
+        let schema = get_schema(&conn).unwrap();
+        let branch = get_schema(&connect_db("example.dev.branch.sql").unwrap()).unwrap();
+        assert!(diff_schema(&schema, &branch).is_empty());
+    });
+
+    db_test!(test_branch_registry, TestBranchRegistry, {
+        setup();
+
+        let position = wal_position("example.sql").unwrap();
+        let now = (now_millis() / 1000) as i64;
+
+        register_branch(
+            "example.dev.branch.sql",
+            "example.sql",
+            position,
+            Some("admin@admin.com"),
+            Some(now - 1),
+        )
+        .unwrap();
+        register_branch("example.qa.branch.sql", "example.sql", None, None, None).unwrap();
+
+        let records = list_branch_records().unwrap();
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "example.dev.branch.sql");
+        assert_eq!(records[0].db_name, "example.sql");
+        assert_eq!(records[0].source_wal_offset, position.map(|v| v.1));
+        assert_eq!(records[0].created_by, Some("admin@admin.com".to_string()));
+        assert_eq!(records[1].expires_at, None);
+
+        assert_eq!(
+            delete_expired_branches().unwrap(),
+            vec!["example.dev.branch.sql".to_string()]
+        );
+        assert!(!Path::new(&db_path("example.dev.branch.sql")).exists());
+
+        let records = list_branch_records().unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].name, "example.qa.branch.sql");
+        assert_eq!(unregister_branch("example.qa.branch.sql").unwrap(), 1);
+    });
+
+    db_test!(test_merge_branch, TestMergeBranch, {
+        setup();
+
+        assert!(merge_branch(
+            "example.sql",
+            "example.dev.branch.sql",
+            &["missing".to_string()]
+        )
+        .is_err());
+
+        let (diff, sql) = merge_branch(
+            "example.sql",
+            "example.dev.branch.sql",
+            &["author".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(diff.tables.len(), 1);
+        assert!(sql.contains("ADD COLUMN \"bio\""));
+
+        assert_eq!(count("example.sql", "author"), 2);
+        assert_eq!(count("example.sql", "book"), 2);
+
+        let conn = connect_db("example.sql").unwrap();
+        let schema = get_schema(&conn).unwrap();
+        let branch = get_schema(&connect_db("example.dev.branch.sql").unwrap()).unwrap();
+        assert!(diff_schema(&schema, &branch).is_empty());
+    });
+
+    db_test!(test_merge_branch_cascade, TestMergeBranchCascade, {
+        connect_db("example.sql")
+            .unwrap()
+            .execute_batch(
+                "
+                CREATE TABLE author (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+                CREATE TABLE book (
+                    id INTEGER PRIMARY KEY,
+                    author_id INTEGER REFERENCES author (id) ON DELETE CASCADE
+                );
+                INSERT INTO author (id, name) VALUES (1, 'Frank Herbert');
+                INSERT INTO book (author_id) VALUES (1), (1);
+                ",
+            )
+            .unwrap();
+
+        connect_db("example.sql")
+            .unwrap()
+            .execute("VACUUM INTO ?;", [db_path("example.dev.branch.sql")])
+            .unwrap();
+
+        connect_db("example.dev.branch.sql")
+            .unwrap()
+            .execute_batch("UPDATE author SET name = 'Brian Herbert';")
+            .unwrap();
+
+        // NOTE: The books aren't merged, so they are kept
+        merge_branch(
+            "example.sql",
+            "example.dev.branch.sql",
+            &["author".to_string()],
+        )
+        .unwrap();
+
+        assert_eq!(count("example.sql", "book"), 2);
+
+        let conn = connect_db("example.sql").unwrap();
+        let name: String = conn
+            .query_row("SELECT name FROM author WHERE id = 1;", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(name, "Brian Herbert");
+
+        let foreign_keys: i64 = conn
+            .query_row("PRAGMA foreign_keys;", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(foreign_keys, 1);
+
+        // NOTE: The merge is rolled back if it breaks a foreign key of a table that isn't merged
+        connect_db("example.dev.branch.sql")
+            .unwrap()
+            .execute_batch("DELETE FROM author;")
+            .unwrap();
+
+        assert!(merge_branch(
+            "example.sql",
+            "example.dev.branch.sql",
+            &["author".to_string()],
+        )
+        .is_err());
+
+        assert_eq!(count("example.sql", "author"), 1);
+        assert_eq!(count("example.sql", "book"), 2);
+    });
+}
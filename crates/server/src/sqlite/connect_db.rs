@@ -5,8 +5,10 @@ use rusqlite::{limits::Limit, trace::{TraceEvent, TraceEventCodes}, Connection};
 use tracing::info;
 
 use crate::{
+    archive::archiver::WAL_AUTOCHECKPOINT_FALLBACK,
     constants::{
-        DB_ASSET_NAME, DB_CACHE_INVALIDATION_NAME, DB_CONFIG_NAME, DB_FUNCTION_NAME, DB_PLUGIN_NAME,
+        DB_ASSET_NAME, DB_AUDIT_NAME, DB_CACHE_INVALIDATION_NAME, DB_CONFIG_NAME, DB_FUNCTION_NAME,
+        DB_PLUGIN_NAME,
     },
     env::Env,
 };
@@ -40,6 +42,14 @@ pub(crate) fn connect_asset_db() -> Result<Connection> {
     Ok(conn)
 }
 
+pub fn connect_audit_db() -> Result<Connection> {
+    let conn = connection(DB_AUDIT_NAME)?;
+
+    conn.set_limit(Limit::SQLITE_LIMIT_ATTACHED, 0)?;
+
+    Ok(conn)
+}
+
 pub fn connect_function_db() -> Result<Connection> {
     let conn = connection(DB_FUNCTION_NAME)?;
 
@@ -86,6 +96,11 @@ pub fn connection(db_name: &str) -> Result<Connection> {
     conn.pragma_update(None, "temp_store", "MEMORY")?;
     conn.pragma_update(None, "foreign_keys", "ON")?;
 
+    // NOTE: The WAL archive checkpoints the WAL, the automatic checkpoint is a fallback
+    if !Env::wal_archive_path().is_empty() {
+        conn.pragma_update(None, "wal_autocheckpoint", WAL_AUTOCHECKPOINT_FALLBACK)?;
+    }
+
     // Performance pragmas
     conn.pragma_update(None, "mmap_size", "30000000000")?;
     conn.pragma_update(None, "cache_size", -32000)?;
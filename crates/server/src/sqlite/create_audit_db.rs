@@ -0,0 +1,57 @@
+use tracing::error;
+
+use super::connect_db::connect_audit_db;
+
+pub fn create_audit_db() {
+    match connect_audit_db() {
+        Ok(connection) => {
+            match connection.execute_batch(
+                &[
+                    "BEGIN;".to_string(),
+                    create_audit_log_table(),
+                    "COMMIT;".to_string(),
+                ]
+                .join("\n"),
+            ) {
+                Ok(_) => (),
+                Err(err) => error!("Can't create audit database: {}", err),
+            }
+        }
+        Err(err) => error!("Can't connect to the audit database: {}", err),
+    }
+}
+
+// NOTE: The audit log is append-only, the triggers reject the updates and the deletes
+fn create_audit_log_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS audit_log(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            request_id TEXT NOT NULL,
+            actor TEXT,
+            actor_type TEXT CHECK (actor_type IN ('user', 'token')),
+            action TEXT NOT NULL,
+            target TEXT NOT NULL,
+            ip TEXT,
+            status INTEGER NOT NULL,
+            outcome TEXT NOT NULL CHECK (outcome IN ('success', 'failure')),
+            created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+
+        CREATE INDEX IF NOT EXISTS audit_log_idx_actor ON audit_log(actor);
+
+        CREATE INDEX IF NOT EXISTS audit_log_idx_created_at ON audit_log(created_at);
+
+        CREATE TRIGGER IF NOT EXISTS trigger_audit_log_update
+            BEFORE UPDATE ON audit_log
+        BEGIN
+            SELECT RAISE(FAIL, 'It is not allowed to update a row from audit_log');
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS trigger_audit_log_delete
+            BEFORE DELETE ON audit_log
+        BEGIN
+            SELECT RAISE(FAIL, 'It is not allowed to delete a row from audit_log');
+        END;
+    "#
+    .to_string()
+}
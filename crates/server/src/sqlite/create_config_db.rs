@@ -2,6 +2,7 @@ use argon2::{
     password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
     Argon2,
 };
+use rusqlite::Connection;
 use tracing::error;
 
 use crate::env::Env;
@@ -18,7 +19,17 @@ pub fn create_config_db() {
                     insert_admin_user(),
                     create_user_token_table(),
                     insert_admin_user_token(),
+                    create_user_refresh_token_table(),
                     create_token_table(),
+                    create_user_token_permission_table(),
+                    create_token_permission_table(),
+                    create_token_access_table(),
+                    create_role_tables(),
+                    create_policy_table(),
+                    create_persisted_query_table(),
+                    create_change_subscription_table(),
+                    create_webhook_table(),
+                    create_branch_table(),
                     options(),
                     "COMMIT;".to_string(),
                 ]
@@ -27,6 +38,19 @@ pub fn create_config_db() {
                 Ok(_) => (),
                 Err(err) => error!("Can't create config database: {}", err),
             }
+
+            match add_user_totp_columns(&connection) {
+                Ok(_) => (),
+                Err(err) => error!("Can't add the TOTP columns to the config database: {}", err),
+            }
+
+            match add_permission_restriction(&connection) {
+                Ok(_) => (),
+                Err(err) => error!(
+                    "Can't add the permission restriction to the config database: {}",
+                    err
+                ),
+            }
         }
         Err(err) => error!("Can't create config database: {}", err),
     };
@@ -59,6 +83,116 @@ fn create_user_table() -> String {
     .to_string()
 }
 
+// NOTE: The columns are added to the users tables created before the two-factor authentication.
+// The secret is encrypted, and only the hashes of the recovery codes are stored. The failed
+// attempts lock the codes of the user for a while.
+fn add_user_totp_columns(conn: &Connection) -> rusqlite::Result<()> {
+    add_columns(
+        conn,
+        "_config_user",
+        &[
+            ("totp_secret", "TEXT"),
+            (
+                "totp_enabled",
+                "BOOLEAN NOT NULL CHECK (totp_enabled IN (0, 1)) DEFAULT (0)",
+            ),
+            ("totp_recovery_codes", "TEXT"),
+            ("totp_last_step", "INTEGER"),
+            ("totp_failed_attempts", "INTEGER NOT NULL DEFAULT (0)"),
+            ("totp_locked_until", "INTEGER"),
+        ],
+    )
+}
+
+// NOTE: A token is restricted to its permissions once a permission is granted, and it stays
+// restricted after the last permission is revoked, so the revocation can't give it access to
+// everything. The flag isn't stored in the token tables, because any update of a token
+// generates a new token. The tokens with permissions before the flag existed are restricted.
+fn add_permission_restriction(conn: &Connection) -> rusqlite::Result<()> {
+    let restricted = "BOOLEAN NOT NULL CHECK (permissions_restricted IN (0, 1)) DEFAULT (0)";
+
+    add_columns(
+        conn,
+        "_config_user",
+        &[("permissions_restricted", restricted)],
+    )?;
+    add_columns(
+        conn,
+        "_config_token_access",
+        &[("permissions_restricted", restricted)],
+    )?;
+
+    conn.execute_batch(
+        r#"
+        BEGIN;
+
+        CREATE TRIGGER IF NOT EXISTS _trigger_config_user_token_permission_insert
+            AFTER INSERT ON _config_user_token_permission
+        BEGIN
+            UPDATE
+                _config_user
+            SET
+                permissions_restricted = 1
+            WHERE
+                uuid = NEW.user_uuid
+            AND
+                permissions_restricted = 0;
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS _trigger_config_token_permission_insert
+            AFTER INSERT ON _config_token_permission
+        BEGIN
+            INSERT INTO
+                _config_token_access(token_name, permissions_restricted)
+            VALUES
+                (NEW.token_name, 1)
+            ON CONFLICT(token_name) DO UPDATE SET
+                permissions_restricted = 1;
+        END;
+
+        UPDATE
+            _config_user
+        SET
+            permissions_restricted = 1
+        WHERE
+            permissions_restricted = 0
+        AND
+            uuid IN (SELECT user_uuid FROM _config_user_token_permission);
+
+        INSERT INTO
+            _config_token_access(token_name, permissions_restricted)
+        SELECT DISTINCT
+            token_name, 1
+        FROM
+            _config_token_permission
+        WHERE
+            true
+        ON CONFLICT(token_name) DO UPDATE SET
+            permissions_restricted = 1;
+
+        COMMIT;
+        "#,
+    )
+}
+
+fn add_columns(conn: &Connection, table: &str, columns: &[(&str, &str)]) -> rusqlite::Result<()> {
+    for (name, definition) in columns {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info(?) WHERE name = ?",
+            [table, name],
+            |row| row.get(0),
+        )?;
+
+        if !exists {
+            conn.execute_batch(&format!(
+                "ALTER TABLE {table} ADD COLUMN {name} {definition};"
+            ))?;
+        }
+    }
+
+    Ok(())
+}
+
 fn insert_admin_user() -> String {
     let email: String = Env::admin_email();
     let password = Env::admin_password();
@@ -136,8 +270,6 @@ fn create_user_token_table() -> String {
     .to_string()
 }
 
-// TODO: create a table adding restrictions by database name, action (migration and query) and operations (SELECT, INSERT, DELETE, UPDATE) for each user token
-
 fn insert_admin_user_token() -> String {
     let email: String = Env::admin_email();
 
@@ -161,6 +293,28 @@ fn insert_admin_user_token() -> String {
     )
 }
 
+// NOTE: Only the hash of a refresh token is stored. The refresh tokens issued from the same login
+// are a family, a used refresh token has a used_at, and the reuse of one revokes its family.
+fn create_user_refresh_token_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_user_refresh_token(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_hash TEXT NOT NULL UNIQUE CHECK (token_hash != ''),
+            family_id TEXT NOT NULL CHECK (family_id != ''),
+            user_uuid TEXT NOT NULL,
+            expires_at INTEGER NOT NULL,
+            used_at INTEGER,
+            revoked_at INTEGER,
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            FOREIGN KEY (user_uuid) REFERENCES _config_user(uuid) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS _index_config_user_refresh_token_family_id
+            ON _config_user_refresh_token(family_id);
+    "#
+    .to_string()
+}
+
 fn create_token_table() -> String {
     r#"
         CREATE TABLE IF NOT EXISTS _config_token(
@@ -194,7 +348,255 @@ fn create_token_table() -> String {
     .to_string()
 }
 
-// TODO: create a table adding restrictions by database name, action (migration and query) and operations (SELECT, INSERT, DELETE, UPDATE) for each token
+// NOTE: A token without permissions has access to every database, action and operation.
+// Once a permission is granted, the token is restricted to the granted permissions, even
+// after they are revoked. Check add_permission_restriction.
+fn create_user_token_permission_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_user_token_permission(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_uuid TEXT NOT NULL,
+            db_name TEXT NOT NULL CHECK (db_name != '') DEFAULT ('*'),
+            action TEXT NOT NULL CHECK (action IN ('*', 'query', 'migration', 'branch')) DEFAULT ('*'),
+            operation TEXT NOT NULL CHECK (operation IN ('*', 'SELECT', 'INSERT', 'UPDATE', 'DELETE')) DEFAULT ('*'),
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            UNIQUE (user_uuid, db_name, action, operation),
+            FOREIGN KEY (user_uuid) REFERENCES _config_user_token(user_uuid) ON DELETE CASCADE
+        );
+    "#
+    .to_string()
+}
+
+fn create_token_permission_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_token_permission(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_name TEXT NOT NULL,
+            db_name TEXT NOT NULL CHECK (db_name != '') DEFAULT ('*'),
+            action TEXT NOT NULL CHECK (action IN ('*', 'query', 'migration', 'branch')) DEFAULT ('*'),
+            operation TEXT NOT NULL CHECK (operation IN ('*', 'SELECT', 'INSERT', 'UPDATE', 'DELETE')) DEFAULT ('*'),
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            UNIQUE (token_name, db_name, action, operation),
+            FOREIGN KEY (token_name) REFERENCES _config_token(name) ON DELETE CASCADE ON UPDATE CASCADE
+        );
+    "#
+    .to_string()
+}
+
+// NOTE: The access of a token, kept apart from _config_token because any update of a token
+// generates a new token. The allowed IPs are comma-separated CIDRs, any IP if it is empty, and
+// the last use is written in batches.
+fn create_token_access_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_token_access(
+            token_name TEXT PRIMARY KEY,
+            allowed_ips TEXT NOT NULL DEFAULT (''),
+            revoked_at INTEGER,
+            last_used_at INTEGER,
+            last_used_ip TEXT,
+            FOREIGN KEY (token_name) REFERENCES _config_token(name) ON DELETE CASCADE ON UPDATE CASCADE
+        );
+    "#
+    .to_string()
+}
+
+// NOTE: The roles grant permissions to the non-admin users and tokens, e.g. deploy_functions
+fn create_role_tables() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_role(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE CHECK (name != ''),
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now')))
+        );
+
+        CREATE TABLE IF NOT EXISTS _config_role_permission(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            role_name TEXT NOT NULL,
+            permission TEXT NOT NULL CHECK (permission IN ('deploy_functions', 'push_assets', 'run_migrations', 'manage_branches', 'manage_users', 'manage_tokens')),
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            UNIQUE (role_name, permission),
+            FOREIGN KEY (role_name) REFERENCES _config_role(name) ON DELETE CASCADE ON UPDATE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS _config_user_role(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_uuid TEXT NOT NULL,
+            role_name TEXT NOT NULL,
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            UNIQUE (user_uuid, role_name),
+            FOREIGN KEY (user_uuid) REFERENCES _config_user(uuid) ON DELETE CASCADE,
+            FOREIGN KEY (role_name) REFERENCES _config_role(name) ON DELETE CASCADE ON UPDATE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS _config_token_role(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_name TEXT NOT NULL,
+            role_name TEXT NOT NULL,
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            UNIQUE (token_name, role_name),
+            FOREIGN KEY (token_name) REFERENCES _config_token(name) ON DELETE CASCADE ON UPDATE CASCADE,
+            FOREIGN KEY (role_name) REFERENCES _config_role(name) ON DELETE CASCADE ON UPDATE CASCADE
+        );
+    "#
+    .to_string()
+}
+
+// NOTE: The policies restrict the rows of a table that non-admin user tokens can access.
+// The predicate is a SQL expression that can use the auth_uid() function.
+fn create_policy_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_policy(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            db_name TEXT NOT NULL CHECK (db_name != ''),
+            table_name TEXT NOT NULL CHECK (table_name != ''),
+            operation TEXT NOT NULL CHECK (operation IN ('*', 'SELECT', 'INSERT', 'UPDATE', 'DELETE')) DEFAULT ('*'),
+            predicate TEXT NOT NULL CHECK (predicate != ''),
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            updated_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            UNIQUE (db_name, table_name, operation)
+        );
+
+        CREATE TRIGGER IF NOT EXISTS _trigger_config_policy_update
+            AFTER UPDATE ON _config_policy
+        BEGIN
+            UPDATE
+                _config_policy
+            SET
+                updated_at = (strftime('%s', datetime('now')))
+            WHERE
+                id = OLD.id;
+        END;
+    "#
+    .to_string()
+}
+
+// NOTE: The hash is the SHA-256 of the query, so the clients can call a persisted query by
+// its name or by its hash.
+fn create_persisted_query_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_persisted_query(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE CHECK (name != ''),
+            hash TEXT NOT NULL UNIQUE CHECK (hash != ''),
+            db_name TEXT NOT NULL CHECK (db_name != ''),
+            query TEXT NOT NULL CHECK (query != ''),
+            permission TEXT NOT NULL CHECK (permission IN ('read', 'write', 'admin')) DEFAULT ('read'),
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            updated_at INTEGER DEFAULT (strftime('%s', datetime('now')))
+        );
+
+        CREATE TRIGGER IF NOT EXISTS _trigger_config_persisted_query_update
+            AFTER UPDATE ON _config_persisted_query
+        BEGIN
+            UPDATE
+                _config_persisted_query
+            SET
+                updated_at = (strftime('%s', datetime('now')))
+            WHERE
+                id = OLD.id;
+        END;
+    "#
+    .to_string()
+}
+
+// NOTE: The table_name * subscribes to the changes of all the captured tables, and the cursor is
+// the id of the last change delivered to the function.
+fn create_change_subscription_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_change_subscription(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            db_name TEXT NOT NULL CHECK (db_name != ''),
+            table_name TEXT NOT NULL CHECK (table_name != '') DEFAULT ('*'),
+            function_path TEXT NOT NULL CHECK (function_path != ''),
+            cursor INTEGER NOT NULL DEFAULT 0,
+            last_delivered_at INTEGER,
+            last_error TEXT,
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            updated_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            UNIQUE (db_name, table_name, function_path)
+        );
+
+        CREATE TRIGGER IF NOT EXISTS _trigger_config_change_subscription_update
+            AFTER UPDATE ON _config_change_subscription
+        BEGIN
+            UPDATE
+                _config_change_subscription
+            SET
+                updated_at = (strftime('%s', datetime('now')))
+            WHERE
+                id = OLD.id;
+        END;
+    "#
+    .to_string()
+}
+
+// NOTE: The operations are * or a comma-separated list of INSERT, UPDATE and DELETE. The cursor
+// is the id of the last change delivered, and the attempts and the retry_at are the state of the
+// delivery of the next change after a failure.
+fn create_webhook_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_webhook(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE CHECK (name != ''),
+            db_name TEXT NOT NULL CHECK (db_name != ''),
+            table_name TEXT NOT NULL CHECK (table_name != '') DEFAULT ('*'),
+            operations TEXT NOT NULL CHECK (operations != '') DEFAULT ('*'),
+            url TEXT NOT NULL CHECK (url != ''),
+            secret TEXT NOT NULL CHECK (secret != ''),
+            active BOOLEAN NOT NULL DEFAULT TRUE,
+            cursor INTEGER NOT NULL DEFAULT 0,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            retry_at INTEGER,
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now'))),
+            updated_at INTEGER DEFAULT (strftime('%s', datetime('now')))
+        );
+
+        CREATE TRIGGER IF NOT EXISTS _trigger_config_webhook_update
+            AFTER UPDATE ON _config_webhook
+        BEGIN
+            UPDATE
+                _config_webhook
+            SET
+                updated_at = (strftime('%s', datetime('now')))
+            WHERE
+                id = OLD.id;
+        END;
+
+        CREATE TABLE IF NOT EXISTS _config_webhook_delivery(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            webhook_name TEXT NOT NULL,
+            change_id INTEGER NOT NULL,
+            attempt INTEGER NOT NULL,
+            status INTEGER,
+            error TEXT,
+            duration INTEGER NOT NULL DEFAULT 0,
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now')))
+        );
+
+        CREATE INDEX IF NOT EXISTS _index_config_webhook_delivery_webhook_name
+            ON _config_webhook_delivery (webhook_name);
+    "#
+    .to_string()
+}
+
+// NOTE: The source WAL position is the checkpoint sequence and the offset after the last frame
+// committed in the WAL of the source database when the branch was copied, they are NULL when the
+// source database didn't have a WAL. The expires_at is NULL for the branches without a TTL.
+fn create_branch_table() -> String {
+    r#"
+        CREATE TABLE IF NOT EXISTS _config_branch(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE CHECK (name != ''),
+            db_name TEXT NOT NULL CHECK (db_name != ''),
+            source_wal_checkpoint INTEGER,
+            source_wal_offset INTEGER,
+            created_by TEXT,
+            expires_at INTEGER,
+            created_at INTEGER DEFAULT (strftime('%s', datetime('now')))
+        );
+    "#
+    .to_string()
+}
 
 // IMPORTANT! Insert the initial name and value for each option
 fn options() -> String {
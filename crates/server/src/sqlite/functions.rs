@@ -1,13 +1,12 @@
 use std::sync::Arc;
 
-use jsonwebtoken::{encode, EncodingKey, Header};
 use rbase64;
 use regex::Regex;
 use rusqlite::ffi;
 use rusqlite::{functions::FunctionFlags, Connection, Error, Result};
 use uuid::Uuid;
 
-use crate::env::Env;
+use crate::controllers::utils::token_keys::encode_token;
 
 pub fn _uuid_function(conn: &Connection) -> Result<()> {
     conn.create_scalar_function(
@@ -20,6 +19,17 @@ pub fn _uuid_function(conn: &Connection) -> Result<()> {
     Ok(())
 }
 
+pub fn _auth_uid_function(conn: &Connection, user_uuid: String) -> Result<()> {
+    conn.create_scalar_function(
+        "auth_uid",
+        0,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |_ctx| Ok(user_uuid.clone()),
+    )?;
+
+    Ok(())
+}
+
 pub fn _regexp_function(conn: &Connection) -> Result<()> {
     type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
@@ -74,11 +84,7 @@ pub fn _token_function(conn: &Connection) -> Result<(), Error> {
             let claims = ctx.get_raw(0).as_str()?;
             let claims: serde_json::Value = serde_json::from_str(claims).unwrap();
 
-            let token = match encode(
-                &Header::default(),
-                &claims,
-                &EncodingKey::from_secret(Env::token_secret().as_ref()),
-            ) {
+            let token = match encode_token(&claims) {
                 Ok(v) => v,
                 Err(e) => {
                     return Err(Error::SqlInputError {
@@ -146,6 +152,7 @@ mod tests {
 
     use super::*;
 
+    use crate::env::Env;
     use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
     use rusqlite::{Connection, Result};
     use serde::{Deserialize, Serialize};
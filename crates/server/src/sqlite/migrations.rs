@@ -0,0 +1,422 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use rusqlite::{named_params, Connection, OptionalExtension, TransactionBehavior};
+use serde_json::{json, Value};
+
+use crate::controllers::utils::hex::sha256_hex;
+
+pub const MIGRATIONS_TABLE: &str = "_query_migrations";
+
+#[derive(Debug, PartialEq)]
+pub struct Migration {
+    pub version: String,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: i64,
+}
+
+impl Migration {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "version": self.version,
+            "name": self.name,
+            "checksum": self.checksum,
+            "applied_at": self.applied_at,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MigrationStatus {
+    Applied,
+    Skipped,
+    RolledBack,
+}
+
+#[derive(Debug)]
+pub struct DryRun {
+    pub skipped: bool,
+    pub error: Option<String>,
+    pub changes: u64,
+    pub schema_changes: Vec<Value>,
+    pub foreign_key_violations: Vec<Value>,
+}
+
+impl DryRun {
+    pub fn to_value(&self) -> Value {
+        json!({
+            "skipped": self.skipped,
+            "error": self.error,
+            "changes": self.changes,
+            "schema_changes": self.schema_changes,
+            "foreign_key_violations": self.foreign_key_violations,
+        })
+    }
+}
+
+fn create_migrations_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!(
+        "
+        CREATE TABLE IF NOT EXISTS {MIGRATIONS_TABLE} (
+            version TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            checksum TEXT NOT NULL,
+            applied_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+        );
+        "
+    ))?;
+
+    Ok(())
+}
+
+pub fn applied_migrations(conn: &Connection) -> Result<Vec<Migration>> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = ?;",
+        [MIGRATIONS_TABLE],
+        |row| row.get(0),
+    )?;
+
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "
+        SELECT
+            version,
+            name,
+            checksum,
+            applied_at
+        FROM
+            {MIGRATIONS_TABLE}
+        ORDER BY
+            version;
+        "
+    ))?;
+
+    let migrations = stmt
+        .query_map([], |row| {
+            Ok(Migration {
+                version: row.get(0)?,
+                name: row.get(1)?,
+                checksum: row.get(2)?,
+                applied_at: row.get(3)?,
+            })
+        })?
+        .collect::<Result<Vec<Migration>, rusqlite::Error>>()?;
+
+    Ok(migrations)
+}
+
+// NOTE: The migration and its record are written in the same transaction, so a migration is
+// never applied without being recorded. A version already applied is skipped when the query is
+// the same, and refused when the query changed after it was applied.
+pub fn apply_migration(
+    conn: &mut Connection,
+    version: &str,
+    name: &str,
+    query: &str,
+) -> Result<MigrationStatus> {
+    let checksum = sha256_hex(query);
+
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    create_migrations_table(&tx)?;
+
+    let applied: Option<String> = tx
+        .query_row(
+            &format!("SELECT checksum FROM {MIGRATIONS_TABLE} WHERE version = ?;"),
+            [version],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    match applied {
+        Some(v) if v == checksum => return Ok(MigrationStatus::Skipped),
+        Some(_) => bail!(
+            "The migration {} was already applied with a different checksum",
+            version
+        ),
+        None => (),
+    }
+
+    tx.execute_batch(query)?;
+
+    tx.execute(
+        &format!(
+            "
+            INSERT INTO {MIGRATIONS_TABLE} (version, name, checksum)
+            VALUES (:version, :name, :checksum);
+            "
+        ),
+        named_params! {
+            ":version": version,
+            ":name": name,
+            ":checksum": checksum,
+        },
+    )?;
+
+    tx.commit()?;
+
+    Ok(MigrationStatus::Applied)
+}
+
+// NOTE: The down migration and the removal of its record are written in the same transaction,
+// so only the migrations in the history can be rolled back
+pub fn rollback_migration(
+    conn: &mut Connection,
+    version: &str,
+    query: &str,
+) -> Result<MigrationStatus> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    create_migrations_table(&tx)?;
+
+    let deleted = tx.execute(
+        &format!("DELETE FROM {MIGRATIONS_TABLE} WHERE version = ?;"),
+        [version],
+    )?;
+
+    if deleted == 0 {
+        bail!("The migration {} isn't applied", version);
+    }
+
+    tx.execute_batch(query)?;
+    tx.commit()?;
+
+    Ok(MigrationStatus::RolledBack)
+}
+
+// NOTE: Runs the migration in the connection, that should be a throwaway copy of the database,
+// and reports what it changed. The changes don't count the rows of the migration history.
+pub fn dry_run_migration(
+    conn: &mut Connection,
+    version: Option<&str>,
+    name: &str,
+    query: &str,
+    rollback: bool,
+) -> Result<DryRun> {
+    let schema_before = schema_objects(conn)?;
+    let changes_before = conn.total_changes();
+
+    let result = match version {
+        Some(v) if rollback => rollback_migration(conn, v, query),
+        Some(v) => apply_migration(conn, v, name, query),
+        None => execute_migration(conn, query),
+    };
+
+    let history_changes = match (&result, version) {
+        (Ok(MigrationStatus::Applied | MigrationStatus::RolledBack), Some(_)) => 1,
+        _ => 0,
+    };
+
+    let changes = conn.total_changes() - changes_before - history_changes;
+    let schema_after = schema_objects(conn)?;
+
+    let mut schema_changes = Vec::new();
+
+    for ((object_type, name), sql) in &schema_after {
+        let change = match schema_before.get(&(object_type.to_string(), name.to_string())) {
+            None => "created",
+            Some(v) if v != sql => "altered",
+            Some(_) => continue,
+        };
+
+        schema_changes.push(json!({ "type": object_type, "name": name, "change": change }));
+    }
+
+    for (object_type, name) in schema_before.keys() {
+        if !schema_after.contains_key(&(object_type.to_string(), name.to_string())) {
+            schema_changes.push(json!({ "type": object_type, "name": name, "change": "dropped" }));
+        }
+    }
+
+    Ok(DryRun {
+        skipped: matches!(result, Ok(MigrationStatus::Skipped)),
+        error: result.err().map(|e| e.to_string()),
+        changes,
+        schema_changes,
+        foreign_key_violations: foreign_key_check(conn)?,
+    })
+}
+
+fn execute_migration(conn: &mut Connection, query: &str) -> Result<MigrationStatus> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+    tx.execute_batch(query)?;
+    tx.commit()?;
+
+    Ok(MigrationStatus::Applied)
+}
+
+fn schema_objects(conn: &Connection) -> Result<BTreeMap<(String, String), Option<String>>> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            type,
+            name,
+            sql
+        FROM
+            sqlite_master
+        WHERE
+            name NOT LIKE 'sqlite_%'
+        AND
+            name != ?;
+        ",
+    )?;
+
+    let objects = stmt
+        .query_map([MIGRATIONS_TABLE], |row| {
+            Ok(((row.get(0)?, row.get(1)?), row.get(2)?))
+        })?
+        .collect::<Result<BTreeMap<(String, String), Option<String>>, rusqlite::Error>>()?;
+
+    Ok(objects)
+}
+
+fn foreign_key_check(conn: &Connection) -> Result<Vec<Value>> {
+    let mut stmt = conn.prepare("PRAGMA foreign_key_check;")?;
+
+    let violations = stmt
+        .query_map([], |row| {
+            Ok(json!({
+                "table": row.get::<_, String>(0)?,
+                "rowid": row.get::<_, Option<i64>>(1)?,
+                "parent": row.get::<_, String>(2)?,
+                "fkid": row.get::<_, i64>(3)?,
+            }))
+        })?
+        .collect::<Result<Vec<Value>, rusqlite::Error>>()?;
+
+    Ok(violations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let query = "CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT);";
+
+        assert!(applied_migrations(&conn).unwrap().is_empty());
+
+        assert_eq!(
+            apply_migration(&mut conn, "20250101", "book", query).unwrap(),
+            MigrationStatus::Applied
+        );
+        assert_eq!(
+            apply_migration(&mut conn, "20250101", "book", query).unwrap(),
+            MigrationStatus::Skipped
+        );
+        assert!(apply_migration(&mut conn, "20250101", "book", "DROP TABLE book;").is_err());
+
+        let migrations = applied_migrations(&conn).unwrap();
+
+        assert_eq!(migrations.len(), 1);
+        assert_eq!(migrations[0].version, "20250101");
+        assert_eq!(migrations[0].name, "book");
+        assert_eq!(migrations[0].checksum, sha256_hex(query));
+    }
+
+    #[test]
+    fn test_rollback_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let down = "DROP TABLE book;";
+
+        assert!(rollback_migration(&mut conn, "20250101", down).is_err());
+
+        apply_migration(
+            &mut conn,
+            "20250101",
+            "book",
+            "CREATE TABLE book (id INTEGER PRIMARY KEY);",
+        )
+        .unwrap();
+
+        assert_eq!(
+            rollback_migration(&mut conn, "20250101", down).unwrap(),
+            MigrationStatus::RolledBack
+        );
+        assert!(applied_migrations(&conn).unwrap().is_empty());
+        assert!(conn.prepare("SELECT * FROM book;").is_err());
+    }
+
+    #[test]
+    fn test_dry_run_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(
+            "
+            PRAGMA foreign_keys = OFF;
+            CREATE TABLE author (id INTEGER PRIMARY KEY);
+            CREATE TABLE book (id INTEGER PRIMARY KEY, author_id INTEGER REFERENCES author (id));
+            INSERT INTO author (id) VALUES (1), (2);
+            ",
+        )
+        .unwrap();
+
+        let report = dry_run_migration(
+            &mut conn,
+            Some("20250101"),
+            "book",
+            "
+            CREATE INDEX book_author_id ON book (author_id);
+            DROP TABLE author;
+            INSERT INTO book (author_id) VALUES (3);
+            ",
+            false,
+        )
+        .unwrap();
+
+        assert!(!report.skipped);
+        assert_eq!(report.error, None);
+        assert_eq!(report.changes, 1);
+        assert_eq!(report.schema_changes.len(), 2);
+        assert!(report
+            .schema_changes
+            .contains(&json!({ "type": "index", "name": "book_author_id", "change": "created" })));
+        assert!(report
+            .schema_changes
+            .contains(&json!({ "type": "table", "name": "author", "change": "dropped" })));
+        assert_eq!(report.foreign_key_violations.len(), 1);
+        assert_eq!(report.foreign_key_violations[0]["parent"], "author");
+
+        let report = dry_run_migration(
+            &mut conn,
+            None,
+            "",
+            "INSERT INTO missing VALUES (1);",
+            false,
+        )
+        .unwrap();
+
+        assert!(report.error.is_some());
+        assert!(report.schema_changes.is_empty());
+    }
+
+    #[test]
+    fn test_apply_migration_error() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        assert!(apply_migration(
+            &mut conn,
+            "20250101",
+            "book",
+            "CREATE TABLE book (id INTEGER PRIMARY KEY); INSERT INTO missing VALUES (1);"
+        )
+        .is_err());
+
+        let tables: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE name IN ('book', ?);",
+                [MIGRATIONS_TABLE],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(tables, 0);
+    }
+}
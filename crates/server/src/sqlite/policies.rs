@@ -0,0 +1,573 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, Result};
+use rusqlite::{
+    hooks::{AuthAction, AuthContext, Authorization},
+    Connection,
+};
+
+use crate::changes::capture::CHANGES_TABLE;
+
+use super::{
+    connect_db::connect_config_db, functions::_auth_uid_function, migrations::MIGRATIONS_TABLE,
+};
+
+const POLICY_TRIGGER_PREFIX: &str = "_query_policy_";
+
+// NOTE: The tables written by Query in the databases. The captured changes have the whole rows,
+// so only the admins can access them.
+const INTERNAL_TABLES: [&str; 2] = [CHANGES_TABLE, MIGRATIONS_TABLE];
+
+#[derive(Debug)]
+pub struct Policy {
+    pub table_name: String,
+    pub operation: String,
+    pub predicate: String,
+}
+
+pub fn get_policies(db_name: &str) -> Result<Vec<Policy>> {
+    let conn = connect_config_db()?;
+
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            table_name,
+            operation,
+            predicate
+        FROM
+            _config_policy
+        WHERE
+            db_name = ?
+        ORDER BY
+            id;
+        ",
+    )?;
+
+    let policies = stmt
+        .query_map([db_name], |row| {
+            Ok(Policy {
+                table_name: row.get(0)?,
+                operation: row.get(1)?,
+                predicate: row.get(2)?,
+            })
+        })?
+        .collect::<Result<Vec<Policy>, rusqlite::Error>>()?;
+
+    Ok(policies)
+}
+
+// NOTE: The policies are applied per connection, so the connection must only be used to run
+// the given query. The reads of a table with policies go through a temporary view, with the
+// same name of the table, that filters the rows with the SELECT policies. The table written by
+// the query gets temporary triggers instead, that ignore the updates and deletes of the rows
+// that don't match the policies and abort the inserts and updates of rows that don't match.
+// An operation without policies on a table with policies doesn't match any row. The WHERE of
+// an update or a delete runs on every row of the table, before the triggers, so the query can
+// only read the primary key of the table it writes to, or an error could reveal the other rows.
+// The primary keys are already revealed by the UNIQUE constraint errors.
+pub fn apply_policies(
+    conn: &Connection,
+    policies: &[Policy],
+    user_uuid: &str,
+    query: &str,
+) -> Result<()> {
+    if policies.is_empty() {
+        return Ok(());
+    }
+
+    _auth_uid_function(conn, user_uuid.to_string())?;
+
+    let mut tables = policies
+        .iter()
+        .map(|p| p.table_name.clone())
+        .collect::<Vec<String>>();
+    tables.sort();
+    tables.dedup();
+
+    let target = write_target(conn, query, &tables)?;
+    let target_keys = match &target {
+        Some(table) => primary_key_columns(conn, table)?,
+        None => HashSet::new(),
+    };
+
+    let mut sql: Vec<String> = vec![];
+    let mut accessors: HashSet<String> = HashSet::new();
+
+    for table in &tables {
+        let columns = table_columns(conn, table)?;
+
+        if columns.is_empty() {
+            continue;
+        }
+
+        let quoted_table = quote(table);
+
+        if Some(table) != target.as_ref() {
+            sql.push(format!(
+                "CREATE TEMP VIEW {quoted_table} AS SELECT * FROM main.{quoted_table} AS {quoted_table} WHERE ({});",
+                predicate(policies, table, "SELECT")
+            ));
+            accessors.insert(table.to_string());
+            continue;
+        }
+
+        for operation in ["INSERT", "UPDATE", "DELETE"] {
+            let trigger = format!(
+                "{POLICY_TRIGGER_PREFIX}{}_{}",
+                table,
+                operation.to_lowercase()
+            );
+            let predicate = predicate(policies, table, operation);
+            let ignore_old = format!(
+                "SELECT RAISE(IGNORE) WHERE NOT EXISTS (SELECT 1 FROM (SELECT {}) AS {quoted_table} WHERE ({predicate}));",
+                row_columns(&columns, "OLD")
+            );
+            let abort_new = format!(
+                "SELECT RAISE(ABORT, 'The row violates the {} policy of the table {}') WHERE NOT EXISTS (SELECT 1 FROM (SELECT {}) AS {quoted_table} WHERE ({predicate}));",
+                operation,
+                table.replace('\'', "''"),
+                row_columns(&columns, "NEW")
+            );
+            let body = match operation {
+                "INSERT" => abort_new,
+                "UPDATE" => format!("{ignore_old}\n{abort_new}"),
+                _ => ignore_old,
+            };
+
+            sql.push(format!(
+                "CREATE TEMP TRIGGER {} BEFORE {operation} ON main.{quoted_table}\nBEGIN\n{body}\nEND;",
+                quote(&trigger)
+            ));
+            accessors.insert(trigger);
+        }
+    }
+
+    conn.execute_batch(&sql.join("\n"))?;
+
+    // IMPORTANT! The triggers of the database are created by admins, so they are allowed to
+    // access the tables with policies
+    let mut stmt = conn.prepare("SELECT name FROM main.sqlite_master WHERE type = 'trigger'")?;
+    for trigger in stmt.query_map([], |row| row.get::<_, String>(0))? {
+        accessors.insert(trigger?);
+    }
+
+    let tables: HashSet<String> = tables.into_iter().collect();
+    let is_internal_query = mentions_internal_table(query);
+
+    conn.authorizer(Some(
+        move |ctx: AuthContext<'_>| match authorize_internal_tables(&ctx, is_internal_query) {
+            Authorization::Allow => {
+                authorize(&ctx, &tables, target.as_deref(), &target_keys, &accessors)
+            }
+            authorization => authorization,
+        },
+    ));
+
+    Ok(())
+}
+
+// NOTE: The connection must only be used to run the given query of a non-admin token. The
+// policies deny the internal tables too, so this is only needed for the databases without policies.
+pub fn deny_internal_tables(conn: &Connection, query: &str) {
+    let is_internal_query = mentions_internal_table(query);
+
+    conn.authorizer(Some(move |ctx: AuthContext<'_>| {
+        authorize_internal_tables(&ctx, is_internal_query)
+    }));
+}
+
+pub fn is_internal_table(table_name: &str) -> bool {
+    INTERNAL_TABLES
+        .iter()
+        .any(|t| t.eq_ignore_ascii_case(table_name))
+}
+
+// NOTE: The names of the tables can't be escaped or split in SQL, so a query that doesn't
+// contain them, in any case, can't refer to the internal tables
+fn mentions_internal_table(query: &str) -> bool {
+    let query = query.to_ascii_lowercase();
+
+    INTERNAL_TABLES.iter().any(|t| query.contains(t))
+}
+
+// NOTE: The triggers that capture the changes and the views of the database, created by the
+// admins, can access the internal tables. The bodies of the views and the triggers aren't
+// authorized when they are created, so a query that mentions an internal table can't create them,
+// or they would read or write the internal tables as an accessor.
+fn authorize_internal_tables(ctx: &AuthContext<'_>, is_internal_query: bool) -> Authorization {
+    match ctx.action {
+        AuthAction::CreateTempTrigger { .. }
+        | AuthAction::CreateTempView { .. }
+        | AuthAction::CreateTrigger { .. }
+        | AuthAction::CreateView { .. }
+            if is_internal_query =>
+        {
+            return Authorization::Deny;
+        }
+        _ => {}
+    }
+
+    if ctx.accessor.is_some() {
+        return Authorization::Allow;
+    }
+
+    match ctx.action {
+        AuthAction::Read { table_name, .. }
+        | AuthAction::Insert { table_name }
+        | AuthAction::Update { table_name, .. }
+        | AuthAction::Delete { table_name }
+        | AuthAction::DropTable { table_name }
+        | AuthAction::AlterTable { table_name, .. }
+            if is_internal_table(table_name) =>
+        {
+            Authorization::Deny
+        }
+        _ => Authorization::Allow,
+    }
+}
+
+fn authorize(
+    ctx: &AuthContext<'_>,
+    tables: &HashSet<String>,
+    target: Option<&str>,
+    target_keys: &HashSet<String>,
+    accessors: &HashSet<String>,
+) -> Authorization {
+    let is_protected = |table_name: &str| {
+        ctx.database_name == Some("main")
+            && tables.contains(table_name)
+            && Some(table_name) != target
+            && !ctx.accessor.is_some_and(|a| accessors.contains(a))
+    };
+
+    match ctx.action {
+        AuthAction::Read { table_name, .. }
+        | AuthAction::Insert { table_name }
+        | AuthAction::Update { table_name, .. }
+        | AuthAction::Delete { table_name }
+            if is_protected(table_name) =>
+        {
+            Authorization::Deny
+        }
+        AuthAction::Read {
+            table_name,
+            column_name,
+        } if ctx.database_name == Some("main")
+            && Some(table_name) == target
+            && ctx.accessor.is_none()
+            && !column_name.eq_ignore_ascii_case("rowid")
+            && !target_keys.contains(column_name) =>
+        {
+            Authorization::Deny
+        }
+        AuthAction::DropTable { table_name } | AuthAction::AlterTable { table_name, .. }
+            if tables.contains(table_name) =>
+        {
+            Authorization::Deny
+        }
+        AuthAction::CreateTempTrigger { .. }
+        | AuthAction::CreateTempView { .. }
+        | AuthAction::CreateTrigger { .. }
+        | AuthAction::CreateView { .. }
+        | AuthAction::DropTempTrigger { .. }
+        | AuthAction::DropTempView { .. }
+        | AuthAction::DropTrigger { .. }
+        | AuthAction::DropView { .. }
+        | AuthAction::Attach { .. }
+        | AuthAction::Detach { .. } => Authorization::Deny,
+        _ => Authorization::Allow,
+    }
+}
+
+// NOTE: Prepares the query to find the table with policies that it writes to. The reads of
+// that table aren't filtered by a view, so a write that reads it in a subquery isn't allowed.
+fn write_target(conn: &Connection, query: &str, tables: &[String]) -> Result<Option<String>> {
+    #[derive(Default)]
+    struct Access {
+        writes: HashSet<String>,
+        reads: HashSet<String>,
+        has_select: bool,
+    }
+
+    let access = Arc::new(Mutex::new(Access::default()));
+    let recorder = Arc::clone(&access);
+
+    conn.authorizer(Some(move |ctx: AuthContext<'_>| {
+        let mut access = recorder.lock().unwrap();
+
+        if ctx.accessor.is_none() {
+            match ctx.action {
+                AuthAction::Insert { table_name }
+                | AuthAction::Update { table_name, .. }
+                | AuthAction::Delete { table_name } => {
+                    access.writes.insert(table_name.to_string());
+                }
+                AuthAction::Read { table_name, .. } => {
+                    access.reads.insert(table_name.to_string());
+                }
+                AuthAction::Select => access.has_select = true,
+                _ => {}
+            }
+        }
+
+        Authorization::Allow
+    }));
+
+    let prepared = conn.prepare(query).map(|_| ());
+
+    conn.authorizer(None::<fn(AuthContext<'_>) -> Authorization>);
+
+    // The errors of the query are returned when it runs
+    if prepared.is_err() {
+        return Ok(None);
+    }
+
+    let access = access.lock().unwrap();
+    let targets = tables
+        .iter()
+        .filter(|t| access.writes.contains(*t))
+        .collect::<Vec<&String>>();
+
+    match targets.as_slice() {
+        [] => Ok(None),
+        [target] if access.has_select && access.reads.contains(*target) => bail!(
+            "A write to the table {} can't read it in a subquery because it has policies",
+            target
+        ),
+        [target] => Ok(Some(target.to_string())),
+        _ => bail!("A query can't write to more than one table with policies"),
+    }
+}
+
+fn predicate(policies: &[Policy], table: &str, operation: &str) -> String {
+    let predicates = policies
+        .iter()
+        .filter(|p| p.table_name == table && (p.operation == "*" || p.operation == operation))
+        .map(|p| format!("({})", p.predicate))
+        .collect::<Vec<String>>();
+
+    if predicates.is_empty() {
+        return "0".to_string();
+    }
+
+    predicates.join(" OR ")
+}
+
+fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?, 'main')")?;
+
+    let columns = stmt
+        .query_map([table], |row| row.get(0))?
+        .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+
+    Ok(columns)
+}
+
+// NOTE: The rowid is reported as ROWID, or as the INTEGER PRIMARY KEY column
+fn primary_key_columns(conn: &Connection, table: &str) -> Result<HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?, 'main') WHERE pk > 0")?;
+
+    let columns = stmt
+        .query_map([table], |row| row.get(0))?
+        .collect::<Result<HashSet<String>, rusqlite::Error>>()?;
+
+    Ok(columns)
+}
+
+fn row_columns(columns: &[String], row: &str) -> String {
+    columns
+        .iter()
+        .map(|c| format!("{row}.{} AS {}", quote(c), quote(c)))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup_test_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(
+            "
+            CREATE TABLE notes (
+                id INTEGER PRIMARY KEY,
+                owner TEXT NOT NULL,
+                content TEXT
+            );
+            CREATE TABLE public (
+                id INTEGER PRIMARY KEY,
+                content TEXT
+            );
+            INSERT INTO notes (owner, content) VALUES ('alice', 'a1'), ('bob', 'b1'), ('alice', 'a2');
+            INSERT INTO public (content) VALUES ('p1');
+            ",
+        )
+        .unwrap();
+
+        conn
+    }
+
+    fn owner_policies() -> Vec<Policy> {
+        vec![Policy {
+            table_name: "notes".to_string(),
+            operation: "*".to_string(),
+            predicate: "owner = auth_uid()".to_string(),
+        }]
+    }
+
+    fn count(conn: &Connection, query: &str) -> i64 {
+        conn.query_row(query, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_apply_policies_filters_reads() {
+        let conn = setup_test_db();
+        let query = "SELECT COUNT(*) FROM notes";
+
+        apply_policies(&conn, &owner_policies(), "alice", query).unwrap();
+
+        assert_eq!(count(&conn, query), 2);
+        assert_eq!(count(&conn, "SELECT COUNT(*) FROM public"), 1);
+        assert!(conn.prepare("SELECT COUNT(*) FROM main.notes").is_err());
+        assert!(conn.prepare("DROP VIEW temp.notes").is_err());
+    }
+
+    #[test]
+    fn test_apply_policies_checks_inserts() {
+        let conn = setup_test_db();
+        let query = "INSERT INTO notes (owner, content) VALUES (?, 'new')";
+
+        apply_policies(&conn, &owner_policies(), "alice", query).unwrap();
+
+        assert_eq!(conn.execute(query, ["alice"]).unwrap(), 1);
+        assert!(conn.execute(query, ["bob"]).is_err());
+    }
+
+    #[test]
+    fn test_apply_policies_ignores_other_rows() {
+        let conn = setup_test_db();
+        let query = "UPDATE notes SET content = 'updated'";
+
+        apply_policies(&conn, &owner_policies(), "alice", query).unwrap();
+
+        assert_eq!(conn.execute(query, []).unwrap(), 2);
+        assert!(conn.execute("UPDATE notes SET owner = 'bob'", []).is_err());
+
+        let conn = setup_test_db();
+        let query = "DELETE FROM notes";
+
+        apply_policies(&conn, &owner_policies(), "bob", query).unwrap();
+
+        assert_eq!(conn.execute(query, []).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_apply_policies_without_operation_policy() {
+        let conn = setup_test_db();
+        let policies = vec![Policy {
+            table_name: "notes".to_string(),
+            operation: "SELECT".to_string(),
+            predicate: "owner = auth_uid()".to_string(),
+        }];
+        let query = "DELETE FROM notes";
+
+        apply_policies(&conn, &policies, "alice", query).unwrap();
+
+        assert_eq!(conn.execute(query, []).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_apply_policies_only_reads_the_primary_key_of_target() {
+        let conn = setup_test_db();
+        let query = "UPDATE notes SET content = 'updated' WHERE id = 2";
+
+        apply_policies(&conn, &owner_policies(), "alice", query).unwrap();
+
+        assert_eq!(conn.execute(query, []).unwrap(), 0);
+        assert_eq!(
+            conn.execute("DELETE FROM notes WHERE rowid IN (1, 2)", [])
+                .unwrap(),
+            1
+        );
+
+        // NOTE: The WHERE runs on the rows of the other users before the triggers
+        assert!(conn
+            .prepare("DELETE FROM notes WHERE CASE WHEN content = 'b1' THEN abs(-9223372036854775808) END")
+            .is_err());
+        assert!(conn
+            .prepare("UPDATE notes SET content = content || '!' WHERE id = 3")
+            .is_err());
+    }
+
+    #[test]
+    fn test_deny_internal_tables() {
+        let conn = setup_test_db();
+
+        conn.execute_batch(
+            "
+            CREATE TABLE _query_changes (id INTEGER PRIMARY KEY, new TEXT);
+            CREATE TABLE _query_migrations (version TEXT PRIMARY KEY);
+            CREATE TRIGGER capture AFTER INSERT ON public BEGIN INSERT INTO _query_changes (new) VALUES (NEW.content); END;
+            ",
+        )
+        .unwrap();
+
+        let execute = |query: &str| {
+            deny_internal_tables(&conn, query);
+            conn.execute_batch(query)
+        };
+
+        assert!(execute("SELECT * FROM _query_changes").is_err());
+        assert!(execute("SELECT * FROM main._QUERY_CHANGES").is_err());
+        assert!(execute("DELETE FROM _query_changes").is_err());
+        assert!(execute("SELECT * FROM _query_migrations").is_err());
+        assert!(execute("DROP TABLE _query_migrations").is_err());
+        assert!(execute("INSERT INTO public (content) VALUES ('p2')").is_ok());
+
+        // NOTE: A view or a trigger can't be used to access the internal tables
+        assert!(execute("CREATE VIEW changes AS SELECT * FROM _query_changes").is_err());
+        assert!(execute("CREATE TEMP VIEW changes AS SELECT * FROM \"_Query_Changes\"").is_err());
+        assert!(execute(
+            "CREATE TRIGGER forge AFTER INSERT ON public BEGIN DELETE FROM _query_migrations; END"
+        )
+        .is_err());
+        assert!(execute("CREATE VIEW contents AS SELECT content FROM public").is_ok());
+        assert!(execute("SELECT * FROM contents").is_ok());
+
+        let conn = setup_test_db();
+
+        conn.execute_batch("CREATE TABLE _query_changes (id INTEGER PRIMARY KEY);")
+            .unwrap();
+
+        apply_policies(&conn, &owner_policies(), "alice", "SELECT 1").unwrap();
+
+        assert!(conn
+            .prepare("CREATE VIEW changes AS SELECT * FROM _query_changes")
+            .is_err());
+
+        assert!(conn.prepare("SELECT * FROM _query_changes").is_err());
+        assert!(conn.prepare("SELECT * FROM public").is_ok());
+    }
+
+    #[test]
+    fn test_apply_policies_rejects_subqueries_on_target() {
+        let conn = setup_test_db();
+
+        assert!(apply_policies(
+            &conn,
+            &owner_policies(),
+            "alice",
+            "INSERT INTO notes (owner, content) SELECT 'alice', content FROM notes",
+        )
+        .is_err());
+    }
+}
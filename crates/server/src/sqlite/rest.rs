@@ -0,0 +1,440 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use rusqlite::{types::Value, Connection};
+
+use super::schema::get_columns;
+
+const DEFAULT_LIMIT: u64 = 100;
+const MAX_LIMIT: u64 = 1000;
+const RESERVED_PARAMS: [&str; 4] = ["select", "order", "limit", "offset"];
+
+// NOTE: A table, or a view, exposed as a REST resource. The rows are identified by the column of
+// the primary key, or by the rowid if the table doesn't have one. The views and the tables with
+// a composite primary key can only be listed.
+#[derive(Debug)]
+pub struct RestTable {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub primary_key: Option<String>,
+    pub is_view: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RestQuery {
+    pub sql: String,
+    pub params: Vec<Value>,
+}
+
+impl RestTable {
+    pub fn new(conn: &Connection, table_name: &str) -> Result<RestTable> {
+        let object_type: Option<String> = conn
+            .query_row(
+                "SELECT type FROM main.sqlite_schema WHERE type IN ('table', 'view') AND name = ? AND name NOT LIKE 'sqlite_%'",
+                [table_name],
+                |row| row.get(0),
+            )
+            .ok();
+
+        let is_view = match object_type.as_deref() {
+            Some("view") => true,
+            Some(_) => false,
+            None => bail!("The table {} doesn't exist", table_name),
+        };
+
+        let columns = get_columns(conn, table_name)?;
+
+        let primary_key = match columns
+            .iter()
+            .filter(|c| c.primary_key > 0)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            _ if is_view => None,
+            [] => Some("rowid".to_string()),
+            [column] => Some(column.name.clone()),
+            _ => None,
+        };
+
+        Ok(RestTable {
+            name: table_name.to_string(),
+            columns: columns.into_iter().map(|c| c.name).collect(),
+            primary_key,
+            is_view,
+        })
+    }
+
+    // NOTE: The filters are <column>=<operator>.<value>, e.g. age=gte.18, with the operators
+    // eq, neq, gt, gte, lt, lte, like, in (comma-separated values), is.null and not.null
+    pub fn select(&self, params: &HashMap<String, String>) -> Result<RestQuery> {
+        let columns = match params.get("select") {
+            Some(select) => select
+                .split(',')
+                .map(|c| self.column(c.trim()))
+                .collect::<Result<Vec<_>>>()?
+                .join(", "),
+            None => "*".to_string(),
+        };
+
+        let mut filters = Vec::new();
+        let mut values = Vec::new();
+
+        let mut keys = params
+            .keys()
+            .filter(|k| !RESERVED_PARAMS.contains(&k.as_str()))
+            .collect::<Vec<_>>();
+        keys.sort();
+
+        for key in keys {
+            let column = self.column(key)?;
+            let (operator, value) = params[key].split_once('.').unwrap_or((&params[key], ""));
+
+            let filter = match operator {
+                "eq" => format!("{column} = ?"),
+                "neq" => format!("{column} != ?"),
+                "gt" => format!("{column} > ?"),
+                "gte" => format!("{column} >= ?"),
+                "lt" => format!("{column} < ?"),
+                "lte" => format!("{column} <= ?"),
+                "like" => format!("{column} LIKE ?"),
+                "in" => {
+                    let list = value.split(',').collect::<Vec<_>>();
+
+                    values.extend(list.iter().map(|v| Value::Text(v.to_string())));
+                    filters.push(format!(
+                        "{column} IN ({})",
+                        vec!["?"; list.len()].join(", ")
+                    ));
+
+                    continue;
+                }
+                "is" | "not" if value == "null" => {
+                    let not = if operator == "not" { " NOT" } else { "" };
+
+                    filters.push(format!("{column} IS{not} NULL"));
+
+                    continue;
+                }
+                _ => bail!("The filter {}={} is not valid", key, params[key]),
+            };
+
+            filters.push(filter);
+            values.push(Value::Text(value.to_string()));
+        }
+
+        let mut sql = format!("SELECT {} FROM {}", columns, quote(&self.name));
+
+        if !filters.is_empty() {
+            sql.push_str(&format!(" WHERE {}", filters.join(" AND ")));
+        }
+
+        if let Some(order) = params.get("order") {
+            let order = order
+                .split(',')
+                .map(|o| {
+                    let (column, direction) = o.trim().split_once('.').unwrap_or((o.trim(), "asc"));
+                    let direction = match direction {
+                        "asc" => "ASC",
+                        "desc" => "DESC",
+                        _ => bail!("The order {} is not valid", o),
+                    };
+
+                    Ok(format!("{} {}", self.column(column)?, direction))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            sql.push_str(&format!(" ORDER BY {}", order.join(", ")));
+        }
+
+        let limit = parse_number(params, "limit")?.unwrap_or(DEFAULT_LIMIT);
+        let offset = parse_number(params, "offset")?.unwrap_or(0);
+
+        if limit > MAX_LIMIT {
+            bail!("The limit can't be greater than {}", MAX_LIMIT);
+        }
+
+        sql.push_str(" LIMIT ? OFFSET ?");
+        values.push(Value::Integer(limit as i64));
+        values.push(Value::Integer(offset as i64));
+
+        Ok(RestQuery {
+            sql,
+            params: values,
+        })
+    }
+
+    pub fn select_by_key(&self, key: &str) -> Result<RestQuery> {
+        Ok(RestQuery {
+            sql: format!(
+                "SELECT * FROM {} WHERE {} = ?",
+                quote(&self.name),
+                self.key_column()?
+            ),
+            params: vec![Value::Text(key.to_string())],
+        })
+    }
+
+    pub fn insert(&self, values: Vec<(String, Value)>) -> Result<RestQuery> {
+        self.validate_writable()?;
+
+        if values.is_empty() {
+            return Ok(RestQuery {
+                sql: format!("INSERT INTO {} DEFAULT VALUES", quote(&self.name)),
+                params: vec![],
+            });
+        }
+
+        let columns = values
+            .iter()
+            .map(|(c, _)| self.column(c))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(RestQuery {
+            sql: format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                quote(&self.name),
+                columns.join(", "),
+                vec!["?"; columns.len()].join(", ")
+            ),
+            params: values.into_iter().map(|(_, v)| v).collect(),
+        })
+    }
+
+    pub fn update(&self, key: &str, values: Vec<(String, Value)>) -> Result<RestQuery> {
+        self.validate_writable()?;
+
+        if values.is_empty() {
+            bail!("The body doesn't have any column to update");
+        }
+
+        let columns = values
+            .iter()
+            .map(|(c, _)| Ok(format!("{} = ?", self.column(c)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut params: Vec<Value> = values.into_iter().map(|(_, v)| v).collect();
+        params.push(Value::Text(key.to_string()));
+
+        Ok(RestQuery {
+            sql: format!(
+                "UPDATE {} SET {} WHERE {} = ?",
+                quote(&self.name),
+                columns.join(", "),
+                self.key_column()?
+            ),
+            params,
+        })
+    }
+
+    pub fn delete(&self, key: &str) -> Result<RestQuery> {
+        self.validate_writable()?;
+
+        Ok(RestQuery {
+            sql: format!(
+                "DELETE FROM {} WHERE {} = ?",
+                quote(&self.name),
+                self.key_column()?
+            ),
+            params: vec![Value::Text(key.to_string())],
+        })
+    }
+
+    fn column(&self, name: &str) -> Result<String> {
+        if !self.columns.iter().any(|c| c == name) {
+            bail!("The column {} doesn't exist in {}", name, self.name);
+        }
+
+        Ok(quote(name))
+    }
+
+    fn key_column(&self) -> Result<String> {
+        match &self.primary_key {
+            Some(v) => Ok(quote(v)),
+            None => bail!(
+                "The rows of {} can't be accessed by key, it can only be listed",
+                self.name
+            ),
+        }
+    }
+
+    fn validate_writable(&self) -> Result<()> {
+        if self.is_view {
+            bail!("The view {} is read-only", self.name);
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_number(params: &HashMap<String, String>, name: &str) -> Result<Option<u64>> {
+    match params.get(name) {
+        Some(v) => match v.parse::<u64>() {
+            Ok(v) => Ok(Some(v)),
+            Err(_) => bail!("The {} {} is not valid", name, v),
+        },
+        None => Ok(None),
+    }
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::params_from_iter;
+
+    use crate::controllers::utils::statement_to_vec::statement_to_vec;
+
+    use super::*;
+
+    fn setup() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(
+            "
+            CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT, year INTEGER);
+            INSERT INTO book (title, year) VALUES ('a', 2001), ('b', 2002), ('c', NULL);
+            CREATE TABLE note (body TEXT);
+            CREATE TABLE pair (a INTEGER, b INTEGER, PRIMARY KEY (a, b));
+            CREATE VIEW book_title AS SELECT title FROM book;
+            ",
+        )
+        .unwrap();
+
+        conn
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn count(conn: &Connection, query: RestQuery) -> usize {
+        let stmt = conn.prepare(&query.sql).unwrap();
+
+        statement_to_vec(stmt, params_from_iter(query.params))
+            .unwrap()
+            .len()
+    }
+
+    #[test]
+    fn test_rest_table() {
+        let conn = setup();
+
+        let book = RestTable::new(&conn, "book").unwrap();
+        assert_eq!(book.primary_key, Some("id".to_string()));
+        assert_eq!(book.columns, vec!["id", "title", "year"]);
+        assert!(!book.is_view);
+
+        let note = RestTable::new(&conn, "note").unwrap();
+        assert_eq!(note.primary_key, Some("rowid".to_string()));
+        conn.execute("INSERT INTO note (body) VALUES ('a')", [])
+            .unwrap();
+        assert_eq!(count(&conn, note.select_by_key("1").unwrap()), 1);
+
+        let view = RestTable::new(&conn, "book_title").unwrap();
+        assert!(view.is_view);
+        assert!(view.select_by_key("1").is_err());
+
+        let pair = RestTable::new(&conn, "pair").unwrap();
+        assert_eq!(pair.primary_key, None);
+        assert!(pair.select(&params(&[])).is_ok());
+        assert!(pair.select_by_key("1").is_err());
+        assert!(RestTable::new(&conn, "missing").is_err());
+        assert!(RestTable::new(&conn, "sqlite_schema").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let conn = setup();
+        let book = RestTable::new(&conn, "book").unwrap();
+
+        let query = book
+            .select(&params(&[
+                ("select", "id,title"),
+                ("year", "gte.2002"),
+                ("order", "year.desc,id"),
+                ("limit", "10"),
+            ]))
+            .unwrap();
+
+        assert_eq!(
+            query,
+            RestQuery {
+                sql: "SELECT \"id\", \"title\" FROM \"book\" WHERE \"year\" >= ? ORDER BY \"year\" DESC, \"id\" ASC LIMIT ? OFFSET ?".to_string(),
+                params: vec![
+                    Value::Text("2002".to_string()),
+                    Value::Integer(10),
+                    Value::Integer(0)
+                ],
+            }
+        );
+        assert_eq!(count(&conn, query), 1);
+
+        assert_eq!(count(&conn, book.select(&params(&[])).unwrap()), 3);
+        assert_eq!(
+            count(&conn, book.select(&params(&[("year", "is.null")])).unwrap()),
+            1
+        );
+        assert_eq!(
+            count(&conn, book.select(&params(&[("title", "in.a,b")])).unwrap()),
+            2
+        );
+        assert_eq!(
+            count(
+                &conn,
+                book.select(&params(&[("limit", "2"), ("offset", "2")]))
+                    .unwrap()
+            ),
+            1
+        );
+
+        assert!(book.select(&params(&[("missing", "eq.1")])).is_err());
+        assert!(book.select(&params(&[("year", "between.1")])).is_err());
+        assert!(book.select(&params(&[("order", "year.up")])).is_err());
+        assert!(book.select(&params(&[("limit", "1001")])).is_err());
+        assert!(book.select(&params(&[("select", "title; DROP")])).is_err());
+    }
+
+    #[test]
+    fn test_write() {
+        let conn = setup();
+        let book = RestTable::new(&conn, "book").unwrap();
+
+        let insert = book
+            .insert(vec![("title".to_string(), Value::Text("d".to_string()))])
+            .unwrap();
+        assert_eq!(insert.sql, "INSERT INTO \"book\" (\"title\") VALUES (?)");
+        conn.execute(&insert.sql, params_from_iter(insert.params))
+            .unwrap();
+        assert_eq!(count(&conn, book.select_by_key("4").unwrap()), 1);
+
+        let update = book
+            .update("4", vec![("year".to_string(), Value::Integer(2004))])
+            .unwrap();
+        assert_eq!(
+            conn.execute(&update.sql, params_from_iter(update.params))
+                .unwrap(),
+            1
+        );
+
+        let delete = book.delete("4").unwrap();
+        assert_eq!(
+            conn.execute(&delete.sql, params_from_iter(delete.params))
+                .unwrap(),
+            1
+        );
+        assert_eq!(count(&conn, book.select_by_key("4").unwrap()), 0);
+
+        assert!(book.update("1", vec![]).is_err());
+        assert!(book
+            .insert(vec![("missing".to_string(), Value::Null)])
+            .is_err());
+
+        let view = RestTable::new(&conn, "book_title").unwrap();
+        assert!(view.delete("1").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use anyhow::Result;
+use rusqlite::Connection;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct Schema {
+    pub tables: Vec<Table>,
+    pub views: Vec<View>,
+    pub triggers: Vec<Trigger>,
+}
+
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct Table {
+    pub name: String,
+    pub sql: Option<String>,
+    pub columns: Vec<Column>,
+    pub indexes: Vec<Index>,
+    pub foreign_keys: Vec<ForeignKey>,
+}
+
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct View {
+    pub name: String,
+    pub sql: Option<String>,
+    pub columns: Vec<Column>,
+}
+
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub column_type: String,
+    pub not_null: bool,
+    pub default: Option<String>,
+    // NOTE: The position of the column in the primary key, starting at 1, or 0 if it isn't part
+    // of it
+    pub primary_key: i64,
+}
+
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct Index {
+    pub name: String,
+    pub unique: bool,
+    pub partial: bool,
+    // NOTE: "c" for CREATE INDEX, "u" for UNIQUE constraints and "pk" for PRIMARY KEY constraints
+    pub origin: String,
+    pub columns: Vec<Option<String>>,
+    pub sql: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct ForeignKey {
+    pub table: String,
+    pub from: Vec<String>,
+    pub to: Vec<Option<String>>,
+    pub on_update: String,
+    pub on_delete: String,
+}
+
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+pub struct Trigger {
+    pub name: String,
+    pub table: String,
+    pub sql: Option<String>,
+}
+
+// NOTE: The internal objects of SQLite, prefixed with sqlite_, are left out
+pub fn get_schema(conn: &Connection) -> Result<Schema> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            type,
+            name,
+            tbl_name,
+            sql
+        FROM
+            main.sqlite_schema
+        WHERE
+            type IN ('table', 'view', 'trigger')
+        AND
+            name NOT LIKE 'sqlite_%'
+        ORDER BY
+            name;
+        ",
+    )?;
+
+    let objects = stmt
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+    let mut schema = Schema {
+        tables: vec![],
+        views: vec![],
+        triggers: vec![],
+    };
+
+    for (object_type, name, table, sql) in objects {
+        match object_type.as_str() {
+            "table" => schema.tables.push(Table {
+                columns: get_columns(conn, &name)?,
+                indexes: get_indexes(conn, &name)?,
+                foreign_keys: get_foreign_keys(conn, &name)?,
+                name,
+                sql,
+            }),
+            "view" => schema.views.push(View {
+                columns: get_columns(conn, &name)?,
+                name,
+                sql,
+            }),
+            _ => schema.triggers.push(Trigger { name, table, sql }),
+        }
+    }
+
+    Ok(schema)
+}
+
+pub fn get_columns(conn: &Connection, table_name: &str) -> Result<Vec<Column>> {
+    let mut stmt = conn.prepare(
+        "SELECT name, type, \"notnull\", dflt_value, pk FROM pragma_table_info(?) ORDER BY cid;",
+    )?;
+
+    let columns = stmt
+        .query_map([table_name], |row| {
+            Ok(Column {
+                name: row.get(0)?,
+                column_type: row.get(1)?,
+                not_null: row.get(2)?,
+                default: row.get(3)?,
+                primary_key: row.get(4)?,
+            })
+        })?
+        .collect::<Result<Vec<Column>, rusqlite::Error>>()?;
+
+    Ok(columns)
+}
+
+fn get_indexes(conn: &Connection, table_name: &str) -> Result<Vec<Index>> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            l.name,
+            l.\"unique\",
+            l.partial,
+            l.origin,
+            s.sql
+        FROM
+            pragma_index_list(?) l
+        LEFT JOIN
+            main.sqlite_schema s ON s.type = 'index' AND s.name = l.name
+        ORDER BY
+            l.name;
+        ",
+    )?;
+
+    let indexes = stmt
+        .query_map([table_name], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, bool>(1)?,
+                row.get::<_, bool>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?
+        .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+    let mut stmt = conn.prepare("SELECT name FROM pragma_index_info(?) ORDER BY seqno;")?;
+
+    let mut values = Vec::new();
+
+    for (name, unique, partial, origin, sql) in indexes {
+        // NOTE: The columns of an index on expressions don't have a name
+        let columns = stmt
+            .query_map([&name], |row| row.get::<_, Option<String>>(0))?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        values.push(Index {
+            name,
+            unique,
+            partial,
+            origin,
+            columns,
+            sql,
+        });
+    }
+
+    Ok(values)
+}
+
+// NOTE: The pragma returns a row per column of each foreign key, grouped by the id
+fn get_foreign_keys(conn: &Connection, table_name: &str) -> Result<Vec<ForeignKey>> {
+    let mut stmt = conn.prepare(
+        "
+        SELECT
+            id,
+            \"table\",
+            \"from\",
+            \"to\",
+            on_update,
+            on_delete
+        FROM
+            pragma_foreign_key_list(?)
+        ORDER BY
+            id,
+            seq;
+        ",
+    )?;
+
+    let mut rows = stmt.query([table_name])?;
+
+    let mut foreign_keys: Vec<(i64, ForeignKey)> = Vec::new();
+
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let from: String = row.get(2)?;
+        let to: Option<String> = row.get(3)?;
+
+        match foreign_keys.last_mut() {
+            Some((last_id, foreign_key)) if *last_id == id => {
+                foreign_key.from.push(from);
+                foreign_key.to.push(to);
+            }
+            _ => foreign_keys.push((
+                id,
+                ForeignKey {
+                    table: row.get(1)?,
+                    from: vec![from],
+                    to: vec![to],
+                    on_update: row.get(4)?,
+                    on_delete: row.get(5)?,
+                },
+            )),
+        }
+    }
+
+    Ok(foreign_keys.into_iter().map(|(_, v)| v).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_schema() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(
+            "
+            CREATE TABLE author (id INTEGER PRIMARY KEY, name TEXT NOT NULL UNIQUE);
+            CREATE TABLE book (
+                id INTEGER PRIMARY KEY,
+                author_id INTEGER REFERENCES author (id) ON DELETE CASCADE,
+                title TEXT DEFAULT 'untitled'
+            );
+            CREATE INDEX book_title ON book (title) WHERE title IS NOT NULL;
+            CREATE VIEW book_author AS SELECT book.title, author.name FROM book JOIN author ON author.id = book.author_id;
+            CREATE TRIGGER book_insert AFTER INSERT ON book BEGIN SELECT 1; END;
+            ",
+        )
+        .unwrap();
+
+        let schema = get_schema(&conn).unwrap();
+
+        assert_eq!(
+            schema.tables.iter().map(|t| &t.name).collect::<Vec<_>>(),
+            vec!["author", "book"]
+        );
+
+        let author = &schema.tables[0];
+        assert_eq!(
+            author.columns[0],
+            Column {
+                name: "id".to_string(),
+                column_type: "INTEGER".to_string(),
+                not_null: false,
+                default: None,
+                primary_key: 1,
+            }
+        );
+        assert!(author.columns[1].not_null);
+        assert_eq!(author.indexes.len(), 1);
+        assert!(author.indexes[0].unique);
+        assert_eq!(author.indexes[0].origin, "u");
+        assert_eq!(author.indexes[0].columns, vec![Some("name".to_string())]);
+        assert_eq!(author.indexes[0].sql, None);
+
+        let book = &schema.tables[1];
+        assert_eq!(book.columns[2].default, Some("'untitled'".to_string()));
+        assert_eq!(
+            book.indexes,
+            vec![Index {
+                name: "book_title".to_string(),
+                unique: false,
+                partial: true,
+                origin: "c".to_string(),
+                columns: vec![Some("title".to_string())],
+                sql: Some(
+                    "CREATE INDEX book_title ON book (title) WHERE title IS NOT NULL".to_string()
+                ),
+            }]
+        );
+        assert_eq!(
+            book.foreign_keys,
+            vec![ForeignKey {
+                table: "author".to_string(),
+                from: vec!["author_id".to_string()],
+                to: vec![Some("id".to_string())],
+                on_update: "NO ACTION".to_string(),
+                on_delete: "CASCADE".to_string(),
+            }]
+        );
+
+        assert_eq!(schema.views.len(), 1);
+        assert_eq!(schema.views[0].name, "book_author");
+        assert_eq!(
+            schema.views[0]
+                .columns
+                .iter()
+                .map(|c| &c.name)
+                .collect::<Vec<_>>(),
+            vec!["title", "name"]
+        );
+
+        assert_eq!(
+            schema.triggers,
+            vec![Trigger {
+                name: "book_insert".to_string(),
+                table: "book".to_string(),
+                sql: Some(
+                    "CREATE TRIGGER book_insert AFTER INSERT ON book BEGIN SELECT 1; END"
+                        .to_string()
+                ),
+            }]
+        );
+    }
+}
@@ -0,0 +1,554 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde::Serialize;
+
+use super::schema::{Column, Schema, Table};
+
+// NOTE: The objects managed by Query, as the migration history or the change capture triggers,
+// are left out of the diff
+const INTERNAL_PREFIX: &str = "_query_";
+const REBUILD_PREFIX: &str = "_query_new_";
+
+const CREATED: &str = "created";
+const DROPPED: &str = "dropped";
+const ALTERED: &str = "altered";
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SchemaDiff {
+    pub tables: Vec<TableDiff>,
+    pub indexes: Vec<ObjectDiff>,
+    pub views: Vec<ObjectDiff>,
+    pub triggers: Vec<ObjectDiff>,
+}
+
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+            && self.indexes.is_empty()
+            && self.views.is_empty()
+            && self.triggers.is_empty()
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TableDiff {
+    pub name: String,
+    pub change: String,
+    pub added_columns: Vec<String>,
+    pub dropped_columns: Vec<String>,
+    pub altered_columns: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ObjectDiff {
+    pub name: String,
+    pub change: String,
+}
+
+// NOTE: Returns the changes to make in the source schema to get the branch schema
+pub fn diff_schema(source: &Schema, branch: &Schema) -> SchemaDiff {
+    let source_tables = tables(source);
+    let branch_tables = tables(branch);
+
+    let mut table_diffs = Vec::new();
+
+    for (name, table) in &branch_tables {
+        match source_tables.get(name) {
+            None => table_diffs.push(TableDiff {
+                name: name.to_string(),
+                change: CREATED.to_string(),
+                added_columns: table.columns.iter().map(|c| c.name.to_string()).collect(),
+                dropped_columns: Vec::new(),
+                altered_columns: Vec::new(),
+            }),
+            Some(source_table) if !same_definition(source_table, table) => {
+                table_diffs.push(TableDiff {
+                    name: name.to_string(),
+                    change: ALTERED.to_string(),
+                    added_columns: missing_columns(&table.columns, &source_table.columns),
+                    dropped_columns: missing_columns(&source_table.columns, &table.columns),
+                    altered_columns: table
+                        .columns
+                        .iter()
+                        .filter(|c| {
+                            source_table
+                                .columns
+                                .iter()
+                                .any(|s| s.name == c.name && s != *c)
+                        })
+                        .map(|c| c.name.to_string())
+                        .collect(),
+                })
+            }
+            Some(_) => (),
+        }
+    }
+
+    for name in source_tables.keys() {
+        if !branch_tables.contains_key(name) {
+            table_diffs.push(TableDiff {
+                name: name.to_string(),
+                change: DROPPED.to_string(),
+                added_columns: Vec::new(),
+                dropped_columns: Vec::new(),
+                altered_columns: Vec::new(),
+            });
+        }
+    }
+
+    SchemaDiff {
+        tables: table_diffs,
+        indexes: diff_objects(&indexes(source), &indexes(branch)),
+        views: diff_objects(&views(source), &views(branch)),
+        triggers: diff_objects(&triggers(source), &triggers(branch)),
+    }
+}
+
+// NOTE: The columns can only be added with ALTER TABLE at the end of a table, without other
+// changes. Any other change of a table rebuilds it: a new table is created with the branch
+// definition, the rows of the common columns are copied, and it replaces the old table. The
+// indexes and triggers of a rebuilt table are created again, and the views are dropped before
+// the rebuilds and created again after them.
+pub fn migration_sql(source: &Schema, branch: &Schema, diff: &SchemaDiff) -> String {
+    let source_tables = tables(source);
+    let branch_tables = tables(branch);
+    let branch_indexes = indexes(branch);
+    let branch_views = views(branch);
+    let branch_triggers = triggers(branch);
+
+    let mut alters = Vec::new();
+    let mut rebuilt = Vec::new();
+
+    for table_diff in diff.tables.iter().filter(|t| t.change == ALTERED) {
+        let source_table = source_tables[&table_diff.name];
+        let branch_table = branch_tables[&table_diff.name];
+
+        match add_columns(source_table, branch_table) {
+            Some(v) => alters.extend(v),
+            None => rebuilt.push(branch_table),
+        }
+    }
+
+    let mut statements = Vec::new();
+
+    let changed = |diffs: &[ObjectDiff], change: &str| -> Vec<String> {
+        diffs
+            .iter()
+            .filter(|d| d.change == change)
+            .map(|d| d.name.to_string())
+            .collect()
+    };
+
+    let mut dropped_views = changed(&diff.views, DROPPED);
+    dropped_views.extend(changed(&diff.views, ALTERED));
+
+    if !rebuilt.is_empty() {
+        dropped_views = views(source).into_keys().collect();
+    }
+
+    for name in &dropped_views {
+        statements.push(format!("DROP VIEW IF EXISTS {}", quote(name)));
+    }
+
+    for name in changed(&diff.triggers, DROPPED)
+        .iter()
+        .chain(changed(&diff.triggers, ALTERED).iter())
+    {
+        statements.push(format!("DROP TRIGGER IF EXISTS {}", quote(name)));
+    }
+
+    for name in changed(&diff.indexes, DROPPED)
+        .iter()
+        .chain(changed(&diff.indexes, ALTERED).iter())
+    {
+        statements.push(format!("DROP INDEX IF EXISTS {}", quote(name)));
+    }
+
+    for table_diff in diff.tables.iter().filter(|t| t.change == DROPPED) {
+        statements.push(format!("DROP TABLE {}", quote(&table_diff.name)));
+    }
+
+    for table_diff in diff.tables.iter().filter(|t| t.change == CREATED) {
+        statements.push(
+            branch_tables[&table_diff.name]
+                .sql
+                .clone()
+                .unwrap_or_default(),
+        );
+    }
+
+    statements.extend(alters);
+
+    for table in &rebuilt {
+        let source_table = source_tables[&table.name];
+        let new_name = format!("{}{}", REBUILD_PREFIX, table.name);
+
+        let columns = table
+            .columns
+            .iter()
+            .filter(|c| source_table.columns.iter().any(|s| s.name == c.name))
+            .map(|c| quote(&c.name))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        statements.push(rename_table_sql(table, &new_name));
+        statements.push(format!(
+            "INSERT INTO {} ({}) SELECT {} FROM {}",
+            quote(&new_name),
+            columns,
+            columns,
+            quote(&table.name)
+        ));
+        statements.push(format!("DROP TABLE {}", quote(&table.name)));
+        statements.push(format!(
+            "ALTER TABLE {} RENAME TO {}",
+            quote(&new_name),
+            quote(&table.name)
+        ));
+    }
+
+    let mut created_indexes = changed(&diff.indexes, CREATED);
+    created_indexes.extend(changed(&diff.indexes, ALTERED));
+
+    let mut created_triggers = changed(&diff.triggers, CREATED);
+    created_triggers.extend(changed(&diff.triggers, ALTERED));
+
+    for table in &rebuilt {
+        for index in table.indexes.iter().filter(|i| i.sql.is_some()) {
+            if !created_indexes.contains(&index.name) {
+                created_indexes.push(index.name.to_string());
+            }
+        }
+
+        for trigger in branch.triggers.iter().filter(|t| t.table == table.name) {
+            if !created_triggers.contains(&trigger.name) {
+                created_triggers.push(trigger.name.to_string());
+            }
+        }
+    }
+
+    let mut created_views = changed(&diff.views, CREATED);
+    created_views.extend(changed(&diff.views, ALTERED));
+
+    if !rebuilt.is_empty() {
+        created_views = branch_views.keys().map(|k| k.to_string()).collect();
+    }
+
+    for (names, objects) in [
+        (created_indexes, &branch_indexes),
+        (created_triggers, &branch_triggers),
+        (created_views, &branch_views),
+    ] {
+        for name in names {
+            if let Some(sql) = objects.get(&name) {
+                statements.push(sql.to_string());
+            }
+        }
+    }
+
+    statements
+        .iter()
+        .map(|s| format!("{};\n", s))
+        .collect::<String>()
+}
+
+fn tables(schema: &Schema) -> BTreeMap<String, &Table> {
+    schema
+        .tables
+        .iter()
+        .filter(|t| !t.name.starts_with(INTERNAL_PREFIX))
+        .map(|t| (t.name.to_string(), t))
+        .collect()
+}
+
+// NOTE: Only the indexes created with CREATE INDEX have SQL, the other ones are part of the
+// definition of their table
+fn indexes(schema: &Schema) -> BTreeMap<String, String> {
+    schema
+        .tables
+        .iter()
+        .flat_map(|t| t.indexes.iter())
+        .filter(|i| !i.name.starts_with(INTERNAL_PREFIX))
+        .filter_map(|i| {
+            i.sql
+                .as_ref()
+                .map(|sql| (i.name.to_string(), sql.to_string()))
+        })
+        .collect()
+}
+
+fn views(schema: &Schema) -> BTreeMap<String, String> {
+    schema
+        .views
+        .iter()
+        .filter(|v| !v.name.starts_with(INTERNAL_PREFIX))
+        .filter_map(|v| {
+            v.sql
+                .as_ref()
+                .map(|sql| (v.name.to_string(), sql.to_string()))
+        })
+        .collect()
+}
+
+fn triggers(schema: &Schema) -> BTreeMap<String, String> {
+    schema
+        .triggers
+        .iter()
+        .filter(|t| !t.name.starts_with(INTERNAL_PREFIX))
+        .filter_map(|t| {
+            t.sql
+                .as_ref()
+                .map(|sql| (t.name.to_string(), sql.to_string()))
+        })
+        .collect()
+}
+
+fn diff_objects(
+    source: &BTreeMap<String, String>,
+    branch: &BTreeMap<String, String>,
+) -> Vec<ObjectDiff> {
+    let mut diffs = Vec::new();
+
+    for (name, sql) in branch {
+        let change = match source.get(name) {
+            None => CREATED,
+            Some(v) if v != sql => ALTERED,
+            Some(_) => continue,
+        };
+
+        diffs.push(ObjectDiff {
+            name: name.to_string(),
+            change: change.to_string(),
+        });
+    }
+
+    for name in source.keys() {
+        if !branch.contains_key(name) {
+            diffs.push(ObjectDiff {
+                name: name.to_string(),
+                change: DROPPED.to_string(),
+            });
+        }
+    }
+
+    diffs
+}
+
+fn missing_columns(columns: &[Column], other: &[Column]) -> Vec<String> {
+    columns
+        .iter()
+        .filter(|c| !other.iter().any(|o| o.name == c.name))
+        .map(|c| c.name.to_string())
+        .collect()
+}
+
+// NOTE: Returns None when the table can't be changed only with ADD COLUMN. The added columns
+// can't be part of the primary key, of a unique constraint or of a foreign key, and their
+// default value has to be a constant.
+fn add_columns(source: &Table, branch: &Table) -> Option<Vec<String>> {
+    if branch.columns.len() <= source.columns.len() {
+        return None;
+    }
+
+    let (existing, added) = branch.columns.split_at(source.columns.len());
+
+    if existing != source.columns.as_slice()
+        || source.foreign_keys != branch.foreign_keys
+        || constraints(source) != constraints(branch)
+    {
+        return None;
+    }
+
+    let mut statements = Vec::new();
+
+    for column in added {
+        let is_constant = match &column.default {
+            Some(v) => !v.starts_with('('),
+            None => !column.not_null,
+        };
+
+        if column.primary_key > 0 || !is_constant {
+            return None;
+        }
+
+        let mut definition = quote(&column.name);
+
+        if !column.column_type.is_empty() {
+            definition.push_str(&format!(" {}", column.column_type));
+        }
+
+        if column.not_null {
+            definition.push_str(" NOT NULL");
+        }
+
+        if let Some(default) = &column.default {
+            definition.push_str(&format!(" DEFAULT {}", default));
+        }
+
+        statements.push(format!(
+            "ALTER TABLE {} ADD COLUMN {}",
+            quote(&branch.name),
+            definition
+        ));
+    }
+
+    Some(statements)
+}
+
+// NOTE: The tables are compared by their columns and constraints instead of their SQL, because
+// SQLite changes the SQL of a table when it's renamed or when a column is added. The CHECK
+// constraints aren't compared.
+fn same_definition(source: &Table, branch: &Table) -> bool {
+    source.columns == branch.columns
+        && source.foreign_keys == branch.foreign_keys
+        && constraints(source) == constraints(branch)
+}
+
+// NOTE: The indexes of the UNIQUE and PRIMARY KEY constraints
+fn constraints(table: &Table) -> Vec<(bool, &Vec<Option<String>>)> {
+    table
+        .indexes
+        .iter()
+        .filter(|i| i.origin != "c")
+        .map(|i| (i.unique, &i.columns))
+        .collect()
+}
+
+fn rename_table_sql(table: &Table, name: &str) -> String {
+    let sql = table.sql.clone().unwrap_or_default();
+
+    Regex::new(r#"(?i)^CREATE\s+TABLE\s+(?:IF\s+NOT\s+EXISTS\s+)?(?:"(?:[^"]|"")+"|\[[^\]]+\]|`[^`]+`|[^\s(]+)"#)
+        .unwrap()
+        .replace(&sql, format!("CREATE TABLE {}", quote(name)).replace('$', "$$"))
+        .to_string()
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::sqlite::schema::get_schema;
+
+    use super::*;
+
+    fn conn(sql: &str) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+
+        conn.execute_batch(sql).unwrap();
+
+        conn
+    }
+
+    #[test]
+    fn test_diff_schema() {
+        let source = conn(
+            "
+            CREATE TABLE author (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+            CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT, year INTEGER);
+            CREATE TABLE review (id INTEGER PRIMARY KEY);
+            CREATE INDEX book_title ON book (title);
+            CREATE VIEW book_title_view AS SELECT title FROM book;
+            CREATE TABLE _query_migrations (version TEXT PRIMARY KEY);
+            INSERT INTO author (name) VALUES ('Frank Herbert');
+            INSERT INTO book (title, year) VALUES ('Dune', 1965);
+            ",
+        );
+        let branch = conn(
+            "
+            CREATE TABLE author (id INTEGER PRIMARY KEY, name TEXT NOT NULL, bio TEXT DEFAULT '');
+            CREATE TABLE book (
+                id INTEGER PRIMARY KEY,
+                title TEXT NOT NULL,
+                author_id INTEGER REFERENCES author (id)
+            );
+            CREATE TABLE publisher (id INTEGER PRIMARY KEY);
+            CREATE INDEX book_title ON book (title);
+            CREATE INDEX book_author_id ON book (author_id);
+            CREATE VIEW book_title_view AS SELECT title FROM book;
+            CREATE TRIGGER book_insert AFTER INSERT ON book BEGIN SELECT 1; END;
+            ",
+        );
+
+        let source_schema = get_schema(&source).unwrap();
+        let branch_schema = get_schema(&branch).unwrap();
+
+        let diff = diff_schema(&source_schema, &branch_schema);
+
+        assert_eq!(
+            diff.tables,
+            vec![
+                TableDiff {
+                    name: "author".to_string(),
+                    change: ALTERED.to_string(),
+                    added_columns: vec!["bio".to_string()],
+                    dropped_columns: vec![],
+                    altered_columns: vec![],
+                },
+                TableDiff {
+                    name: "book".to_string(),
+                    change: ALTERED.to_string(),
+                    added_columns: vec!["author_id".to_string()],
+                    dropped_columns: vec!["year".to_string()],
+                    altered_columns: vec!["title".to_string()],
+                },
+                TableDiff {
+                    name: "publisher".to_string(),
+                    change: CREATED.to_string(),
+                    added_columns: vec!["id".to_string()],
+                    dropped_columns: vec![],
+                    altered_columns: vec![],
+                },
+                TableDiff {
+                    name: "review".to_string(),
+                    change: DROPPED.to_string(),
+                    added_columns: vec![],
+                    dropped_columns: vec![],
+                    altered_columns: vec![],
+                },
+            ]
+        );
+        assert_eq!(
+            diff.indexes,
+            vec![ObjectDiff {
+                name: "book_author_id".to_string(),
+                change: CREATED.to_string(),
+            }]
+        );
+        assert!(diff.views.is_empty());
+        assert_eq!(diff.triggers.len(), 1);
+
+        let sql = migration_sql(&source_schema, &branch_schema, &diff);
+
+        assert!(sql.contains(r#"ALTER TABLE "author" ADD COLUMN "bio" TEXT DEFAULT '';"#));
+        assert!(sql.contains(r#"DROP TABLE "review";"#));
+
+        source.execute_batch(&sql).unwrap();
+
+        let migrated_schema = get_schema(&source).unwrap();
+
+        assert!(diff_schema(&migrated_schema, &branch_schema).is_empty());
+
+        let title: String = source
+            .query_row("SELECT title FROM book_title_view;", [], |row| row.get(0))
+            .unwrap();
+
+        assert_eq!(title, "Dune");
+    }
+
+    #[test]
+    fn test_diff_schema_empty() {
+        let sql = "CREATE TABLE book (id INTEGER PRIMARY KEY, title TEXT);";
+
+        let diff = diff_schema(
+            &get_schema(&conn(sql)).unwrap(),
+            &get_schema(&conn(sql)).unwrap(),
+        );
+
+        assert!(diff.is_empty());
+    }
+}
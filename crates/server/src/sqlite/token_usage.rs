@@ -0,0 +1,189 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Duration};
+
+use anyhow::Result;
+use rusqlite::named_params;
+use tokio::{task::JoinHandle, time};
+
+use crate::{archive::archiver::now_millis, env::Env};
+
+use super::connect_db::connect_config_db;
+
+struct TokenUse {
+    used_at: i64,
+    ip: Option<String>,
+}
+
+// NOTE: The last use of each token, by token name, until the next flush writes it to the
+// _config_token_access table, so a request doesn't write to the config database
+static TOKEN_USES: Mutex<Option<HashMap<String, TokenUse>>> = Mutex::new(None);
+
+pub fn record_token_use(name: &str, ip: Option<IpAddr>) {
+    let mut uses = match TOKEN_USES.lock() {
+        Ok(v) => v,
+        Err(e) => e.into_inner(),
+    };
+
+    uses.get_or_insert_with(HashMap::new).insert(
+        name.to_string(),
+        TokenUse {
+            used_at: (now_millis() / 1000) as i64,
+            ip: ip.map(|v| v.to_string()),
+        },
+    );
+}
+
+pub fn start_token_usage_task() -> JoinHandle<()> {
+    let interval_duration = Duration::from_secs(Env::token_usage_interval());
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(interval_duration);
+
+        tracing::info!("Token usage interval duration: {:?}", interval_duration);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = flush_token_usage() {
+                tracing::error!("Error writing the token usage: {}", e);
+            }
+        }
+    })
+}
+
+// NOTE: Writes the last use of the tokens used since the previous flush, in one transaction. The
+// uses of a token deleted in the meantime are discarded. If the write fails, the uses are kept
+// for the next flush, unless the token was used again in the meantime.
+pub fn flush_token_usage() -> Result<usize> {
+    let uses = match TOKEN_USES.lock() {
+        Ok(mut v) => v.take(),
+        Err(e) => e.into_inner().take(),
+    };
+
+    let uses = match uses {
+        Some(v) if !v.is_empty() => v,
+        _ => return Ok(0),
+    };
+
+    if let Err(e) = write_token_uses(&uses) {
+        let mut pending = match TOKEN_USES.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner(),
+        };
+        let pending = pending.get_or_insert_with(HashMap::new);
+
+        for (name, token_use) in uses {
+            pending.entry(name).or_insert(token_use);
+        }
+
+        return Err(e);
+    }
+
+    Ok(uses.len())
+}
+
+fn write_token_uses(uses: &HashMap<String, TokenUse>) -> Result<()> {
+    let mut conn = connect_config_db()?;
+    let tx = conn.transaction()?;
+
+    {
+        let mut stmt = tx.prepare(
+            r#"
+            INSERT INTO
+                _config_token_access(token_name, last_used_at, last_used_ip)
+            SELECT
+                name, :used_at, :ip
+            FROM
+                _config_token
+            WHERE
+                name = :name
+            ON CONFLICT(token_name) DO UPDATE SET
+                last_used_at = excluded.last_used_at,
+                last_used_ip = excluded.last_used_ip
+            "#,
+        )?;
+
+        for (name, token_use) in uses.iter() {
+            stmt.execute(named_params! {
+                ":name": name,
+                ":used_at": token_use.used_at,
+                ":ip": token_use.ip,
+            })?;
+        }
+    }
+
+    tx.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db_test;
+
+    use super::*;
+
+    db_test!(test_token_usage, TestTokenUsage, {
+        let conn = connect_config_db().unwrap();
+
+        conn.execute(
+            r#"
+            INSERT INTO
+                _config_token(name, token, expiration_date, write)
+            VALUES
+                ('usage', token('{"sub": "uuid", "exp": 0, "iat": 0, "iss": "token"}'), 0, 1);
+            "#,
+            (),
+        )
+        .unwrap();
+
+        record_token_use("usage", Some("10.0.0.1".parse().unwrap()));
+        record_token_use("usage", Some("10.0.0.2".parse().unwrap()));
+        record_token_use("deleted", None);
+
+        flush_token_usage().unwrap();
+
+        let (used_at, ip): (Option<i64>, Option<String>) = conn
+            .query_row(
+                "SELECT last_used_at, last_used_ip FROM _config_token_access WHERE token_name = 'usage'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+
+        assert!(used_at.is_some());
+        assert_eq!(ip, Some("10.0.0.2".to_string()));
+
+        let count: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM _config_token_access WHERE token_name = 'deleted'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(count, 0);
+
+        // NOTE: The uses are kept if the write fails
+        conn.execute_batch("ALTER TABLE _config_token_access RENAME TO _config_token_access_old")
+            .unwrap();
+
+        record_token_use("usage", Some("10.0.0.3".parse().unwrap()));
+
+        assert!(flush_token_usage().is_err());
+
+        conn.execute_batch("ALTER TABLE _config_token_access_old RENAME TO _config_token_access")
+            .unwrap();
+
+        flush_token_usage().unwrap();
+
+        let ip: Option<String> = conn
+            .query_row(
+                "SELECT last_used_ip FROM _config_token_access WHERE token_name = 'usage'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+
+        assert_eq!(ip, Some("10.0.0.3".to_string()));
+    });
+}
@@ -0,0 +1,16 @@
+const branchPreviewFunction = `
+import { Database } from 'query:database';
+
+globalThis.___handleRequest = async () => {
+    try {
+        const db = new Database("hurl-test-branch-preview.sql");
+        const result = db.query("SELECT name FROM branch_preview");
+
+        return new Response(result[0].name, { status: 200 });
+    } catch (e) {
+        console.error(e.message + "\\n" + (e.stack || ""));
+        return new Response(e.message + "\\n" + (e.stack || ""), { status: 500 });
+    }
+}`;
+
+console.log(`[${Array.from(new TextEncoder("utf-8").encode(branchPreviewFunction)).toString()}]`);